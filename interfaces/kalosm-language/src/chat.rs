@@ -261,6 +261,7 @@ pub struct ChatBuilder<M: Model> {
     model: M,
     chat_markers: ChatMarkers,
     session: Option<<M::SyncModel as kalosm_language_model::SyncModel>::Session>,
+    session_path: Option<PathBuf>,
     system_prompt: Option<String>,
     sampler: Arc<Mutex<dyn Sampler + Send + Sync>>,
     bot_constraints: Option<ResponseConstraintGenerator>,
@@ -275,6 +276,7 @@ impl<M: Model> ChatBuilder<M> {
             model,
             chat_markers,
             session: None,
+            session_path: None,
             system_prompt: None,
             sampler: Arc::new(Mutex::new(GenerationParameters::default().sampler())),
             bot_constraints: None,
@@ -348,6 +350,7 @@ impl<M: Model> ChatBuilder<M> {
             model: self.model,
             chat_markers: self.chat_markers,
             session: self.session,
+            session_path: self.session_path,
             system_prompt: self.system_prompt,
             sampler: self.sampler,
             bot_constraints: Some(Arc::new(Mutex::new(Box::new(
@@ -387,6 +390,8 @@ impl<M: Model> ChatBuilder<M> {
 
     /// Try to load the chat session from the given path. If the session is not found, the default session will be used.
     ///
+    /// The session is loaded with [`SyncModel::load_session`] when the chat is built. If the session was saved by a different model (or a different tokenizer), it is rejected and the default session will be used instead.
+    ///
     /// # Example
     /// ```rust, no_run
     /// # use kalosm::language::*;
@@ -397,13 +402,9 @@ impl<M: Model> ChatBuilder<M> {
     ///     .build();
     /// # }
     /// ```
-    pub fn with_try_session_path(self, path: impl AsRef<std::path::Path>) -> Self {
-        let session = <M::SyncModel as kalosm_language_model::SyncModel>::Session::load_from(path);
-        if let Ok(session) = session {
-            self.with_session(session)
-        } else {
-            self
-        }
+    pub fn with_try_session_path(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.session_path = Some(path.as_ref().to_path_buf());
+        self
     }
 
     /// Set the initial history of the chat. Each message in the original history will be added to the chat history, and the model will be fed the user messages.
@@ -441,6 +442,7 @@ impl<M: Model> ChatBuilder<M> {
             sampler,
             bot_constraints,
             session,
+            session_path,
             initial_history,
         } = self;
         let system_prompt_marker = chat_markers.system_prompt_marker.to_string();
@@ -460,6 +462,22 @@ impl<M: Model> ChatBuilder<M> {
                     model
                         .run_sync(move |model| {
                             Box::pin(async move {
+                                let session = match session_path {
+                                    Some(path) if path.exists() => {
+                                        match model.load_session(&path) {
+                                            Ok(session) => Some(session),
+                                            Err(err) => {
+                                                tracing::warn!(
+                                                    "Failed to load chat session from {}: {}",
+                                                    path.display(),
+                                                    err
+                                                );
+                                                session
+                                            }
+                                        }
+                                    }
+                                    _ => session,
+                                };
                                 let _ = tx.send(ChatSession::new(
                                     model,
                                     system_prompt_marker,
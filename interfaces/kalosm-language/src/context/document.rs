@@ -1,7 +1,14 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
 use url::Url;
 pub use whatlang::Lang;
 
+use crate::vector_db::{Metadata, MetadataValue};
+
 /// A document is a piece of text with a title.
+///
+/// Documents can also keep track of where they came from. The loaders set the [`DocumentSource`] of the document and [`DocumentSection`]s that map ranges of the body back to pages or headings in the source, so [`Document::source_location`] can cite the exact location of any chunk.
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Document {
     title: String,
@@ -9,6 +16,12 @@ pub struct Document {
     summary: Option<String>,
     created_at: Option<chrono::DateTime<chrono::Utc>>,
     updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    source: Option<DocumentSource>,
+    #[serde(default)]
+    sections: Vec<DocumentSection>,
+    #[serde(default)]
+    metadata: Metadata,
 }
 
 impl Document {
@@ -30,6 +43,9 @@ impl Document {
             summary: None,
             created_at: None,
             updated_at: None,
+            source: None,
+            sections: Vec::new(),
+            metadata: Metadata::new(),
         }
     }
 
@@ -57,6 +73,187 @@ impl Document {
     pub fn body(&self) -> &str {
         &self.body
     }
+
+    /// Get the summary of the document.
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    /// Get the time the document was created.
+    pub fn created_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.created_at
+    }
+
+    /// Get the time the document was last updated.
+    pub fn updated_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.updated_at
+    }
+
+    /// Get the file or URL the document was loaded from.
+    pub fn source(&self) -> Option<&DocumentSource> {
+        self.source.as_ref()
+    }
+
+    /// Set the file or URL the document was loaded from.
+    pub fn set_source(&mut self, source: impl Into<DocumentSource>) {
+        self.source = Some(source.into());
+    }
+
+    /// Set the file or URL the document was loaded from.
+    pub fn with_source(mut self, source: impl Into<DocumentSource>) -> Self {
+        self.set_source(source);
+        self
+    }
+
+    /// Get the sections of the body that map back to pages or headings in the source.
+    pub fn sections(&self) -> &[DocumentSection] {
+        &self.sections
+    }
+
+    /// Add a section of the body that maps back to a page or heading in the source.
+    pub fn push_section(&mut self, section: DocumentSection) {
+        self.sections.push(section);
+    }
+
+    /// Get the metadata of the document.
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Get a mutable reference to the metadata of the document.
+    pub fn metadata_mut(&mut self) -> &mut Metadata {
+        &mut self.metadata
+    }
+
+    /// Add a value to the metadata of the document.
+    pub fn with_metadata(
+        mut self,
+        key: impl Into<String>,
+        value: impl Into<MetadataValue>,
+    ) -> Self {
+        self.metadata.insert(key, value);
+        self
+    }
+
+    /// Find where a byte range of the body came from in the source. The pages and heading path are taken from every section that overlaps the range.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm_language::prelude::*;
+    /// use std::path::PathBuf;
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let document = PdfDocument::try_from(PathBuf::from("./manual.pdf"))?
+    ///         .into_document()
+    ///         .await?;
+    ///     let location = document.source_location(0..100);
+    ///     // Prints something like "./manual.pdf, page 1"
+    ///     println!("{location}");
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn source_location(&self, byte_range: Range<usize>) -> SourceLocation {
+        let overlapping = self.sections.iter().filter(|section| {
+            section.byte_range.start < byte_range.end.max(byte_range.start + 1)
+                && byte_range.start < section.byte_range.end
+        });
+        let mut pages = Vec::new();
+        let mut heading_path = Vec::new();
+        for section in overlapping {
+            if let Some(page) = section.page {
+                if !pages.contains(&page) {
+                    pages.push(page);
+                }
+            }
+            // Use the heading path of the first section with headings
+            if heading_path.is_empty() {
+                heading_path.clone_from(&section.heading_path);
+            }
+        }
+        pages.sort_unstable();
+        SourceLocation {
+            title: self.title.clone(),
+            source: self.source.clone(),
+            byte_range,
+            pages,
+            heading_path,
+        }
+    }
+}
+
+/// The file or URL a [`Document`] was loaded from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum DocumentSource {
+    /// A file on the local file system.
+    Path(PathBuf),
+    /// A web page, feed item or other URL.
+    Url(Url),
+}
+
+impl From<PathBuf> for DocumentSource {
+    fn from(path: PathBuf) -> Self {
+        Self::Path(path)
+    }
+}
+
+impl From<Url> for DocumentSource {
+    fn from(url: Url) -> Self {
+        Self::Url(url)
+    }
+}
+
+impl std::fmt::Display for DocumentSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Url(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+/// A range of the body of a [`Document`] that came from a specific page or heading in the source.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DocumentSection {
+    /// The byte range of the section in the body of the document.
+    pub byte_range: Range<usize>,
+    /// The page number of the section, starting at 1.
+    pub page: Option<u32>,
+    /// The headings the section is nested under, from the top level heading down.
+    pub heading_path: Vec<String>,
+}
+
+/// Where a range of the body of a [`Document`] came from. Created with [`Document::source_location`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    /// The title of the document.
+    pub title: String,
+    /// The file or URL the document was loaded from.
+    pub source: Option<DocumentSource>,
+    /// The byte range in the body of the document.
+    pub byte_range: Range<usize>,
+    /// The pages the range spans, in order.
+    pub pages: Vec<u32>,
+    /// The headings the range is nested under, from the top level heading down.
+    pub heading_path: Vec<String>,
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{source}")?,
+            None => write!(f, "{}", self.title)?,
+        }
+        match self.pages.as_slice() {
+            [] => {}
+            [page] => write!(f, ", page {page}")?,
+            [first, .., last] => write!(f, ", pages {first}-{last}")?,
+        }
+        if !self.heading_path.is_empty() {
+            write!(f, ", {}", self.heading_path.join(" > "))?;
+        }
+        Ok(())
+    }
 }
 
 impl From<String> for Document {
@@ -146,3 +343,30 @@ where
         Ok(documents)
     }
 }
+
+#[test]
+fn source_location_combines_overlapping_sections() {
+    let mut document = Document::from_parts("Manual", "Page one text. Page two text. Page three.")
+        .with_source(PathBuf::from("manual.pdf"));
+    for (page, byte_range) in [(1, 0..15), (2, 15..30), (3, 30..41)] {
+        document.push_section(DocumentSection {
+            byte_range,
+            page: Some(page),
+            heading_path: vec!["Setup".to_string()],
+        });
+    }
+
+    let location = document.source_location(10..20);
+    assert_eq!(location.pages, vec![1, 2]);
+    assert_eq!(location.to_string(), "manual.pdf, pages 1-2, Setup");
+    assert_eq!(
+        document.source_location(31..35).to_string(),
+        "manual.pdf, page 3, Setup"
+    );
+
+    // Documents stored before sources were tracked still deserialize
+    let old = r#"{"title":"Old","body":"text","summary":null,"created_at":null,"updated_at":null}"#;
+    let old: Document = serde_json::from_str(old).unwrap();
+    assert_eq!(old.source(), None);
+    assert_eq!(old.source_location(0..4).to_string(), "Old");
+}
@@ -0,0 +1,129 @@
+use std::path::{Path, PathBuf};
+
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::context::document::{Document, IntoDocument};
+
+/// A programming language that [`CodeDocument`] and [`CodeChunker`](crate::prelude::CodeChunker) understand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodeLanguage {
+    /// Rust (`.rs`)
+    Rust,
+    /// TypeScript (`.ts`, `.mts`, `.cts`)
+    TypeScript,
+    /// TypeScript or JavaScript with JSX (`.tsx`, `.jsx`)
+    Tsx,
+    /// JavaScript (`.js`, `.mjs`, `.cjs`)
+    JavaScript,
+    /// Python (`.py`, `.pyi`)
+    Python,
+}
+
+impl CodeLanguage {
+    /// Detect the language of a file from its extension.
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "rs" => Some(Self::Rust),
+            "ts" | "mts" | "cts" => Some(Self::TypeScript),
+            "tsx" | "jsx" => Some(Self::Tsx),
+            "js" | "mjs" | "cjs" => Some(Self::JavaScript),
+            "py" | "pyi" => Some(Self::Python),
+            _ => None,
+        }
+    }
+
+    /// Detect the language of a file from the extension of its path.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// The name of the language. This is stored in the `language` metadata of a [`CodeDocument`].
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::TypeScript => "typescript",
+            Self::Tsx => "tsx",
+            Self::JavaScript => "javascript",
+            Self::Python => "python",
+        }
+    }
+
+    /// Find the language from its [`CodeLanguage::name`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Rust,
+            Self::TypeScript,
+            Self::Tsx,
+            Self::JavaScript,
+            Self::Python,
+        ]
+        .into_iter()
+        .find(|language| language.name() == name)
+    }
+
+    /// The tree-sitter grammar for the language. JavaScript is parsed with the TSX grammar, which accepts plain JavaScript.
+    pub(crate) fn grammar(&self) -> tree_sitter::Language {
+        match self {
+            Self::Rust => tree_sitter_rust::language(),
+            Self::TypeScript => tree_sitter_typescript::language_typescript(),
+            Self::Tsx | Self::JavaScript => tree_sitter_typescript::language_tsx(),
+            Self::Python => tree_sitter_python::language(),
+        }
+    }
+}
+
+impl std::fmt::Display for CodeLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A source code file that can be read from the file system. The language is detected from the extension of the file.
+///
+/// The title of the document is the file name and the language is stored in the `language` metadata. Use [`CodeChunker`](crate::prelude::CodeChunker) to split the document at function and class boundaries.
+#[derive(Debug, Clone)]
+pub struct CodeDocument {
+    path: PathBuf,
+    language: CodeLanguage,
+}
+
+impl CodeDocument {
+    /// The language of the file.
+    pub fn language(&self) -> CodeLanguage {
+        self.language
+    }
+}
+
+impl TryFrom<PathBuf> for CodeDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        let language = CodeLanguage::from_path(&path)
+            .ok_or_else(|| anyhow::anyhow!("Path is not a supported source code file"))?;
+        Ok(Self { path, language })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for CodeDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let title = self
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let file = File::open(&self.path).await?;
+        let mut text = String::new();
+        tokio::io::BufReader::new(file)
+            .read_to_string(&mut text)
+            .await?;
+        Ok(Document::from_parts(title, text)
+            .with_source(self.path)
+            .with_metadata("language", self.language.name()))
+    }
+}
@@ -28,7 +28,7 @@ impl TryFrom<PathBuf> for DocxDocument {
 #[async_trait::async_trait]
 impl IntoDocument for DocxDocument {
     async fn into_document(self) -> anyhow::Result<Document> {
-        let file = File::open(self.path)?;
+        let file = File::open(&self.path)?;
         let reader = std::io::BufReader::new(file);
         let docx = DocxFile::from_xml(reader)?;
         let mut text = String::new();
@@ -78,6 +78,6 @@ impl IntoDocument for DocxDocument {
                 docx_rs::DocumentChild::TableOfContents(_) => {}
             }
         }
-        Ok(Document::from_parts("", text))
+        Ok(Document::from_parts("", text).with_source(self.path))
     }
 }
@@ -0,0 +1,267 @@
+use std::path::{Path, PathBuf};
+
+use mail_parser::{Address, Message, MessageParser, MessagePart, MimeHeaders};
+
+use super::office::file_title;
+use super::FsDocument;
+use crate::context::document::{Document, IntoDocument, IntoDocuments};
+use crate::context::page::extract_article;
+
+/// An email (.eml) that can be read from the file system.
+///
+/// The title is the subject of the email and the sender, recipients and subject are added to the metadata. The body starts with the sender, recipients and date followed by the text of the email. Attachments are added after the text: text attachments and forwarded emails are included directly and attachments in a format [`FsDocument`] can read (pdf, docx, csv, ...) are converted to text. Other attachments are only listed by name.
+#[derive(Debug, Clone)]
+pub struct EmlDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for EmlDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if path.extension() != Some("eml".as_ref()) {
+            return Err(anyhow::anyhow!("Path is not a eml file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for EmlDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let bytes = tokio::fs::read(&self.path).await?;
+        email_document(&bytes, Some(&self.path)).await
+    }
+}
+
+/// A mailbox (.mbox) that can be read from the file system.
+///
+/// [`IntoDocuments`] reads each email in the mailbox as a separate document like [`EmlDocument`]. [`IntoDocument`] joins every email into one document titled with the file name.
+#[derive(Debug, Clone)]
+pub struct MboxDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for MboxDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if path.extension() != Some("mbox".as_ref()) {
+            return Err(anyhow::anyhow!("Path is not a mbox file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocuments for MboxDocument {
+    async fn into_documents(self) -> anyhow::Result<Vec<Document>> {
+        let bytes = tokio::fs::read(&self.path).await?;
+        let messages = mail_parser::mailbox::mbox::MessageIterator::new(bytes.as_slice())
+            .map(|message| {
+                message
+                    .map(|message| message.unwrap_contents())
+                    .map_err(|_| anyhow::anyhow!("Failed to read a message from the mailbox"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut documents = Vec::with_capacity(messages.len());
+        for message in messages {
+            documents.push(email_document(&message, Some(&self.path)).await?);
+        }
+        Ok(documents)
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for MboxDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let title = file_title(&self.path);
+        let path = self.path.clone();
+        let body = self
+            .into_documents()
+            .await?
+            .iter()
+            .map(|document| format!("Subject: {}\n{}", document.title(), document.body()))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        Ok(Document::from_parts(title, body).with_source(path))
+    }
+}
+
+/// Parse a raw email into a document. The sender, recipients and subject are added to the metadata of the document.
+async fn email_document(bytes: &[u8], path: Option<&Path>) -> anyhow::Result<Document> {
+    let message = MessageParser::default()
+        .parse(bytes)
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse email"))?;
+
+    let title = match message.subject() {
+        Some(subject) if !subject.trim().is_empty() => subject.trim().to_string(),
+        _ => path.map(file_title).unwrap_or_default(),
+    };
+
+    let mut body = message_text(&message);
+    let attachments = message.attachments().collect::<Vec<_>>();
+    if !attachments.is_empty() {
+        let dir = tempfile::tempdir()?;
+        for attachment in attachments {
+            body.push_str("\n\n");
+            body.push_str(&attachment_text(attachment, dir.path()).await);
+        }
+    }
+
+    let mut document = Document::from_parts(title, body);
+    if let Some(path) = path {
+        document.set_source(path.to_path_buf());
+    }
+    if let Some(subject) = message.subject() {
+        document.metadata_mut().insert("subject", subject);
+    }
+    if let Some(from) = message.from() {
+        document.metadata_mut().insert("from", format_address(from));
+    }
+    if let Some(to) = message.to() {
+        document.metadata_mut().insert("to", format_address(to));
+    }
+    if let Some(date) = message
+        .date()
+        .and_then(|date| chrono::DateTime::from_timestamp(date.to_timestamp(), 0))
+    {
+        document.set_created_at(date);
+    }
+    Ok(document)
+}
+
+/// The headers and the text of an email.
+fn message_text(message: &Message) -> String {
+    let mut text = String::new();
+    if let Some(from) = message.from() {
+        text += &format!("From: {}\n", format_address(from));
+    }
+    if let Some(to) = message.to() {
+        text += &format!("To: {}\n", format_address(to));
+    }
+    if let Some(date) = message.date() {
+        text += &format!("Date: {}\n", date.to_rfc3339());
+    }
+    let body = match (message.body_text(0), message.body_html(0)) {
+        (Some(body), _) => body.trim().to_string(),
+        (None, Some(html)) => html_body(&html),
+        (None, None) => String::new(),
+    };
+    if !body.is_empty() {
+        text.push('\n');
+        text += &body;
+    }
+    text
+}
+
+/// The readable text of an HTML body. Bodies that can't be parsed are skipped.
+fn html_body(html: &str) -> String {
+    extract_article(html)
+        .map(|article| article.body().trim().to_string())
+        .unwrap_or_default()
+}
+
+fn format_address(address: &Address) -> String {
+    address
+        .iter()
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(address)) => format!("{name} <{address}>"),
+            (Some(name), None) => name.to_string(),
+            (None, Some(address)) => address.to_string(),
+            (None, None) => String::new(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The text of an attachment, starting with the name of the attachment.
+async fn attachment_text(attachment: &MessagePart<'_>, dir: &Path) -> String {
+    let name = attachment
+        .attachment_name()
+        .and_then(|name| Path::new(name).file_name())
+        .map(|name| name.to_string_lossy().to_string());
+    let mut text = format!("Attachment: {}", name.as_deref().unwrap_or("unnamed"));
+
+    let contents = if let Some(message) = attachment.message() {
+        Some(message_text(message))
+    } else if let Some(document) = read_attachment(attachment, name.as_deref(), dir).await {
+        Some(document.body().to_string())
+    } else if attachment.is_text_html() {
+        attachment.text_contents().map(html_body)
+    } else if attachment.is_text() {
+        attachment.text_contents().map(str::to_string)
+    } else {
+        None
+    };
+    if let Some(contents) = contents {
+        text.push('\n');
+        text += contents.trim();
+    }
+    text
+}
+
+/// Read an attachment in any format [`FsDocument`] supports by writing it to a temporary file.
+async fn read_attachment(
+    attachment: &MessagePart<'_>,
+    name: Option<&str>,
+    dir: &Path,
+) -> Option<Document> {
+    let path = dir.join(name?);
+    tokio::fs::write(&path, attachment.contents()).await.ok()?;
+    let document = FsDocument::try_from(path).ok()?;
+    match document.into_document().await {
+        Ok(document) => Some(document),
+        Err(err) => {
+            tracing::warn!("Failed to read email attachment {name:?}: {err}");
+            None
+        }
+    }
+}
+
+#[tokio::test]
+async fn email_includes_text_attachments() {
+    let raw = "From: Alice <alice@example.com>\r\n\
+To: bob@example.com\r\n\
+Subject: Quarterly numbers\r\n\
+Date: Mon, 2 Oct 2023 10:00:00 +0000\r\n\
+MIME-Version: 1.0\r\n\
+Content-Type: multipart/mixed; boundary=\"b\"\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+The numbers are attached.\r\n\
+--b\r\n\
+Content-Type: text/csv; name=\"numbers.csv\"\r\n\
+Content-Disposition: attachment; filename=\"numbers.csv\"\r\n\
+\r\n\
+region,revenue\r\n\
+north,10\r\n\
+--b\r\n\
+Content-Type: image/png; name=\"chart.png\"\r\n\
+Content-Disposition: attachment; filename=\"chart.png\"\r\n\
+Content-Transfer-Encoding: base64\r\n\
+\r\n\
+iVBORw0KGgo=\r\n\
+--b--\r\n";
+
+    let document = email_document(raw.as_bytes(), None).await.unwrap();
+    assert_eq!(document.title(), "Quarterly numbers");
+    assert_eq!(
+        document.metadata().get("from"),
+        Some(&"Alice <alice@example.com>".into())
+    );
+    let body = document.body();
+    assert!(body.starts_with("From: Alice <alice@example.com>\nTo: bob@example.com\n"));
+    assert!(body.contains("The numbers are attached."));
+    assert!(body.contains("Attachment: numbers.csv\nregion: north, revenue: 10"));
+    assert!(body.ends_with("Attachment: chart.png"));
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use super::office::{file_title, open_archive, read_entry, xml_element_text};
+use crate::context::document::{Document, IntoDocument};
+use crate::context::page::extract_article;
+
+/// An epub e-book that can be read from the file system.
+///
+/// The chapters are read in the reading order of the book. The title is read from the book metadata.
+#[derive(Debug, Clone)]
+pub struct EpubDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for EpubDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if path.extension() != Some("epub".as_ref()) {
+            return Err(anyhow::anyhow!("Path is not a epub file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for EpubDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let mut archive = open_archive(&self.path).await?;
+
+        // The container points to the package file that lists the chapters
+        let container = read_entry(&mut archive, "META-INF/container.xml")?;
+        let package_path = rootfile_path(&container)?
+            .ok_or_else(|| anyhow::anyhow!("Epub file has no package file"))?;
+        let package = read_entry(&mut archive, &package_path)?;
+        let package_dir = package_path
+            .rsplit_once('/')
+            .map(|(dir, _)| dir)
+            .unwrap_or_default();
+
+        let title = xml_element_text(&package, "title")?.unwrap_or_else(|| file_title(&self.path));
+
+        let mut chapters = Vec::new();
+        for href in spine(&package)? {
+            let path = resolve_href(package_dir, &href);
+            // Some books list chapters that are missing from the archive
+            let Ok(chapter) = read_entry(&mut archive, &path) else {
+                continue;
+            };
+            let chapter = extract_article(&chapter)?;
+            let text = chapter.body().trim();
+            if !text.is_empty() {
+                chapters.push(text.to_string());
+            }
+        }
+
+        Ok(Document::from_parts(title, chapters.join("\n\n")).with_source(self.path))
+    }
+}
+
+/// Find the path of the package file in the container file.
+fn rootfile_path(container: &str) -> anyhow::Result<Option<String>> {
+    let mut reader = Reader::from_str(container);
+    loop {
+        match reader.read_event()? {
+            Event::Start(event) | Event::Empty(event)
+                if event.local_name().as_ref() == b"rootfile" =>
+            {
+                if let Some(path) = attribute(&reader, &event, "full-path")? {
+                    return Ok(Some(path));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Get the chapter paths from the package file in reading order.
+fn spine(package: &str) -> anyhow::Result<Vec<String>> {
+    let mut reader = Reader::from_str(package);
+    let mut manifest = HashMap::new();
+    let mut order = Vec::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(event) | Event::Empty(event) => match event.local_name().as_ref() {
+                b"item" => {
+                    if let (Some(id), Some(href)) = (
+                        attribute(&reader, &event, "id")?,
+                        attribute(&reader, &event, "href")?,
+                    ) {
+                        manifest.insert(id, href);
+                    }
+                }
+                b"itemref" => {
+                    if let Some(id) = attribute(&reader, &event, "idref")? {
+                        order.push(id);
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(order
+        .into_iter()
+        .filter_map(|id| manifest.remove(&id))
+        .collect())
+}
+
+fn attribute(
+    reader: &Reader<&[u8]>,
+    event: &BytesStart,
+    name: &str,
+) -> anyhow::Result<Option<String>> {
+    Ok(match event.try_get_attribute(name)? {
+        Some(attribute) => Some(
+            attribute
+                .decode_and_unescape_value(reader.decoder())?
+                .to_string(),
+        ),
+        None => None,
+    })
+}
+
+/// Resolve a link relative to the package file into a path in the archive.
+fn resolve_href(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut segments = base
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect::<Vec<_>>();
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/").replace("%20", " ")
+}
+
+#[tokio::test]
+async fn epub_document_reads_chapters_in_order() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("book.epub");
+    let chapter = |title: &str, text: &str| {
+        format!("<html><head><title>{title}</title></head><body><h1>{title}</h1><p>{text}</p></body></html>")
+    };
+    super::office::write_archive(
+        &path,
+        &[
+            (
+                "META-INF/container.xml",
+                r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/content.opf",
+                r#"<package><metadata><dc:title xmlns:dc="dc">The Lighthouse</dc:title></metadata><manifest><item id="one" href="text/one.xhtml"/><item id="two" href="text/two.xhtml"/></manifest><spine><itemref idref="two"/><itemref idref="one"/></spine></package>"#,
+            ),
+            (
+                "OEBPS/text/one.xhtml",
+                &chapter("Arrival", "The keeper climbed the stairs of the lighthouse every evening to light the lamp before the ships came in."),
+            ),
+            (
+                "OEBPS/text/two.xhtml",
+                &chapter("Storm", "The storm broke over the island at night and the waves reached the door of the lighthouse."),
+            ),
+        ],
+    );
+
+    let document = EpubDocument::try_from(path)
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(document.title(), "The Lighthouse");
+    let body = document.body();
+    let storm = body.find("The storm broke").unwrap();
+    let keeper = body.find("The keeper climbed").unwrap();
+    assert!(storm < keeper);
+}
@@ -0,0 +1,503 @@
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures_util::{Stream, StreamExt};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use super::FsDocument;
+use crate::context::document::{Document, IntoDocuments};
+
+/// A folder full of documents.
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm::language::*;
+/// # use std::io::Write;
+/// # use std::path::PathBuf;
+/// #[tokio::main]
+/// async fn main() {
+///     // You can load a whole folder full of documents with the DocumentFolder source
+///     let folder = DocumentFolder::try_from(PathBuf::from("./documents")).unwrap();
+///     // Grab all the documents out of the folder
+///     let documents = folder.into_documents().await.unwrap();
+///
+///     // Then chunk the documents into sentences and use those chunks however you need
+///     let model = Bert::new().await.unwrap();
+///     let chunked = SemanticChunker::new().chunk_batch(&documents, &model).await.unwrap();
+///     println!("{:?}", chunked);
+/// }
+/// ```
+///
+/// Large folders can be read one file at a time with [`DocumentFolder::stream`].
+#[derive(Debug, Clone)]
+pub struct DocumentFolder {
+    path: PathBuf,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    include_set: Option<GlobSet>,
+    exclude_set: GlobSet,
+    max_file_size: Option<u64>,
+    concurrency: usize,
+}
+
+impl TryFrom<PathBuf> for DocumentFolder {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_dir() {
+            return Err(anyhow::anyhow!("Path is not a directory"));
+        }
+        Ok(Self {
+            path,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            include_set: None,
+            exclude_set: GlobSet::empty(),
+            max_file_size: None,
+            concurrency: std::thread::available_parallelism()
+                .map(|threads| threads.get())
+                .unwrap_or(4),
+        })
+    }
+}
+
+/// Read every supported file in the folder. Files that fail to load are skipped with a warning. Use [`DocumentFolder::stream`] to see which files failed.
+#[async_trait::async_trait]
+impl IntoDocuments for DocumentFolder {
+    async fn into_documents(self) -> anyhow::Result<Vec<Document>> {
+        let mut stream = self.stream();
+        let mut documents = Vec::new();
+        while let Some(file) = stream.next().await {
+            match file.document {
+                Ok(document) => documents.push(document),
+                Err(err) => tracing::warn!("Failed to read {}: {err}", file.path.display()),
+            }
+        }
+        Ok(documents)
+    }
+}
+
+impl DocumentFolder {
+    /// Try to create a new document folder from a path.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm_language::prelude::*;
+    ///
+    /// let folder = DocumentFolder::new("./documents").unwrap();
+    /// ```
+    pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        Self::try_from(path.into())
+    }
+
+    /// Get the path of the folder.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if a file would be read from this folder: it is inside the folder, matches the include and exclude patterns, is not larger than the size limit and is a supported file type.
+    pub fn contains(&self, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.path) else {
+            return false;
+        };
+        if relative
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .any(|ancestor| self.exclude_set.is_match(ancestor))
+        {
+            return false;
+        }
+        if let Some(include) = &self.include_set {
+            if !include.is_match(relative) {
+                return false;
+            }
+        }
+        if let Some(limit) = self.max_file_size {
+            match path.metadata() {
+                Ok(metadata) if metadata.len() <= limit => {}
+                _ => return false,
+            }
+        }
+        FsDocument::try_from(path.to_path_buf()).is_ok()
+    }
+
+    /// Only read files that match a glob pattern like `**/*.pdf`. Patterns are matched against the path relative to the folder. If any include patterns are set, files must match at least one of them.
+    pub fn with_include(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.include.push(Glob::new(pattern)?);
+        self.include_set = Some(glob_set(&self.include)?);
+        Ok(self)
+    }
+
+    /// Skip files and folders that match a glob pattern like `**/drafts/**`. Patterns are matched against the path relative to the folder.
+    pub fn with_exclude(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.exclude.push(Glob::new(pattern)?);
+        self.exclude_set = glob_set(&self.exclude)?;
+        Ok(self)
+    }
+
+    /// Skip files larger than this many bytes.
+    pub fn with_max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    /// Set the number of files read at the same time. Defaults to the number of available threads.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Read the documents in the folder one file at a time. At most [`DocumentFolder::with_concurrency`] files are read at once and a file that fails to load does not stop the rest of the folder.
+    ///
+    /// Each item is a document with the path of the file it was read from. Mailboxes yield one item per email. Once the stream finishes, [`DocumentFolderStream::summary`] lists every file that was skipped or failed to load.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use futures_util::StreamExt;
+    /// use kalosm_language::prelude::*;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut documents = DocumentFolder::new("./documents")
+    ///         .unwrap()
+    ///         .with_include("**/*.pdf")
+    ///         .unwrap()
+    ///         .with_max_file_size(10 * 1024 * 1024)
+    ///         .stream();
+    ///     while let Some(file) = documents.next().await {
+    ///         match file.document {
+    ///             Ok(document) => println!("{}: {}", file.path.display(), document.title()),
+    ///             Err(err) => println!("{}: {err}", file.path.display()),
+    ///         }
+    ///     }
+    ///     println!("{:?}", documents.summary());
+    /// }
+    /// ```
+    pub fn stream(&self) -> DocumentFolderStream {
+        self.stream_filtered(|_, _| true)
+    }
+
+    /// Read the documents in the folder like [`DocumentFolder::stream`], but only read the files the filter returns `true` for. The filter is called with the path and metadata of every supported file before it is read, so it can skip files that did not change since an earlier read without parsing them.
+    ///
+    /// Files rejected by the filter are not listed in the [`FolderSummary`].
+    pub fn stream_filtered(
+        &self,
+        filter: impl Fn(&Path, &std::fs::Metadata) -> bool + Send + Sync + 'static,
+    ) -> DocumentFolderStream {
+        let summary = Arc::new(Mutex::new(FolderSummary::default()));
+        let walk = FolderWalk {
+            root: self.path.clone(),
+            include: self.include_set.clone(),
+            exclude: self.exclude_set.clone(),
+            filter: Box::new(filter),
+            max_file_size: self.max_file_size,
+            folders: vec![self.path.clone()],
+            current: None,
+            summary: summary.clone(),
+        };
+        let files = futures_util::stream::unfold(walk, |mut walk| async move {
+            let document = walk.next_document().await?;
+            Some((document, walk))
+        });
+
+        let load_summary = summary.clone();
+        let documents = files
+            .map(move |(path, document)| {
+                let summary = load_summary.clone();
+                async move {
+                    let result = match tokio::spawn(document.into_documents()).await {
+                        Ok(result) => result,
+                        Err(err) => Err(anyhow::anyhow!("Reading the file panicked: {err}")),
+                    };
+                    let mut summary = summary.lock().unwrap();
+                    match &result {
+                        Ok(documents) => {
+                            summary.loaded += 1;
+                            summary.documents += documents.len();
+                        }
+                        Err(err) => summary.failed.push(FailedFile {
+                            path: path.clone(),
+                            error: err.to_string(),
+                        }),
+                    }
+                    (path, result)
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .flat_map(|(path, result)| {
+                let files = match result {
+                    Ok(documents) => documents
+                        .into_iter()
+                        .map(|document| FolderDocument {
+                            path: path.clone(),
+                            document: Ok(document),
+                        })
+                        .collect(),
+                    Err(err) => vec![FolderDocument {
+                        path,
+                        document: Err(err),
+                    }],
+                };
+                futures_util::stream::iter(files)
+            });
+
+        DocumentFolderStream {
+            inner: Box::pin(documents),
+            summary,
+        }
+    }
+}
+
+fn glob_set(globs: &[Glob]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(glob.clone());
+    }
+    Ok(builder.build()?)
+}
+
+/// Walks the folder lazily so only the folders that have not been read yet are kept in memory.
+struct FolderWalk {
+    root: PathBuf,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    filter: Box<dyn Fn(&Path, &std::fs::Metadata) -> bool + Send + Sync>,
+    max_file_size: Option<u64>,
+    folders: Vec<PathBuf>,
+    current: Option<(PathBuf, tokio::fs::ReadDir)>,
+    summary: Arc<Mutex<FolderSummary>>,
+}
+
+impl FolderWalk {
+    /// Find the next file that should be read.
+    async fn next_document(&mut self) -> Option<(PathBuf, FsDocument)> {
+        loop {
+            let Some((folder, read_dir)) = &mut self.current else {
+                let folder = self.folders.pop()?;
+                match tokio::fs::read_dir(&folder).await {
+                    Ok(read_dir) => self.current = Some((folder, read_dir)),
+                    Err(err) => self.fail(folder, err.into()),
+                }
+                continue;
+            };
+            let entry = match read_dir.next_entry().await {
+                Ok(Some(entry)) => entry,
+                Ok(None) => {
+                    self.current = None;
+                    continue;
+                }
+                Err(err) => {
+                    let folder = folder.clone();
+                    self.current = None;
+                    self.fail(folder, err.into());
+                    continue;
+                }
+            };
+
+            let path = entry.path();
+            let relative = path.strip_prefix(&self.root).unwrap_or(&path).to_path_buf();
+            if self.exclude.is_match(&relative) {
+                continue;
+            }
+            let metadata = match tokio::fs::metadata(&path).await {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    self.fail(path, err.into());
+                    continue;
+                }
+            };
+            if metadata.is_dir() {
+                self.folders.push(path);
+                continue;
+            }
+            if !self.included(&relative) {
+                continue;
+            }
+            if let Some(limit) = self.max_file_size {
+                if metadata.len() > limit {
+                    self.skip(
+                        path,
+                        SkipReason::TooLarge {
+                            size: metadata.len(),
+                            limit,
+                        },
+                    );
+                    continue;
+                }
+            }
+            match FsDocument::try_from(path.clone()) {
+                Ok(document) if (self.filter)(&path, &metadata) => return Some((path, document)),
+                Ok(_) => {}
+                Err(_) => self.skip(path, SkipReason::Unsupported),
+            }
+        }
+    }
+
+    fn included(&self, relative: &Path) -> bool {
+        match &self.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        }
+    }
+
+    fn skip(&self, path: PathBuf, reason: SkipReason) {
+        self.summary
+            .lock()
+            .unwrap()
+            .skipped
+            .push(SkippedFile { path, reason });
+    }
+
+    fn fail(&self, path: PathBuf, error: anyhow::Error) {
+        self.summary.lock().unwrap().failed.push(FailedFile {
+            path,
+            error: error.to_string(),
+        });
+    }
+}
+
+/// A stream of the documents in a [`DocumentFolder`] created with [`DocumentFolder::stream`].
+pub struct DocumentFolderStream {
+    inner: Pin<Box<dyn Stream<Item = FolderDocument> + Send>>,
+    summary: Arc<Mutex<FolderSummary>>,
+}
+
+impl DocumentFolderStream {
+    /// A summary of the files that have been read so far. Once the stream is finished, this covers the whole folder.
+    pub fn summary(&self) -> FolderSummary {
+        self.summary.lock().unwrap().clone()
+    }
+}
+
+impl Stream for DocumentFolderStream {
+    type Item = FolderDocument;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// A document read from a [`DocumentFolder`] with the path of the file it came from.
+#[derive(Debug)]
+pub struct FolderDocument {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The document, or the error that stopped the file from loading.
+    pub document: anyhow::Result<Document>,
+}
+
+/// The files that were skipped or failed while reading a [`DocumentFolder`]. Files filtered out by the include and exclude patterns are not listed.
+#[derive(Debug, Clone, Default)]
+pub struct FolderSummary {
+    /// The number of files that were read.
+    pub loaded: usize,
+    /// The number of documents read from those files.
+    pub documents: usize,
+    /// Files that were not read.
+    pub skipped: Vec<SkippedFile>,
+    /// Files and folders that failed to load.
+    pub failed: Vec<FailedFile>,
+}
+
+/// A file that was not read from a [`DocumentFolder`].
+#[derive(Debug, Clone)]
+pub struct SkippedFile {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// Why the file was skipped.
+    pub reason: SkipReason,
+}
+
+/// The reason a file was skipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The file is larger than [`DocumentFolder::with_max_file_size`].
+    TooLarge {
+        /// The size of the file in bytes.
+        size: u64,
+        /// The maximum size in bytes.
+        limit: u64,
+    },
+    /// The file type is not supported by [`FsDocument`].
+    Unsupported,
+}
+
+/// A file or folder that failed to load from a [`DocumentFolder`].
+#[derive(Debug, Clone)]
+pub struct FailedFile {
+    /// The path of the file or folder.
+    pub path: PathBuf,
+    /// The error that stopped the file from loading.
+    pub error: String,
+}
+
+#[tokio::test]
+async fn folder_stream_reports_skipped_and_failed_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("notes/drafts")).unwrap();
+    std::fs::write(root.join("notes/a.txt"), "first note").unwrap();
+    std::fs::write(root.join("notes/drafts/b.txt"), "draft").unwrap();
+    std::fs::write(root.join("big.txt"), "x".repeat(100)).unwrap();
+    std::fs::write(root.join("image.png"), [0u8; 4]).unwrap();
+    std::fs::write(root.join("broken.pdf"), "not a pdf").unwrap();
+
+    let folder = DocumentFolder::new(root)
+        .unwrap()
+        .with_exclude("**/drafts")
+        .unwrap()
+        .with_max_file_size(50)
+        .with_concurrency(2);
+    assert!(folder.contains(&root.join("notes/a.txt")));
+    assert!(!folder.contains(&root.join("notes/drafts/b.txt")));
+    assert!(!folder.contains(&root.join("big.txt")));
+    assert!(!folder.contains(&root.join("image.png")));
+
+    let mut stream = folder.stream();
+    let mut loaded = Vec::new();
+    let mut failed = Vec::new();
+    while let Some(file) = stream.next().await {
+        match file.document {
+            Ok(document) => loaded.push(document.body().to_string()),
+            Err(_) => failed.push(file.path),
+        }
+    }
+    assert_eq!(loaded, ["first note"]);
+    assert_eq!(failed, [root.join("broken.pdf")]);
+
+    let summary = stream.summary();
+    assert_eq!(summary.loaded, 1);
+    assert_eq!(summary.documents, 1);
+    assert_eq!(summary.failed.len(), 1);
+    let mut skipped = summary
+        .skipped
+        .iter()
+        .map(|file| (file.path.clone(), file.reason.clone()))
+        .collect::<Vec<_>>();
+    skipped.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        skipped,
+        [
+            (
+                root.join("big.txt"),
+                SkipReason::TooLarge {
+                    size: 100,
+                    limit: 50
+                }
+            ),
+            (root.join("image.png"), SkipReason::Unsupported),
+        ]
+    );
+
+    // Files rejected by the filter are not read or listed
+    let mut stream = folder.stream_filtered(|path, _| !path.ends_with("broken.pdf"));
+    let mut loaded = 0;
+    while let Some(file) = stream.next().await {
+        assert!(file.document.is_ok());
+        loaded += 1;
+    }
+    assert_eq!(loaded, 1);
+    assert!(stream.summary().failed.is_empty());
+}
@@ -30,11 +30,11 @@ impl TryFrom<PathBuf> for HtmlDocument {
 #[async_trait::async_trait]
 impl IntoDocument for HtmlDocument {
     async fn into_document(self) -> anyhow::Result<Document> {
-        let file = File::open(self.path).await?;
+        let file = File::open(&self.path).await?;
         let mut html = String::new();
         tokio::io::BufReader::new(file)
             .read_to_string(&mut html)
             .await?;
-        extract_article(&html)
+        Ok(extract_article(&html)?.with_source(self.path))
     }
 }
@@ -1,13 +1,14 @@
 use std::path::PathBuf;
 
+use pulldown_cmark::{Event, HeadingLevel, Tag};
 use tokio::{fs::File, io::AsyncReadExt};
 
-use crate::context::{
-    document::{Document, IntoDocument},
-    page::extract_article,
-};
+use super::office::file_title;
+use crate::context::document::{Document, DocumentSection, IntoDocument};
 
 /// A markdown document that can be read from the file system.
+///
+/// The title is the first top level heading, or the file name if the document doesn't have one. The text under each heading is recorded as a [`DocumentSection`] with the path of headings it is nested under.
 #[derive(Debug, Clone)]
 pub struct MdDocument {
     path: PathBuf,
@@ -30,15 +31,123 @@ impl TryFrom<PathBuf> for MdDocument {
 #[async_trait::async_trait]
 impl IntoDocument for MdDocument {
     async fn into_document(self) -> anyhow::Result<Document> {
-        let file = File::open(self.path).await?;
+        let file = File::open(&self.path).await?;
         let mut md = String::new();
         tokio::io::BufReader::new(file)
             .read_to_string(&mut md)
             .await?;
-        let parser = pulldown_cmark::Parser::new(&md);
 
-        let mut html_output = String::new();
-        pulldown_cmark::html::push_html(&mut html_output, parser);
-        extract_article(&html_output)
+        let mut document = markdown_document(&md);
+        if document.title().is_empty() {
+            let body = document.body().to_string();
+            let sections = document.sections().to_vec();
+            document = Document::from_parts(file_title(&self.path), body);
+            for section in sections {
+                document.push_section(section);
+            }
+        }
+        Ok(document.with_source(self.path))
+    }
+}
+
+/// Convert markdown into plain text with a section for the text under each heading. The title is the first top level heading.
+fn markdown_document(md: &str) -> Document {
+    let mut title = None;
+    let mut body = String::new();
+    let mut sections = Vec::new();
+    // The headings the current text is nested under
+    let mut heading_path: Vec<(HeadingLevel, String)> = Vec::new();
+    let mut heading: Option<(HeadingLevel, String)> = None;
+    let mut section_start = 0;
+
+    let mut close_section =
+        |body: &str, section_start: usize, heading_path: &[(HeadingLevel, String)]| {
+            if body.len() > section_start && !heading_path.is_empty() {
+                sections.push(DocumentSection {
+                    byte_range: section_start..body.trim_end().len(),
+                    page: None,
+                    heading_path: heading_path.iter().map(|(_, name)| name.clone()).collect(),
+                });
+            }
+        };
+
+    for event in pulldown_cmark::Parser::new(md) {
+        match event {
+            Event::Start(Tag::Heading(level, _, _)) => {
+                close_section(&body, section_start, &heading_path);
+                section_start = body.len();
+                heading = Some((level, String::new()));
+            }
+            Event::End(Tag::Heading(..)) => {
+                if let Some((level, name)) = heading.take() {
+                    let name = name.trim().to_string();
+                    if level == HeadingLevel::H1 && title.is_none() {
+                        title = Some(name.clone());
+                    }
+                    while heading_path
+                        .last()
+                        .is_some_and(|(parent, _)| *parent >= level)
+                    {
+                        heading_path.pop();
+                    }
+                    heading_path.push((level, name));
+                }
+                push_break(&mut body);
+            }
+            Event::Text(text) | Event::Code(text) => {
+                body.push_str(&text);
+                if let Some((_, name)) = &mut heading {
+                    name.push_str(&text);
+                }
+            }
+            Event::SoftBreak => body.push(' '),
+            Event::HardBreak => body.push('\n'),
+            Event::End(
+                Tag::Paragraph | Tag::CodeBlock(_) | Tag::Item | Tag::BlockQuote | Tag::TableRow,
+            ) => push_break(&mut body),
+            Event::End(Tag::TableCell) => body.push('\t'),
+            _ => {}
+        }
     }
+    close_section(&body, section_start, &heading_path);
+
+    let mut document = Document::from_parts(title.unwrap_or_default(), body.trim_end());
+    for section in sections {
+        document.push_section(section);
+    }
+    document
+}
+
+/// End the current block of text with a blank line.
+fn push_break(body: &mut String) {
+    while !body.is_empty() && !body.ends_with("\n\n") {
+        body.push('\n');
+    }
+}
+
+#[test]
+fn markdown_sections_have_heading_paths() {
+    let md = "# Guide\n\nIntro text.\n\n## Install\n\nRun `cargo add`.\n\n### Linux\n\nUse apt.\n\n## Usage\n\nCall it.";
+    let document = markdown_document(md);
+    assert_eq!(document.title(), "Guide");
+    assert_eq!(
+        document.body(),
+        "Guide\n\nIntro text.\n\nInstall\n\nRun cargo add.\n\nLinux\n\nUse apt.\n\nUsage\n\nCall it."
+    );
+
+    let apt = document.body().find("apt").unwrap();
+    assert_eq!(
+        document.source_location(apt..apt + 3).heading_path,
+        ["Guide", "Install", "Linux"]
+    );
+    let call = document.body().find("Call").unwrap();
+    assert_eq!(
+        document.source_location(call..call + 4).heading_path,
+        ["Guide", "Usage"]
+    );
+    let sections = document.sections();
+    assert_eq!(
+        &document.body()[sections[0].byte_range.clone()],
+        "Guide\n\nIntro text."
+    );
 }
@@ -2,15 +2,30 @@ use crate::context::document::Document;
 use crate::context::document::IntoDocument;
 use crate::context::document::IntoDocuments;
 use std::path::PathBuf;
-use tokio::task::JoinSet;
+mod code;
+pub use code::*;
 mod docx;
 pub use docx::*;
+mod email;
+pub use email::*;
+mod epub;
+pub use epub::*;
+mod folder;
+pub use folder::*;
 mod html;
 pub use html::*;
 mod md;
 pub use md::*;
+mod odt;
+pub use odt::*;
+mod office;
 mod pdf;
 pub use self::pdf::*;
+mod pdf_layout;
+mod pptx;
+pub use pptx::*;
+mod spreadsheet;
+pub use spreadsheet::*;
 mod txt;
 pub use txt::*;
 
@@ -29,14 +44,30 @@ pub use txt::*;
 /// ```
 #[derive(Debug, Clone)]
 pub enum FsDocument {
+    /// A source code file.
+    Code(CodeDocument),
+    /// A csv or tsv document.
+    Csv(CsvDocument),
     /// A docx document.
     Docx(DocxDocument),
+    /// An email.
+    Eml(EmlDocument),
+    /// An epub e-book.
+    Epub(EpubDocument),
     /// An html document.
     Html(HtmlDocument),
+    /// A mailbox of emails.
+    Mbox(MboxDocument),
     /// A markdown document.
     Md(MdDocument),
+    /// An OpenDocument text document.
+    Odt(OdtDocument),
     /// A pdf document.
     Pdf(PdfDocument),
+    /// A pptx slide deck.
+    Pptx(PptxDocument),
+    /// A spreadsheet (xlsx, xls or ods).
+    Spreadsheet(SpreadsheetDocument),
     /// A text document.
     Txt(TextDocument),
 }
@@ -49,11 +80,23 @@ impl TryFrom<PathBuf> for FsDocument {
             return Err(anyhow::anyhow!("Path is not a file"));
         }
         match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv" | "tsv") => Ok(Self::Csv(CsvDocument::try_from(path)?)),
             Some("docx") => Ok(Self::Docx(DocxDocument::try_from(path)?)),
+            Some("eml") => Ok(Self::Eml(EmlDocument::try_from(path)?)),
+            Some("epub") => Ok(Self::Epub(EpubDocument::try_from(path)?)),
             Some("html") => Ok(Self::Html(HtmlDocument::try_from(path)?)),
+            Some("mbox") => Ok(Self::Mbox(MboxDocument::try_from(path)?)),
             Some("md") => Ok(Self::Md(MdDocument::try_from(path)?)),
+            Some("odt") => Ok(Self::Odt(OdtDocument::try_from(path)?)),
             Some("pdf") => Ok(Self::Pdf(PdfDocument::try_from(path)?)),
+            Some("pptx") => Ok(Self::Pptx(PptxDocument::try_from(path)?)),
+            Some("xlsx" | "xlsm" | "xls" | "ods") => {
+                Ok(Self::Spreadsheet(SpreadsheetDocument::try_from(path)?))
+            }
             Some("txt") => Ok(Self::Txt(TextDocument::try_from(path)?)),
+            Some(extension) if CodeLanguage::from_extension(extension).is_some() => {
+                Ok(Self::Code(CodeDocument::try_from(path)?))
+            }
             _ => Err(anyhow::anyhow!("Path is not a supported file type")),
         }
     }
@@ -63,95 +106,30 @@ impl TryFrom<PathBuf> for FsDocument {
 impl IntoDocument for FsDocument {
     async fn into_document(self) -> anyhow::Result<Document> {
         match self {
+            Self::Code(code) => code.into_document().await,
+            Self::Csv(csv) => csv.into_document().await,
             Self::Docx(docx) => docx.into_document().await,
+            Self::Eml(eml) => eml.into_document().await,
+            Self::Epub(epub) => epub.into_document().await,
             Self::Html(html) => html.into_document().await,
+            Self::Mbox(mbox) => mbox.into_document().await,
             Self::Md(md) => md.into_document().await,
+            Self::Odt(odt) => odt.into_document().await,
             Self::Pdf(pdf) => pdf.into_document().await,
+            Self::Pptx(pptx) => pptx.into_document().await,
+            Self::Spreadsheet(spreadsheet) => spreadsheet.into_document().await,
             Self::Txt(txt) => txt.into_document().await,
         }
     }
 }
 
-/// A folder full of documents.
-///
-/// # Example
-/// ```rust, no_run
-/// # use kalosm::language::*;
-/// # use std::io::Write;
-/// # use std::path::PathBuf;
-/// #[tokio::main]
-/// async fn main() {
-///     // You can load a whole folder full of documents with the DocumentFolder source
-///     let folder = DocumentFolder::try_from(PathBuf::from("./documents")).unwrap();
-///     // Grab all the documents out of the folder
-///     let documents = folder.into_documents().await.unwrap();
-///
-///     // Then chunk the documents into sentences and use those chunks however you need
-///     let model = Bert::new().await.unwrap();
-///     let chunked = SemanticChunker::new().chunk_batch(&documents, &model).await.unwrap();
-///     println!("{:?}", chunked);
-/// }
-/// ```
-#[derive(Debug, Clone)]
-pub struct DocumentFolder {
-    path: PathBuf,
-}
-
-impl TryFrom<PathBuf> for DocumentFolder {
-    type Error = anyhow::Error;
-
-    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
-        if !path.is_dir() {
-            return Err(anyhow::anyhow!("Path is not a directory"));
-        }
-        Ok(Self { path })
-    }
-}
-
+/// Mailboxes are split into one document per email. Every other file is a single document.
 #[async_trait::async_trait]
-impl IntoDocuments for DocumentFolder {
+impl IntoDocuments for FsDocument {
     async fn into_documents(self) -> anyhow::Result<Vec<Document>> {
-        let mut set = JoinSet::new();
-        self.start_into_documents(&mut set).await?;
-        let mut documents = Vec::new();
-        while let Some(join) = set.join_next().await {
-            documents.push(join??);
+        match self {
+            Self::Mbox(mbox) => mbox.into_documents().await,
+            document => Ok(vec![document.into_document().await?]),
         }
-        Ok(documents)
-    }
-}
-
-impl DocumentFolder {
-    /// Try to create a new document folder from a path.
-    ///
-    /// # Example
-    /// ```rust, no_run
-    /// use kalosm_language::prelude::*;
-    ///
-    /// let folder = DocumentFolder::new("./documents").unwrap();
-    /// ```
-    pub fn new(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
-        Self::try_from(path.into())
-    }
-
-    fn start_into_documents<'a>(
-        &'a self,
-        set: &'a mut JoinSet<anyhow::Result<Document>>,
-    ) -> std::pin::Pin<Box<dyn std::future::Future<Output = anyhow::Result<()>> + Send + Sync + 'a>>
-    {
-        Box::pin(async move {
-            let mut read_dir = tokio::fs::read_dir(&self.path).await?;
-            while let Some(entry) = read_dir.next_entry().await? {
-                let path = entry.path();
-                if path.is_dir() {
-                    if let Ok(folder) = DocumentFolder::try_from(path) {
-                        folder.start_into_documents(set).await?;
-                    }
-                } else if let Ok(document) = FsDocument::try_from(path) {
-                    set.spawn(document.into_document());
-                }
-            }
-            Ok(())
-        })
     }
 }
@@ -0,0 +1,85 @@
+use std::path::PathBuf;
+
+use super::office::{file_title, open_archive, read_entry, xml_element_text, xml_text};
+use crate::context::document::{Document, IntoDocument};
+
+/// An OpenDocument text file that can be read from the file system.
+///
+/// The title is read from the document metadata, or the first heading if the metadata doesn't have a title.
+#[derive(Debug, Clone)]
+pub struct OdtDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for OdtDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if path.extension() != Some("odt".as_ref()) {
+            return Err(anyhow::anyhow!("Path is not a odt file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for OdtDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let mut archive = open_archive(&self.path).await?;
+
+        let content = read_entry(&mut archive, "content.xml")?;
+        let text = xml_text(&content, &["p", "h", "table-row"])?;
+
+        let title = match read_entry(&mut archive, "meta.xml") {
+            Ok(meta) => xml_element_text(&meta, "title")?,
+            Err(_) => None,
+        };
+        let title = match title {
+            Some(title) => title,
+            None => xml_element_text(&content, "h")?.unwrap_or_else(|| file_title(&self.path)),
+        };
+
+        Ok(Document::from_parts(title, text).with_source(self.path))
+    }
+}
+
+#[tokio::test]
+async fn odt_document_reads_text_and_title() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.odt");
+    let content = r#"<office:document-content xmlns:office="o" xmlns:text="t" xmlns:table="tb"><office:body><office:text><text:h>Meeting notes</text:h><text:p>Ship the release on Friday.</text:p><table:table><table:table-row><table:table-cell><text:span>Owner</text:span></table:table-cell><table:table-cell><text:span>Alice</text:span></table:table-cell></table:table-row></table:table></office:text></office:body></office:document-content>"#;
+    super::office::write_archive(&path, &[("content.xml", content)]);
+
+    let document = OdtDocument::try_from(path.clone())
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    // Without metadata the first heading is the title
+    assert_eq!(document.title(), "Meeting notes");
+    assert_eq!(
+        document.body(),
+        "Meeting notes\nShip the release on Friday.\nOwner\tAlice"
+    );
+
+    let meta = r#"<office:document-meta xmlns:office="o" xmlns:dc="dc"><office:meta><dc:title>Release plan</dc:title></office:meta></office:document-meta>"#;
+    super::office::write_archive(&path, &[("content.xml", content), ("meta.xml", meta)]);
+    let document = OdtDocument::try_from(path.clone())
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(document.title(), "Release plan");
+
+    // A path without an extension is an error instead of a panic
+    let without_extension = dir.path().join("notes");
+    std::fs::copy(&path, &without_extension).unwrap();
+    assert!(OdtDocument::try_from(without_extension.clone()).is_err());
+    assert!(super::PptxDocument::try_from(without_extension.clone()).is_err());
+    assert!(super::EpubDocument::try_from(without_extension.clone()).is_err());
+    assert!(super::EmlDocument::try_from(without_extension.clone()).is_err());
+    assert!(super::MboxDocument::try_from(without_extension).is_err());
+}
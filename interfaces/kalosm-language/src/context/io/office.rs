@@ -0,0 +1,120 @@
+//! Helpers for the zip based document formats (EPUB, PPTX and the OpenDocument formats).
+
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use convert_case::{Case, Casing};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use zip::ZipArchive;
+
+pub(crate) type Archive = ZipArchive<Cursor<Vec<u8>>>;
+
+/// Read a zip archive into memory.
+pub(crate) async fn open_archive(path: &Path) -> anyhow::Result<Archive> {
+    let bytes = tokio::fs::read(path).await?;
+    Ok(ZipArchive::new(Cursor::new(bytes))?)
+}
+
+/// Read a file inside a zip archive as a string.
+pub(crate) fn read_entry(archive: &mut Archive, name: &str) -> anyhow::Result<String> {
+    let mut entry = archive.by_name(name)?;
+    let mut text = String::new();
+    entry.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+/// A title made from the file name, used when a document doesn't have a title in its metadata.
+pub(crate) fn file_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_case(Case::Title))
+        .unwrap_or_default()
+}
+
+/// Extract the text of an XML document. A line break is added after every element with a local name in `blocks` and a tab after every table cell.
+pub(crate) fn xml_text(xml: &str, blocks: &[&str]) -> anyhow::Result<String> {
+    let mut reader = Reader::from_str(xml);
+    let mut text = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Text(event) => text.push_str(&event.unescape()?),
+            Event::CData(event) => text.push_str(&String::from_utf8_lossy(&event)),
+            Event::Empty(event) => match event.local_name().as_ref() {
+                b"s" => text.push(' '),
+                b"tab" => text.push('\t'),
+                b"br" | b"line-break" => text.push('\n'),
+                _ => {}
+            },
+            // Keep the cells of a table row apart
+            Event::End(event) if event.local_name().as_ref() == b"table-cell" => text.push('\t'),
+            Event::End(event)
+                if blocks
+                    .iter()
+                    .any(|block| block.as_bytes() == event.local_name().as_ref()) =>
+            {
+                text.push('\n');
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(clean_lines(&text))
+}
+
+/// Find the text of the first element with the local name in an XML document.
+pub(crate) fn xml_element_text(xml: &str, local_name: &str) -> anyhow::Result<Option<String>> {
+    let mut reader = Reader::from_str(xml);
+    let mut inside = false;
+    let mut text = String::new();
+    loop {
+        match reader.read_event()? {
+            Event::Start(event) if event.local_name().as_ref() == local_name.as_bytes() => {
+                inside = true;
+            }
+            Event::Text(event) if inside => text.push_str(&event.unescape()?),
+            Event::End(event) if inside && event.local_name().as_ref() == local_name.as_bytes() => {
+                let text = text.trim();
+                return Ok((!text.is_empty()).then(|| text.to_string()));
+            }
+            Event::Eof => return Ok(None),
+            _ => {}
+        }
+    }
+}
+
+/// Trim every line and remove the empty lines.
+pub(crate) fn clean_lines(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn xml_text_keeps_paragraphs() {
+    let xml = r#"<office:text xmlns:office="o" xmlns:text="t"><text:h>Intro</text:h><text:p>Hello<text:s/>world &amp; <text:span>friends</text:span></text:p><text:p>Second<text:line-break/>line</text:p></office:text>"#;
+    assert_eq!(
+        xml_text(xml, &["h", "p"]).unwrap(),
+        "Intro\nHello world & friends\nSecond\nline"
+    );
+    assert_eq!(
+        xml_element_text(xml, "h").unwrap().as_deref(),
+        Some("Intro")
+    );
+}
+
+/// Write a zip archive with the given files for the loader tests.
+#[cfg(test)]
+pub(crate) fn write_archive(path: &Path, files: &[(&str, &str)]) {
+    use std::io::Write;
+
+    let mut writer = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, contents) in files {
+        writer
+            .start_file(*name, zip::write::FileOptions::default())
+            .unwrap();
+        writer.write_all(contents.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap();
+}
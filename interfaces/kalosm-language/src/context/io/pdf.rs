@@ -1,12 +1,19 @@
 use crate::context::document::Document;
+use crate::context::document::DocumentSection;
 use crate::context::document::IntoDocument;
-use itertools::Itertools;
-use std::fmt::Write;
 use std::path::PathBuf;
 
 use pdf::file::FileOptions;
+use pdf::object::{Page, Resolve, XObject};
+
+use super::office::file_title;
+use super::pdf_layout::{page_blocks, FontSizes, LayoutLine, LayoutWord};
 
 /// A pdf document that can be read from the file system.
+///
+/// The text of each page is read in reading order, even if the page has two columns. Headings are detected from their font size and marked with `#` like Markdown and tables are converted to Markdown tables. Each page is recorded as a [`DocumentSection`] with the page number and the headings it is under.
+///
+/// Pages with images but no text layer, like scanned pages, are listed in the `pages_without_text` metadata so they can be sent to OCR. Pages whose text could not be read are listed in the `pages_with_errors` metadata. Use [`PdfDocument::pages`] to read the structure of each page and the error for each page directly.
 #[derive(Debug, Clone)]
 pub struct PdfDocument {
     path: PathBuf,
@@ -26,34 +33,269 @@ impl TryFrom<PathBuf> for PdfDocument {
     }
 }
 
+impl PdfDocument {
+    /// Read the headings, paragraphs and tables of each page of the pdf.
+    pub fn pages(&self) -> anyhow::Result<Vec<PdfPage>> {
+        Ok(self.read()?.1)
+    }
+
+    /// Read the title and pages of the pdf.
+    fn read(&self) -> anyhow::Result<(Option<String>, Vec<PdfPage>)> {
+        let file = FileOptions::cached()
+            .open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Failed to open pdf: {err}"))?;
+        let resolver = file.resolver();
+
+        let title = file
+            .trailer
+            .info_dict
+            .as_ref()
+            .and_then(|info| info.title.as_ref())
+            .map(|title| title.to_string_lossy().trim().to_string())
+            .filter(|title| !title.is_empty());
+
+        // The lines of each page, if the page has images and the error that stopped the text from being read
+        let mut pages = Vec::new();
+        for page in file.pages() {
+            let page = match page {
+                Ok(page) => page,
+                Err(err) => {
+                    pages.push((
+                        Vec::new(),
+                        false,
+                        Some(format!("Failed to read page: {err}")),
+                    ));
+                    continue;
+                }
+            };
+            let has_images = has_images(&page, &resolver);
+            match pdf_text::run(&file, &page, &resolver) {
+                Ok(flow) => {
+                    let lines = flow
+                        .runs
+                        .into_iter()
+                        .flat_map(|run| run.lines)
+                        .map(|line| LayoutLine {
+                            words: line
+                                .words
+                                .into_iter()
+                                .filter(|word| !word.text.trim().is_empty())
+                                .map(|word| LayoutWord {
+                                    text: word.text,
+                                    x0: word.rect.min_x(),
+                                    x1: word.rect.max_x(),
+                                    y0: word.rect.min_y(),
+                                    y1: word.rect.max_y(),
+                                })
+                                .collect(),
+                        })
+                        .filter(|line| !line.words.is_empty())
+                        .collect::<Vec<_>>();
+                    pages.push((lines, has_images, None));
+                }
+                Err(err) => pages.push((
+                    Vec::new(),
+                    has_images,
+                    Some(format!("Failed to read text: {err}")),
+                )),
+            }
+        }
+
+        // Headings are found from the font sizes of the whole document
+        let sizes = FontSizes::new(pages.iter().map(|(lines, _, _)| lines.as_slice()));
+        let pages = pages
+            .into_iter()
+            .enumerate()
+            .map(|(index, (lines, has_images, error))| PdfPage {
+                number: index as u32 + 1,
+                needs_ocr: lines.is_empty() && has_images && error.is_none(),
+                blocks: page_blocks(lines, &sizes),
+                error,
+            })
+            .collect();
+        Ok((title, pages))
+    }
+}
+
 #[async_trait::async_trait]
 impl IntoDocument for PdfDocument {
     async fn into_document(self) -> anyhow::Result<Document> {
-        let file = FileOptions::cached().open(self.path).unwrap();
-        let resolver = file.resolver();
-        let mut title = String::new();
+        let (title, pages) = self.read()?;
+        let title = title
+            .or_else(|| {
+                pages
+                    .iter()
+                    .flat_map(|page| &page.blocks)
+                    .find_map(|block| match block {
+                        PdfBlock::Heading { level: 1, text } => Some(text.clone()),
+                        _ => None,
+                    })
+            })
+            .unwrap_or_else(|| file_title(&self.path));
+
         let mut text = String::new();
+        let mut sections = Vec::new();
+        // The headings the current text is nested under
+        let mut heading_path: Vec<(usize, String)> = Vec::new();
+        for page in &pages {
+            let mut section_start = text.len();
+            for block in &page.blocks {
+                if let PdfBlock::Heading {
+                    level,
+                    text: heading,
+                } = block
+                {
+                    if text.len() > section_start {
+                        sections.push(page_section(page, section_start, &text, &heading_path));
+                    }
+                    heading_path.retain(|(parent, _)| parent < level);
+                    heading_path.push((*level, heading.clone()));
+                    if !text.is_empty() {
+                        text += "\n\n";
+                    }
+                    section_start = text.len();
+                } else if !text.is_empty() {
+                    text += "\n\n";
+                }
+                text += &block.to_string();
+            }
+            if text.len() > section_start {
+                sections.push(page_section(page, section_start, &text, &heading_path));
+            }
+        }
+
+        let mut document = Document::from_parts(title, text).with_source(self.path);
+        for section in sections {
+            document.push_section(section);
+        }
+        for (key, pages) in [
+            (
+                "pages_without_text",
+                page_numbers(pages.iter().filter(|page| page.needs_ocr)),
+            ),
+            (
+                "pages_with_errors",
+                page_numbers(pages.iter().filter(|page| page.error.is_some())),
+            ),
+        ] {
+            if !pages.is_empty() {
+                document.metadata_mut().insert(key, pages);
+            }
+        }
+        Ok(document)
+    }
+}
+
+/// Check if a page draws any images. A page with images and no text is usually a scanned page.
+fn has_images(page: &Page, resolver: &impl Resolve) -> bool {
+    let Ok(resources) = page.resources() else {
+        return false;
+    };
+    resources
+        .xobjects
+        .values()
+        .any(|xobject| matches!(resolver.get(*xobject).as_deref(), Ok(XObject::Image(_))))
+}
+
+/// A comma separated list of page numbers.
+fn page_numbers<'a>(pages: impl Iterator<Item = &'a PdfPage>) -> String {
+    pages
+        .map(|page| page.number.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn page_section(
+    page: &PdfPage,
+    start: usize,
+    text: &str,
+    heading_path: &[(usize, String)],
+) -> DocumentSection {
+    // Skip the break before the first block of the section
+    let start = start + text[start..].len() - text[start..].trim_start().len();
+    DocumentSection {
+        byte_range: start..text.len(),
+        page: Some(page.number),
+        heading_path: heading_path.iter().map(|(_, name)| name.clone()).collect(),
+    }
+}
 
-        if let Some(info) = &file.trailer.info_dict {
-            if let Some(pdf_title) = info.title.as_ref().map(|p| p.to_string_lossy()) {
-                title = pdf_title;
+/// A page of a pdf read with [`PdfDocument::pages`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfPage {
+    /// The number of the page, starting from 1.
+    pub number: u32,
+    /// The headings, paragraphs and tables on the page in reading order.
+    pub blocks: Vec<PdfBlock>,
+    /// If the page draws images but has no text layer. These pages are usually scanned images that need OCR to be read.
+    pub needs_ocr: bool,
+    /// The error that stopped the page or its text from being read. Pages with an error have no blocks.
+    pub error: Option<String>,
+}
+
+impl std::fmt::Display for PdfPage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, block) in self.blocks.iter().enumerate() {
+            if index > 0 {
+                write!(f, "\n\n")?;
             }
+            write!(f, "{block}")?;
         }
+        Ok(())
+    }
+}
 
-        for page in file.pages().flatten() {
-            if let Ok(flow) = pdf_text::run(&file, &page, &resolver) {
-                for run in flow.runs {
-                    for line in run.lines {
-                        let _ = text.write_fmt(format_args!(
-                            "{}",
-                            line.words.iter().map(|w| &w.text).format(" ")
-                        ));
-                        text += "\n\n";
+/// A part of a [`PdfPage`]. Blocks are displayed as Markdown.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PdfBlock {
+    /// A heading found from its font size. Level 1 is the largest font size in the document.
+    Heading {
+        /// The level of the heading, starting from 1.
+        level: usize,
+        /// The text of the heading.
+        text: String,
+    },
+    /// A paragraph of text.
+    Paragraph(String),
+    /// A table. The first row is the header.
+    Table(Vec<Vec<String>>),
+}
+
+impl std::fmt::Display for PdfBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Heading { level, text } => write!(f, "{} {text}", "#".repeat(*level)),
+            Self::Paragraph(text) => write!(f, "{text}"),
+            Self::Table(rows) => {
+                let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+                for (index, row) in rows.iter().enumerate() {
+                    if index > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "|")?;
+                    for column in 0..columns {
+                        let cell = row.get(column).map(String::as_str).unwrap_or_default();
+                        write!(f, " {} |", cell.replace('|', "\\|"))?;
+                    }
+                    if index == 0 {
+                        write!(f, "\n|{}", " --- |".repeat(columns))?;
                     }
                 }
+                Ok(())
             }
         }
-
-        Ok(Document::from_parts(title, text))
     }
 }
+
+#[test]
+fn pdf_tables_display_as_markdown() {
+    let table = PdfBlock::Table(vec![
+        vec!["Region".into(), "Sales".into()],
+        vec!["North".into(), "10|12".into()],
+        vec!["South".into()],
+    ]);
+    assert_eq!(
+        table.to_string(),
+        "| Region | Sales |\n| --- | --- |\n| North | 10\\|12 |\n| South |  |"
+    );
+}
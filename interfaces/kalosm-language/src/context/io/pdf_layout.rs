@@ -0,0 +1,446 @@
+//! Rebuild the structure of a pdf page from the position of its words.
+
+use std::collections::HashMap;
+
+use super::PdfBlock;
+
+/// Only lines with at most this many words can be headings.
+const MAX_HEADING_WORDS: usize = 15;
+/// Lines must be at least this much larger than the body text to be headings.
+const HEADING_SCALE: f32 = 1.15;
+/// The number of heading levels detected from font sizes.
+const MAX_HEADING_LEVELS: usize = 3;
+/// Tables are runs of at least this many lines whose cells line up in columns.
+const MIN_TABLE_ROWS: usize = 3;
+
+/// A word on a pdf page with its bounding box. The y axis points down the page.
+#[derive(Debug, Clone)]
+pub(crate) struct LayoutWord {
+    pub text: String,
+    pub x0: f32,
+    pub x1: f32,
+    pub y0: f32,
+    pub y1: f32,
+}
+
+/// A line of words on a pdf page.
+#[derive(Debug, Clone)]
+pub(crate) struct LayoutLine {
+    pub words: Vec<LayoutWord>,
+}
+
+impl LayoutLine {
+    fn x0(&self) -> f32 {
+        self.words
+            .iter()
+            .map(|word| word.x0)
+            .fold(f32::MAX, f32::min)
+    }
+
+    fn x1(&self) -> f32 {
+        self.words
+            .iter()
+            .map(|word| word.x1)
+            .fold(f32::MIN, f32::max)
+    }
+
+    fn y0(&self) -> f32 {
+        self.words
+            .iter()
+            .map(|word| word.y0)
+            .fold(f32::MAX, f32::min)
+    }
+
+    fn y1(&self) -> f32 {
+        self.words
+            .iter()
+            .map(|word| word.y1)
+            .fold(f32::MIN, f32::max)
+    }
+
+    /// The height of the line, which is close to the font size.
+    fn height(&self) -> f32 {
+        self.y1() - self.y0()
+    }
+
+    fn text(&self) -> String {
+        self.words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Split the line into cells where the gap between two words is wider than the font size. Each cell has its horizontal extent and text.
+    fn cells(&self) -> Vec<(f32, f32, String)> {
+        let gap = self.height();
+        let mut cells: Vec<(f32, f32, String)> = Vec::new();
+        for word in &self.words {
+            match cells.last_mut() {
+                Some((_, x1, text)) if word.x0 - *x1 <= gap => {
+                    text.push(' ');
+                    text.push_str(&word.text);
+                    *x1 = word.x1;
+                }
+                _ => cells.push((word.x0, word.x1, word.text.clone())),
+            }
+        }
+        cells
+    }
+}
+
+/// The font sizes of the body text and headings in a document.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FontSizes {
+    headings: Vec<i32>,
+}
+
+impl FontSizes {
+    /// Find the font sizes of every page. The most common size is the body text and the larger sizes used by short lines are headings, largest first.
+    pub(crate) fn new<'a>(pages: impl IntoIterator<Item = &'a [LayoutLine]>) -> Self {
+        let lines = pages.into_iter().flatten().collect::<Vec<_>>();
+        let mut counts = HashMap::<i32, usize>::new();
+        for line in &lines {
+            let characters = line.words.iter().map(|word| word.text.len()).sum::<usize>();
+            *counts.entry(size_key(line.height())).or_default() += characters;
+        }
+        let body = counts
+            .into_iter()
+            .max_by_key(|(size, count)| (*count, -size))
+            .map(|(size, _)| size)
+            .unwrap_or_default();
+
+        let mut headings = lines
+            .iter()
+            .filter(|line| line.words.len() <= MAX_HEADING_WORDS)
+            .map(|line| size_key(line.height()))
+            .filter(|size| *size as f32 >= body as f32 * HEADING_SCALE)
+            .collect::<Vec<_>>();
+        headings.sort_unstable_by(|a, b| b.cmp(a));
+        headings.dedup();
+        headings.truncate(MAX_HEADING_LEVELS);
+        Self { headings }
+    }
+
+    fn heading_level(&self, line: &LayoutLine) -> Option<usize> {
+        if line.words.len() > MAX_HEADING_WORDS {
+            return None;
+        }
+        let size = size_key(line.height());
+        self.headings
+            .iter()
+            .position(|heading| *heading == size)
+            .map(|index| index + 1)
+    }
+}
+
+/// Round font sizes to half points so small differences in the bounding boxes don't create new sizes.
+fn size_key(height: f32) -> i32 {
+    (height * 2.).round() as i32
+}
+
+/// Turn the lines of a page into headings, paragraphs and tables in reading order.
+pub(crate) fn page_blocks(lines: Vec<LayoutLine>, sizes: &FontSizes) -> Vec<PdfBlock> {
+    let lines = reading_order(lines);
+    let mut blocks = Vec::new();
+    // The line that was last added to the last block if it was a heading or paragraph
+    let mut previous: Option<&LayoutLine> = None;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = &lines[index];
+        if let Some(level) = sizes.heading_level(line) {
+            match (blocks.last_mut(), previous) {
+                (Some(PdfBlock::Heading { level: last, text }), Some(previous))
+                    if *last == level && close_below(previous, line) =>
+                {
+                    text.push(' ');
+                    text.push_str(&line.text());
+                }
+                _ => blocks.push(PdfBlock::Heading {
+                    level,
+                    text: line.text(),
+                }),
+            }
+            previous = Some(line);
+            index += 1;
+            continue;
+        }
+
+        let rows = table_rows(&lines[index..], sizes);
+        if rows >= MIN_TABLE_ROWS {
+            blocks.push(PdfBlock::Table(table(&lines[index..index + rows])));
+            previous = None;
+            index += rows;
+            continue;
+        }
+
+        let text = line.text();
+        match (blocks.last_mut(), previous) {
+            (Some(PdfBlock::Paragraph(paragraph)), Some(previous))
+                if close_below(previous, line) =>
+            {
+                // Join words that were hyphenated at the end of the line
+                if paragraph.ends_with('-') && text.starts_with(char::is_lowercase) {
+                    paragraph.pop();
+                } else {
+                    paragraph.push(' ');
+                }
+                paragraph.push_str(&text);
+            }
+            _ => blocks.push(PdfBlock::Paragraph(text)),
+        }
+        previous = Some(line);
+        index += 1;
+    }
+    blocks
+}
+
+/// Check if a line directly follows another line in the same block.
+fn close_below(previous: &LayoutLine, line: &LayoutLine) -> bool {
+    let gap = line.y0() - previous.y1();
+    (-previous.height()..=previous.height() * 0.8).contains(&gap)
+}
+
+/// Count the lines at the start that could be the rows of a table: every line is split into at least two cells and every cell overlaps a cell of the first line, so the cells line up in columns.
+fn table_rows(lines: &[LayoutLine], sizes: &FontSizes) -> usize {
+    let Some(first) = lines.first() else {
+        return 0;
+    };
+    let columns = first.cells();
+    if columns.len() < 2 {
+        return 0;
+    }
+    lines
+        .iter()
+        .take_while(|line| {
+            let cells = line.cells();
+            let tolerance = line.height() * 0.5;
+            sizes.heading_level(line).is_none()
+                && cells.len() > 1
+                && cells.iter().all(|(x0, x1, _)| {
+                    columns.iter().any(|(column_x0, column_x1, _)| {
+                        *x0 <= column_x1 + tolerance && *x1 >= column_x0 - tolerance
+                    })
+                })
+        })
+        .count()
+}
+
+/// Line up the cells of each row with the columns of the row with the most cells.
+fn table(lines: &[LayoutLine]) -> Vec<Vec<String>> {
+    let rows = lines.iter().map(LayoutLine::cells).collect::<Vec<_>>();
+    let columns = rows
+        .iter()
+        .max_by_key(|row| row.len())
+        .map(|row| row.iter().map(|(x, _, _)| *x).collect::<Vec<_>>())
+        .unwrap_or_default();
+    rows.into_iter()
+        .map(|row| {
+            let mut cells = vec![String::new(); columns.len()];
+            for (x, _, text) in row {
+                let column = columns
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| (*a - x).abs().total_cmp(&(*b - x).abs()))
+                    .map(|(column, _)| column)
+                    .unwrap_or_default();
+                if !cells[column].is_empty() {
+                    cells[column].push(' ');
+                }
+                cells[column].push_str(&text);
+            }
+            cells
+        })
+        .collect()
+}
+
+/// Sort the lines of a page in reading order. If the page has columns, each column is read top to bottom before the next one. Lines that cross the columns, like titles, split the page into bands that are read in order.
+fn reading_order(lines: Vec<LayoutLine>) -> Vec<LayoutLine> {
+    let Some(gutter) = find_gutter(&lines) else {
+        return lines;
+    };
+
+    let mut lines = lines;
+    lines.sort_by(|a, b| a.y0().total_cmp(&b.y0()));
+    let mut ordered = Vec::with_capacity(lines.len());
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    for line in lines {
+        if line.x1() <= gutter {
+            left.push(line);
+        } else if line.x0() >= gutter {
+            right.push(line);
+        } else {
+            ordered.append(&mut left);
+            ordered.append(&mut right);
+            ordered.push(line);
+        }
+    }
+    ordered.append(&mut left);
+    ordered.append(&mut right);
+    ordered
+}
+
+/// Find the x position of the space between two columns of text, if the page has two columns.
+fn find_gutter(lines: &[LayoutLine]) -> Option<f32> {
+    if lines.len() < 6 {
+        return None;
+    }
+    let left = lines.iter().map(LayoutLine::x0).fold(f32::MAX, f32::min);
+    let right = lines.iter().map(LayoutLine::x1).fold(f32::MIN, f32::max);
+    let middle = left..=right;
+    let width = right - left;
+    let search = left + width * 0.3..=left + width * 0.7;
+
+    let mut best: Option<(usize, f32)> = None;
+    for line in lines {
+        for candidate in [line.x1() + 0.1, line.x0() - 0.1] {
+            if !search.contains(&candidate) || !middle.contains(&candidate) {
+                continue;
+            }
+            let crossing = lines
+                .iter()
+                .filter(|line| line.x0() < candidate && line.x1() > candidate)
+                .count();
+            let center_distance = (candidate - (left + width / 2.)).abs();
+            let better = match best {
+                Some((best_crossing, best_candidate)) => {
+                    crossing < best_crossing
+                        || (crossing == best_crossing
+                            && center_distance < (best_candidate - (left + width / 2.)).abs())
+                }
+                None => true,
+            };
+            if better {
+                best = Some((crossing, candidate));
+            }
+        }
+    }
+
+    let (crossing, gutter) = best?;
+    let left_lines = lines
+        .iter()
+        .filter(|line| line.x1() <= gutter)
+        .collect::<Vec<_>>();
+    let right_lines = lines
+        .iter()
+        .filter(|line| line.x0() >= gutter)
+        .collect::<Vec<_>>();
+    if crossing * 5 > lines.len() || left_lines.len() < 3 || right_lines.len() < 3 {
+        return None;
+    }
+    // The columns must be side by side, not one above the other
+    let top = |lines: &[&LayoutLine]| lines.iter().map(|line| line.y0()).fold(f32::MAX, f32::min);
+    let bottom =
+        |lines: &[&LayoutLine]| lines.iter().map(|line| line.y1()).fold(f32::MIN, f32::max);
+    if top(&left_lines).max(top(&right_lines)) >= bottom(&left_lines).min(bottom(&right_lines)) {
+        return None;
+    }
+    Some(gutter)
+}
+
+#[cfg(test)]
+fn test_line(x: f32, y: f32, size: f32, text: &str) -> LayoutLine {
+    let mut x = x;
+    let words = text
+        .split(' ')
+        .filter_map(|word| {
+            // Two spaces in the text are a wide gap, like the space between table cells
+            let (x0, text) = match word {
+                "" => {
+                    x += size * 2.;
+                    return None;
+                }
+                word => (x, word.to_string()),
+            };
+            x += word.len() as f32 * size * 0.5 + size * 0.25;
+            Some(LayoutWord {
+                text,
+                x0,
+                x1: x - size * 0.25,
+                y0: y,
+                y1: y + size,
+            })
+        })
+        .collect();
+    LayoutLine { words }
+}
+
+#[test]
+fn layout_reads_columns_headings_and_tables() {
+    let lines = vec![
+        test_line(50., 40., 20., "Annual Report"),
+        test_line(50., 80., 10., "The left column starts"),
+        test_line(320., 80., 10., "The right column starts"),
+        test_line(50., 92., 10., "here and contin-"),
+        test_line(320., 92., 10., "over here."),
+        test_line(50., 104., 10., "ues down."),
+        test_line(50., 130., 14., "Results"),
+        test_line(320., 130., 10., "More right text"),
+        test_line(50., 150., 10., "Region  Sales"),
+        test_line(50., 162., 10., "North  10"),
+        test_line(50., 174., 10., "South  20"),
+    ];
+    let sizes = FontSizes::new([lines.as_slice()]);
+    let blocks = page_blocks(lines, &sizes);
+    assert_eq!(
+        blocks,
+        [
+            PdfBlock::Heading {
+                level: 1,
+                text: "Annual Report".into()
+            },
+            PdfBlock::Paragraph("The left column starts here and continues down.".into()),
+            PdfBlock::Heading {
+                level: 2,
+                text: "Results".into()
+            },
+            PdfBlock::Table(vec![
+                vec!["Region".into(), "Sales".into()],
+                vec!["North".into(), "10".into()],
+                vec!["South".into(), "20".into()],
+            ]),
+            PdfBlock::Paragraph("The right column starts over here.".into()),
+            PdfBlock::Paragraph("More right text".into()),
+        ]
+    );
+}
+
+#[test]
+fn tables_need_aligned_rows() {
+    let sizes = FontSizes::new([[test_line(50., 0., 10., "body text")].as_slice()]);
+
+    // Two lines with wide gaps are not enough for a table
+    let lines = vec![
+        test_line(50., 0., 10., "Name:  Alice"),
+        test_line(50., 12., 10., "Date:  Monday"),
+    ];
+    assert!(page_blocks(lines, &sizes)
+        .iter()
+        .all(|block| matches!(block, PdfBlock::Paragraph(_))));
+
+    // Lines with gaps that don't line up are not a table
+    let lines = vec![
+        test_line(50., 0., 10., "Name  Alice"),
+        test_line(50., 12., 10., "Everything we know  so far"),
+        test_line(50., 24., 10., "Date of the next meeting  Monday"),
+    ];
+    assert!(page_blocks(lines, &sizes)
+        .iter()
+        .all(|block| matches!(block, PdfBlock::Paragraph(_))));
+
+    // Right aligned numbers still line up with their column
+    let lines = vec![
+        test_line(50., 0., 10., "Region  Sales"),
+        test_line(50., 12., 10., "North   1000"),
+        test_line(50., 24., 10., "South  20"),
+    ];
+    assert_eq!(
+        page_blocks(lines, &sizes),
+        [PdfBlock::Table(vec![
+            vec!["Region".into(), "Sales".into()],
+            vec!["North".into(), "1000".into()],
+            vec!["South".into(), "20".into()],
+        ])]
+    );
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+
+use super::office::{file_title, open_archive, read_entry, xml_element_text, xml_text};
+use crate::context::document::{Document, DocumentSection, IntoDocument};
+
+/// A pptx slide deck that can be read from the file system.
+///
+/// The text of each slide is read in slide order. Each slide is recorded as a [`DocumentSection`] with the slide number as the page. The title is read from the deck properties, or the file name if the deck doesn't have a title.
+#[derive(Debug, Clone)]
+pub struct PptxDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for PptxDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if path.extension() != Some("pptx".as_ref()) {
+            return Err(anyhow::anyhow!("Path is not a pptx file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for PptxDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let mut archive = open_archive(&self.path).await?;
+
+        let title = match read_entry(&mut archive, "docProps/core.xml") {
+            Ok(properties) => xml_element_text(&properties, "title")?,
+            Err(_) => None,
+        }
+        .unwrap_or_else(|| file_title(&self.path));
+
+        // Slides are stored as ppt/slides/slide{number}.xml
+        let mut slides = archive
+            .file_names()
+            .filter_map(|name| {
+                let number = name
+                    .strip_prefix("ppt/slides/slide")?
+                    .strip_suffix(".xml")?
+                    .parse::<usize>()
+                    .ok()?;
+                Some((number, name.to_string()))
+            })
+            .collect::<Vec<_>>();
+        slides.sort();
+
+        let mut body = String::new();
+        let mut sections = Vec::with_capacity(slides.len());
+        for (number, name) in slides {
+            let slide = read_entry(&mut archive, &name)?;
+            let slide_text = xml_text(&slide, &["p"])?;
+            if slide_text.is_empty() {
+                continue;
+            }
+            if !body.is_empty() {
+                body += "\n\n";
+            }
+            let start = body.len();
+            body += &slide_text;
+            sections.push(DocumentSection {
+                byte_range: start..body.len(),
+                page: Some(number as u32),
+                heading_path: Vec::new(),
+            });
+        }
+
+        let mut document = Document::from_parts(title, body).with_source(self.path);
+        for section in sections {
+            document.push_section(section);
+        }
+        Ok(document)
+    }
+}
+
+#[tokio::test]
+async fn pptx_document_records_slides() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("quarterly-review.pptx");
+    let slide = |text: &str| {
+        format!(
+            r#"<p:sld xmlns:p="p" xmlns:a="a"><p:cSld><p:spTree><p:sp><p:txBody><a:p><a:r><a:t>{text}</a:t></a:r></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>"#
+        )
+    };
+    super::office::write_archive(
+        &path,
+        &[
+            ("ppt/slides/slide10.xml", &slide("Questions")),
+            ("ppt/slides/slide2.xml", &slide("Revenue grew")),
+            ("ppt/slides/slide1.xml", &slide("Agenda")),
+        ],
+    );
+
+    let document = PptxDocument::try_from(path)
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(document.title(), "Quarterly Review");
+    assert_eq!(document.body(), "Agenda\n\nRevenue grew\n\nQuestions");
+    let pages = document
+        .sections()
+        .iter()
+        .map(|section| (section.page, &document.body()[section.byte_range.clone()]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        pages,
+        [
+            (Some(1), "Agenda"),
+            (Some(2), "Revenue grew"),
+            (Some(10), "Questions")
+        ]
+    );
+}
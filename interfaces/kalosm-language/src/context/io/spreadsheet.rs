@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+
+use calamine::Reader;
+
+use super::office::file_title;
+use crate::context::document::{Document, IntoDocument};
+
+/// A csv or tsv file that can be read from the file system.
+///
+/// The first row is treated as the header. Each following row becomes a line of `header: value` pairs so every value keeps the name of its column after the document is chunked.
+#[derive(Debug, Clone)]
+pub struct CsvDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for CsvDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if !matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("csv" | "tsv")
+        ) {
+            return Err(anyhow::anyhow!("Path is not a csv file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for CsvDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let delimiter = match self.path.extension().and_then(|ext| ext.to_str()) {
+            Some("tsv") => b'\t',
+            _ => b',',
+        };
+        let bytes = tokio::fs::read(&self.path).await?;
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(bytes.as_slice());
+        let rows = reader
+            .records()
+            .map(|record| Ok(record?.iter().map(str::to_string).collect()))
+            .collect::<anyhow::Result<Vec<Vec<String>>>>()?;
+
+        Ok(Document::from_parts(file_title(&self.path), table_text(&rows)).with_source(self.path))
+    }
+}
+
+/// A spreadsheet (xlsx, xls or ods) that can be read from the file system.
+///
+/// Each sheet starts with the name of the sheet followed by its rows. The first row of each sheet is treated as the header like [`CsvDocument`].
+#[derive(Debug, Clone)]
+pub struct SpreadsheetDocument {
+    path: PathBuf,
+}
+
+impl TryFrom<PathBuf> for SpreadsheetDocument {
+    type Error = anyhow::Error;
+
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Path is not a file"));
+        }
+        if !matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("xlsx" | "xlsm" | "xls" | "ods")
+        ) {
+            return Err(anyhow::anyhow!("Path is not a spreadsheet file"));
+        }
+        Ok(Self { path })
+    }
+}
+
+#[async_trait::async_trait]
+impl IntoDocument for SpreadsheetDocument {
+    async fn into_document(self) -> anyhow::Result<Document> {
+        let title = file_title(&self.path);
+        let path = self.path.clone();
+        let sheets = tokio::task::spawn_blocking(move || {
+            let mut workbook = calamine::open_workbook_auto(&path)?;
+            let mut sheets = Vec::new();
+            for name in workbook.sheet_names() {
+                let range = workbook.worksheet_range(&name)?;
+                let rows = range
+                    .rows()
+                    .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+                    .collect::<Vec<Vec<String>>>();
+                let text = table_text(&rows);
+                if !text.is_empty() {
+                    sheets.push(format!("{name}\n{text}"));
+                }
+            }
+            anyhow::Ok(sheets)
+        })
+        .await??;
+
+        Ok(Document::from_parts(title, sheets.join("\n\n")).with_source(self.path))
+    }
+}
+
+/// Turn a table into one line per row of `header: value` pairs. Empty cells are skipped. A table with only a header row becomes a single line with the headers.
+fn table_text(rows: &[Vec<String>]) -> String {
+    let mut rows = rows
+        .iter()
+        .filter(|row| row.iter().any(|cell| !cell.trim().is_empty()))
+        .peekable();
+    let Some(headers) = rows.next() else {
+        return String::new();
+    };
+    if rows.peek().is_none() {
+        return headers
+            .iter()
+            .map(|header| header.trim())
+            .filter(|header| !header.is_empty())
+            .collect::<Vec<_>>()
+            .join(", ");
+    }
+    rows.map(|row| {
+        row.iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.trim().is_empty())
+            .map(
+                |(i, cell)| match headers.get(i).map(|header| header.trim()) {
+                    Some(header) if !header.is_empty() => format!("{header}: {}", cell.trim()),
+                    _ => cell.trim().to_string(),
+                },
+            )
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[test]
+fn table_text_pairs_values_with_headers() {
+    let rows = [
+        vec!["name", "age", "city"],
+        vec!["", "", ""],
+        vec!["Alice", "30", "Paris"],
+        vec!["Bob", "", "Oslo", "extra"],
+    ]
+    .map(|row| row.into_iter().map(String::from).collect::<Vec<_>>());
+    assert_eq!(
+        table_text(&rows),
+        "name: Alice, age: 30, city: Paris\nname: Bob, city: Oslo, extra"
+    );
+    assert_eq!(table_text(&[]), "");
+    assert_eq!(table_text(&rows[..2]), "name, age, city");
+}
+
+#[tokio::test]
+async fn csv_document_reads_rows() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("people.csv");
+    std::fs::write(&path, "name,age\nAlice,30\nBob,25\n").unwrap();
+    let document = CsvDocument::try_from(path.clone())
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(document.title(), "People");
+    assert_eq!(document.body(), "name: Alice, age: 30\nname: Bob, age: 25");
+
+    // A file with only a header is not empty
+    std::fs::write(&path, "name,age\n").unwrap();
+    let document = CsvDocument::try_from(path)
+        .unwrap()
+        .into_document()
+        .await
+        .unwrap();
+    assert_eq!(document.body(), "name, age");
+}
@@ -34,11 +34,11 @@ impl IntoDocument for TextDocument {
             .to_string_lossy()
             .to_string()
             .to_case(Case::Title);
-        let file = File::open(self.path).await?;
+        let file = File::open(&self.path).await?;
         let mut text = String::new();
         tokio::io::BufReader::new(file)
             .read_to_string(&mut text)
             .await?;
-        Ok(Document::from_parts(title, text))
+        Ok(Document::from_parts(title, text).with_source(self.path))
     }
 }
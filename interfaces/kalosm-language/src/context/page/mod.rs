@@ -13,12 +13,11 @@ pub use page::*;
 
 pub(crate) async fn get_article(url: Url) -> Result<Document, anyhow::Error> {
     let html = reqwest::get(url.clone()).await?.text().await?;
-    extract_article(&html)
+    Ok(extract_article(&html)?.with_source(url))
 }
 
 pub(crate) fn extract_article(html: &str) -> anyhow::Result<Document> {
     let cleaned =
-        readability::extractor::extract(&mut html.as_bytes(), &Url::parse("https://example.com")?)
-            .unwrap();
+        readability::extractor::extract(&mut html.as_bytes(), &Url::parse("https://example.com")?)?;
     Ok(Document::from_parts(cleaned.title, cleaned.text))
 }
@@ -229,7 +229,7 @@ impl StaticPage {
 
     /// Extract the article from the page.
     pub async fn article(&self) -> anyhow::Result<Document> {
-        extract_article(&self.html_ref().await?.html())
+        Ok(extract_article(&self.html_ref().await?.html())?.with_source(self.url()))
     }
 
     /// Get the title of the page.
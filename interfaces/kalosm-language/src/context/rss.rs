@@ -74,7 +74,17 @@ impl RssFeed {
             let article =
                 readability::extractor::extract(&mut std::io::Cursor::new(&content), &url)?;
 
-            documents.push(Document::from_parts(article.title, article.text));
+            let mut document = Document::from_parts(article.title, article.text);
+            if let Some(link) = item.link().and_then(|link| Url::parse(link).ok()) {
+                document.set_source(link);
+            }
+            if let Some(date) = item
+                .pub_date()
+                .and_then(|date| chrono::DateTime::parse_from_rfc2822(date).ok())
+            {
+                document.set_created_at(date.with_timezone(&chrono::Utc));
+            }
+            documents.push(document);
         }
         Ok(documents)
     }
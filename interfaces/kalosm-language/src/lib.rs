@@ -25,10 +25,12 @@ pub mod prelude {
     pub use crate::vector_db::*;
     pub use futures_util::StreamExt as _;
     pub use kalosm_language_model::*;
-    pub use kalosm_llama::{Llama, LlamaBuilder, LlamaSession, LlamaSource};
+    pub use kalosm_llama::{
+        Llama, LlamaBuilder, LlamaPooling, LlamaSession, LlamaSource, LlamaSpace,
+    };
     pub use kalosm_sample::*;
     pub use kalosm_streams::text_stream::*;
-    pub use rbert::{Bert, BertBuilder, BertSource, BertSpace};
+    pub use rbert::{Bert, BertBuilder, BertReranker, BertRerankerBuilder, BertSource, BertSpace};
     pub use rphi::{Phi, PhiBuilder, PhiSource};
     pub use scraper::Html;
 }
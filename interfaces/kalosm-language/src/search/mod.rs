@@ -1,19 +1,44 @@
 //! The index module contains different types of search indexes that can be used to search for [`crate::context::Document`]s created from [`crate::context::IntoDocument`] or [`crate::context::IntoDocuments`]
 
 mod postprocessing;
+pub use postprocessing::*;
 mod preprocessing;
 pub use preprocessing::*;
+mod query;
+pub use query::*;
 
 use kalosm_language_model::*;
 use std::{fmt::Debug, ops::Range};
 
 /// A document snippet that can be used to display a snippet of a document.
+///
+/// Create a chunk with [`Chunk::new`] so new fields can be added without breaking your code.
 #[derive(Clone)]
+#[non_exhaustive]
 pub struct Chunk<S: VectorSpace> {
     /// The byte range of the chunk in the original document.
     pub byte_range: Range<usize>,
     /// The embeddings of the chunk.
     pub embeddings: Vec<Embedding<S>>,
+    /// Metadata about the chunk, like the symbols a [`CodeChunker`] chunk defines. It is stored with the chunk along with the metadata of the document.
+    pub metadata: crate::prelude::Metadata,
+}
+
+impl<S: VectorSpace> Chunk<S> {
+    /// Create a new chunk of a document without metadata.
+    pub fn new(byte_range: Range<usize>, embeddings: Vec<Embedding<S>>) -> Self {
+        Self {
+            byte_range,
+            embeddings,
+            metadata: Default::default(),
+        }
+    }
+
+    /// Set the metadata of the chunk.
+    pub fn with_metadata(mut self, metadata: crate::prelude::Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 
 impl<S: VectorSpace> Debug for Chunk<S> {
@@ -21,6 +46,7 @@ impl<S: VectorSpace> Debug for Chunk<S> {
         f.debug_struct("Chunk")
             .field("byte_range", &self.byte_range)
             .field("embeddings", &self.embeddings)
+            .field("metadata", &self.metadata)
             .finish()
     }
 }
@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+
+use tokenizers::Tokenizer;
+
+/// A postprocessing step that turns the chunks retrieved for a query into context for a prompt.
+///
+/// Extractors take the text of each retrieved chunk in order of relevance and return a (usually smaller) list of passages. They can be chained with [`ContextExtractorExt::then`] and ended with a [`ContextBudget`] to get compact context that fits in the prompt.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let chunks = vec![
+///         "Kalosm is a library for local AI. It was written in Rust.".to_string(),
+///         "The quick brown fox jumps over the lazy dog.".to_string(),
+///     ];
+///     let extractor = EntitySentences::new().then(ContextBudget::new(256));
+///     let context = extractor.extract("What is Kalosm?", chunks).await?;
+///     println!("{}", context.join("\n"));
+///     Ok(())
+/// }
+/// ```
+pub trait ContextExtractor {
+    /// Extract the passages relevant to the query from the retrieved chunks.
+    fn extract(
+        &self,
+        query: &str,
+        chunks: Vec<String>,
+    ) -> impl Future<Output = anyhow::Result<Vec<String>>> + Send;
+}
+
+/// An extension trait for [`ContextExtractor`] that lets you chain extractors together.
+pub trait ContextExtractorExt: ContextExtractor {
+    /// Run another extractor on the passages this extractor returns.
+    fn then<E: ContextExtractor>(self, next: E) -> ChainedExtractor<Self, E>
+    where
+        Self: Sized,
+    {
+        ChainedExtractor { first: self, next }
+    }
+}
+
+impl<E: ContextExtractor> ContextExtractorExt for E {}
+
+/// Two [`ContextExtractor`]s that run one after the other. Created with [`ContextExtractorExt::then`].
+pub struct ChainedExtractor<A, B> {
+    first: A,
+    next: B,
+}
+
+impl<A, B> ContextExtractor for ChainedExtractor<A, B>
+where
+    A: ContextExtractor + Sync,
+    B: ContextExtractor + Sync,
+{
+    async fn extract(&self, query: &str, chunks: Vec<String>) -> anyhow::Result<Vec<String>> {
+        let passages = self.first.extract(query, chunks).await?;
+        self.next.extract(query, passages).await
+    }
+}
+
+/// A [`ContextExtractor`] that removes duplicate passages and keeps the most relevant passages that fit in a token budget.
+///
+/// Passages are expected to be sorted from most to least relevant. A passage is dropped if it repeats a passage that was already kept (ignoring case and whitespace). The remaining passages are kept in order until the next passage would go over the budget. Smaller passages after that point are still kept if they fit.
+#[derive(Debug, Clone)]
+pub struct ContextBudget {
+    max_tokens: usize,
+    tokenizer: Option<Arc<Tokenizer>>,
+}
+
+impl ContextBudget {
+    /// Create a new budget with the maximum number of tokens the context can take up.
+    ///
+    /// Without a tokenizer, the number of tokens is estimated as one token for every four bytes of text.
+    pub fn new(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            tokenizer: None,
+        }
+    }
+
+    /// Count tokens with the tokenizer of the model the context will be given to. You can get the tokenizer of a model with [`kalosm_language_model::Model::tokenizer`].
+    pub fn with_tokenizer(mut self, tokenizer: Arc<Tokenizer>) -> Self {
+        self.tokenizer = Some(tokenizer);
+        self
+    }
+
+    /// Get the maximum number of tokens the context can take up.
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Count the number of tokens in a passage.
+    pub fn count_tokens(&self, text: &str) -> anyhow::Result<usize> {
+        match &self.tokenizer {
+            Some(tokenizer) => Ok(tokenizer
+                .encode(text, false)
+                .map_err(anyhow::Error::msg)?
+                .len()),
+            None => Ok(text.len().div_ceil(4)),
+        }
+    }
+
+    /// Deduplicate the passages and keep the passages that fit in the budget.
+    pub fn fit(&self, passages: impl IntoIterator<Item = String>) -> anyhow::Result<Vec<String>> {
+        let mut kept = Vec::new();
+        let mut kept_normalized = HashSet::new();
+        let mut remaining = self.max_tokens;
+        for passage in passages {
+            let normalized = normalize(&passage);
+            if normalized.is_empty() || kept_normalized.contains(&normalized) {
+                continue;
+            }
+            let tokens = self.count_tokens(&passage)?;
+            if tokens > remaining {
+                continue;
+            }
+            remaining -= tokens;
+            kept_normalized.insert(normalized);
+            kept.push(passage);
+        }
+        Ok(kept)
+    }
+}
+
+impl ContextExtractor for ContextBudget {
+    async fn extract(&self, _: &str, chunks: Vec<String>) -> anyhow::Result<Vec<String>> {
+        self.fit(chunks)
+    }
+}
+
+/// Lowercase the text and collapse whitespace so passages that only differ in formatting are treated as duplicates.
+fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[test]
+fn budget_only_drops_exact_duplicates() {
+    let kept = ContextBudget::new(1000)
+        .fit([
+            "I trust Rust.".to_string(),
+            "rust.".to_string(),
+            "I  TRUST rust.".to_string(),
+        ])
+        .unwrap();
+    assert_eq!(kept, ["I trust Rust.", "rust."]);
+}
+
+#[tokio::test]
+async fn extract_entity_context_in_budget() {
+    use super::{AllSentences, EntitySentences};
+
+    let chunks = vec![
+        "Kalosm is a library for local AI. The weather was nice today.".to_string(),
+        "Kalosm is a library for local AI.".to_string(),
+        "Kalosm  is a library for LOCAL AI. It supports Llama models.".to_string(),
+        "Rust is a programming language. Kalosm is written in Rust.".to_string(),
+    ];
+
+    let all = AllSentences
+        .then(ContextBudget::new(1000))
+        .extract("What is Kalosm?", chunks.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        all,
+        [
+            "Kalosm is a library for local AI.",
+            "The weather was nice today.",
+            "It supports Llama models.",
+            "Rust is a programming language.",
+            "Kalosm is written in Rust.",
+        ]
+    );
+
+    let mentions_kalosm = EntitySentences::new()
+        .then(ContextBudget::new(1000))
+        .extract("What is Kalosm?", chunks.clone())
+        .await
+        .unwrap();
+    assert_eq!(
+        mentions_kalosm,
+        [
+            "Kalosm is a library for local AI.",
+            "Kalosm is written in Rust."
+        ]
+    );
+
+    // The first sentence takes up 9 of the 12 tokens, so the second sentence doesn't fit
+    let budgeted = EntitySentences::new()
+        .then(ContextBudget::new(12))
+        .extract("What is Kalosm?", chunks)
+        .await
+        .unwrap();
+    assert_eq!(budgeted, ["Kalosm is a library for local AI."]);
+}
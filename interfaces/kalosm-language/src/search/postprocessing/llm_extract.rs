@@ -0,0 +1,107 @@
+use kalosm_language_model::{Model, SyncModel};
+use kalosm_sample::{IntegerParser, LiteralParser, ParserExt, SeparatedParser, SequenceParser};
+
+use crate::prelude::{StructuredRunner, Task};
+
+use super::{split_sentences, ContextExtractor};
+
+/// The maximum number of sentences shown to the model in one prompt
+const SENTENCES_PER_PROMPT: usize = 16;
+
+const TASK_DESCRIPTION: &str = "You find the sentences that help answer a question. You are given a question and a numbered list of sentences. Respond with the numbers of the sentences that contain information relevant to the question. If none of the sentences are relevant, respond with 0.";
+
+const EXAMPLES: [(&str, &str); 2] = [
+    (
+        "Question: Where is the Eiffel Tower?\nSentences:\n1. The Eiffel Tower was finished in 1889.\n2. It is located on the Champ de Mars in Paris.\n3. Many tourists enjoy French food.\n4. The tower is in the 7th arrondissement of the city.",
+        "Relevant sentences: 2, 4",
+    ),
+    (
+        "Question: Who wrote Hamlet?\nSentences:\n1. The weather in Denmark is often cold.\n2. Castles are common in Europe.",
+        "Relevant sentences: 0",
+    ),
+];
+
+const PREFIX: &str = "Relevant sentences: ";
+
+type Constraints = SequenceParser<LiteralParser, SeparatedParser<IntegerParser, LiteralParser>>;
+
+fn create_constraints() -> Constraints {
+    LiteralParser::new(PREFIX).then(SeparatedParser::new(
+        IntegerParser::new(0..=SENTENCES_PER_PROMPT as i128),
+        LiteralParser::new(", "),
+        1..=SENTENCES_PER_PROMPT,
+    ))
+}
+
+/// A [`ContextExtractor`] that splits every chunk into sentences and asks a language model which sentences are relevant to the query.
+///
+/// The sentences are shown to the model in numbered batches and the response is constrained to a list of sentence numbers, so the model can only return sentences from the retrieved chunks. The relevant sentences are returned in the order they appear in the chunks.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let llm = Llama::new_chat().await?;
+///     let chunks = vec![
+///         "Kalosm is a library for local AI. The weather was nice today.".to_string(),
+///     ];
+///     let extractor = LlmSentenceExtractor::new(llm).then(ContextBudget::new(512));
+///     let context = extractor.extract("What is Kalosm?", chunks).await?;
+///     println!("{}", context.join("\n"));
+///     Ok(())
+/// }
+/// ```
+pub struct LlmSentenceExtractor<M> {
+    model: M,
+    task: Task<StructuredRunner<Constraints>>,
+}
+
+impl<M> LlmSentenceExtractor<M>
+where
+    M: Model,
+    <M::SyncModel as SyncModel>::Session: Sync + Send,
+{
+    /// Create a new extractor that uses the given model.
+    pub fn new(model: M) -> Self {
+        let task = Task::builder(TASK_DESCRIPTION)
+            .with_constraints(create_constraints())
+            .with_examples(EXAMPLES)
+            .build();
+        Self { model, task }
+    }
+
+    /// Get the model used to pick relevant sentences.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+}
+
+impl<M> ContextExtractor for LlmSentenceExtractor<M>
+where
+    M: Model + Sync,
+    <M::SyncModel as SyncModel>::Session: Sync + Send,
+{
+    async fn extract(&self, query: &str, chunks: Vec<String>) -> anyhow::Result<Vec<String>> {
+        let sentences = split_sentences(&chunks);
+        let mut relevant = Vec::new();
+        for batch in sentences.chunks(SENTENCES_PER_PROMPT) {
+            let mut prompt = format!("Question: {query}\nSentences:");
+            for (i, sentence) in batch.iter().enumerate() {
+                prompt += &format!("\n{}. {}", i + 1, sentence.replace('\n', " "));
+            }
+            let (_, mut numbers) = self.task.run(prompt, &self.model).result().await?;
+            numbers.sort();
+            numbers.dedup();
+            // 0 means none of the sentences are relevant and numbers past the end of a short batch are ignored
+            relevant.extend(
+                numbers
+                    .into_iter()
+                    .filter_map(|number| batch.get((number as usize).checked_sub(1)?))
+                    .cloned(),
+            );
+        }
+        Ok(relevant)
+    }
+}
@@ -1,4 +1,8 @@
-// Context extraction strategies:
-// 1. Dump all sentences
-// 2. Dump all sentences that mention an entity
-// 3. Extract relevant sentences with an llm
+mod context;
+pub use context::*;
+mod sentences;
+pub use sentences::*;
+mod llm_extract;
+pub use llm_extract::*;
+mod rerank;
+pub use rerank::*;
@@ -0,0 +1,198 @@
+use std::borrow::Cow;
+
+use kalosm_language_model::Reranker;
+
+use crate::context::Document;
+
+/// A search result with text that can be scored by a [`Reranker`].
+pub trait RerankCandidate {
+    /// The text of the search result that is compared to the query.
+    fn rerank_text(&self) -> Cow<'_, str>;
+}
+
+impl RerankCandidate for String {
+    fn rerank_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl RerankCandidate for &str {
+    fn rerank_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl RerankCandidate for Document {
+    fn rerank_text(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.body())
+    }
+}
+
+/// A search result that was re-scored by a [`RerankStage`].
+#[derive(Debug, Clone)]
+pub struct Reranked<T> {
+    /// How relevant the result is to the query according to the reranker. Higher scores are more relevant.
+    pub score: f32,
+    /// The original search result.
+    pub result: T,
+}
+
+/// A postprocessing stage that re-scores search results with a [`Reranker`] and sorts them from most to least relevant.
+///
+/// Vector search only compares the embedding of the query with the embedding of each result. A cross-encoder reranker reads the query and result together, so running it over the top results of a vector search gives much better ordering.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::collections::HashMap;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let bert = Bert::new_for_search().await?;
+///     let sentences = [
+///         "Kalosm can be used to build local AI applications",
+///         "With private LLMs data never leaves your computer",
+///         "The quick brown fox jumps over the lazy dog",
+///     ];
+///     let db = VectorDB::new()?;
+///     let ids = db.add_embeddings(bert.embed_batch(sentences).await?)?;
+///     let sentence_for_id: HashMap<EmbeddingId, &str> = HashMap::from_iter(ids.into_iter().zip(sentences));
+///
+///     let query = "What is Kalosm?";
+///     let closest = db.get_closest(bert.embed_query(query).await?, 3)?;
+///
+///     // Re-score the closest sentences with a cross-encoder
+///     let rerank = RerankStage::new(BertReranker::new().await?).with_top_k(1);
+///     let reranked = rerank
+///         .rerank_by(query, closest, |result| sentence_for_id[&result.value].to_string())
+///         .await?;
+///     println!("{}", sentence_for_id[&reranked[0].result.value]);
+///     Ok(())
+/// }
+/// ```
+pub struct RerankStage<R> {
+    reranker: R,
+    top_k: Option<usize>,
+    min_score: Option<f32>,
+}
+
+impl<R: Reranker> RerankStage<R> {
+    /// Create a new reranking stage with a reranker.
+    pub fn new(reranker: R) -> Self {
+        Self {
+            reranker,
+            top_k: None,
+            min_score: None,
+        }
+    }
+
+    /// Only keep the top k results after reranking. (Defaults to keeping every result)
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Drop results with a score lower than the minimum score after reranking. (Defaults to keeping every result)
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = Some(min_score);
+        self
+    }
+
+    /// Get the reranker this stage uses.
+    pub fn reranker(&self) -> &R {
+        &self.reranker
+    }
+
+    /// Re-score and re-order search results that have text.
+    pub async fn rerank<T: RerankCandidate>(
+        &self,
+        query: &str,
+        results: Vec<T>,
+    ) -> anyhow::Result<Vec<Reranked<T>>> {
+        self.rerank_by(query, results, |result| result.rerank_text().into_owned())
+            .await
+    }
+
+    /// Re-score and re-order search results with a function that gets the text of each result. This is useful for results that only store an id like [`crate::vector_db::VectorDBSearchResult`].
+    pub async fn rerank_by<T>(
+        &self,
+        query: &str,
+        results: Vec<T>,
+        mut text: impl FnMut(&T) -> String,
+    ) -> anyhow::Result<Vec<Reranked<T>>> {
+        if results.is_empty() {
+            return Ok(Vec::new());
+        }
+        let documents = results.iter().map(&mut text).collect();
+        let scores = self
+            .reranker
+            .score_batch(query.to_string(), documents)
+            .await?;
+        anyhow::ensure!(
+            scores.len() == results.len(),
+            "The reranker returned {} scores for {} results",
+            scores.len(),
+            results.len()
+        );
+
+        let mut reranked = scores
+            .into_iter()
+            .zip(results)
+            .map(|(score, result)| Reranked { score, result })
+            .filter(|reranked| match self.min_score {
+                Some(min_score) => reranked.score >= min_score,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        reranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        if let Some(top_k) = self.top_k {
+            reranked.truncate(top_k);
+        }
+        Ok(reranked)
+    }
+}
+
+#[tokio::test]
+async fn rerank_orders_and_filters_results() {
+    use kalosm_llama::BoxedFuture;
+
+    /// Scores documents by the number of query words they contain
+    struct WordOverlap;
+
+    impl Reranker for WordOverlap {
+        fn score_batch(
+            &self,
+            query: String,
+            documents: Vec<String>,
+        ) -> BoxedFuture<'_, anyhow::Result<Vec<f32>>> {
+            Box::pin(async move {
+                Ok(documents
+                    .iter()
+                    .map(|document| {
+                        query
+                            .split_whitespace()
+                            .filter(|word| document.contains(word))
+                            .count() as f32
+                    })
+                    .collect())
+            })
+        }
+    }
+
+    let results = vec!["the dog", "a cat", "the quick dog"];
+    let reranked = RerankStage::new(WordOverlap)
+        .rerank("the quick dog", results.clone())
+        .await
+        .unwrap();
+    let order = reranked.iter().map(|r| r.result).collect::<Vec<_>>();
+    assert_eq!(order, ["the quick dog", "the dog", "a cat"]);
+
+    let reranked = RerankStage::new(WordOverlap)
+        .with_top_k(2)
+        .with_min_score(1.)
+        .rerank_by("cat", results, |result| result.to_string())
+        .await
+        .unwrap();
+    assert_eq!(reranked.len(), 1);
+    assert_eq!(reranked[0].result, "a cat");
+}
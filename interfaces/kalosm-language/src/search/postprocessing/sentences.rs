@@ -0,0 +1,101 @@
+use crate::prelude::SentenceChunker;
+
+use super::ContextExtractor;
+
+/// Split each chunk into trimmed sentences with the default [`SentenceChunker`] rules.
+pub(crate) fn split_sentences(chunks: &[String]) -> Vec<String> {
+    let chunker = SentenceChunker::default();
+    chunks
+        .iter()
+        .flat_map(|chunk| {
+            chunker
+                .split_sentences(chunk)
+                .into_iter()
+                .map(|range| chunk[range].trim())
+                .filter(|sentence| !sentence.is_empty())
+                .map(ToString::to_string)
+        })
+        .collect()
+}
+
+/// A [`ContextExtractor`] that splits every chunk into sentences and returns all of them.
+///
+/// This is mostly useful before a [`super::ContextBudget`] so duplicate sentences from overlapping chunks are removed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllSentences;
+
+impl ContextExtractor for AllSentences {
+    async fn extract(&self, _: &str, chunks: Vec<String>) -> anyhow::Result<Vec<String>> {
+        Ok(split_sentences(&chunks))
+    }
+}
+
+/// Common words that are ignored when the entities are taken from the query.
+const STOP_WORDS: &[&str] = &[
+    "about", "and", "are", "can", "did", "does", "for", "from", "has", "have", "how", "into",
+    "its", "the", "that", "their", "there", "this", "was", "were", "what", "when", "where",
+    "which", "who", "whom", "whose", "why", "will", "with", "you", "your",
+];
+
+/// A [`ContextExtractor`] that splits every chunk into sentences and returns the sentences that mention an entity.
+///
+/// By default, the entities are the words in the query other than common words like "what" or "the". You can set the entities explicitly with [`EntitySentences::with_entities`]. Entities are matched against whole words, ignoring case.
+#[derive(Debug, Clone, Default)]
+pub struct EntitySentences {
+    entities: Option<Vec<Vec<String>>>,
+}
+
+impl EntitySentences {
+    /// Create a new extractor that looks for the entities in the query.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Look for these entities instead of the words in the query. Each entity may be multiple words long.
+    pub fn with_entities(mut self, entities: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        self.entities = Some(
+            entities
+                .into_iter()
+                .map(|entity| words(entity.as_ref()))
+                .filter(|entity| !entity.is_empty())
+                .collect(),
+        );
+        self
+    }
+
+    fn entities(&self, query: &str) -> Vec<Vec<String>> {
+        match &self.entities {
+            Some(entities) => entities.clone(),
+            None => words(query)
+                .into_iter()
+                .filter(|word| word.chars().count() > 2 && !STOP_WORDS.contains(&word.as_str()))
+                .map(|word| vec![word])
+                .collect(),
+        }
+    }
+}
+
+impl ContextExtractor for EntitySentences {
+    async fn extract(&self, query: &str, chunks: Vec<String>) -> anyhow::Result<Vec<String>> {
+        let entities = self.entities(query);
+        Ok(split_sentences(&chunks)
+            .into_iter()
+            .filter(|sentence| {
+                let sentence_words = words(sentence);
+                entities.iter().any(|entity| {
+                    sentence_words
+                        .windows(entity.len())
+                        .any(|window| window == entity.as_slice())
+                })
+            })
+            .collect())
+    }
+}
+
+/// Split text into lowercase words.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
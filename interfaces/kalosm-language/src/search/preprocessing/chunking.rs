@@ -1,7 +1,7 @@
 use kalosm_language_model::Embedder;
 use std::ops::Range;
 
-use super::{Chunker, SentenceChunker};
+use super::{words::word_ranges, Chunker, SentenceChunker};
 use crate::{prelude::Document, search::Chunk};
 
 /// A strategy for chunking a document into smaller pieces.
@@ -23,7 +23,7 @@ pub enum ChunkStrategy {
         /// The number of sentences to overlap between chunks.
         overlap: usize,
     },
-    /// Split the document into words.
+    /// Split the document into words. Chinese and Japanese text without spaces is split into single characters.
     Words {
         /// The number of words to include in each chunk.
         word_count: usize,
@@ -90,24 +90,17 @@ impl ChunkStrategy {
                 overlap,
             } => {
                 let mut chunks = Vec::new();
+                let words = word_ranges(string);
+                let word_count = (*word_count).max(1);
+                let step = word_count.saturating_sub(*overlap).max(1);
                 let mut start = 0;
-                let mut word_start_indexes = Vec::new();
-                for (i, c) in string.char_indices() {
-                    if c == ' ' {
-                        word_start_indexes.push(i + 1);
-                        if word_start_indexes.len() >= *word_count {
-                            if !string[start..i].trim().is_empty() {
-                                chunks.push(start..i);
-                            }
-                            for _ in 0..(word_start_indexes.len() - *overlap) {
-                                start = word_start_indexes.remove(0);
-                            }
-                        }
+                while start < words.len() {
+                    let end = (start + word_count).min(words.len());
+                    chunks.push(words[start].start..words[end - 1].end);
+                    if end == words.len() {
+                        break;
                     }
-                }
-
-                if !string[start..].trim().is_empty() {
-                    chunks.push(start..string.len());
+                    start += step;
                 }
 
                 chunks
@@ -213,10 +206,7 @@ impl Chunker for ChunkStrategy {
         }
         let embeddings = embedder.embed_vec(documents).await?;
         for (byte_range, embedding) in chunk_ranges.into_iter().zip(embeddings) {
-            chunks.push(Chunk {
-                byte_range,
-                embeddings: vec![embedding],
-            });
+            chunks.push(Chunk::new(byte_range, vec![embedding]));
         }
         Ok(chunks)
     }
@@ -249,14 +239,15 @@ impl Chunker for ChunkStrategy {
             let mut document_chunks = Vec::new();
             for byte_range in chunk {
                 let embedding = embeddings.next().unwrap();
-                document_chunks.push(Chunk {
-                    byte_range,
-                    embeddings: vec![embedding],
-                });
+                document_chunks.push(Chunk::new(byte_range, vec![embedding]));
             }
             embedded_chunks.push(document_chunks);
         }
 
         Ok(embedded_chunks)
     }
+
+    fn chunk_ranges<E: Embedder>(&self, document: &Document, _: &E) -> Option<Vec<Range<usize>>> {
+        Some(self.chunk_str(document.body()))
+    }
 }
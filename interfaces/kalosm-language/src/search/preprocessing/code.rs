@@ -0,0 +1,531 @@
+use kalosm_language_model::Embedder;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use tree_sitter::{Node, Parser};
+
+use super::{embed_chunks, embed_document_chunks, heading::trim_range, Chunker, PendingChunk};
+use crate::{
+    prelude::{CodeLanguage, Document, DocumentSource, Metadata},
+    search::Chunk,
+};
+
+/// The syntax nodes the [`CodeChunker`] splits a language at.
+struct Syntax {
+    /// Nodes that define a symbol, like functions and classes
+    items: &'static [&'static str],
+    /// Items with a body of other items that is split when the item is too large
+    containers: &'static [&'static str],
+    /// Nodes that belong to the item after them, like doc comments, attributes and decorators
+    attached: &'static [&'static str],
+    /// Nodes that wrap an item and the field the item is stored in
+    wrappers: &'static [(&'static str, &'static str)],
+    /// The separator between the name of a container and the name of an item inside it
+    separator: &'static str,
+}
+
+const RUST: Syntax = Syntax {
+    items: &[
+        "function_item",
+        "function_signature_item",
+        "impl_item",
+        "trait_item",
+        "struct_item",
+        "enum_item",
+        "union_item",
+        "mod_item",
+        "macro_definition",
+        "const_item",
+        "static_item",
+        "type_item",
+    ],
+    containers: &["impl_item", "trait_item", "mod_item"],
+    attached: &["line_comment", "block_comment", "attribute_item"],
+    wrappers: &[],
+    separator: "::",
+};
+
+const TYPESCRIPT: Syntax = Syntax {
+    items: &[
+        "function_declaration",
+        "generator_function_declaration",
+        "class_declaration",
+        "abstract_class_declaration",
+        "interface_declaration",
+        "type_alias_declaration",
+        "enum_declaration",
+        "internal_module",
+        "method_definition",
+        "abstract_method_signature",
+        "lexical_declaration",
+    ],
+    containers: &[
+        "class_declaration",
+        "abstract_class_declaration",
+        "internal_module",
+    ],
+    attached: &["comment", "decorator"],
+    wrappers: &[("export_statement", "declaration")],
+    separator: ".",
+};
+
+const PYTHON: Syntax = Syntax {
+    items: &["function_definition", "class_definition"],
+    containers: &["class_definition"],
+    attached: &["comment"],
+    wrappers: &[("decorated_definition", "definition")],
+    separator: ".",
+};
+
+impl CodeLanguage {
+    fn syntax(&self) -> &'static Syntax {
+        match self {
+            Self::Rust => &RUST,
+            Self::TypeScript | Self::Tsx | Self::JavaScript => &TYPESCRIPT,
+            Self::Python => &PYTHON,
+        }
+    }
+}
+
+/// A range of a source file and the symbols defined in it, created by [`CodeChunker::split`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeChunk {
+    /// The byte range of the chunk in the body of the document.
+    pub byte_range: Range<usize>,
+    /// The lines the chunk covers, starting from 1.
+    pub lines: RangeInclusive<usize>,
+    /// The names of the functions, classes and other items in the chunk, including the names of the items they are nested in, like `Point::new`. Parts of the file outside of any item are named after the item they are in, if any.
+    pub symbols: Vec<String>,
+}
+
+impl CodeChunk {
+    /// The symbols and lines of the chunk as metadata that can be stored in a [`VectorDB`](crate::prelude::VectorDB). The symbols are stored as a comma separated list under `symbols` and the lines under `start_line` and `end_line`.
+    ///
+    /// The chunks [`CodeChunker`] embeds carry this metadata in [`Chunk::metadata`], so tables store it with each chunk.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new()
+            .with("symbols", self.symbols.join(", "))
+            .with("start_line", *self.lines.start() as i64)
+            .with("end_line", *self.lines.end() as i64)
+    }
+}
+
+/// A chunker that splits source code at function, impl and class boundaries using a [tree-sitter](https://tree-sitter.github.io) parse of the file.
+///
+/// Items that fit in the maximum chunk size become their own chunk along with their doc comments and attributes. Items that are too large are split into the items inside them, like the methods of an impl block or class, and any item without smaller items inside it is split between lines. Chunks smaller than the minimum chunk size are merged with their neighbours. Each chunk is embedded with the file name and the symbols it defines.
+///
+/// The language is detected from the `language` metadata of a [`CodeDocument`](crate::prelude::CodeDocument) or the extension of the source of the document. Documents in other languages are split between lines.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use std::path::PathBuf;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let document = CodeDocument::try_from(PathBuf::from("./src/main.rs"))
+///         .unwrap()
+///         .into_document()
+///         .await
+///         .unwrap();
+///     for chunk in CodeChunker::new().split(&document) {
+///         println!("{:?} (lines {:?})", chunk.symbols, chunk.lines);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CodeChunker {
+    max_chunk_size: usize,
+    min_chunk_size: usize,
+    language: Option<CodeLanguage>,
+    symbol_prefix: bool,
+}
+
+impl Default for CodeChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodeChunker {
+    /// Create a new [`CodeChunker`] that splits items larger than 2000 bytes and merges chunks smaller than 200 bytes.
+    pub const fn new() -> Self {
+        Self {
+            max_chunk_size: 2000,
+            min_chunk_size: 200,
+            language: None,
+            symbol_prefix: true,
+        }
+    }
+
+    /// Set the size in bytes above which an item is split into the items inside it, or between lines if it has none. (default: 2000)
+    pub fn with_max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        self.max_chunk_size = max_chunk_size.max(1);
+        self
+    }
+
+    /// Set the size in bytes below which an item is merged with the items next to it. (default: 200)
+    pub fn with_min_chunk_size(mut self, min_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size;
+        self
+    }
+
+    /// Parse every document as the given language instead of detecting the language of each document.
+    pub fn with_language(mut self, language: CodeLanguage) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Set if the file name and symbols are added to the start of each chunk before it is embedded. (default: true)
+    pub fn with_symbol_prefix(mut self, symbol_prefix: bool) -> Self {
+        self.symbol_prefix = symbol_prefix;
+        self
+    }
+
+    /// Split a document into chunks at the items in the code without embedding them.
+    pub fn split(&self, document: &Document) -> Vec<CodeChunk> {
+        let source = document.body();
+        let mut splitter = Splitter {
+            source,
+            syntax: &RUST,
+            max_chunk_size: self.max_chunk_size,
+            chunks: Vec::new(),
+        };
+
+        let tree = self.language(document).and_then(|language| {
+            let mut parser = Parser::new();
+            parser.set_language(&language.grammar()).ok()?;
+            splitter.syntax = language.syntax();
+            parser.parse(source, None)
+        });
+        match &tree {
+            Some(tree) => splitter.split_children(tree.root_node(), 0, source.len(), &[]),
+            None => splitter.push_lines(0..source.len(), Vec::new()),
+        }
+
+        // Merge small chunks with the chunk before them
+        let mut merged: Vec<(Range<usize>, Vec<String>)> = Vec::new();
+        for (range, symbols) in splitter.chunks {
+            match merged.last_mut() {
+                Some((last, last_symbols))
+                    if (last.len() < self.min_chunk_size || range.len() < self.min_chunk_size)
+                        && range.end - last.start <= self.max_chunk_size =>
+                {
+                    last.end = range.end;
+                    for symbol in symbols {
+                        if !last_symbols.contains(&symbol) {
+                            last_symbols.push(symbol);
+                        }
+                    }
+                }
+                _ => merged.push((range, symbols)),
+            }
+        }
+
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect::<Vec<_>>();
+        let line = |offset: usize| line_starts.partition_point(|start| *start <= offset);
+        merged
+            .into_iter()
+            .map(|(byte_range, symbols)| CodeChunk {
+                lines: line(byte_range.start)..=line(byte_range.end - 1),
+                byte_range,
+                symbols,
+            })
+            .collect()
+    }
+
+    /// Find the language of a document from the chunker, the metadata of the document or the extension of its source.
+    fn language(&self, document: &Document) -> Option<CodeLanguage> {
+        if let Some(language) = self.language {
+            return Some(language);
+        }
+        if let Some(crate::prelude::MetadataValue::String(name)) =
+            document.metadata().get("language")
+        {
+            if let Some(language) = CodeLanguage::from_name(name) {
+                return Some(language);
+            }
+        }
+        match document.source() {
+            Some(DocumentSource::Path(path)) => CodeLanguage::from_path(path),
+            Some(DocumentSource::Url(url)) => CodeLanguage::from_path(Path::new(url.path())),
+            None => CodeLanguage::from_path(Path::new(document.title())),
+        }
+    }
+
+    /// Split a document into chunks with the text that is embedded for each chunk and the symbols and lines of the chunk as metadata.
+    fn pending_chunks(&self, document: &Document) -> Vec<PendingChunk> {
+        self.split(document)
+            .into_iter()
+            .map(|chunk| PendingChunk {
+                text: self.embedding_text(document, &chunk),
+                metadata: chunk.metadata(),
+                byte_range: chunk.byte_range,
+            })
+            .collect()
+    }
+
+    /// The text of a chunk that is embedded.
+    fn embedding_text(&self, document: &Document, chunk: &CodeChunk) -> String {
+        let code = &document.body()[chunk.byte_range.clone()];
+        if !self.symbol_prefix {
+            return code.to_string();
+        }
+        if chunk.symbols.is_empty() {
+            format!("{}\n\n{code}", document.title())
+        } else {
+            format!(
+                "{}: {}\n\n{code}",
+                document.title(),
+                chunk.symbols.join(", ")
+            )
+        }
+    }
+}
+
+impl Chunker for CodeChunker {
+    async fn chunk<E: Embedder + Send>(
+        &self,
+        document: &Document,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Chunk<E::VectorSpace>>> {
+        embed_document_chunks(self.pending_chunks(document), embedder).await
+    }
+
+    async fn chunk_batch<'a, I, E: Embedder + Send>(
+        &self,
+        documents: I,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Vec<Chunk<E::VectorSpace>>>>
+    where
+        I: IntoIterator<Item = &'a Document> + Send,
+        I::IntoIter: Send,
+    {
+        let documents = documents
+            .into_iter()
+            .map(|document| self.pending_chunks(document))
+            .collect();
+        embed_chunks(documents, embedder).await
+    }
+
+    fn chunk_ranges<E: Embedder>(&self, document: &Document, _: &E) -> Option<Vec<Range<usize>>> {
+        Some(
+            self.split(document)
+                .into_iter()
+                .map(|chunk| chunk.byte_range)
+                .collect(),
+        )
+    }
+}
+
+/// Splits the syntax tree of a file into chunks.
+struct Splitter<'a> {
+    source: &'a str,
+    syntax: &'static Syntax,
+    max_chunk_size: usize,
+    chunks: Vec<(Range<usize>, Vec<String>)>,
+}
+
+impl Splitter<'_> {
+    /// Split the children of a node into items and the text between them. The text between `start` and the first item and between the last item and `end` is kept with the items.
+    fn split_children(&mut self, parent: Node, mut start: usize, end: usize, path: &[String]) {
+        let container = match path.is_empty() {
+            true => Vec::new(),
+            false => vec![path.join(self.syntax.separator)],
+        };
+        // The start of the comments and attributes right before the current node
+        let mut attached_start = None;
+        let mut cursor = parent.walk();
+        for child in parent.children(&mut cursor) {
+            if self.syntax.attached.contains(&child.kind()) {
+                attached_start.get_or_insert(child.start_byte());
+                continue;
+            }
+            let Some(item) = self.item(child) else {
+                attached_start = None;
+                continue;
+            };
+            let item_start = attached_start
+                .take()
+                .unwrap_or(child.start_byte())
+                .max(start);
+            self.push_lines(start..item_start, container.clone());
+            self.split_item(item, item_start..child.end_byte(), path);
+            start = child.end_byte();
+        }
+        self.push_lines(start..end, container);
+    }
+
+    /// Split an item into one chunk, the items inside it, or groups of lines.
+    fn split_item(&mut self, item: Node, range: Range<usize>, path: &[String]) {
+        let mut path = path.to_vec();
+        if let Some(name) = self.name(item) {
+            path.push(name);
+        }
+        if range.len() <= self.max_chunk_size {
+            self.push(range, vec![path.join(self.syntax.separator)]);
+            return;
+        }
+        if self.syntax.containers.contains(&item.kind()) {
+            if let Some(body) = item.child_by_field_name("body") {
+                self.split_children(body, range.start, range.end, &path);
+                return;
+            }
+        }
+        self.push_lines(range, vec![path.join(self.syntax.separator)]);
+    }
+
+    /// Get the item a node defines, if any. Wrapper nodes like `export` statements and decorated definitions are unwrapped.
+    fn item<'tree>(&self, node: Node<'tree>) -> Option<Node<'tree>> {
+        let node = match self
+            .syntax
+            .wrappers
+            .iter()
+            .find(|(kind, _)| *kind == node.kind())
+        {
+            Some((_, field)) => node.child_by_field_name(field)?,
+            None => node,
+        };
+        self.syntax.items.contains(&node.kind()).then_some(node)
+    }
+
+    /// Get the name of an item.
+    fn name(&self, item: Node) -> Option<String> {
+        let name = match item.kind() {
+            // `impl Trait for Type` blocks are named after the type
+            "impl_item" => item.child_by_field_name("type")?,
+            "lexical_declaration" => item.named_child(0)?.child_by_field_name("name")?,
+            _ => item.child_by_field_name("name")?,
+        };
+        Some(self.source[name.byte_range()].to_string())
+    }
+
+    /// Add a range as one chunk, or split it between lines if it is too large.
+    fn push_lines(&mut self, range: Range<usize>, symbols: Vec<String>) {
+        let Some(range) = trim_range(self.source, range) else {
+            return;
+        };
+        if range.len() <= self.max_chunk_size {
+            self.push(range, symbols);
+            return;
+        }
+
+        let mut current: Option<Range<usize>> = None;
+        let mut offset = range.start;
+        for line in self.source[range].split_inclusive('\n') {
+            let line_range = offset..offset + line.len();
+            offset = line_range.end;
+            current = match current {
+                Some(current) if line_range.end - current.start <= self.max_chunk_size => {
+                    Some(current.start..line_range.end)
+                }
+                Some(current) => {
+                    self.push(current, symbols.clone());
+                    Some(line_range)
+                }
+                None => Some(line_range),
+            };
+        }
+        if let Some(current) = current {
+            self.push(current, symbols);
+        }
+    }
+
+    fn push(&mut self, range: Range<usize>, symbols: Vec<String>) {
+        if let Some(range) = trim_range(self.source, range) {
+            self.chunks.push((range, symbols));
+        }
+    }
+}
+
+#[test]
+fn code_chunker_splits_rust_items() {
+    let source = r#"use std::fmt;
+
+/// A point
+#[derive(Debug)]
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    fn new(x: i32) -> Self {
+        Self { x }
+    }
+
+    fn double(&self) -> i32 {
+        self.x * 2
+    }
+}
+
+fn main() {
+    let a = Point::new(1);
+    let b = a.double();
+    println!("{b}");
+}
+"#;
+    let document = Document::from_parts("point.rs", source).with_metadata("language", "rust");
+    let chunks = CodeChunker::new()
+        .with_min_chunk_size(0)
+        .with_max_chunk_size(70)
+        .split(&document)
+        .into_iter()
+        .map(|chunk| (&source[chunk.byte_range], chunk.lines, chunk.symbols))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            ("use std::fmt;", 1..=1, vec![]),
+            (
+                "/// A point\n#[derive(Debug)]\nstruct Point {\n    x: i32,\n}",
+                3..=7,
+                vec!["Point".to_string()]
+            ),
+            ("impl Point {", 9..=9, vec!["Point".into()]),
+            (
+                "fn new(x: i32) -> Self {\n        Self { x }\n    }",
+                10..=12,
+                vec!["Point::new".into()]
+            ),
+            (
+                "fn double(&self) -> i32 {\n        self.x * 2\n    }",
+                14..=16,
+                vec!["Point::double".into()]
+            ),
+            ("}", 17..=17, vec!["Point".into()]),
+            (
+                "fn main() {\n    let a = Point::new(1);\n    let b = a.double();",
+                19..=21,
+                vec!["main".into()]
+            ),
+            ("println!(\"{b}\");\n}", 22..=23, vec!["main".into()]),
+        ]
+    );
+}
+
+#[test]
+fn code_chunker_merges_small_python_items() {
+    let source = "import os\n\n@decorator\ndef first():\n    return 1\n\nclass Thing:\n    def method(self):\n        return 2\n";
+    let document = Document::from_parts("thing.py", source);
+    let chunks = CodeChunker::new()
+        .with_min_chunk_size(30)
+        .with_max_chunk_size(60)
+        .split(&document)
+        .into_iter()
+        .map(|chunk| (&source[chunk.byte_range], chunk.symbols))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            (
+                "import os\n\n@decorator\ndef first():\n    return 1",
+                vec!["first".to_string()]
+            ),
+            (
+                "class Thing:\n    def method(self):\n        return 2",
+                vec!["Thing".into()]
+            ),
+        ]
+    );
+}
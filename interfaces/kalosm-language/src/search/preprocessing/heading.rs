@@ -0,0 +1,584 @@
+use kalosm_language_model::Embedder;
+use std::ops::Range;
+
+use super::{embed_chunks, embed_document_chunks, Chunker, PendingChunk};
+use crate::{prelude::Document, search::Chunk};
+
+/// Separators tried in order when a Markdown section is too large for one chunk.
+const MARKDOWN_SEPARATORS: &[&str] = &["\n\n", "\n", ". ", " "];
+/// Separators tried in order when an HTML section is too large for one chunk.
+const HTML_SEPARATORS: &[&str] = &[
+    "</p>", "</li>", "</tr>", "</dd>", "</pre>", "<br>", "\n", ". ", " ",
+];
+
+/// The format of the body of a document for the [`HeadingChunker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocumentFormat {
+    /// Use the headings of the [`Document::sections`] if there are any. Otherwise bodies that start with a tag are read as HTML and every other body is read as Markdown.
+    #[default]
+    Auto,
+    /// A Markdown body with `#` or underlined headings.
+    Markdown,
+    /// An HTML body with `<h1>` to `<h6>` headings, like the output of [`HtmlSimplifier`](crate::prelude::HtmlSimplifier).
+    Html,
+}
+
+/// A range of a document and the headings it is under, created by [`HeadingChunker::split`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadingChunk {
+    /// The byte range of the chunk in the body of the document.
+    pub byte_range: Range<usize>,
+    /// The headings the chunk is under, from the top level heading down.
+    pub heading_path: Vec<String>,
+}
+
+/// A chunker that splits Markdown or HTML along its headings, so each chunk stays inside one section of the document.
+///
+/// Sections larger than the maximum chunk size are split at paragraphs, then lines, then sentences. Chunks smaller than the minimum chunk size are merged with a neighbouring chunk from the same section or a sibling section. Each chunk is embedded with the path of headings it is under, like `Guide > Install > Linux`, so the embedding keeps the context of the section. The byte range of the chunk only covers the text in the body of the document.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let document = Document::from_parts(
+///         "Guide",
+///         "# Guide\n\n## Install\n\nRun the installer.\n\n## Usage\n\nOpen the app.",
+///     );
+///     let chunker = HeadingChunker::new().with_max_chunk_size(1000);
+///     for chunk in chunker.split(&document) {
+///         println!("{:?}: {}", chunk.heading_path, &document.body()[chunk.byte_range]);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct HeadingChunker {
+    max_chunk_size: usize,
+    min_chunk_size: usize,
+    format: DocumentFormat,
+    heading_prefix: bool,
+}
+
+impl Default for HeadingChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HeadingChunker {
+    /// Create a new [`HeadingChunker`] with chunks between 200 and 2000 bytes.
+    pub const fn new() -> Self {
+        Self {
+            max_chunk_size: 2000,
+            min_chunk_size: 200,
+            format: DocumentFormat::Auto,
+            heading_prefix: true,
+        }
+    }
+
+    /// Set the maximum size of a chunk in bytes. (default: 2000)
+    pub fn with_max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        self.max_chunk_size = max_chunk_size.max(1);
+        self
+    }
+
+    /// Set the size in bytes below which a chunk is merged with a neighbouring chunk. (default: 200)
+    pub fn with_min_chunk_size(mut self, min_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size;
+        self
+    }
+
+    /// Set the format of the documents. (default: [`DocumentFormat::Auto`])
+    pub fn with_format(mut self, format: DocumentFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set if the heading path is added to the start of each chunk before it is embedded. (default: true)
+    pub fn with_heading_prefix(mut self, heading_prefix: bool) -> Self {
+        self.heading_prefix = heading_prefix;
+        self
+    }
+
+    /// Split a document into chunks along its headings without embedding them.
+    pub fn split(&self, document: &Document) -> Vec<HeadingChunk> {
+        let body = document.body();
+        let (sections, separators) = match self.format {
+            DocumentFormat::Markdown => (markdown_sections(body), MARKDOWN_SEPARATORS),
+            DocumentFormat::Html => (html_sections(body), HTML_SEPARATORS),
+            DocumentFormat::Auto => {
+                let html = body.trim_start().starts_with('<');
+                let separators = if html {
+                    HTML_SEPARATORS
+                } else {
+                    MARKDOWN_SEPARATORS
+                };
+                match document_sections(document) {
+                    Some(sections) => (sections, separators),
+                    None if html => (html_sections(body), separators),
+                    None => (markdown_sections(body), separators),
+                }
+            }
+        };
+
+        let mut chunks = Vec::new();
+        for section in sections {
+            for byte_range in split_range(body, section.byte_range, self.max_chunk_size, separators)
+            {
+                if let Some(byte_range) = trim_range(body, byte_range) {
+                    chunks.push(HeadingChunk {
+                        byte_range,
+                        heading_path: section.heading_path.clone(),
+                    });
+                }
+            }
+        }
+        self.merge_small_chunks(chunks)
+    }
+
+    /// Merge chunks smaller than the minimum size with the next chunk if both are in the same section or in sibling sections under the same parent heading.
+    fn merge_small_chunks(&self, chunks: Vec<HeadingChunk>) -> Vec<HeadingChunk> {
+        let mut merged: Vec<HeadingChunk> = Vec::new();
+        for chunk in chunks {
+            if let Some(last) = merged.last_mut() {
+                let small = last.byte_range.len() < self.min_chunk_size
+                    || chunk.byte_range.len() < self.min_chunk_size;
+                let fits = chunk.byte_range.end - last.byte_range.start <= self.max_chunk_size;
+                // Only chunks of the same section or sibling sections are merged
+                let depth = last.heading_path.len();
+                let same_parent = depth == chunk.heading_path.len()
+                    && last.heading_path[..depth.saturating_sub(1)]
+                        == chunk.heading_path[..depth.saturating_sub(1)];
+                if small && fits && same_parent {
+                    last.byte_range.end = chunk.byte_range.end;
+                    if last.heading_path != chunk.heading_path {
+                        last.heading_path.pop();
+                    }
+                    continue;
+                }
+            }
+            merged.push(chunk);
+        }
+        merged
+    }
+
+    /// The text that is embedded for a chunk.
+    fn embedding_text(&self, body: &str, chunk: &HeadingChunk) -> String {
+        let text = &body[chunk.byte_range.clone()];
+        if self.heading_prefix && !chunk.heading_path.is_empty() {
+            format!("{}\n\n{text}", chunk.heading_path.join(" > "))
+        } else {
+            text.to_string()
+        }
+    }
+
+    /// Split a document into chunks with the text that is embedded for each chunk.
+    fn pending_chunks(&self, document: &Document) -> Vec<PendingChunk> {
+        self.split(document)
+            .into_iter()
+            .map(|chunk| PendingChunk {
+                text: self.embedding_text(document.body(), &chunk),
+                byte_range: chunk.byte_range,
+                metadata: Default::default(),
+            })
+            .collect()
+    }
+}
+
+impl Chunker for HeadingChunker {
+    async fn chunk<E: Embedder + Send>(
+        &self,
+        document: &Document,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Chunk<E::VectorSpace>>> {
+        embed_document_chunks(self.pending_chunks(document), embedder).await
+    }
+
+    async fn chunk_batch<'a, I, E: Embedder + Send>(
+        &self,
+        documents: I,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Vec<Chunk<E::VectorSpace>>>>
+    where
+        I: IntoIterator<Item = &'a Document> + Send,
+        I::IntoIter: Send,
+    {
+        let documents = documents
+            .into_iter()
+            .map(|document| self.pending_chunks(document))
+            .collect();
+        embed_chunks(documents, embedder).await
+    }
+
+    fn chunk_ranges<E: Embedder>(&self, document: &Document, _: &E) -> Option<Vec<Range<usize>>> {
+        Some(
+            self.split(document)
+                .into_iter()
+                .map(|chunk| chunk.byte_range)
+                .collect(),
+        )
+    }
+}
+
+/// Use the heading paths the document was loaded with, if it has any.
+fn document_sections(document: &Document) -> Option<Vec<HeadingChunk>> {
+    let mut sections = document
+        .sections()
+        .iter()
+        .filter(|section| section.byte_range.end <= document.body().len())
+        .collect::<Vec<_>>();
+    if sections
+        .iter()
+        .all(|section| section.heading_path.is_empty())
+    {
+        return None;
+    }
+    sections.sort_by_key(|section| section.byte_range.start);
+
+    // Keep any text that is not in a section, like an introduction before the first heading
+    let mut chunks = Vec::new();
+    let mut end = 0;
+    for section in sections {
+        if section.byte_range.start < end {
+            continue;
+        }
+        if section.byte_range.start > end {
+            chunks.push(HeadingChunk {
+                byte_range: end..section.byte_range.start,
+                heading_path: Vec::new(),
+            });
+        }
+        chunks.push(HeadingChunk {
+            byte_range: section.byte_range.clone(),
+            heading_path: section.heading_path.clone(),
+        });
+        end = section.byte_range.end;
+    }
+    if end < document.body().len() {
+        chunks.push(HeadingChunk {
+            byte_range: end..document.body().len(),
+            heading_path: Vec::new(),
+        });
+    }
+    Some(chunks)
+}
+
+/// Split Markdown into sections that start at each heading.
+fn markdown_sections(body: &str) -> Vec<HeadingChunk> {
+    let mut sections = HeadingSections::default();
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    // The start and text of the previous line if it could be the text of an underlined heading
+    let mut previous: Option<(usize, &str)> = None;
+    for line in body.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let content = line.trim();
+
+        if let Some(marker) = fence {
+            if content.starts_with(marker) {
+                fence = None;
+            }
+            previous = None;
+            continue;
+        }
+        if content.starts_with("```") || content.starts_with("~~~") {
+            fence = Some(&content[..3]);
+            previous = None;
+            continue;
+        }
+
+        let indent = line.len() - line.trim_start().len();
+        let level = content.bytes().take_while(|byte| *byte == b'#').count();
+        if indent < 4
+            && (1..=6).contains(&level)
+            && (content.len() == level || content[level..].starts_with([' ', '\t']))
+        {
+            let text = content[level..].trim().trim_end_matches('#').trim();
+            sections.heading(line_start, level, text);
+            previous = None;
+            continue;
+        }
+
+        if let Some((previous_start, text)) = previous {
+            let underline = content.len() > 1
+                && (content.bytes().all(|byte| byte == b'=')
+                    || content.bytes().all(|byte| byte == b'-'));
+            if underline {
+                let level = if content.starts_with('=') { 1 } else { 2 };
+                sections.heading(previous_start, level, text);
+                previous = None;
+                continue;
+            }
+        }
+        previous = (!content.is_empty()).then_some((line_start, content));
+    }
+    sections.finish(body.len())
+}
+
+/// Split HTML into sections that start at each `<h1>` to `<h6>` tag.
+fn html_sections(body: &str) -> Vec<HeadingChunk> {
+    let mut sections = HeadingSections::default();
+    // Lowercasing ascii characters keeps the byte offsets the same
+    let lower = body.to_ascii_lowercase();
+    let mut index = 0;
+    while let Some(position) = lower[index..].find("<h") {
+        let start = index + position;
+        index = start + 2;
+        let bytes = lower.as_bytes();
+        let Some(level @ b'1'..=b'6') = bytes.get(start + 2).copied() else {
+            continue;
+        };
+        if !matches!(bytes.get(start + 3), Some(b'>' | b' ' | b'\t' | b'\n')) {
+            continue;
+        }
+        let Some(open_end) = lower[start..].find('>').map(|end| start + end + 1) else {
+            continue;
+        };
+        let close = format!("</h{}>", level as char);
+        let Some(close_start) = lower[open_end..].find(&close).map(|end| open_end + end) else {
+            continue;
+        };
+        let text = html_text(&body[open_end..close_start]);
+        sections.heading(start, (level - b'0') as usize, &text);
+        index = close_start + close.len();
+    }
+    sections.finish(body.len())
+}
+
+/// Remove the tags from a fragment of HTML and decode common entities.
+fn html_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for character in html.chars() {
+        match character {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            character if !in_tag => text.push(character),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Builds sections from the headings of a document.
+#[derive(Default)]
+struct HeadingSections {
+    sections: Vec<HeadingChunk>,
+    heading_path: Vec<(usize, String)>,
+    start: usize,
+}
+
+impl HeadingSections {
+    /// Start a new section at a heading.
+    fn heading(&mut self, start: usize, level: usize, text: &str) {
+        self.close(start);
+        self.heading_path.retain(|(parent, _)| *parent < level);
+        self.heading_path.push((level, text.to_string()));
+        self.start = start;
+    }
+
+    fn close(&mut self, end: usize) {
+        if end > self.start {
+            self.sections.push(HeadingChunk {
+                byte_range: self.start..end,
+                heading_path: self
+                    .heading_path
+                    .iter()
+                    .map(|(_, text)| text.clone())
+                    .collect(),
+            });
+        }
+    }
+
+    fn finish(mut self, end: usize) -> Vec<HeadingChunk> {
+        self.close(end);
+        self.sections
+    }
+}
+
+/// Split a range into pieces no larger than `max` bytes. Each separator is tried in order before falling back to splitting at any character.
+fn split_range(
+    body: &str,
+    range: Range<usize>,
+    max: usize,
+    separators: &[&str],
+) -> Vec<Range<usize>> {
+    if range.len() <= max {
+        return vec![range];
+    }
+    let Some((separator, rest)) = separators.split_first() else {
+        let mut pieces = Vec::new();
+        let mut start = range.start;
+        while start < range.end {
+            let mut end = (start + max).min(range.end);
+            while !body.is_char_boundary(end) {
+                end -= 1;
+            }
+            if end == start {
+                end = start
+                    + body[start..]
+                        .chars()
+                        .next()
+                        .map(char::len_utf8)
+                        .unwrap_or(1);
+            }
+            pieces.push(start..end);
+            start = end;
+        }
+        return pieces;
+    };
+
+    // Split after each separator
+    let mut pieces = Vec::new();
+    let mut start = range.start;
+    for (index, _) in body[range.clone()].match_indices(separator) {
+        let end = range.start + index + separator.len();
+        if end > start {
+            pieces.push(start..end);
+            start = end;
+        }
+    }
+    if start < range.end {
+        pieces.push(start..range.end);
+    }
+    if pieces.len() == 1 {
+        return split_range(body, range, max, rest);
+    }
+
+    // Then pack as many pieces as fit into each chunk
+    let mut packed = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    for piece in pieces {
+        for piece in split_range(body, piece, max, rest) {
+            current = match current {
+                Some(current) if piece.end - current.start <= max => Some(current.start..piece.end),
+                Some(current) => {
+                    packed.push(current);
+                    Some(piece)
+                }
+                None => Some(piece),
+            };
+        }
+    }
+    packed.extend(current);
+    packed
+}
+
+/// Remove the whitespace around a range. Returns `None` if the range only contains whitespace.
+pub(super) fn trim_range(body: &str, range: Range<usize>) -> Option<Range<usize>> {
+    let text = &body[range.clone()];
+    let trimmed = text.trim_start();
+    let start = range.start + text.len() - trimmed.len();
+    let end = start + trimmed.trim_end().len();
+    (end > start).then_some(start..end)
+}
+
+#[test]
+fn heading_chunker_splits_markdown_sections() {
+    let body = "# Guide\n\nIntro text.\n\n## Install\n\nRun the installer.\n\n```sh\n# not a heading\n```\n\n### Linux\n\nUse apt to install the package. Then reboot the machine.\n\nUsage\n-----\n\nOpen the app.";
+    let document = Document::from_parts("Guide", body);
+    let chunker = HeadingChunker::new()
+        .with_min_chunk_size(0)
+        .with_max_chunk_size(40);
+    let chunks = chunker
+        .split(&document)
+        .into_iter()
+        .map(|chunk| (&body[chunk.byte_range], chunk.heading_path.join(" > ")))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            ("# Guide\n\nIntro text.", "Guide".to_string()),
+            ("## Install\n\nRun the installer.", "Guide > Install".into()),
+            ("```sh\n# not a heading\n```", "Guide > Install".into()),
+            ("### Linux", "Guide > Install > Linux".into()),
+            (
+                "Use apt to install the package.",
+                "Guide > Install > Linux".into()
+            ),
+            ("Then reboot the machine.", "Guide > Install > Linux".into()),
+            ("Usage\n-----\n\nOpen the app.", "Guide > Usage".into()),
+        ]
+    );
+
+    // Small sections are never merged across sections at different depths
+    let chunks = HeadingChunker::new()
+        .with_min_chunk_size(30)
+        .split(&document)
+        .into_iter()
+        .map(|chunk| chunk.heading_path.join(" > "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            "Guide",
+            "Guide > Install",
+            "Guide > Install > Linux",
+            "Guide > Usage"
+        ]
+    );
+
+    // Small sibling sections are merged under their parent heading
+    let body = "# Guide\n\nIntro text.\n\n## Install\n\nRun it.\n\n## Usage\n\nOpen it.\n\n# Reference\n\nSee the docs for every option.";
+    let document = Document::from_parts("Guide", body);
+    let chunks = HeadingChunker::new()
+        .with_min_chunk_size(30)
+        .split(&document)
+        .into_iter()
+        .map(|chunk| (&body[chunk.byte_range], chunk.heading_path.join(" > ")))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            ("# Guide\n\nIntro text.", "Guide".to_string()),
+            (
+                "## Install\n\nRun it.\n\n## Usage\n\nOpen it.",
+                "Guide".into()
+            ),
+            (
+                "# Reference\n\nSee the docs for every option.",
+                "Reference".into()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn heading_chunker_splits_html_sections() {
+    let body = "<h1>Guide</h1><p>Intro.</p><h2 title=\"x\">Install &amp; setup</h2><p>Run it.</p><h3>Linux</h3><p>Use apt.</p><h2>Usage</h2><p>Open it.</p>";
+    let document = Document::from_parts("Guide", body);
+    let chunks = HeadingChunker::new()
+        .with_min_chunk_size(0)
+        .split(&document)
+        .into_iter()
+        .map(|chunk| (&body[chunk.byte_range], chunk.heading_path))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            ("<h1>Guide</h1><p>Intro.</p>", vec!["Guide".to_string()]),
+            (
+                "<h2 title=\"x\">Install &amp; setup</h2><p>Run it.</p>",
+                vec!["Guide".into(), "Install & setup".into()]
+            ),
+            (
+                "<h3>Linux</h3><p>Use apt.</p>",
+                vec!["Guide".into(), "Install & setup".into(), "Linux".into()]
+            ),
+            (
+                "<h2>Usage</h2><p>Open it.</p>",
+                vec!["Guide".into(), "Usage".into()]
+            ),
+        ]
+    );
+}
@@ -203,10 +203,7 @@ where
                 }
             }
             remaining_embeddings -= 1;
-            chunks.push(Chunk {
-                byte_range: byte_chunk.clone(),
-                embeddings: vec![embedding],
-            });
+            chunks.push(Chunk::new(byte_chunk.clone(), vec![embedding]));
         }
 
         Ok(chunks)
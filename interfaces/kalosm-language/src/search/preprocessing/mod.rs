@@ -12,14 +12,19 @@
 // 2. Dump all sentences that mention an entity
 // 3. Extract relevant sentences with an llm
 
+use std::ops::Range;
+
 use kalosm_language_model::Embedder;
 
 use crate::context::Document;
+use crate::prelude::Metadata;
 
 use super::Chunk;
 
 mod chunking;
 pub use chunking::*;
+mod code;
+pub use code::*;
 mod hypothetical;
 pub use hypothetical::*;
 mod summary;
@@ -28,8 +33,13 @@ mod sentence;
 pub use sentence::*;
 mod semantic;
 pub use semantic::*;
+mod heading;
+pub use heading::*;
+mod token;
+pub use token::*;
 mod html;
 pub use html::*;
+mod words;
 
 /// A strategy for chunking a document into smaller pieces.
 pub trait Chunker {
@@ -40,6 +50,16 @@ pub trait Chunker {
         embedder: &E,
     ) -> impl std::future::Future<Output = anyhow::Result<Vec<Chunk<E::VectorSpace>>>> + Send;
 
+    /// Split a document into the byte ranges of its chunks without embedding them. Chunkers that need embeddings to find the chunk boundaries return `None`. (default: `None`)
+    fn chunk_ranges<E: Embedder>(
+        &self,
+        document: &Document,
+        embedder: &E,
+    ) -> Option<Vec<Range<usize>>> {
+        let _ = (document, embedder);
+        None
+    }
+
     /// Chunk a batch of documents into embedded snippets.
     fn chunk_batch<'a, I, E: Embedder + Send>(
         &self,
@@ -60,3 +80,51 @@ pub trait Chunker {
         }
     }
 }
+
+/// A chunk of a document that is ready to be embedded.
+pub(super) struct PendingChunk {
+    /// The byte range of the chunk in the body of the document.
+    pub(super) byte_range: Range<usize>,
+    /// The text that is embedded for the chunk.
+    pub(super) text: String,
+    /// The metadata stored with the chunk.
+    pub(super) metadata: Metadata,
+}
+
+/// Embed the chunks of a batch of documents with a single call to the embedder.
+pub(super) async fn embed_chunks<E: Embedder>(
+    documents: Vec<Vec<PendingChunk>>,
+    embedder: &E,
+) -> anyhow::Result<Vec<Vec<Chunk<E::VectorSpace>>>> {
+    let texts = documents
+        .iter()
+        .flatten()
+        .map(|chunk| chunk.text.clone())
+        .collect();
+    let mut embeddings = embedder.embed_vec(texts).await?.into_iter();
+    documents
+        .into_iter()
+        .map(|chunks| {
+            chunks
+                .into_iter()
+                .map(|chunk| {
+                    let embedding = embeddings
+                        .next()
+                        .ok_or_else(|| anyhow::anyhow!("Missing embedding for chunk"))?;
+                    Ok(Chunk::new(chunk.byte_range, vec![embedding]).with_metadata(chunk.metadata))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Embed the chunks of one document.
+pub(super) async fn embed_document_chunks<E: Embedder>(
+    chunks: Vec<PendingChunk>,
+    embedder: &E,
+) -> anyhow::Result<Vec<Chunk<E::VectorSpace>>> {
+    Ok(embed_chunks(vec![chunks], embedder)
+        .await?
+        .pop()
+        .unwrap_or_default())
+}
@@ -228,10 +228,7 @@ impl Chunker for SemanticChunker {
             let SemanticChunk {
                 range, embedding, ..
             } = chunk;
-            final_chunks.push(Chunk {
-                byte_range: range,
-                embeddings: vec![embedding],
-            });
+            final_chunks.push(Chunk::new(range, vec![embedding]));
         }
 
         Ok(final_chunks)
@@ -2,15 +2,20 @@ use crate::prelude::{Chunk, Chunker, Document, Embedder};
 use srx::SRX;
 use std::cell::OnceCell;
 use std::future::Future;
+use std::ops::Range;
 use std::rc::Rc;
 use std::str::FromStr;
+use whatlang::Lang;
 
 /// A [`Chunker`] that splits a string into sentences with a given [SRX](https://www.unicode.org/uli/pas/srx/srx20.html) rules.
 ///
+/// The language of the text is detected with [whatlang](https://crates.io/crates/whatlang) and the SRX rules for that language are used, so German abbreviations or Japanese and Chinese sentence endings like `。` are handled correctly. Set the language with [`SentenceChunker::with_language`] if every document is in the same language. Sentences in Chinese, Japanese and Korean are also split after their end punctuation when the rules of the language miss it, like in Korean or in CJK text inside a document in another language.
+///
 /// This uses the [srx](https://crates.io/crates/srx) crate to parse and apply the rules.
 #[derive(Debug, Clone)]
 pub struct SentenceChunker {
     srx: Rc<SRX>,
+    language: Option<Lang>,
 }
 
 impl SentenceChunker {
@@ -20,6 +25,7 @@ impl SentenceChunker {
             srx: SRX::from_str(rules)
                 .expect("the rules file is valid")
                 .into(),
+            language: None,
         }
     }
 
@@ -27,20 +33,196 @@ impl SentenceChunker {
     pub fn load(reader: impl std::io::Read) -> Result<Self, srx::Error> {
         Ok(Self {
             srx: SRX::from_reader(reader)?.into(),
+            language: None,
         })
     }
 
+    /// Use the rules for a language for every string instead of detecting the language of each string.
+    pub fn with_language(mut self, language: Lang) -> Self {
+        self.language = Some(language);
+        self
+    }
+
     /// Split the body of a document into a list of ranges with sentences
-    pub fn split_sentences(&self, string: &str) -> Vec<std::ops::Range<usize>> {
+    pub fn split_sentences(&self, string: &str) -> Vec<Range<usize>> {
         // Try to autodetect the language of the document
-        let language = whatlang::detect_lang(string)
-            .map(|lang_code| lang_code.code())
-            .unwrap_or("en");
+        let language = self
+            .language
+            .or_else(|| whatlang::detect_lang(string))
+            .unwrap_or(Lang::Eng);
 
-        // Then get the language specific rules to split the document into sentences
-        let rules = self.srx.language_rules(language);
+        self.split_sentences_in(string, language)
+    }
 
-        rules.split_ranges(string)
+    /// Split the body of a document into sentences with the language of the chunker or the document
+    fn document_sentences(&self, document: &Document) -> Vec<Range<usize>> {
+        let body = document.body();
+        match self.language.or_else(|| document.language()) {
+            Some(language) => self.split_sentences_in(body, language),
+            None => self.split_sentences(body),
+        }
+    }
+
+    /// Split a string in a known language into a list of ranges with sentences
+    pub fn split_sentences_in(&self, string: &str, language: Lang) -> Vec<Range<usize>> {
+        // The rules in SRX files are keyed by two letter language codes
+        let rules = self.srx.language_rules(iso_639_1(language));
+
+        split_cjk_sentences(string, rules.split_ranges(string))
+    }
+}
+
+/// Split sentence ranges again after the punctuation that ends a Chinese, Japanese or Korean sentence. Korean and CJK text in a document in another language are not split by the SRX rules, which look for a capital letter at the start of the next sentence.
+fn split_cjk_sentences(text: &str, ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut split = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let mut start = range.start;
+        let mut chars = text[range.clone()]
+            .char_indices()
+            .map(|(index, c)| (range.start + index, c))
+            .peekable();
+        while let Some((index, c)) = chars.next() {
+            let full_width = matches!(c, '。' | '！' | '？' | '｡' | '．');
+            if !full_width && !matches!(c, '.' | '!' | '?') {
+                continue;
+            }
+            // The sentence ends after any more end punctuation, closing quotes and whitespace
+            let mut end = index + c.len_utf8();
+            let mut space = false;
+            while let Some(&(index, c)) = chars.peek() {
+                let closing = matches!(
+                    c,
+                    '。' | '！'
+                        | '？'
+                        | '｡'
+                        | '．'
+                        | '.'
+                        | '!'
+                        | '?'
+                        | '」'
+                        | '』'
+                        | '）'
+                        | '】'
+                        | '〕'
+                        | ')'
+                        | '"'
+                        | '\''
+                        | '”'
+                        | '’'
+                );
+                if (closing && !space) || c.is_whitespace() {
+                    space |= c.is_whitespace();
+                    end = index + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let Some(&(_, next)) = chars.peek() else {
+                break;
+            };
+            // Half width punctuation only ends a sentence before a space and more CJK text
+            if full_width || (space && is_cjk(next)) {
+                split.push(start..end);
+                start = end;
+            }
+        }
+        if start < range.end {
+            split.push(start..range.end);
+        }
+    }
+    split
+}
+
+/// Check if a character is Chinese, Japanese or Korean.
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c,
+        '\u{1100}'..='\u{11FF}'
+            | '\u{3040}'..='\u{30FF}'
+            | '\u{3130}'..='\u{318F}'
+            | '\u{31F0}'..='\u{31FF}'
+            | '\u{3400}'..='\u{4DBF}'
+            | '\u{4E00}'..='\u{9FFF}'
+            | '\u{AC00}'..='\u{D7AF}'
+            | '\u{F900}'..='\u{FAFF}'
+            | '\u{FF66}'..='\u{FF9F}'
+            | '\u{20000}'..='\u{2FA1F}'
+    )
+}
+
+/// The ISO 639-1 code of a language.
+fn iso_639_1(language: Lang) -> &'static str {
+    match language {
+        Lang::Epo => "eo",
+        Lang::Eng => "en",
+        Lang::Rus => "ru",
+        Lang::Cmn => "zh",
+        Lang::Spa => "es",
+        Lang::Por => "pt",
+        Lang::Ita => "it",
+        Lang::Ben => "bn",
+        Lang::Fra => "fr",
+        Lang::Deu => "de",
+        Lang::Ukr => "uk",
+        Lang::Kat => "ka",
+        Lang::Ara => "ar",
+        Lang::Hin => "hi",
+        Lang::Jpn => "ja",
+        Lang::Heb => "he",
+        Lang::Yid => "yi",
+        Lang::Pol => "pl",
+        Lang::Amh => "am",
+        Lang::Jav => "jv",
+        Lang::Kor => "ko",
+        Lang::Nob => "nb",
+        Lang::Dan => "da",
+        Lang::Swe => "sv",
+        Lang::Fin => "fi",
+        Lang::Tur => "tr",
+        Lang::Nld => "nl",
+        Lang::Hun => "hu",
+        Lang::Ces => "cs",
+        Lang::Ell => "el",
+        Lang::Bul => "bg",
+        Lang::Bel => "be",
+        Lang::Mar => "mr",
+        Lang::Kan => "kn",
+        Lang::Ron => "ro",
+        Lang::Slv => "sl",
+        Lang::Hrv => "hr",
+        Lang::Srp => "sr",
+        Lang::Mkd => "mk",
+        Lang::Lit => "lt",
+        Lang::Lav => "lv",
+        Lang::Est => "et",
+        Lang::Tam => "ta",
+        Lang::Vie => "vi",
+        Lang::Urd => "ur",
+        Lang::Tha => "th",
+        Lang::Guj => "gu",
+        Lang::Uzb => "uz",
+        Lang::Pan => "pa",
+        Lang::Aze => "az",
+        Lang::Ind => "id",
+        Lang::Tel => "te",
+        Lang::Pes => "fa",
+        Lang::Mal => "ml",
+        Lang::Ori => "or",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Sin => "si",
+        Lang::Khm => "km",
+        Lang::Tuk => "tk",
+        Lang::Aka => "ak",
+        Lang::Zul => "zu",
+        Lang::Sna => "sn",
+        Lang::Afr => "af",
+        Lang::Lat => "la",
+        Lang::Slk => "sk",
+        Lang::Cat => "ca",
+        Lang::Tgl => "tl",
+        Lang::Hye => "hy",
     }
 }
 
@@ -62,7 +244,10 @@ impl Default for SentenceChunker {
                 .clone()
         });
 
-        Self { srx: rules }
+        Self {
+            srx: rules,
+            language: None,
+        }
     }
 }
 
@@ -77,7 +262,7 @@ impl Chunker for SentenceChunker {
         // Split the document into sentences. We first just collect the sentences as strings and byte ranges
         let mut initial_chunks = Vec::new();
         let body = document.body();
-        let ranges = self.split_sentences(document.body());
+        let ranges = self.document_sentences(document);
         for chunk in &ranges {
             initial_chunks.push(body[chunk.clone()].to_string());
         }
@@ -89,14 +274,63 @@ impl Chunker for SentenceChunker {
             // Now merge the embeddings and ranges into chunks
             let mut chunks = Vec::new();
             for (embedding, chunk) in embeddings.into_iter().zip(ranges) {
-                let chunk = Chunk {
-                    byte_range: chunk,
-                    embeddings: vec![embedding],
-                };
+                let chunk = Chunk::new(chunk, vec![embedding]);
                 chunks.push(chunk);
             }
 
             Ok(chunks)
         }
     }
+
+    fn chunk_ranges<E: Embedder>(&self, document: &Document, _: &E) -> Option<Vec<Range<usize>>> {
+        Some(self.document_sentences(document))
+    }
+}
+
+#[test]
+fn sentence_chunker_uses_language_rules() {
+    let chunker = SentenceChunker::default();
+    let sentences = |text: &str, language: Option<Lang>| {
+        let ranges = match language {
+            Some(language) => chunker.split_sentences_in(text, language),
+            None => chunker.split_sentences(text),
+        };
+        ranges
+            .into_iter()
+            .map(|range| text[range].trim().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        sentences(
+            "今日は晴れです。明日は雨が降るでしょう。傘を持って行きます！",
+            None
+        ),
+        [
+            "今日は晴れです。",
+            "明日は雨が降るでしょう。",
+            "傘を持って行きます！"
+        ]
+    );
+    assert_eq!(
+        sentences("我喜欢学习中文。你呢？我们一起学习吧。", None),
+        ["我喜欢学习中文。", "你呢？", "我们一起学习吧。"]
+    );
+    assert_eq!(
+        sentences(
+            "오늘은 날씨가 좋습니다. 내일은 비가 올 것 같아요.",
+            Some(Lang::Kor)
+        ),
+        ["오늘은 날씨가 좋습니다.", "내일은 비가 올 것 같아요."]
+    );
+    // German ordinals like "3. Oktober" don't end a sentence
+    let german = "Der Termin ist am 3. Oktober. Bitte bringen Sie die Unterlagen mit.";
+    assert_eq!(
+        sentences(german, Some(Lang::Deu)),
+        [
+            "Der Termin ist am 3. Oktober.",
+            "Bitte bringen Sie die Unterlagen mit."
+        ]
+    );
+    assert_eq!(sentences(german, Some(Lang::Eng)).len(), 3);
 }
@@ -1,12 +1,19 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::{Stream, StreamExt};
 use kalosm_language_model::{Embedder, Model, SyncModel};
-use kalosm_sample::{LiteralParser, ParserExt};
+use kalosm_sample::{LiteralParser, Parse, ParserExt, Schema};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 
 use crate::{
     prelude::{Document, OneLine, StructuredRunner, Task},
     search::Chunk,
 };
 
-use super::{ChunkStrategy, Chunker};
+use super::token::tokens_in;
+use super::{ChunkStrategy, Chunker, TokenChunker};
 
 const TASK_DESCRIPTION: &str = "You generate summaries of the given text.";
 
@@ -18,13 +25,19 @@ pub struct Summarizer {
     task: Task<StructuredRunner<Constraints>>,
 }
 
+fn summary_task() -> Task<StructuredRunner<Constraints>> {
+    Task::builder(TASK_DESCRIPTION)
+        .with_constraints(LiteralParser::new("Summary: ").then(OneLine))
+        .build()
+}
+
 impl Summarizer {
     /// Create a new summary generator.
     pub fn new(chunking: Option<ChunkStrategy>) -> Self {
-        let task = Task::builder(TASK_DESCRIPTION)
-            .with_constraints(LiteralParser::new("Summary: ").then(OneLine))
-            .build();
-        Self { chunking, task }
+        Self {
+            chunking,
+            task: summary_task(),
+        }
     }
 
     /// Generate a summary for a document.
@@ -100,11 +113,421 @@ where
                 byte_chunk = byte_chunks.next().unwrap();
             }
             remaining_embeddings -= 1;
-            chunks.push(Chunk {
-                byte_range: byte_chunk.clone(),
-                embeddings: vec![embedding],
-            });
+            chunks.push(Chunk::new(byte_chunk.clone(), vec![embedding]));
         }
         Ok(chunks)
     }
 }
+
+/// Summarizes documents that are too long for one call to the model. The text is split into chunks that fit in the token budget and each chunk is summarized. Then the summaries are grouped into parts that fit in the budget and summarized again, until one last call can read every summary and write the final summary.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let llm = Llama::new_chat().await?;
+///     let book = std::fs::read_to_string("book.txt")?;
+///     let summarizer = HierarchicalSummarizer::new()
+///         .with_max_input_tokens(2048)
+///         .with_target_words(300)
+///         .with_focus("the main characters");
+///
+///     let mut summary = summarizer.summarize(&book, &llm);
+///     while let Some(progress) = summary.next().await {
+///         println!(
+///             "level {}: {}/{}",
+///             progress.level,
+///             progress.index + 1,
+///             progress.total
+///         );
+///     }
+///     println!("{}", summary.await?);
+///     Ok(())
+/// }
+/// ```
+pub struct HierarchicalSummarizer {
+    max_input_tokens: usize,
+    target_words: usize,
+    intermediate_words: usize,
+    focus: Option<String>,
+    task: Task<StructuredRunner<Constraints>>,
+}
+
+impl Default for HierarchicalSummarizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HierarchicalSummarizer {
+    /// Create a new hierarchical summarizer with a budget of 2048 tokens per call and a final summary of about 200 words.
+    pub fn new() -> Self {
+        Self {
+            max_input_tokens: 2048,
+            target_words: 200,
+            intermediate_words: 150,
+            focus: None,
+            task: summary_task(),
+        }
+    }
+
+    /// Set the maximum number of tokens given to the model in each call, including the instructions and focus of the prompt. (default: 2048)
+    pub fn with_max_input_tokens(mut self, max_input_tokens: usize) -> Self {
+        self.max_input_tokens = max_input_tokens.max(1);
+        self
+    }
+
+    /// Set the length of the final summary in words. The model is asked to write a summary of about this length. (default: 200)
+    pub fn with_target_words(mut self, target_words: usize) -> Self {
+        self.target_words = target_words;
+        self
+    }
+
+    /// Set the length in words of the summaries of chunks and groups of summaries before the final summary. Shorter summaries fit more of the document in each call. (default: 150)
+    pub fn with_intermediate_words(mut self, intermediate_words: usize) -> Self {
+        self.intermediate_words = intermediate_words;
+        self
+    }
+
+    /// Focus every summary on the information relevant to a query.
+    pub fn with_focus(mut self, focus: impl ToString) -> Self {
+        self.focus = Some(focus.to_string());
+        self
+    }
+
+    /// Summarize a text. The returned stream reports each summary as it is written, and awaiting it returns the final summary.
+    pub fn summarize<'a, M>(&'a self, text: &'a str, model: &'a M) -> SummaryStream<'a, String>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+    {
+        SummaryStream::new(move |progress| async move {
+            let (level, prompt) = self.reduce(text, model, &progress).await?;
+            let summary = self.task.run(prompt, model).result().await?.1;
+            let summary = summary.trim().to_string();
+            let _ = progress.send(SummaryProgress {
+                level,
+                index: 0,
+                total: 1,
+                summary: summary.clone(),
+            });
+            Ok(summary)
+        })
+    }
+
+    /// Summarize a text into a structured type. Only the final summary is structured. The summaries before it are text.
+    pub fn summarize_structured<'a, M, P>(
+        &'a self,
+        text: &'a str,
+        model: &'a M,
+    ) -> SummaryStream<'a, P>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+        P: Parse + Schema + 'static,
+    {
+        SummaryStream::new(move |progress| async move {
+            let (level, prompt) = self.reduce(text, model, &progress).await?;
+            let task = Task::builder_for::<P>(TASK_DESCRIPTION).build();
+            let (mut stream, result) = task.run(prompt, model).split();
+            let mut summary = String::new();
+            while let Some(token) = stream.next().await {
+                summary.push_str(&token);
+            }
+            let output = result.await??;
+            let _ = progress.send(SummaryProgress {
+                level,
+                index: 0,
+                total: 1,
+                summary,
+            });
+            Ok(output)
+        })
+    }
+
+    /// Summarize the text until everything left fits in one call. Returns the level and prompt of the final summary.
+    async fn reduce<M>(
+        &self,
+        text: &str,
+        model: &M,
+        progress: &UnboundedSender<SummaryProgress>,
+    ) -> anyhow::Result<(usize, String)>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Sync + Send,
+    {
+        let tokenizer = model.tokenizer();
+        let tokenize = |text: &str| token_starts(&tokenizer, text);
+        // The instructions of the prompt take up part of the budget of each call
+        let text_budget = |words: usize, combine: bool| {
+            let prompt_tokens = tokenize(&self.prompt("", words, combine)).len();
+            self.max_input_tokens.saturating_sub(prompt_tokens).max(1)
+        };
+
+        // Tokenize the text once and count the tokens in each part from the offsets
+        let text_tokens = tokenize(text);
+        if text_tokens.len() <= text_budget(self.target_words, false) {
+            return Ok((0, self.prompt(text, self.target_words, false)));
+        }
+
+        let part_budget = text_budget(self.intermediate_words, false);
+        let mut parts = TokenChunker::new()
+            .with_target_tokens(part_budget)
+            .with_max_tokens(part_budget)
+            .with_overlap_tokens(0)
+            .split(text, None, |range| tokens_in(&text_tokens, range))
+            .into_iter()
+            .map(|range| text[range].to_string())
+            .collect::<Vec<_>>();
+        let mut level = 0;
+        loop {
+            let total = parts.len();
+            let mut summaries = Vec::with_capacity(total);
+            for (index, part) in parts.iter().enumerate() {
+                let prompt = self.prompt(part, self.intermediate_words, level > 0);
+                let summary = self.task.run(prompt, model).result().await?.1;
+                let summary = summary.trim().to_string();
+                let _ = progress.send(SummaryProgress {
+                    level,
+                    index,
+                    total,
+                    summary: summary.clone(),
+                });
+                summaries.push(summary);
+            }
+            level += 1;
+
+            let combine_budget = text_budget(self.intermediate_words.max(self.target_words), true);
+            parts = group_by_tokens(&summaries, combine_budget, tokenize);
+            if let [part] = parts.as_slice() {
+                return Ok((level, self.prompt(part, self.target_words, true)));
+            }
+            if parts.len() >= summaries.len() {
+                anyhow::bail!(
+                    "The summaries don't fit in fewer calls with {} tokens per call. Increase the maximum input tokens or decrease the intermediate words",
+                    self.max_input_tokens
+                );
+            }
+        }
+    }
+
+    fn prompt(&self, text: &str, words: usize, combine: bool) -> String {
+        let mut prompt = if combine {
+            format!("The following are summaries of consecutive parts of a longer document. Combine them into one summary of about {words} words.")
+        } else {
+            format!("Generate a summary of about {words} words of the following text.")
+        };
+        if let Some(focus) = &self.focus {
+            prompt.push_str(&format!(" Focus on the information relevant to: {focus}"));
+        }
+        format!("{prompt}\n{text}")
+    }
+}
+
+/// Get the sorted start offsets of the tokens in text. If the text can't be tokenized, one token is estimated for every four bytes.
+fn token_starts(tokenizer: &tokenizers::Tokenizer, text: &str) -> Vec<usize> {
+    match tokenizer.encode(text, false) {
+        Ok(encoding) => {
+            let mut starts = encoding
+                .get_offsets()
+                .iter()
+                .map(|&(start, _)| start)
+                .collect::<Vec<_>>();
+            starts.sort_unstable();
+            starts
+        }
+        Err(_) => (0..text.len()).step_by(4).collect(),
+    }
+}
+
+/// Join consecutive summaries into parts that fit in the token budget. A summary that doesn't fit in the budget on its own is split between sentences. `token_starts` returns the start offsets of the tokens in text.
+fn group_by_tokens(
+    summaries: &[String],
+    max_tokens: usize,
+    token_starts: impl Fn(&str) -> Vec<usize>,
+) -> Vec<String> {
+    const SEPARATOR: &str = "\n\n";
+    let separator_tokens = token_starts(SEPARATOR).len();
+
+    // Split the summaries into pieces that each fit in the budget
+    let mut pieces = Vec::new();
+    for summary in summaries {
+        let tokens = token_starts(summary);
+        if tokens.len() <= max_tokens {
+            pieces.push((summary.as_str(), tokens.len()));
+            continue;
+        }
+        let ranges = TokenChunker::new()
+            .with_target_tokens(max_tokens)
+            .with_max_tokens(max_tokens)
+            .with_overlap_tokens(0)
+            .split(summary, None, |range| tokens_in(&tokens, range));
+        for range in ranges {
+            let piece_tokens = tokens_in(&tokens, range.clone());
+            pieces.push((&summary[range], piece_tokens));
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut current_tokens = 0;
+    for (piece, tokens) in pieces {
+        if !current.is_empty() && current_tokens + separator_tokens + tokens > max_tokens {
+            parts.push(current.join(SEPARATOR));
+            current.clear();
+        }
+        current_tokens = if current.is_empty() {
+            tokens
+        } else {
+            current_tokens + separator_tokens + tokens
+        };
+        current.push(piece);
+    }
+    if !current.is_empty() {
+        parts.push(current.join(SEPARATOR));
+    }
+    parts
+}
+
+/// A summary written by [`HierarchicalSummarizer`].
+#[derive(Debug, Clone)]
+pub struct SummaryProgress {
+    /// The level of the summary. Level 0 summarizes chunks of the text, and each level after that summarizes groups of summaries from the level before.
+    pub level: usize,
+    /// The index of the summary in its level.
+    pub index: usize,
+    /// The number of summaries in the level.
+    pub total: usize,
+    /// The text of the summary.
+    pub summary: String,
+}
+
+/// The progress and result of [`HierarchicalSummarizer::summarize`]. The stream reports each summary as it is written, and awaiting it returns the final summary.
+///
+/// The summaries are only written while the stream or future is polled.
+pub struct SummaryStream<'a, O> {
+    task: Option<Pin<Box<dyn Future<Output = anyhow::Result<O>> + Send + 'a>>>,
+    progress: UnboundedReceiver<SummaryProgress>,
+    result: Option<anyhow::Result<O>>,
+}
+
+impl<'a, O> SummaryStream<'a, O> {
+    fn new<F>(task: impl FnOnce(UnboundedSender<SummaryProgress>) -> F) -> Self
+    where
+        F: Future<Output = anyhow::Result<O>> + Send + 'a,
+    {
+        let (tx, rx) = unbounded_channel();
+        Self {
+            task: Some(Box::pin(task(tx))),
+            progress: rx,
+            result: None,
+        }
+    }
+
+    fn poll_task(&mut self, cx: &mut Context<'_>) {
+        if let Some(task) = &mut self.task {
+            if let Poll::Ready(result) = task.as_mut().poll(cx) {
+                self.result = Some(result);
+                self.task = None;
+            }
+        }
+    }
+
+    /// Get the final summary.
+    pub async fn result(self) -> anyhow::Result<O> {
+        self.await
+    }
+}
+
+impl<O> Unpin for SummaryStream<'_, O> {}
+
+impl<O> Stream for SummaryStream<'_, O> {
+    type Item = SummaryProgress;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        this.poll_task(cx);
+        match this.progress.poll_recv(cx) {
+            // The sender is dropped with the task, so the channel closes after the last summary is read
+            Poll::Pending if this.task.is_none() => Poll::Ready(None),
+            poll => poll,
+        }
+    }
+}
+
+impl<O> Future for SummaryStream<'_, O> {
+    type Output = anyhow::Result<O>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.poll_task(cx);
+        match this.result.take() {
+            Some(result) => Poll::Ready(result),
+            None if this.task.is_none() => {
+                Poll::Ready(Err(anyhow::anyhow!("The summary was already returned")))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test]
+fn group_by_tokens_fits_budget_and_shrinks() {
+    let word_starts = |text: &str| {
+        text.split_whitespace()
+            .map(|word| word.as_ptr() as usize - text.as_ptr() as usize)
+            .collect::<Vec<_>>()
+    };
+    let summaries = ["a b", "c d", "e f", "g h i j k", "l"]
+        .map(String::from)
+        .to_vec();
+    let parts = group_by_tokens(&summaries, 6, word_starts);
+    assert_eq!(parts, ["a b\n\nc d\n\ne f", "g h i j k\n\nl"]);
+    assert!(parts.iter().all(|part| word_starts(part).len() <= 6));
+
+    // Summaries larger than the budget are split instead of forced into a part
+    let parts = group_by_tokens(&summaries, 2, word_starts);
+    assert_eq!(parts, ["a b", "c d", "e f", "g h", "i j", "k\n\nl"]);
+    assert!(parts.iter().all(|part| word_starts(part).len() <= 2));
+}
+
+#[tokio::test]
+async fn summary_stream_reports_progress_before_the_result() {
+    let mut summary = SummaryStream::new(|progress| async move {
+        for index in 0..2 {
+            // Give the stream a chance to read the progress before the next summary is written
+            tokio::task::yield_now().await;
+            progress
+                .send(SummaryProgress {
+                    level: 0,
+                    index,
+                    total: 2,
+                    summary: format!("part {index}"),
+                })
+                .unwrap();
+        }
+        Ok("final")
+    });
+    let mut progress = Vec::new();
+    while let Some(part) = summary.next().await {
+        progress.push(part.summary);
+    }
+    assert_eq!(progress, ["part 0", "part 1"]);
+    assert_eq!(summary.await.unwrap(), "final");
+
+    // Awaiting the stream without reading the progress still runs the task
+    let summary = SummaryStream::new(|progress| async move {
+        progress
+            .send(SummaryProgress {
+                level: 0,
+                index: 0,
+                total: 1,
+                summary: "part".into(),
+            })
+            .unwrap();
+        Ok("final")
+    });
+    assert_eq!(summary.await.unwrap(), "final");
+}
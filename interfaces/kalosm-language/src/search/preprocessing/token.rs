@@ -0,0 +1,333 @@
+use kalosm_language_model::{Embedder, EmbeddingVariant};
+use std::ops::Range;
+
+use super::{
+    embed_chunks, embed_document_chunks, heading::trim_range, words::word_ranges, Chunker,
+    PendingChunk, SentenceChunker,
+};
+use crate::{prelude::Document, search::Chunk};
+
+/// A chunker that sizes chunks with the tokenizer of the embedding model while keeping whole sentences together.
+///
+/// Sentences are packed into a chunk until the chunk reaches the target number of tokens. A chunk never grows past the maximum number of tokens or the [`Embedder::max_input_tokens`] of the model minus the tokens of the [`Embedder::input_prefix`] the model adds to documents, so the embedding model never silently truncates a chunk. Sentences that are longer than the maximum on their own are split between words, or between characters in Chinese and Japanese text. Each chunk starts with the last sentences of the previous chunk that fit in the overlap.
+///
+/// Each document is tokenized once with [`Embedder::token_offsets`]. If the embedder doesn't expose its tokenizer, the number of tokens is estimated as one token for every four bytes of text.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let bert = Bert::new().await.unwrap();
+///     let chunker = TokenChunker::new()
+///         .with_target_tokens(128)
+///         .with_max_tokens(256)
+///         .with_overlap_tokens(16);
+///     let text = "The quick brown fox jumps over the lazy dog. The dog sleeps.";
+///     for range in chunker.chunk_str(text, &bert) {
+///         println!("{}", &text[range]);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TokenChunker {
+    target_tokens: usize,
+    max_tokens: usize,
+    overlap_tokens: usize,
+}
+
+impl Default for TokenChunker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TokenChunker {
+    /// Create a new [`TokenChunker`] with a target of 256 tokens, a maximum of 512 tokens and 32 tokens of overlap.
+    pub fn new() -> Self {
+        Self {
+            target_tokens: 256,
+            max_tokens: 512,
+            overlap_tokens: 32,
+        }
+    }
+
+    /// Set the number of tokens a chunk is filled to before a new chunk is started. (default: 256)
+    pub fn with_target_tokens(mut self, target_tokens: usize) -> Self {
+        self.target_tokens = target_tokens.max(1);
+        self
+    }
+
+    /// Set the maximum number of tokens in a chunk. The limit of the embedding model is used instead if it is smaller. (default: 512)
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens.max(1);
+        self
+    }
+
+    /// Set the maximum number of tokens from the end of a chunk that are repeated at the start of the next chunk. Only whole sentences are repeated. (default: 32)
+    pub fn with_overlap_tokens(mut self, overlap_tokens: usize) -> Self {
+        self.overlap_tokens = overlap_tokens;
+        self
+    }
+
+    /// Split a string into chunks sized with the tokenizer of the embedder.
+    pub fn chunk_str<E: Embedder>(&self, text: &str, embedder: &E) -> Vec<Range<usize>> {
+        // The prefix the model adds before every document takes up part of the input
+        let max_input_tokens = embedder.max_input_tokens().map(|limit| {
+            let prefix_tokens = embedder
+                .input_prefix(EmbeddingVariant::Document)
+                .map(|prefix| {
+                    embedder
+                        .count_tokens(prefix)
+                        .unwrap_or_else(|| estimate_tokens(prefix.len()))
+                })
+                .unwrap_or(0);
+            limit.saturating_sub(prefix_tokens)
+        });
+
+        match embedder.token_offsets(text) {
+            Some(tokens) => {
+                let mut token_starts = tokens
+                    .into_iter()
+                    .map(|token| token.start)
+                    .collect::<Vec<_>>();
+                token_starts.sort_unstable();
+                self.split(text, max_input_tokens, |range| {
+                    tokens_in(&token_starts, range)
+                })
+            }
+            None => self.split(text, max_input_tokens, |range| estimate_tokens(range.len())),
+        }
+    }
+
+    /// Split a document into chunks with the text that is embedded for each chunk.
+    fn pending_chunks<E: Embedder>(&self, document: &Document, embedder: &E) -> Vec<PendingChunk> {
+        let body = document.body();
+        self.chunk_str(body, embedder)
+            .into_iter()
+            .map(|byte_range| PendingChunk {
+                text: body[byte_range.clone()].to_string(),
+                byte_range,
+                metadata: Default::default(),
+            })
+            .collect()
+    }
+
+    /// Split text into chunks. `count_tokens` returns the number of tokens in a byte range of the text.
+    pub(super) fn split(
+        &self,
+        text: &str,
+        max_input_tokens: Option<usize>,
+        count_tokens: impl Fn(Range<usize>) -> usize,
+    ) -> Vec<Range<usize>> {
+        let max_tokens = match max_input_tokens {
+            Some(limit) => self.max_tokens.min(limit.max(1)),
+            None => self.max_tokens,
+        };
+        let target_tokens = self.target_tokens.min(max_tokens);
+
+        // Split the text into sentences, and any sentence that is too long on its own into groups of words
+        let mut units = Vec::new();
+        for sentence in SentenceChunker::default().split_sentences(text) {
+            let Some(sentence) = trim_range(text, sentence) else {
+                continue;
+            };
+            if count_tokens(sentence.clone()) <= max_tokens {
+                units.push(sentence);
+            } else {
+                units.extend(split_words(text, sentence, max_tokens, &count_tokens));
+            }
+        }
+
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < units.len() {
+            // Every chunk has at least one unit
+            let mut end = start + 1;
+            while end < units.len() {
+                if count_tokens(units[start].start..units[end - 1].end) >= target_tokens {
+                    break;
+                }
+                if count_tokens(units[start].start..units[end].end) > max_tokens {
+                    break;
+                }
+                end += 1;
+            }
+            chunks.push(units[start].start..units[end - 1].end);
+            if end == units.len() {
+                break;
+            }
+
+            // Start the next chunk with the sentences at the end of this chunk that fit in the overlap
+            let mut next_start = end;
+            while next_start > start + 1
+                && count_tokens(units[next_start - 1].start..units[end - 1].end)
+                    <= self.overlap_tokens
+            {
+                next_start -= 1;
+            }
+            start = next_start;
+        }
+
+        chunks
+    }
+}
+
+impl Chunker for TokenChunker {
+    async fn chunk<E: Embedder + Send>(
+        &self,
+        document: &Document,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Chunk<E::VectorSpace>>> {
+        embed_document_chunks(self.pending_chunks(document, embedder), embedder).await
+    }
+
+    async fn chunk_batch<'a, I, E: Embedder + Send>(
+        &self,
+        documents: I,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Vec<Chunk<E::VectorSpace>>>>
+    where
+        I: IntoIterator<Item = &'a Document> + Send,
+        I::IntoIter: Send,
+    {
+        let documents = documents
+            .into_iter()
+            .map(|document| self.pending_chunks(document, embedder))
+            .collect();
+        embed_chunks(documents, embedder).await
+    }
+
+    fn chunk_ranges<E: Embedder>(
+        &self,
+        document: &Document,
+        embedder: &E,
+    ) -> Option<Vec<Range<usize>>> {
+        Some(self.chunk_str(document.body(), embedder))
+    }
+}
+
+/// Estimate the number of tokens in a number of bytes of text for embedders without a tokenizer.
+fn estimate_tokens(bytes: usize) -> usize {
+    bytes.div_ceil(4)
+}
+
+/// Count the tokens that start in a byte range from the sorted start offsets of the tokens.
+pub(super) fn tokens_in(token_starts: &[usize], range: Range<usize>) -> usize {
+    let before_end = token_starts.partition_point(|&start| start < range.end);
+    let before_start = token_starts.partition_point(|&start| start < range.start);
+    before_end - before_start
+}
+
+/// Split a sentence into groups of words with at most `max_tokens` tokens. A single word with more tokens than the maximum is kept in its own group.
+fn split_words(
+    text: &str,
+    sentence: Range<usize>,
+    max_tokens: usize,
+    count_tokens: impl Fn(Range<usize>) -> usize,
+) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut current: Option<Range<usize>> = None;
+    for word in word_ranges(&text[sentence.clone()]) {
+        let word = sentence.start + word.start..sentence.start + word.end;
+        current = match current {
+            Some(group) if count_tokens(group.start..word.end) <= max_tokens => {
+                Some(group.start..word.end)
+            }
+            Some(group) => {
+                groups.push(group);
+                Some(word)
+            }
+            None => Some(word),
+        };
+    }
+    groups.extend(current);
+    groups
+}
+
+#[test]
+fn token_chunker_packs_sentences_by_tokens() {
+    let text = "One two three. Four five six. Seven eight nine. Ten eleven twelve.";
+    let count_words = |range: Range<usize>| text[range].split_whitespace().count();
+    let chunker = TokenChunker::new()
+        .with_target_tokens(6)
+        .with_max_tokens(8)
+        .with_overlap_tokens(3);
+    let chunks = chunker
+        .split(text, None, count_words)
+        .into_iter()
+        .map(|range| &text[range])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        [
+            "One two three. Four five six.",
+            "Four five six. Seven eight nine.",
+            "Seven eight nine. Ten eleven twelve.",
+        ]
+    );
+
+    // The limit of the model caps the maximum size and long sentences are split between words
+    let text = "One two three four five six seven. Eight nine.";
+    let count_words = |range: Range<usize>| text[range].split_whitespace().count();
+    let chunks = TokenChunker::new()
+        .with_overlap_tokens(0)
+        .split(text, Some(3), count_words)
+        .into_iter()
+        .map(|range| &text[range])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        chunks,
+        ["One two three", "four five six", "seven. Eight nine."]
+    );
+}
+
+#[test]
+fn token_chunker_leaves_room_for_the_document_prefix() {
+    use kalosm_language_model::{Embedding, EmbeddingInput, UnknownVectorSpace};
+    use std::{future::Future, pin::Pin};
+
+    /// An embedder with one token per word that adds a two word prefix to documents. Without the prefix, the whole text would fit in one chunk
+    struct WordEmbedder;
+
+    impl Embedder for WordEmbedder {
+        type VectorSpace = UnknownVectorSpace;
+
+        fn embed_for(
+            &self,
+            _: EmbeddingInput,
+        ) -> Pin<Box<dyn Future<Output = anyhow::Result<Embedding<UnknownVectorSpace>>> + Send + '_>>
+        {
+            Box::pin(async { anyhow::bail!("WordEmbedder only counts tokens") })
+        }
+
+        fn count_tokens(&self, text: &str) -> Option<usize> {
+            Some(text.split_whitespace().count())
+        }
+
+        fn token_offsets(&self, text: &str) -> Option<Vec<Range<usize>>> {
+            Some(word_ranges(text))
+        }
+
+        fn max_input_tokens(&self) -> Option<usize> {
+            Some(6)
+        }
+
+        fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+            match variant {
+                EmbeddingVariant::Query => None,
+                EmbeddingVariant::Document => Some("search document: "),
+            }
+        }
+    }
+
+    let text = "One two three. Four five six.";
+    let chunks = TokenChunker::new()
+        .with_overlap_tokens(0)
+        .chunk_str(text, &WordEmbedder)
+        .into_iter()
+        .map(|range| &text[range])
+        .collect::<Vec<_>>();
+    assert_eq!(chunks, ["One two three.", "Four five six."]);
+}
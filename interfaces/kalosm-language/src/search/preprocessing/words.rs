@@ -0,0 +1,49 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Split text into the byte ranges of its words. Word boundaries follow the [Unicode word segmentation rules](https://www.unicode.org/reports/tr29/#Word_Boundaries), so Chinese and Japanese text without spaces is split into single characters, and each kana word in Japanese is kept together.
+///
+/// Each range includes the punctuation after the word, but not the whitespace.
+pub(crate) fn word_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut words: Vec<Range<usize>> = Vec::new();
+    // If the last segment was whitespace, punctuation starts a new word
+    let mut after_space = true;
+    for (start, segment) in text.split_word_bound_indices() {
+        let end = start + segment.len();
+        if segment.chars().all(char::is_whitespace) {
+            after_space = true;
+            continue;
+        }
+        let is_word = segment.chars().any(char::is_alphanumeric);
+        match words.last_mut() {
+            Some(last) if !is_word && !after_space => last.end = end,
+            _ => words.push(start..end),
+        }
+        after_space = false;
+    }
+    words
+}
+
+#[test]
+fn word_ranges_split_cjk_text() {
+    let text = "Hello, world! 我喜欢学习。 カタカナです";
+    let words = word_ranges(text)
+        .into_iter()
+        .map(|range| &text[range])
+        .collect::<Vec<_>>();
+    assert_eq!(
+        words,
+        [
+            "Hello,",
+            "world!",
+            "我",
+            "喜",
+            "欢",
+            "学",
+            "习。",
+            "カタカナ",
+            "で",
+            "す"
+        ]
+    );
+}
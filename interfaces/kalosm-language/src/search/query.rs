@@ -0,0 +1,564 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+
+use kalosm_language_model::{
+    Embedder, Embedding, EmbeddingInput, EmbeddingVariant, Model, SyncModel,
+};
+use kalosm_sample::{ParserExt, RepeatParser, StopOn};
+
+use crate::prelude::{StructuredRunner, Task};
+
+const MULTI_QUERY_TASK_DESCRIPTION: &str = "You rewrite search queries. Write each rewrite on its own line. Every rewrite asks for the same information as the original query with different words";
+
+const MULTI_QUERY_EXAMPLE: (&str, &str) = (
+    "how do i make my rust program faster",
+    "How can I improve the performance of a Rust program?\nWhat are ways to optimize Rust code for speed?\nWhy is my Rust application slow and how do I fix it?\n",
+);
+
+const HYPOTHETICAL_DOCUMENT_TASK_DESCRIPTION: &str = "You write a short passage that answers the question as if it were taken from a document about the topic. Write the passage as a single paragraph";
+
+const HYPOTHETICAL_DOCUMENT_EXAMPLE: (&str, &str) = (
+    "What does a content delivery network do?",
+    "A content delivery network, or CDN, is a group of servers spread around the world that store copies of web content. When a user requests a page, the CDN serves it from the server closest to them, which reduces latency and takes load off the origin server.\n",
+);
+
+const STEP_BACK_TASK_DESCRIPTION: &str = "You turn specific questions into a more general step back question about the concepts or principles behind them. Write the step back question on a single line";
+
+const STEP_BACK_EXAMPLE: (&str, &str) = (
+    "Why did my sourdough bread not rise after 12 hours at 18 degrees?",
+    "How do temperature and time affect the fermentation of sourdough?\n",
+);
+
+/// A search query and the variants of the query created by a [`QueryTransformer`].
+///
+/// Each variant is an [`EmbeddingInput`]: rewritten questions are embedded as queries and hypothetical documents are embedded as documents. Search with each embedding from [`ExpandedQuery::embed`] and combine the results with [`reciprocal_rank_fusion`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedQuery {
+    variants: Vec<EmbeddingInput>,
+}
+
+impl ExpandedQuery {
+    /// Create a new expanded query with only the original query.
+    pub fn new(query: impl ToString) -> Self {
+        Self {
+            variants: vec![EmbeddingInput::new(query, EmbeddingVariant::Query)],
+        }
+    }
+
+    /// Add a variant to the query. Variants that are already in the query are skipped.
+    pub fn with_variant(mut self, variant: EmbeddingInput) -> Self {
+        if !self.variants.contains(&variant) {
+            self.variants.push(variant);
+        }
+        self
+    }
+
+    /// Get the original query.
+    pub fn query(&self) -> &str {
+        &self.variants[0].text
+    }
+
+    /// Get the original query followed by every variant.
+    pub fn variants(&self) -> &[EmbeddingInput] {
+        &self.variants
+    }
+
+    /// Embed the original query and every variant. Returns the embeddings in the same order as [`ExpandedQuery::variants`].
+    pub async fn embed<E: Embedder>(
+        &self,
+        embedder: &E,
+    ) -> anyhow::Result<Vec<Embedding<E::VectorSpace>>> {
+        embedder.embed_vec_for(self.variants.clone()).await
+    }
+}
+
+/// A search result fused from the results of several searches with [`reciprocal_rank_fusion`].
+#[derive(Debug, Clone)]
+pub struct Fused<T> {
+    /// The fused score of the result. Higher scores are more relevant.
+    pub score: f32,
+    /// The result. If the result was found by several searches, this is the first copy that was found.
+    pub result: T,
+}
+
+/// Combine the results of several searches with [reciprocal rank fusion](https://plg.uwaterloo.ca/~gvcormac/cormacksigir09-rrf.pdf). Each result scores `1 / (k + rank)` in each search it appears in. Results with the same key are merged.
+///
+/// Returns the results sorted from the highest to the lowest score. 60 is a good default for k.
+pub fn reciprocal_rank_fusion<T, K: Hash + Eq>(
+    searches: impl IntoIterator<Item = Vec<T>>,
+    k: f32,
+    key: impl Fn(&T) -> K,
+) -> Vec<Fused<T>> {
+    let mut fused: Vec<Fused<T>> = Vec::new();
+    let mut index_for_key: HashMap<K, usize> = HashMap::new();
+    for results in searches {
+        for (rank, result) in results.into_iter().enumerate() {
+            let score = 1. / (k + rank as f32 + 1.);
+            match index_for_key.get(&key(&result)) {
+                Some(&index) => fused[index].score += score,
+                None => {
+                    index_for_key.insert(key(&result), fused.len());
+                    fused.push(Fused { score, result });
+                }
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score));
+    fused
+}
+
+/// A transformation that uses a [`Model`] to rewrite a search query into variants that find more relevant results.
+///
+/// Transformers can be combined with a tuple. `(MultiQuery::new(), StepBack::new())` creates the variants of both transformers.
+pub trait QueryTransformer {
+    /// Create variants of the query. The variants don't include the original query.
+    fn transform<M>(
+        &self,
+        query: &str,
+        model: &M,
+    ) -> impl Future<Output = anyhow::Result<Vec<EmbeddingInput>>> + Send
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync;
+
+    /// Expand the query into the original query and its variants. Duplicate variants are removed.
+    fn expand<M>(
+        &self,
+        query: &str,
+        model: &M,
+    ) -> impl Future<Output = anyhow::Result<ExpandedQuery>> + Send
+    where
+        Self: Sync,
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        async move {
+            let variants = self.transform(query, model).await?;
+            Ok(variants
+                .into_iter()
+                .fold(ExpandedQuery::new(query), ExpandedQuery::with_variant))
+        }
+    }
+}
+
+impl<A, B> QueryTransformer for (A, B)
+where
+    A: QueryTransformer + Sync,
+    B: QueryTransformer + Sync,
+{
+    async fn transform<M>(&self, query: &str, model: &M) -> anyhow::Result<Vec<EmbeddingInput>>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        let mut variants = self.0.transform(query, model).await?;
+        variants.extend(self.1.transform(query, model).await?);
+        Ok(variants)
+    }
+}
+
+impl<A, B, C> QueryTransformer for (A, B, C)
+where
+    A: QueryTransformer + Sync,
+    B: QueryTransformer + Sync,
+    C: QueryTransformer + Sync,
+{
+    async fn transform<M>(&self, query: &str, model: &M) -> anyhow::Result<Vec<EmbeddingInput>>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        let mut variants = self.0.transform(query, model).await?;
+        variants.extend(self.1.transform(query, model).await?);
+        variants.extend(self.2.transform(query, model).await?);
+        Ok(variants)
+    }
+}
+
+/// Generate the text of a task that writes a single line.
+async fn run_line_task<M>(
+    task: &Task<StructuredRunner<StopOn>>,
+    input: &str,
+    model: &M,
+) -> anyhow::Result<String>
+where
+    M: Model,
+    <M::SyncModel as SyncModel>::Session: Send + Sync,
+{
+    let line = task.run(input, model).result().await?;
+    Ok(line.trim().to_string())
+}
+
+/// Rewrites a query into several paraphrases that ask for the same information.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let llm = Llama::new_chat().await?;
+///     let expanded = MultiQuery::new()
+///         .with_count(3)
+///         .expand("how do i make my rust program faster", &llm)
+///         .await?;
+///     for variant in expanded.variants() {
+///         println!("{}", variant.text);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct MultiQuery {
+    count: usize,
+    task: Task<StructuredRunner<RepeatParser<StopOn>>>,
+}
+
+impl Default for MultiQuery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MultiQuery {
+    /// Create a new transformer that writes three paraphrases of each query.
+    pub fn new() -> Self {
+        Self {
+            count: 3,
+            task: Self::task(3),
+        }
+    }
+
+    /// Set the number of paraphrases to write. (default: 3)
+    pub fn with_count(mut self, count: usize) -> Self {
+        self.count = count;
+        self.task = Self::task(count);
+        self
+    }
+
+    fn task(count: usize) -> Task<StructuredRunner<RepeatParser<StopOn>>> {
+        Task::builder(MULTI_QUERY_TASK_DESCRIPTION)
+            .with_constraints(StopOn::new("\n").repeat(count..=count))
+            .with_example(MULTI_QUERY_EXAMPLE.0, MULTI_QUERY_EXAMPLE.1)
+            .build()
+    }
+}
+
+impl QueryTransformer for MultiQuery {
+    async fn transform<M>(&self, query: &str, model: &M) -> anyhow::Result<Vec<EmbeddingInput>>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        if self.count == 0 {
+            return Ok(Vec::new());
+        }
+        let lines = self.task.run(query, model).result().await?;
+        Ok(lines
+            .iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && *line != query.trim())
+            .map(|line| EmbeddingInput::new(line, EmbeddingVariant::Query))
+            .collect())
+    }
+}
+
+/// Writes a hypothetical document that answers the query ([HyDE](https://arxiv.org/abs/2212.10496)). The document is embedded as a document instead of a query, so it is compared to the chunks in the database like another chunk.
+///
+/// The hypothetical answer may contain made up facts. It is only used to find real documents with similar content.
+pub struct HypotheticalDocument {
+    task: Task<StructuredRunner<StopOn>>,
+}
+
+impl Default for HypotheticalDocument {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HypotheticalDocument {
+    /// Create a new hypothetical document transformer.
+    pub fn new() -> Self {
+        Self {
+            task: Task::builder(HYPOTHETICAL_DOCUMENT_TASK_DESCRIPTION)
+                .with_constraints(StopOn::new("\n"))
+                .with_example(
+                    HYPOTHETICAL_DOCUMENT_EXAMPLE.0,
+                    HYPOTHETICAL_DOCUMENT_EXAMPLE.1,
+                )
+                .build(),
+        }
+    }
+}
+
+impl QueryTransformer for HypotheticalDocument {
+    async fn transform<M>(&self, query: &str, model: &M) -> anyhow::Result<Vec<EmbeddingInput>>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        let document = run_line_task(&self.task, query, model).await?;
+        Ok(vec![EmbeddingInput::new(
+            document,
+            EmbeddingVariant::Document,
+        )])
+    }
+}
+
+/// Writes a broader step back question about the concepts behind the query ([Step-Back Prompting](https://arxiv.org/abs/2310.06117)). This finds background information that a specific query can miss.
+pub struct StepBack {
+    task: Task<StructuredRunner<StopOn>>,
+}
+
+impl Default for StepBack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StepBack {
+    /// Create a new step back transformer.
+    pub fn new() -> Self {
+        Self {
+            task: Task::builder(STEP_BACK_TASK_DESCRIPTION)
+                .with_constraints(StopOn::new("\n"))
+                .with_example(STEP_BACK_EXAMPLE.0, STEP_BACK_EXAMPLE.1)
+                .build(),
+        }
+    }
+}
+
+impl QueryTransformer for StepBack {
+    async fn transform<M>(&self, query: &str, model: &M) -> anyhow::Result<Vec<EmbeddingInput>>
+    where
+        M: Model,
+        <M::SyncModel as SyncModel>::Session: Send + Sync,
+    {
+        let question = run_line_task(&self.task, query, model).await?;
+        Ok(vec![EmbeddingInput::new(question, EmbeddingVariant::Query)])
+    }
+}
+
+#[test]
+fn reciprocal_rank_fusion_merges_searches() {
+    let fused = reciprocal_rank_fusion(
+        [vec!["a", "b", "c"], vec!["b", "d"], vec!["b", "a"]],
+        60.,
+        |result| *result,
+    );
+    let order = fused.iter().map(|fused| fused.result).collect::<Vec<_>>();
+    assert_eq!(order, ["b", "a", "d", "c"]);
+    assert!((fused[0].score - (2. / 61. + 1. / 62.)).abs() < 1e-6);
+}
+
+/// A model that answers each task with a fixed response, one byte per token.
+#[cfg(test)]
+#[derive(Clone)]
+struct StubModel {
+    tokenizer: std::sync::Arc<tokenizers::Tokenizer>,
+    responses: Vec<(&'static str, &'static str)>,
+}
+
+#[cfg(test)]
+impl StubModel {
+    /// Create a stub model that answers the task with each description with the matching response.
+    fn new(responses: impl IntoIterator<Item = (&'static str, &'static str)>) -> Self {
+        use tokenizers::models::bpe::BPE;
+        use tokenizers::pre_tokenizers::byte_level::ByteLevel;
+
+        let vocab = ByteLevel::alphabet()
+            .into_iter()
+            .enumerate()
+            .map(|(id, byte)| (byte.to_string(), id as u32))
+            .collect();
+        let bpe = BPE::builder()
+            .vocab_and_merges(vocab, Vec::new())
+            .build()
+            .unwrap();
+        let mut tokenizer = tokenizers::Tokenizer::new(bpe);
+        tokenizer
+            .with_pre_tokenizer(ByteLevel::default().add_prefix_space(false))
+            .with_decoder(ByteLevel::default());
+        Self {
+            tokenizer: std::sync::Arc::new(tokenizer),
+            responses: responses.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+struct StubSession(Vec<u32>);
+
+#[cfg(test)]
+impl kalosm_language_model::Session for StubSession {
+    fn tokens(&self) -> &[u32] {
+        &self.0
+    }
+
+    fn try_clone(&self) -> anyhow::Result<Self> {
+        Ok(Self(self.0.clone()))
+    }
+}
+
+#[cfg(test)]
+impl SyncModel for StubModel {
+    type Session = StubSession;
+
+    fn new_session(&self) -> anyhow::Result<Self::Session> {
+        Ok(StubSession(Vec::new()))
+    }
+
+    fn feed_text(
+        &self,
+        session: &mut Self::Session,
+        prompt: &str,
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        let tokens = self
+            .tokenizer
+            .encode(prompt, false)
+            .map_err(|err| anyhow::anyhow!(err))?;
+        self.feed_tokens(session, tokens.get_ids(), into)
+    }
+
+    fn feed_tokens(
+        &self,
+        session: &mut Self::Session,
+        tokens: &[u32],
+        into: &mut Vec<f32>,
+    ) -> anyhow::Result<()> {
+        session.0.extend_from_slice(tokens);
+        into.clear();
+        into.resize(self.tokenizer.get_vocab_size(true), -50.);
+
+        // Continue the response of the task that is in the prompt after the last output marker
+        let text = self
+            .tokenizer
+            .decode(&session.0, false)
+            .map_err(|err| anyhow::anyhow!(err))?;
+        let response = self
+            .responses
+            .iter()
+            .find(|(description, _)| text.contains(description));
+        if let (Some((_, response)), Some(start)) = (response, text.rfind("# Output")) {
+            let script = format!("# Output\n{response}");
+            if let Some(next) = script
+                .strip_prefix(&text[start..])
+                .and_then(|rest| rest.chars().next())
+            {
+                let next = self
+                    .tokenizer
+                    .encode(next.to_string(), false)
+                    .map_err(|err| anyhow::anyhow!(err))?;
+                into[next.get_ids()[0] as usize] = 50.;
+            }
+        }
+        Ok(())
+    }
+
+    fn stop_token(&self) -> anyhow::Result<u32> {
+        anyhow::bail!("The stub model has no stop token")
+    }
+
+    fn tokenizer(&self) -> std::sync::Arc<tokenizers::Tokenizer> {
+        self.tokenizer.clone()
+    }
+}
+
+#[cfg(test)]
+#[async_trait::async_trait]
+impl Model for StubModel {
+    type TextStream = kalosm_streams::text_stream::ChannelTextStream;
+    type SyncModel = Self;
+
+    fn tokenizer(&self) -> std::sync::Arc<tokenizers::Tokenizer> {
+        self.tokenizer.clone()
+    }
+
+    fn run_sync_raw(
+        &self,
+        f: Box<
+            dyn for<'a> FnOnce(
+                    &'a mut Self::SyncModel,
+                )
+                    -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + 'a>>
+                + Send,
+        >,
+    ) -> anyhow::Result<()> {
+        let mut model = self.clone();
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(f(&mut model))
+        });
+        Ok(())
+    }
+
+    async fn stream_text_inner(
+        &self,
+        _prompt: &str,
+        _parameters: kalosm_language_model::GenerationParameters,
+    ) -> anyhow::Result<Self::TextStream> {
+        anyhow::bail!("The stub model only runs tasks")
+    }
+}
+
+#[tokio::test]
+async fn query_transformers_expand_in_order() {
+    let query = "how do i make rust fast";
+    let model = StubModel::new([
+        (
+            MULTI_QUERY_TASK_DESCRIPTION,
+            "How can I speed up Rust?\nhow do i make rust fast\nHow can I speed up Rust?\n",
+        ),
+        (
+            HYPOTHETICAL_DOCUMENT_TASK_DESCRIPTION,
+            "Rust programs run faster when they are built in release mode.\n",
+        ),
+        (
+            STEP_BACK_TASK_DESCRIPTION,
+            "What makes compiled programs fast?\n",
+        ),
+    ]);
+    let paraphrase = EmbeddingInput::new("How can I speed up Rust?", EmbeddingVariant::Query);
+    let step_back = EmbeddingInput::new(
+        "What makes compiled programs fast?",
+        EmbeddingVariant::Query,
+    );
+    let document = EmbeddingInput::new(
+        "Rust programs run faster when they are built in release mode.",
+        EmbeddingVariant::Document,
+    );
+
+    let expanded = MultiQuery::new().expand(query, &model).await.unwrap();
+    assert_eq!(expanded.query(), query);
+    assert_eq!(
+        expanded.variants(),
+        [
+            EmbeddingInput::new(query, EmbeddingVariant::Query),
+            paraphrase.clone()
+        ]
+    );
+
+    let expanded = (MultiQuery::new(), StepBack::new())
+        .expand(query, &model)
+        .await
+        .unwrap();
+    assert_eq!(expanded.query(), query);
+    assert_eq!(
+        expanded.variants()[1..],
+        [paraphrase.clone(), step_back.clone()]
+    );
+
+    let expanded = (
+        StepBack::new(),
+        HypotheticalDocument::new(),
+        MultiQuery::new(),
+    )
+        .expand(query, &model)
+        .await
+        .unwrap();
+    assert_eq!(expanded.query(), query);
+    assert_eq!(expanded.variants()[1..], [step_back, document, paraphrase]);
+}
@@ -0,0 +1,113 @@
+pub use arroy::distances::{Angular, DotProduct, Euclidean, Manhattan};
+
+/// A distance metric that a [`super::VectorDB`] can search with. Smaller distances are always closer.
+///
+/// This is implemented for every distance in [`arroy::distances`]:
+/// - [`Angular`]: `(1 - cos(a, b)) / 2`. This is the default and works for most embedding models.
+/// - [`Euclidean`]: the length of `a - b`.
+/// - [`DotProduct`]: the negative dot product. Use this for models trained with dot product similarity.
+/// - [`Manhattan`]: the sum of the absolute differences of each dimension.
+pub trait VectorDistance: arroy::Distance {
+    /// Compute the exact distance between two vectors.
+    fn distance(a: &[f32], b: &[f32]) -> f32;
+
+    /// Compute the distance between two int8 quantized vectors on the same scale as [`VectorDistance::distance`]. The value of each dimension is the quantized value multiplied by the scale of the vector.
+    fn int8_distance(a: &[i8], a_scale: f32, b: &[i8], b_scale: f32) -> f32;
+
+    /// Convert a distance returned by the approximate index into the same scale as [`VectorDistance::distance`].
+    fn from_index_distance(distance: f32) -> f32 {
+        distance
+    }
+}
+
+impl VectorDistance for Angular {
+    fn distance(a: &[f32], b: &[f32]) -> f32 {
+        let norm = dot(a, a).sqrt() * dot(b, b).sqrt();
+        if norm == 0. {
+            0.
+        } else {
+            (1. - dot(a, b) / norm) / 2.
+        }
+    }
+
+    fn int8_distance(a: &[i8], _: f32, b: &[i8], _: f32) -> f32 {
+        // The scales cancel out in the cosine
+        let (dot, a_norm, b_norm) = int8_products(a, b);
+        let norm = (a_norm as f32).sqrt() * (b_norm as f32).sqrt();
+        if norm == 0. {
+            0.
+        } else {
+            (1. - dot as f32 / norm) / 2.
+        }
+    }
+}
+
+impl VectorDistance for Euclidean {
+    fn distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            .sqrt()
+    }
+
+    fn int8_distance(a: &[i8], a_scale: f32, b: &[i8], b_scale: f32) -> f32 {
+        let (dot, a_norm, b_norm) = int8_products(a, b);
+        let squared = a_scale * a_scale * a_norm as f32 + b_scale * b_scale * b_norm as f32
+            - 2. * a_scale * b_scale * dot as f32;
+        squared.max(0.).sqrt()
+    }
+}
+
+impl VectorDistance for DotProduct {
+    fn distance(a: &[f32], b: &[f32]) -> f32 {
+        -dot(a, b)
+    }
+
+    fn int8_distance(a: &[i8], a_scale: f32, b: &[i8], b_scale: f32) -> f32 {
+        let (dot, _, _) = int8_products(a, b);
+        -(a_scale * b_scale * dot as f32)
+    }
+
+    fn from_index_distance(distance: f32) -> f32 {
+        // arroy returns the dot product itself, which is larger for closer vectors
+        -distance
+    }
+}
+
+impl VectorDistance for Manhattan {
+    fn distance(a: &[f32], b: &[f32]) -> f32 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+    }
+
+    fn int8_distance(a: &[i8], a_scale: f32, b: &[i8], b_scale: f32) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (*a as f32 * a_scale - *b as f32 * b_scale).abs())
+            .sum()
+    }
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Get the dot product of two int8 vectors and the squared length of each vector.
+fn int8_products(a: &[i8], b: &[i8]) -> (i64, i64, i64) {
+    a.iter()
+        .zip(b)
+        .fold((0, 0, 0), |(dot, a_norm, b_norm), (a, b)| {
+            let (a, b) = (*a as i64, *b as i64);
+            (dot + a * b, a_norm + a * a, b_norm + b * b)
+        })
+}
+
+/// How a [`super::VectorDB`] finds the closest embeddings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VectorIndex {
+    /// Build a forest of random projection trees with [arroy](https://github.com/meilisearch/arroy). Searches are fast, but approximate and the trees are rebuilt every time embeddings are added or removed.
+    #[default]
+    Approximate,
+    /// Compare the query with every embedding. Searches are exact and adding embeddings is cheap, but searches get slower as the database grows. This is a good choice for collections of up to tens of thousands of embeddings.
+    Flat,
+}
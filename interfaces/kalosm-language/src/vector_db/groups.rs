@@ -0,0 +1,85 @@
+use heed::types::{SerdeJson, U32};
+use heed::EnvOpenOptions;
+
+type GroupOfDatabase =
+    heed::Database<U32<heed::byteorder::NativeEndian>, U32<heed::byteorder::NativeEndian>>;
+type GroupMembersDatabase = heed::Database<U32<heed::byteorder::NativeEndian>, SerdeJson<Vec<u32>>>;
+
+/// Groups of embeddings that are scored together, like the token embeddings of a single passage for late interaction search. Each group is identified by the id of its first embedding.
+pub(crate) struct EmbeddingGroups {
+    env: heed::Env,
+    group_of: GroupOfDatabase,
+    members: GroupMembersDatabase,
+}
+
+impl EmbeddingGroups {
+    pub(crate) fn open(path: &std::path::Path, map_size: usize) -> heed::Result<Self> {
+        std::fs::create_dir_all(path)?;
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(map_size)
+                .max_dbs(2)
+                .open(path)
+        }?;
+        let mut wtxn = env.write_txn()?;
+        let group_of = env.create_database(&mut wtxn, Some("group-of"))?;
+        let members = env.create_database(&mut wtxn, Some("members"))?;
+        wtxn.commit()?;
+        Ok(Self {
+            env,
+            group_of,
+            members,
+        })
+    }
+
+    /// Add a new group. Returns the id of the group.
+    pub(crate) fn insert(&self, members: &[u32]) -> anyhow::Result<Option<u32>> {
+        let Some(group) = members.first().copied() else {
+            return Ok(None);
+        };
+        let mut wtxn = self.env.write_txn()?;
+        for member in members {
+            self.group_of.put(&mut wtxn, member, &group)?;
+        }
+        self.members.put(&mut wtxn, &group, &members.to_vec())?;
+        wtxn.commit()?;
+        Ok(Some(group))
+    }
+
+    /// Remove an embedding from its group. The group is removed once it has no members.
+    pub(crate) fn remove(&self, id: u32) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        if let Some(group) = self.group_of.get(&wtxn, &id)? {
+            self.group_of.delete(&mut wtxn, &id)?;
+            let mut members = self.members.get(&wtxn, &group)?.unwrap_or_default();
+            members.retain(|member| *member != id);
+            if members.is_empty() {
+                self.members.delete(&mut wtxn, &group)?;
+            } else {
+                self.members.put(&mut wtxn, &group, &members)?;
+            }
+        }
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&self) -> anyhow::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        self.group_of.clear(&mut wtxn)?;
+        self.members.clear(&mut wtxn)?;
+        wtxn.commit()?;
+        Ok(())
+    }
+
+    /// Get the group an embedding belongs to.
+    pub(crate) fn group_of(&self, id: u32) -> anyhow::Result<Option<u32>> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self.group_of.get(&rtxn, &id)?)
+    }
+
+    /// Get the embeddings in a group.
+    pub(crate) fn members(&self, group: u32) -> anyhow::Result<Vec<u32>> {
+        let rtxn = self.env.read_txn()?;
+        Ok(self.members.get(&rtxn, &group)?.unwrap_or_default())
+    }
+}
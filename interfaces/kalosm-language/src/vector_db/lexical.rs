@@ -0,0 +1,187 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use super::EmbeddingId;
+
+/// The text indexed for a single embedding.
+#[derive(Debug, Clone)]
+struct IndexedText {
+    term_counts: FxHashMap<String, u32>,
+    length: u32,
+}
+
+/// An in memory [BM25](https://en.wikipedia.org/wiki/Okapi_BM25) index of the text behind each embedding.
+///
+/// Embedding search is good at finding text with a similar meaning, but it often misses exact identifiers, product codes and rare names. A lexical index scores text by the words it shares with the query instead, so the two complement each other in [`super::VectorDB::get_closest_hybrid`].
+#[derive(Debug, Clone)]
+pub struct LexicalIndex {
+    documents: FxHashMap<EmbeddingId, IndexedText>,
+    postings: FxHashMap<String, FxHashSet<EmbeddingId>>,
+    total_length: u64,
+    k1: f32,
+    b: f32,
+}
+
+impl Default for LexicalIndex {
+    fn default() -> Self {
+        Self {
+            documents: Default::default(),
+            postings: Default::default(),
+            total_length: 0,
+            k1: 1.2,
+            b: 0.75,
+        }
+    }
+}
+
+impl LexicalIndex {
+    /// Create a new empty index with the standard BM25 parameters (k1 = 1.2, b = 0.75).
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the BM25 parameters. `k1` controls how quickly repeated terms stop increasing the score and `b` controls how much long texts are penalized.
+    pub fn with_parameters(mut self, k1: f32, b: f32) -> Self {
+        self.k1 = k1;
+        self.b = b;
+        self
+    }
+
+    /// Get the number of texts in the index.
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Check if the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Index the text for an embedding. If the embedding already has text in the index, the text is replaced.
+    pub fn insert(&mut self, id: EmbeddingId, text: &str) {
+        self.remove(id);
+
+        let mut term_counts = FxHashMap::default();
+        let mut length = 0;
+        for term in tokenize(text) {
+            *term_counts.entry(term).or_insert(0) += 1;
+            length += 1;
+        }
+        for term in term_counts.keys() {
+            self.postings.entry(term.clone()).or_default().insert(id);
+        }
+        self.total_length += length as u64;
+        self.documents.insert(
+            id,
+            IndexedText {
+                term_counts,
+                length,
+            },
+        );
+    }
+
+    /// Remove the text for an embedding from the index.
+    pub fn remove(&mut self, id: EmbeddingId) {
+        if let Some(text) = self.documents.remove(&id) {
+            self.total_length -= text.length as u64;
+            for term in text.term_counts.keys() {
+                if let Some(ids) = self.postings.get_mut(term) {
+                    ids.remove(&id);
+                    if ids.is_empty() {
+                        self.postings.remove(term);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove all text from the index.
+    pub fn clear(&mut self) {
+        self.documents.clear();
+        self.postings.clear();
+        self.total_length = 0;
+    }
+
+    /// Get the BM25 score of the text for an embedding. Returns zero if the embedding is not in the index or doesn't share any terms with the query.
+    pub fn score(&self, id: EmbeddingId, query: &str) -> f32 {
+        let terms = query_terms(query);
+        self.score_terms(id, &terms)
+    }
+
+    /// Get the top `n` embeddings whose text best matches the query, sorted from the highest to the lowest score.
+    pub fn search(&self, query: &str, n: usize) -> Vec<(EmbeddingId, f32)> {
+        let terms = query_terms(query);
+        let candidates = terms
+            .iter()
+            .filter_map(|term| self.postings.get(term))
+            .flatten()
+            .copied()
+            .collect::<FxHashSet<_>>();
+        let mut results = candidates
+            .into_iter()
+            .map(|id| (id, self.score_terms(id, &terms)))
+            .collect::<Vec<_>>();
+        results.sort_by(|(a_id, a), (b_id, b)| b.total_cmp(a).then(a_id.cmp(b_id)));
+        results.truncate(n);
+        results
+    }
+
+    fn score_terms(&self, id: EmbeddingId, terms: &[String]) -> f32 {
+        let Some(text) = self.documents.get(&id) else {
+            return 0.;
+        };
+        let document_count = self.documents.len() as f32;
+        let average_length = self.total_length as f32 / document_count;
+        let length_norm = 1. - self.b + self.b * text.length as f32 / average_length.max(1.);
+        terms
+            .iter()
+            .filter_map(|term| {
+                let frequency = *text.term_counts.get(term)? as f32;
+                let document_frequency = self.postings.get(term).map_or(0, |ids| ids.len()) as f32;
+                let idf = (1.
+                    + (document_count - document_frequency + 0.5) / (document_frequency + 0.5))
+                    .ln();
+                Some(idf * frequency * (self.k1 + 1.) / (frequency + self.k1 * length_norm))
+            })
+            .sum()
+    }
+}
+
+/// Split text into lowercase alphanumeric terms.
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.to_lowercase())
+}
+
+/// Get the unique terms in a query.
+fn query_terms(query: &str) -> Vec<String> {
+    let mut terms = tokenize(query).collect::<Vec<_>>();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+#[test]
+fn bm25_prefers_rare_exact_terms() {
+    let mut index = LexicalIndex::new();
+    index.insert(
+        EmbeddingId(0),
+        "The router restarts when the firmware updates",
+    );
+    index.insert(
+        EmbeddingId(1),
+        "Part XR-2291 is the replacement fan for the router",
+    );
+    index.insert(EmbeddingId(2), "The router has four ethernet ports");
+
+    let results = index.search("replacement for xr-2291", 2);
+    assert_eq!(results[0].0, EmbeddingId(1));
+    assert_eq!(results.len(), 1);
+
+    // Terms that appear in every text barely contribute to the score
+    assert!(index.score(EmbeddingId(0), "router") < index.score(EmbeddingId(1), "xr"));
+
+    index.remove(EmbeddingId(1));
+    assert!(index.search("xr-2291", 2).is_empty());
+    assert_eq!(index.len(), 2);
+}
@@ -0,0 +1,485 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+/// A value in the [`Metadata`] of an embedding.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetadataValue {
+    /// A boolean value
+    Bool(bool),
+    /// An integer value
+    Int(i64),
+    /// A floating point value
+    Float(f64),
+    /// A string value. Dates should be stored as ISO 8601 strings so they sort correctly.
+    String(String),
+}
+
+impl MetadataValue {
+    /// Compare two values. Integers and floats are compared as numbers. Values of different types are not comparable.
+    fn compare(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Bool(a), Self::Bool(b)) => Some(a.cmp(b)),
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            (Self::String(a), Self::String(b)) => Some(a.cmp(b)),
+            (Self::Float(a), Self::Float(b)) => a.partial_cmp(b),
+            (Self::Int(a), Self::Float(b)) => (*a as f64).partial_cmp(b),
+            (Self::Float(a), Self::Int(b)) => a.partial_cmp(&(*b as f64)),
+            _ => None,
+        }
+    }
+}
+
+impl From<bool> for MetadataValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<i64> for MetadataValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<i32> for MetadataValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<u32> for MetadataValue {
+    fn from(value: u32) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<f64> for MetadataValue {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<String> for MetadataValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for MetadataValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<chrono::DateTime<chrono::Utc>> for MetadataValue {
+    fn from(value: chrono::DateTime<chrono::Utc>) -> Self {
+        Self::String(value.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    }
+}
+
+/// Metadata attached to an embedding in a [`super::VectorDB`] that searches can be filtered by.
+///
+/// ```rust
+/// use kalosm_language::prelude::*;
+///
+/// let metadata = Metadata::new()
+///     .with("tenant", "acme")
+///     .with("language", "en")
+///     .with("year", 2024);
+/// assert_eq!(metadata.get("tenant"), Some(&MetadataValue::from("acme")));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata(BTreeMap<String, MetadataValue>);
+
+impl Metadata {
+    /// Create new empty metadata.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value to the metadata.
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<MetadataValue>) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Insert a value into the metadata. Returns the old value if there was one.
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        value: impl Into<MetadataValue>,
+    ) -> Option<MetadataValue> {
+        self.0.insert(key.into(), value.into())
+    }
+
+    /// Get a value from the metadata.
+    pub fn get(&self, key: &str) -> Option<&MetadataValue> {
+        self.0.get(key)
+    }
+
+    /// Check if the metadata is empty.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Iterate over the keys and values in the metadata.
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &MetadataValue)> {
+        self.0.iter()
+    }
+
+    /// Insert all values from other metadata, replacing the values of keys that are in both.
+    pub fn merge(&mut self, other: Metadata) {
+        self.0.extend(other.0);
+    }
+}
+
+impl<K: Into<String>, V: Into<MetadataValue>> FromIterator<(K, V)> for Metadata {
+    fn from_iter<T: IntoIterator<Item = (K, V)>>(iter: T) -> Self {
+        Self(
+            iter.into_iter()
+                .map(|(key, value)| (key.into(), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// A predicate on the [`Metadata`] of an embedding. Searches with a filter only return embeddings whose metadata matches the filter.
+///
+/// ```rust
+/// use kalosm_language::prelude::*;
+///
+/// // Documents from the acme tenant written in 2023 or later
+/// let filter = MetadataFilter::eq("tenant", "acme").and(MetadataFilter::at_least("year", 2023));
+/// assert!(filter.matches(&Metadata::new().with("tenant", "acme").with("year", 2024)));
+/// assert!(!filter.matches(&Metadata::new().with("tenant", "acme").with("year", 2020)));
+/// assert!(!filter.matches(&Metadata::new().with("year", 2024)));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetadataFilter {
+    /// The key has exactly this value
+    Eq(String, MetadataValue),
+    /// The key has one of these values
+    OneOf(String, Vec<MetadataValue>),
+    /// The value of the key is within the bounds. Both bounds are inclusive.
+    Range {
+        /// The key to compare
+        key: String,
+        /// The smallest allowed value
+        min: Option<MetadataValue>,
+        /// The largest allowed value
+        max: Option<MetadataValue>,
+    },
+    /// The key has any value
+    Exists(String),
+    /// Every filter matches
+    And(Vec<MetadataFilter>),
+    /// At least one filter matches
+    Or(Vec<MetadataFilter>),
+    /// The filter doesn't match
+    Not(Box<MetadataFilter>),
+}
+
+impl MetadataFilter {
+    /// Match embeddings where the key has exactly this value.
+    pub fn eq(key: impl Into<String>, value: impl Into<MetadataValue>) -> Self {
+        Self::Eq(key.into(), value.into())
+    }
+
+    /// Match embeddings where the key has one of these values.
+    pub fn one_of(
+        key: impl Into<String>,
+        values: impl IntoIterator<Item = impl Into<MetadataValue>>,
+    ) -> Self {
+        Self::OneOf(key.into(), values.into_iter().map(Into::into).collect())
+    }
+
+    /// Match embeddings where the value of the key is between `min` and `max` (inclusive).
+    pub fn between(
+        key: impl Into<String>,
+        min: impl Into<MetadataValue>,
+        max: impl Into<MetadataValue>,
+    ) -> Self {
+        Self::Range {
+            key: key.into(),
+            min: Some(min.into()),
+            max: Some(max.into()),
+        }
+    }
+
+    /// Match embeddings where the value of the key is at least `min`.
+    pub fn at_least(key: impl Into<String>, min: impl Into<MetadataValue>) -> Self {
+        Self::Range {
+            key: key.into(),
+            min: Some(min.into()),
+            max: None,
+        }
+    }
+
+    /// Match embeddings where the value of the key is at most `max`.
+    pub fn at_most(key: impl Into<String>, max: impl Into<MetadataValue>) -> Self {
+        Self::Range {
+            key: key.into(),
+            min: None,
+            max: Some(max.into()),
+        }
+    }
+
+    /// Match embeddings that have a value for the key.
+    pub fn exists(key: impl Into<String>) -> Self {
+        Self::Exists(key.into())
+    }
+
+    /// Match embeddings that match both this filter and the other filter.
+    pub fn and(self, other: MetadataFilter) -> Self {
+        match self {
+            Self::And(mut filters) => {
+                filters.push(other);
+                Self::And(filters)
+            }
+            _ => Self::And(vec![self, other]),
+        }
+    }
+
+    /// Match embeddings that match this filter or the other filter.
+    pub fn or(self, other: MetadataFilter) -> Self {
+        match self {
+            Self::Or(mut filters) => {
+                filters.push(other);
+                Self::Or(filters)
+            }
+            _ => Self::Or(vec![self, other]),
+        }
+    }
+
+    /// Match embeddings that don't match this filter.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(self) -> Self {
+        Self::Not(Box::new(self))
+    }
+
+    /// Check if the metadata matches this filter.
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        match self {
+            Self::Eq(key, value) => metadata
+                .get(key)
+                .and_then(|found| found.compare(value))
+                .is_some_and(Ordering::is_eq),
+            Self::OneOf(key, values) => metadata.get(key).is_some_and(|found| {
+                values
+                    .iter()
+                    .any(|value| found.compare(value).is_some_and(Ordering::is_eq))
+            }),
+            Self::Range { key, min, max } => metadata.get(key).is_some_and(|found| {
+                let above_min = match min {
+                    Some(min) => found.compare(min).is_some_and(Ordering::is_ge),
+                    None => true,
+                };
+                let below_max = match max {
+                    Some(max) => found.compare(max).is_some_and(Ordering::is_le),
+                    None => true,
+                };
+                above_min && below_max
+            }),
+            Self::Exists(key) => metadata.get(key).is_some(),
+            Self::And(filters) => filters.iter().all(|filter| filter.matches(metadata)),
+            Self::Or(filters) => filters.iter().any(|filter| filter.matches(metadata)),
+            Self::Not(filter) => !filter.matches(metadata),
+        }
+    }
+}
+
+/// A [`MetadataValue`] that can be used as a hash map key. Floats are stored by their bits.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum IndexedValue {
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    String(String),
+}
+
+impl IndexedValue {
+    fn new(value: &MetadataValue) -> Self {
+        match value {
+            MetadataValue::Bool(value) => Self::Bool(*value),
+            MetadataValue::Int(value) => Self::Int(*value),
+            // Both zeros compare equal, so they share a key
+            MetadataValue::Float(value) if *value == 0. => Self::Float(0f64.to_bits()),
+            MetadataValue::Float(value) => Self::Float(value.to_bits()),
+            MetadataValue::String(value) => Self::String(value.clone()),
+        }
+    }
+
+    /// Get every indexed value that is equal to the value. Integers and floats with the same number are equal.
+    fn equal_to(value: &MetadataValue) -> Vec<Self> {
+        let mut values = vec![Self::new(value)];
+        match value {
+            MetadataValue::Int(int) => values.push(Self::new(&MetadataValue::Float(*int as f64))),
+            MetadataValue::Float(float)
+                if float.fract() == 0. && (*float as i64) as f64 == *float =>
+            {
+                values.push(Self::Int(*float as i64))
+            }
+            _ => {}
+        }
+        values
+    }
+
+    fn value(&self) -> MetadataValue {
+        match self {
+            Self::Bool(value) => MetadataValue::Bool(*value),
+            Self::Int(value) => MetadataValue::Int(*value),
+            Self::Float(bits) => MetadataValue::Float(f64::from_bits(*bits)),
+            Self::String(value) => MetadataValue::String(value.clone()),
+        }
+    }
+}
+
+/// An in memory inverted index from each metadata key and value to the embeddings that have that value.
+///
+/// Equality filters are answered with a lookup. Range filters compare each distinct value of the key once instead of reading the metadata of every embedding.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MetadataIndex {
+    values: FxHashMap<String, FxHashMap<IndexedValue, RoaringBitmap>>,
+    all: RoaringBitmap,
+}
+
+impl MetadataIndex {
+    /// Add the metadata of an embedding to the index. Remove any old metadata for the embedding first.
+    pub(crate) fn insert(&mut self, id: u32, metadata: &Metadata) {
+        self.all.insert(id);
+        for (key, value) in metadata.iter() {
+            self.values
+                .entry(key.clone())
+                .or_default()
+                .entry(IndexedValue::new(value))
+                .or_default()
+                .insert(id);
+        }
+    }
+
+    /// Remove the metadata of an embedding from the index.
+    pub(crate) fn remove(&mut self, id: u32, metadata: &Metadata) {
+        self.all.remove(id);
+        for (key, value) in metadata.iter() {
+            let Some(values) = self.values.get_mut(key) else {
+                continue;
+            };
+            let value = IndexedValue::new(value);
+            if let Some(ids) = values.get_mut(&value) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    values.remove(&value);
+                }
+            }
+            if values.is_empty() {
+                self.values.remove(key);
+            }
+        }
+    }
+
+    /// Remove every embedding from the index.
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.all.clear();
+    }
+
+    /// Get the ids of every embedding with metadata that matches the filter.
+    pub(crate) fn matching(&self, filter: &MetadataFilter) -> RoaringBitmap {
+        match filter {
+            MetadataFilter::Eq(key, value) => self.equal_to(key, value),
+            MetadataFilter::OneOf(key, values) => values
+                .iter()
+                .map(|value| self.equal_to(key, value))
+                .fold(RoaringBitmap::new(), |a, b| a | b),
+            MetadataFilter::Range { key, .. } | MetadataFilter::Exists(key) => {
+                self.matching_values(key, filter)
+            }
+            MetadataFilter::And(filters) => {
+                let mut filters = filters.iter();
+                let Some(first) = filters.next() else {
+                    return self.all.clone();
+                };
+                filters.fold(self.matching(first), |ids, filter| {
+                    if ids.is_empty() {
+                        ids
+                    } else {
+                        ids & self.matching(filter)
+                    }
+                })
+            }
+            MetadataFilter::Or(filters) => filters
+                .iter()
+                .map(|filter| self.matching(filter))
+                .fold(RoaringBitmap::new(), |a, b| a | b),
+            MetadataFilter::Not(filter) => &self.all - self.matching(filter),
+        }
+    }
+
+    /// Check the filter against each distinct value of the key.
+    fn matching_values(&self, key: &str, filter: &MetadataFilter) -> RoaringBitmap {
+        let Some(values) = self.values.get(key) else {
+            return RoaringBitmap::new();
+        };
+        values
+            .iter()
+            .filter(|(value, _)| filter.matches(&Metadata::new().with(key, value.value())))
+            .fold(RoaringBitmap::new(), |ids, (_, matching)| ids | matching)
+    }
+
+    fn equal_to(&self, key: &str, value: &MetadataValue) -> RoaringBitmap {
+        let Some(values) = self.values.get(key) else {
+            return RoaringBitmap::new();
+        };
+        IndexedValue::equal_to(value)
+            .iter()
+            .filter_map(|value| values.get(value))
+            .fold(RoaringBitmap::new(), |ids, matching| ids | matching)
+    }
+}
+
+#[test]
+fn index_matches_filters() {
+    let records = [
+        Metadata::new().with("tenant", "acme").with("year", 2023),
+        Metadata::new().with("tenant", "acme").with("year", 2024.),
+        Metadata::new().with("tenant", "globex").with("year", 2024),
+        Metadata::new().with("tenant", "globex"),
+    ];
+    let mut index = MetadataIndex::default();
+    for (id, metadata) in records.iter().enumerate() {
+        index.insert(id as u32, metadata);
+    }
+
+    let filters = [
+        MetadataFilter::eq("tenant", "acme"),
+        MetadataFilter::eq("year", 2024),
+        MetadataFilter::one_of("tenant", ["acme", "initech"]),
+        MetadataFilter::at_least("year", 2024),
+        MetadataFilter::exists("year"),
+        MetadataFilter::eq("tenant", "globex").and(MetadataFilter::between("year", 2020, 2030)),
+        MetadataFilter::eq("year", 2023).or(MetadataFilter::eq("tenant", "globex")),
+        MetadataFilter::eq("tenant", "acme").not(),
+        MetadataFilter::And(Vec::new()),
+    ];
+    for filter in &filters {
+        let expected = records
+            .iter()
+            .enumerate()
+            .filter(|(_, metadata)| filter.matches(metadata))
+            .map(|(id, _)| id as u32)
+            .collect::<RoaringBitmap>();
+        assert_eq!(index.matching(filter), expected, "{filter:?}");
+    }
+
+    index.remove(0, &records[0]);
+    assert_eq!(
+        index.matching(&MetadataFilter::eq("tenant", "acme")),
+        [1].into_iter().collect::<RoaringBitmap>()
+    );
+    assert!(!index.values["year"].contains_key(&IndexedValue::Int(2023)));
+}
@@ -0,0 +1,1366 @@
+//! A vector database that can be used to store embeddings and search for similar embeddings.
+
+use std::fmt::Debug;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Mutex, RwLock};
+
+use arroy::{Database as ArroyDatabase, Reader, Writer};
+use candle_core::Tensor;
+use heed::types::{SerdeJson, U32};
+use heed::EnvOpenOptions;
+use kalosm_language_model::*;
+use kalosm_llama::accelerated_device_if_available;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use roaring::RoaringBitmap;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+mod distance;
+pub use distance::*;
+mod groups;
+use groups::*;
+mod lexical;
+pub use lexical::*;
+mod metadata;
+pub use metadata::*;
+mod quantization;
+pub use quantization::Quantization;
+use quantization::*;
+
+type MetadataDatabase = heed::Database<U32<heed::byteorder::NativeEndian>, SerdeJson<Metadata>>;
+
+/// A vector database that can be used to store embeddings and search for similar embeddings.
+///
+/// It uses an in memory database with fast lookups for nearest neighbors and points within a certain distance.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # use kalosm_language_model::*;
+/// # use rbert::*;
+/// # use std::collections::HashMap;
+/// # #[tokio::main]
+/// # async fn main() {
+/// // Create a good default Bert model for search
+/// let bert = Bert::new_for_search().await.unwrap();
+/// let sentences = [
+///     "Kalosm can be used to build local AI applications",
+///     "With private LLMs data never leaves your computer",
+///     "The quick brown fox jumps over the lazy dog",
+/// ];
+/// // Embed sentences into the vector space
+/// let embeddings = bert.embed_batch(sentences).await.unwrap();
+/// println!("embeddings {:?}", embeddings);
+///
+/// // Create a vector database from the embeddings along with a map between the embedding ids and the sentences
+/// let db = VectorDB::new().unwrap();
+/// let embeddings = db.add_embeddings(embeddings).unwrap();
+/// let embedding_id_to_sentence: HashMap<EmbeddingId, &str> =
+///     HashMap::from_iter(embeddings.into_iter().zip(sentences));
+///
+/// // Find the closest sentence to "What is Kalosm?"
+/// let query = "What is Kalosm?";
+/// // Embed the query into the vector space. We use `embed_query` instead of `embed` because some models embed queries differently than normal text.
+/// let embedding = bert.embed_query(query).await.unwrap();
+/// let closest = db.get_closest(embedding, 1).unwrap();
+/// if let [closest] = closest.as_slice() {
+///     let distance = closest.distance;
+///     let text = embedding_id_to_sentence.get(&closest.value).unwrap();
+///     println!("distance: {distance}");
+///     println!("closest:  {text}");
+/// }
+/// # }
+/// ```
+#[doc(alias = "VectorDatabase")]
+#[doc(alias = "Vector Database")]
+pub struct VectorDB<S = UnknownVectorSpace, D = Angular> {
+    database: ArroyDatabase<D>,
+    index: VectorIndex,
+    env: heed::Env,
+    metadata: MetadataDatabase,
+    metadata_env: heed::Env,
+    metadata_index: RwLock<MetadataIndex>,
+    max_id: Mutex<EmbeddingId>,
+    recycled_ids: Mutex<Vec<EmbeddingId>>,
+    dim: AtomicUsize,
+    lexical: RwLock<LexicalIndex>,
+    quantized: Option<QuantizedStore>,
+    rescore: Option<usize>,
+    dimensions: Option<usize>,
+    groups: EmbeddingGroups,
+    _phantom: std::marker::PhantomData<S>,
+}
+
+impl<S: VectorSpace + Sync, D: VectorDistance> Default for VectorDB<S, D> {
+    fn default() -> Self {
+        VectorDBBuilder::default().build().unwrap()
+    }
+}
+
+impl<S: VectorSpace + Sync> VectorDB<S> {
+    /// Create a new temporary vector database.
+    #[tracing::instrument]
+    pub fn new() -> heed::Result<Self> {
+        Self::builder().build()
+    }
+
+    /// Create a new vector database at the given path.
+    pub fn new_at(path: impl AsRef<std::path::Path>) -> heed::Result<Self> {
+        Self::builder().at(path).build()
+    }
+
+    /// Create a new [`VectorDBBuilder`] to configure the distance metric, index or location of the database.
+    pub fn builder() -> VectorDBBuilder<S> {
+        VectorDBBuilder::default()
+    }
+}
+
+impl<S: VectorSpace + Sync, D: VectorDistance> VectorDB<S, D> {
+    fn set_dim(&self, dim: usize) {
+        if dim == 0 {
+            panic!("Dimension cannot be 0");
+        }
+        self.dim.store(dim, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn get_dim(&self) -> anyhow::Result<usize> {
+        let mut dims = self.dim.load(std::sync::atomic::Ordering::Relaxed);
+        if dims == 0 {
+            dims = match (&self.quantized, self.index) {
+                (Some(store), _) => match store.dimensions(&self.env.read_txn()?)? {
+                    Some(dims) => dims,
+                    None => anyhow::bail!("The vector database is empty"),
+                },
+                (None, VectorIndex::Approximate) => {
+                    let rtxn = self.env.read_txn()?;
+                    Reader::<D>::open(&rtxn, 0, self.database)?.dimensions()
+                }
+                // The flat index never builds the metadata the reader needs, so read the length of any item instead
+                (None, VectorIndex::Flat) => {
+                    let rtxn = self.env.read_txn()?;
+                    let first = self.writer(0).iter(&rtxn)?.next();
+                    match first {
+                        Some(item) => item?.1.len(),
+                        None => anyhow::bail!("The vector database is empty"),
+                    }
+                }
+            };
+            self.set_dim(dims);
+        }
+        Ok(dims)
+    }
+
+    fn open(path: &std::path::Path, options: &VectorDBBuilder<S, D>) -> heed::Result<Self> {
+        const TWENTY_HUNDRED_MIB: usize = 2 * 1024 * 1024 * 1024;
+
+        std::fs::create_dir_all(path)?;
+        let metadata_path = path.join("metadata");
+
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(TWENTY_HUNDRED_MIB)
+                .max_dbs(1)
+                .open(path)
+        }?;
+
+        let mut wtxn = env.write_txn()?;
+        let db: ArroyDatabase<D> = env.create_database(&mut wtxn, None)?;
+        // The quantized embeddings are a named database in the same environment as the full embeddings so both are written in one transaction. Arroy only reads keys that start with its index, so it never sees the name of the quantized database.
+        let quantized = match options.quantization {
+            Quantization::None => None,
+            quantization => Some(QuantizedStore::open(&env, &mut wtxn, quantization)?),
+        };
+        wtxn.commit()?;
+
+        // Metadata is stored in a separate environment so it never shares keys with the arroy database
+        std::fs::create_dir_all(&metadata_path)?;
+        let metadata_env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(TWENTY_HUNDRED_MIB)
+                .open(metadata_path)
+        }?;
+        let mut wtxn = metadata_env.write_txn()?;
+        let metadata: MetadataDatabase = metadata_env.create_database(&mut wtxn, None)?;
+        wtxn.commit()?;
+
+        // The metadata index is kept in memory, so rebuild it from the stored metadata
+        let mut metadata_index = MetadataIndex::default();
+        let rtxn = metadata_env.read_txn()?;
+        for entry in metadata.iter(&rtxn)? {
+            let (id, metadata) = entry?;
+            metadata_index.insert(id, &metadata);
+        }
+        drop(rtxn);
+
+        let groups = EmbeddingGroups::open(&path.join("groups"), TWENTY_HUNDRED_MIB)?;
+
+        Ok(Self {
+            database: db,
+            index: options.index,
+            env,
+            metadata,
+            metadata_env,
+            metadata_index: RwLock::new(metadata_index),
+            max_id: Mutex::new(EmbeddingId(0)),
+            recycled_ids: Mutex::new(Vec::new()),
+            dim: AtomicUsize::new(0),
+            lexical: RwLock::new(LexicalIndex::new()),
+            quantized,
+            rescore: options.rescore,
+            dimensions: options.dimensions,
+            groups,
+            _phantom: std::marker::PhantomData,
+        })
+    }
+
+    fn take_id(&self) -> EmbeddingId {
+        self.recycled_ids.lock().unwrap().pop().unwrap_or_else(|| {
+            let mut locked = self.max_id.lock().unwrap();
+            let id = *locked;
+            locked.0 += 1;
+            id
+        })
+    }
+
+    fn recycle_id(&self, id: EmbeddingId) {
+        self.recycled_ids.lock().unwrap().push(id);
+    }
+
+    fn writer(&self, dims: usize) -> Writer<D> {
+        Writer::<D>::new(self.database, 0, dims)
+    }
+
+    /// Rebuild the trees of the approximate index after the embeddings change.
+    fn build_index(&self, wtxn: &mut heed::RwTxn, writer: Writer<D>) -> anyhow::Result<()> {
+        if self.index == VectorIndex::Approximate && self.quantized.is_none() {
+            let mut rng = StdRng::from_entropy();
+            writer.build(wtxn, &mut rng, None)?;
+        }
+        Ok(())
+    }
+
+    fn item_vector(&self, rtxn: &heed::RoTxn, id: u32) -> anyhow::Result<Option<Vec<f32>>> {
+        // The dimensions of the writer are only used when adding items
+        Ok(self.writer(0).item_vector(rtxn, id)?)
+    }
+
+    /// Quantized databases only keep the f32 embeddings if they are needed for rescoring.
+    fn stores_full_vectors(&self) -> bool {
+        self.quantized.is_none() || self.rescore.is_some()
+    }
+
+    /// Convert an embedding into the vector that is stored and searched, truncating it if the database has a fixed number of dimensions.
+    fn prepare(&self, embedding: &Embedding<S>) -> anyhow::Result<Vec<f32>> {
+        match self.dimensions {
+            Some(dimensions) => Ok(embedding.truncate(dimensions).to_vec()),
+            None => Ok(embedding.vector().to_vec1()?),
+        }
+    }
+
+    /// Get the closest N items to the vector, only considering the candidates if they are set.
+    fn search(
+        &self,
+        vector: &[f32],
+        n: usize,
+        candidates: Option<&RoaringBitmap>,
+    ) -> anyhow::Result<Vec<(u32, f32)>> {
+        let rtxn = self.env.read_txn()?;
+
+        if let Some(store) = &self.quantized {
+            let query = QuantizedQuery::new(store.quantization(), vector);
+            return match self.rescore {
+                Some(oversample) => {
+                    let results = store.closest::<D>(
+                        &rtxn,
+                        &query,
+                        n.saturating_mul(oversample),
+                        candidates,
+                    )?;
+                    let items = results.into_iter().filter_map(|(id, _)| {
+                        self.item_vector(&rtxn, id)
+                            .transpose()
+                            .map(|item| item.map(|item| (id, item)))
+                    });
+                    Self::exact_closest(vector, items, n)
+                }
+                None => store.closest::<D>(&rtxn, &query, n, candidates),
+            };
+        }
+
+        match candidates {
+            Some(candidates)
+                if self.index == VectorIndex::Flat || candidates.len() <= EXACT_FILTER_LIMIT =>
+            {
+                let items = candidates.iter().filter_map(|id| {
+                    self.item_vector(&rtxn, id)
+                        .transpose()
+                        .map(|item| item.map(|item| (id, item)))
+                });
+                Self::exact_closest(vector, items, n)
+            }
+            None if self.index == VectorIndex::Flat => Self::exact_closest(
+                vector,
+                self.writer(0)
+                    .iter(&rtxn)?
+                    .map(|item| item.map_err(anyhow::Error::from)),
+                n,
+            ),
+            _ => {
+                let reader = Reader::<D>::open(&rtxn, 0, self.database)?;
+                Ok(reader
+                    .nns_by_vector(&rtxn, vector, n, None, candidates)?
+                    .into_iter()
+                    .map(|(id, distance)| (id, D::from_index_distance(distance)))
+                    .collect())
+            }
+        }
+    }
+
+    /// Get the distance between the vector and a single item on the same scale as [`VectorDB::search`].
+    fn distance_to(&self, vector: &[f32], id: u32) -> anyhow::Result<Option<f32>> {
+        if let (Some(store), None) = (&self.quantized, self.rescore) {
+            let query = QuantizedQuery::new(store.quantization(), vector);
+            return store.distance::<D>(&self.env.read_txn()?, &query, id);
+        }
+        let rtxn = self.env.read_txn()?;
+        Ok(self
+            .item_vector(&rtxn, id)?
+            .map(|item| D::distance(vector, &item)))
+    }
+
+    /// Get the distance between the vector and the closest embedding in a group. Embeddings that are not in a group are a group of one.
+    fn group_distance(&self, vector: &[f32], group: u32) -> anyhow::Result<Option<f32>> {
+        let mut members = self.groups.members(group)?;
+        if members.is_empty() {
+            members.push(group);
+        }
+        let mut closest = None;
+        for member in members {
+            if let Some(distance) = self.distance_to(vector, member)? {
+                closest = Some(closest.map_or(distance, |closest: f32| closest.min(distance)));
+            }
+        }
+        Ok(closest)
+    }
+
+    /// Compute the exact distance to each item and return the closest N.
+    fn exact_closest(
+        vector: &[f32],
+        items: impl IntoIterator<Item = anyhow::Result<(u32, Vec<f32>)>>,
+        n: usize,
+    ) -> anyhow::Result<Vec<(u32, f32)>> {
+        let mut results = Vec::new();
+        for item in items {
+            let (id, item) = item?;
+            results.push((id, D::distance(vector, &item)));
+        }
+        results.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        results.truncate(n);
+        Ok(results)
+    }
+
+    /// Get the stored vector for an item, dequantizing it if the full vector is not stored.
+    fn stored_vector(&self, id: u32) -> anyhow::Result<Option<Vec<f32>>> {
+        let rtxn = self.env.read_txn()?;
+        match &self.quantized {
+            Some(store) if !self.stores_full_vectors() => {
+                Ok(store.get(&rtxn, id)?.map(|item| item.to_vec()))
+            }
+            _ => self.item_vector(&rtxn, id),
+        }
+    }
+
+    /// Get the underlying database.
+    pub fn raw(&self) -> (&ArroyDatabase<D>, &heed::Env) {
+        (&self.database, &self.env)
+    }
+
+    /// Get the index this database searches with.
+    pub fn index(&self) -> VectorIndex {
+        self.index
+    }
+
+    /// Get the quantization this database stores embeddings with.
+    pub fn quantization(&self) -> Quantization {
+        self.quantized
+            .as_ref()
+            .map(|store| store.quantization())
+            .unwrap_or_default()
+    }
+
+    /// Clear the vector database.
+    pub async fn clear(&self) -> anyhow::Result<()> {
+        let dims = match self.stores_full_vectors() {
+            true => Some(self.get_dim()?),
+            false => None,
+        };
+        let mut wtxn = self.env.write_txn()?;
+        if let Some(dims) = dims {
+            self.writer(dims).clear(&mut wtxn)?;
+        }
+        if let Some(store) = &self.quantized {
+            store.clear(&mut wtxn)?;
+        }
+        wtxn.commit()?;
+        self.groups.clear()?;
+
+        let mut wtxn = self.metadata_env.write_txn()?;
+        self.metadata.clear(&mut wtxn)?;
+        wtxn.commit()?;
+        self.metadata_index.write().unwrap().clear();
+
+        // Reset the ids
+        self.max_id.lock().unwrap().0 = 0;
+        self.recycled_ids.lock().unwrap().clear();
+        self.lexical.write().unwrap().clear();
+
+        Ok(())
+    }
+
+    /// Remove an embedding from the vector database.
+    pub fn remove_embedding(&self, embedding_id: EmbeddingId) -> anyhow::Result<()> {
+        let dims = match self.stores_full_vectors() {
+            true => Some(self.get_dim()?),
+            false => None,
+        };
+        let mut wtxn = self.env.write_txn()?;
+        if let Some(dims) = dims {
+            let writer = self.writer(dims);
+
+            writer.del_item(&mut wtxn, embedding_id.0)?;
+
+            self.build_index(&mut wtxn, writer)?;
+        }
+        if let Some(store) = &self.quantized {
+            store.remove(&mut wtxn, embedding_id.0)?;
+        }
+        wtxn.commit()?;
+        self.groups.remove(embedding_id.0)?;
+        self.lexical.write().unwrap().remove(embedding_id);
+        self.recycle_id(embedding_id);
+
+        let mut wtxn = self.metadata_env.write_txn()?;
+        let old = self.metadata.get(&wtxn, &embedding_id.0)?;
+        self.metadata.delete(&mut wtxn, &embedding_id.0)?;
+        wtxn.commit()?;
+        if let Some(old) = old {
+            self.metadata_index
+                .write()
+                .unwrap()
+                .remove(embedding_id.0, &old);
+        }
+
+        Ok(())
+    }
+
+    /// Add a new embedding to the vector database.
+    ///
+    /// Note: Adding embeddings in a batch with [`VectorDB::add_embeddings`] will be faster.
+    pub fn add_embedding(&self, embedding: Embedding<S>) -> anyhow::Result<EmbeddingId> {
+        let ids = self.add_embeddings([embedding])?;
+        Ok(ids[0])
+    }
+
+    /// Add a new batch of embeddings to the vector database.
+    pub fn add_embeddings(
+        &self,
+        embedding: impl IntoIterator<Item = Embedding<S>>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        let embeddings = embedding
+            .into_iter()
+            .map(|e| self.prepare(&e))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let Some(first_embedding) = embeddings.first() else {
+            return Ok(Vec::new());
+        };
+        self.set_dim(first_embedding.len());
+
+        let ids: Vec<_> = embeddings.iter().map(|_| self.take_id()).collect();
+
+        // The full and quantized embeddings are written in one transaction so they never disagree
+        let mut wtxn = self.env.write_txn()?;
+        if self.stores_full_vectors() {
+            let writer = self.writer(first_embedding.len());
+
+            for (id, embedding) in ids.iter().zip(&embeddings) {
+                writer.add_item(&mut wtxn, id.0, embedding)?;
+            }
+
+            self.build_index(&mut wtxn, writer)?;
+        }
+        if let Some(store) = &self.quantized {
+            store.insert(
+                &mut wtxn,
+                ids.iter()
+                    .zip(&embeddings)
+                    .map(|(id, embedding)| (id.0, embedding.as_slice())),
+            )?;
+        }
+        wtxn.commit()?;
+
+        Ok(ids)
+    }
+
+    /// Get the embedding for an embedding id.
+    ///
+    /// If the database is quantized without rescoring, this returns the dequantized embedding.
+    pub fn get_embedding(&self, embedding_id: EmbeddingId) -> anyhow::Result<Embedding<S>> {
+        let embedding = self
+            .stored_vector(embedding_id.0)?
+            .ok_or_else(|| anyhow::anyhow!("Embedding not found"))?;
+
+        let shape = (embedding.len(),);
+        Ok(Embedding::new(Tensor::from_vec(
+            embedding,
+            shape,
+            &accelerated_device_if_available()?,
+        )?))
+    }
+
+    /// Get the closest N embeddings to the given embedding.
+    pub fn get_closest(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        let vector = self.prepare(&embedding)?;
+        let arroy_results = self.search(&vector, n, None)?;
+
+        Ok(arroy_results
+            .into_iter()
+            .map(|(id, distance)| {
+                let value = EmbeddingId(id);
+                VectorDBSearchResult { distance, value }
+            })
+            .collect::<Vec<_>>())
+    }
+
+    /// Attach metadata to an embedding. The metadata replaces any metadata the embedding already had. Searches with [`VectorDB::get_closest_filtered`] can filter embeddings by their metadata.
+    pub fn set_metadata(
+        &self,
+        embedding_id: EmbeddingId,
+        metadata: &Metadata,
+    ) -> anyhow::Result<()> {
+        self.put_metadata([(embedding_id.0, metadata)])
+    }
+
+    /// Store the metadata of each embedding and update the metadata index.
+    fn put_metadata<'a>(
+        &self,
+        entries: impl IntoIterator<Item = (u32, &'a Metadata)>,
+    ) -> anyhow::Result<()> {
+        let mut index = self.metadata_index.write().unwrap();
+        let mut wtxn = self.metadata_env.write_txn()?;
+        let mut updates = Vec::new();
+        for (id, metadata) in entries {
+            let old = self.metadata.get(&wtxn, &id)?;
+            self.metadata.put(&mut wtxn, &id, metadata)?;
+            updates.push((id, old, metadata));
+        }
+        wtxn.commit()?;
+        for (id, old, metadata) in updates {
+            if let Some(old) = old {
+                index.remove(id, &old);
+            }
+            index.insert(id, metadata);
+        }
+        Ok(())
+    }
+
+    /// Get the metadata attached to an embedding.
+    pub fn get_metadata(&self, embedding_id: EmbeddingId) -> anyhow::Result<Option<Metadata>> {
+        let rtxn = self.metadata_env.read_txn()?;
+        Ok(self.metadata.get(&rtxn, &embedding_id.0)?)
+    }
+
+    /// Add a new batch of embeddings with metadata to the vector database.
+    pub fn add_embeddings_with_metadata(
+        &self,
+        embeddings: impl IntoIterator<Item = (Embedding<S>, Metadata)>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        let (embeddings, metadata): (Vec<_>, Vec<_>) = embeddings.into_iter().unzip();
+        let ids = self.add_embeddings(embeddings)?;
+        self.put_metadata(ids.iter().map(|id| id.0).zip(&metadata))?;
+        Ok(ids)
+    }
+
+    /// Get the ids of every embedding with metadata that matches the filter.
+    ///
+    /// Equality filters are looked up in an in memory index of the metadata. Range filters only compare each distinct value of the key.
+    pub fn matching_ids(&self, filter: &MetadataFilter) -> anyhow::Result<RoaringBitmap> {
+        Ok(self.metadata_index.read().unwrap().matching(filter))
+    }
+
+    /// Get the closest N embeddings to the given embedding that have metadata matching the filter.
+    ///
+    /// The filter is applied during the search instead of to the results, so you get N results even if most embeddings don't match. When only a few embeddings match or the database uses a [`VectorIndex::Flat`] index, their distances are computed exactly instead of searching the approximate index.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # use rbert::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let db = VectorDB::new()?;
+    /// db.add_embeddings_with_metadata([
+    ///     (
+    ///         bert.embed("Our refund window is 30 days").await?,
+    ///         Metadata::new().with("tenant", "acme").with("language", "en"),
+    ///     ),
+    ///     (
+    ///         bert.embed("Refunds are processed within a week").await?,
+    ///         Metadata::new().with("tenant", "globex").with("language", "en"),
+    ///     ),
+    /// ])?;
+    ///
+    /// let closest = db.get_closest_filtered(
+    ///     bert.embed_query("How do refunds work?").await?,
+    ///     1,
+    ///     &MetadataFilter::eq("tenant", "acme"),
+    /// )?;
+    /// println!("{:?}", closest);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_closest_filtered(
+        &self,
+        embedding: Embedding<S>,
+        n: usize,
+        filter: &MetadataFilter,
+    ) -> anyhow::Result<Vec<VectorDBSearchResult>> {
+        let candidates = self.matching_ids(filter)?;
+        if candidates.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let vector = self.prepare(&embedding)?;
+        let arroy_results = self.search(&vector, n, Some(&candidates))?;
+
+        Ok(arroy_results
+            .into_iter()
+            .map(|(id, distance)| {
+                let value = EmbeddingId(id);
+                VectorDBSearchResult { distance, value }
+            })
+            .collect::<Vec<_>>())
+    }
+
+    /// Index the text behind an embedding so it can be found by [`VectorDB::get_closest_hybrid`]. If the embedding already has text in the index, the text is replaced.
+    ///
+    /// The lexical index is kept in memory. If you reopen a database with [`VectorDB::new_at`], you need to index the text again.
+    pub fn index_text(&self, embedding_id: EmbeddingId, text: &str) {
+        self.lexical.write().unwrap().insert(embedding_id, text);
+    }
+
+    /// Get the top N embeddings whose indexed text best matches the query with [BM25](https://en.wikipedia.org/wiki/Okapi_BM25) scoring.
+    pub fn get_closest_lexical(&self, query: &str, n: usize) -> Vec<LexicalSearchResult> {
+        self.lexical
+            .read()
+            .unwrap()
+            .search(query, n)
+            .into_iter()
+            .map(|(value, score)| LexicalSearchResult { score, value })
+            .collect()
+    }
+
+    /// Get the closest N embeddings by combining a vector search for the embedding with a lexical search for the query text.
+    ///
+    /// The top candidates from both searches are scored with both the vector distance and the lexical score, then the scores are fused with the [`HybridFusion`] strategy. Only text added with [`VectorDB::index_text`] can be found by the lexical search.
+    ///
+    /// Embeddings added with [`VectorDB::add_embedding_group`] are returned once per group with the id of the group and the distance of the closest embedding in the group, so the text of a group only needs to be indexed under the id of the group.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # use rbert::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let sentences = [
+    ///     "Part XR-2291 is the replacement fan for the router",
+    ///     "The router has four ethernet ports",
+    /// ];
+    /// let db = VectorDB::new()?;
+    /// let ids = db.add_embeddings(bert.embed_batch(sentences).await?)?;
+    /// for (id, sentence) in ids.iter().zip(sentences) {
+    ///     db.index_text(*id, sentence);
+    /// }
+    ///
+    /// let query = "Where can I buy XR-2291?";
+    /// let closest = db.get_closest_hybrid(
+    ///     bert.embed_query(query).await?,
+    ///     query,
+    ///     1,
+    ///     HybridFusion::default(),
+    /// )?;
+    /// println!("{:?}", closest);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_closest_hybrid(
+        &self,
+        embedding: Embedding<S>,
+        query: &str,
+        n: usize,
+        fusion: HybridFusion,
+    ) -> anyhow::Result<Vec<HybridSearchResult>> {
+        let candidate_count = n.saturating_mul(HYBRID_CANDIDATE_MULTIPLIER);
+        let vector = self.prepare(&embedding)?;
+        // Embeddings in a group are returned once as their group, with the distance of the closest member
+        let mut vector_results: Vec<(u32, f32)> = Vec::new();
+        for (id, distance) in self.search(&vector, candidate_count, None)? {
+            let group = self.groups.group_of(id)?.unwrap_or(id);
+            if vector_results.iter().all(|(other, _)| *other != group) {
+                vector_results.push((group, distance));
+            }
+        }
+        let lexical = self.lexical.read().unwrap();
+        let lexical_results = lexical.search(query, candidate_count);
+
+        let vector_ranks = vector_results
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (EmbeddingId(*id), rank))
+            .collect::<FxHashMap<_, _>>();
+        let lexical_ranks = lexical_results
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (*id, rank))
+            .collect::<FxHashMap<_, _>>();
+
+        // Score every candidate from either search with both the vector distance and the lexical score
+        let mut candidates = vector_results
+            .iter()
+            .map(|(id, distance)| (EmbeddingId(*id), *distance))
+            .collect::<Vec<_>>();
+        for (id, _) in &lexical_results {
+            if vector_ranks.contains_key(id) {
+                continue;
+            }
+            if let Some(distance) = self.group_distance(&vector, id.0)? {
+                candidates.push((*id, distance));
+            }
+        }
+        let candidates = candidates
+            .into_iter()
+            .map(|(value, distance)| HybridSearchResult {
+                score: 0.,
+                distance,
+                lexical_score: lexical.score(value, query),
+                value,
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = fusion.fuse(candidates, |result| {
+            (
+                vector_ranks.get(&result.value).copied(),
+                lexical_ranks.get(&result.value).copied(),
+            )
+        });
+        results.truncate(n);
+        Ok(results)
+    }
+
+    /// Add a group of embeddings that are scored together by [`VectorDB::get_closest_max_sim`], like the token embeddings from [`MultiVectorEmbedder`]. Returns the ids of the embeddings in the group. The first id identifies the group.
+    ///
+    /// Each embedding in the group is also stored as a normal embedding, so it can still be found by [`VectorDB::get_closest`]. Removing an embedding removes it from the group.
+    pub fn add_embedding_group(
+        &self,
+        embeddings: impl IntoIterator<Item = Embedding<S>>,
+    ) -> anyhow::Result<Vec<EmbeddingId>> {
+        let ids = self.add_embeddings(embeddings)?;
+        self.groups
+            .insert(&ids.iter().map(|id| id.0).collect::<Vec<_>>())?;
+        Ok(ids)
+    }
+
+    /// Get the group an embedding was added to with [`VectorDB::add_embedding_group`]. Returns `None` if the embedding is not in a group.
+    pub fn get_group(&self, embedding_id: EmbeddingId) -> anyhow::Result<Option<EmbeddingId>> {
+        Ok(self.groups.group_of(embedding_id.0)?.map(EmbeddingId))
+    }
+
+    /// Get the closest N groups of embeddings to a multi vector query with [late interaction](https://arxiv.org/abs/2004.12832) scoring.
+    ///
+    /// Every query embedding searches for its closest embeddings to find candidate groups. The candidates are then scored exactly with [`max_sim_vectors`]: the sum over the query embeddings of the highest cosine similarity with any embedding in the group. Embeddings that were not added with [`VectorDB::add_embedding_group`] are scored as a group of one.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm_language::prelude::*;
+    /// # use rbert::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search().await?;
+    /// let db = VectorDB::new()?;
+    /// for passage in [
+    ///     "Hold the reset button for ten seconds to restore the factory settings",
+    ///     "The router has four ethernet ports",
+    /// ] {
+    ///     db.add_embedding_group(bert.embed_document_tokens(passage).await?)?;
+    /// }
+    ///
+    /// let query = bert.embed_query_tokens("How do I reset the router?").await?;
+    /// let closest = db.get_closest_max_sim(&query, 1)?;
+    /// println!("{:?}", closest);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn get_closest_max_sim(
+        &self,
+        query: &[Embedding<S>],
+        n: usize,
+    ) -> anyhow::Result<Vec<MaxSimSearchResult>> {
+        let query = query
+            .iter()
+            .map(|embedding| self.prepare(embedding))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let candidates_per_vector = n.max(MAX_SIM_CANDIDATES_PER_QUERY_VECTOR);
+        let mut groups = Vec::new();
+        for vector in &query {
+            for (id, _) in self.search(vector, candidates_per_vector, None)? {
+                let group = self.groups.group_of(id)?.unwrap_or(id);
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+        }
+
+        let mut results = Vec::with_capacity(groups.len());
+        for group in groups {
+            let mut members = self.groups.members(group)?;
+            if members.is_empty() {
+                members.push(group);
+            }
+            let mut vectors = Vec::with_capacity(members.len());
+            for member in &members {
+                if let Some(vector) = self.stored_vector(*member)? {
+                    vectors.push(vector);
+                }
+            }
+            results.push(MaxSimSearchResult {
+                score: max_sim_vectors(&query, &vectors),
+                value: EmbeddingId(group),
+                embeddings: members.into_iter().map(EmbeddingId).collect(),
+            });
+        }
+        results.sort_by(|a, b| b.score.total_cmp(&a.score));
+        results.truncate(n);
+        Ok(results)
+    }
+}
+
+/// The minimum number of embeddings each query embedding searches for in [`VectorDB::get_closest_max_sim`]
+const MAX_SIM_CANDIDATES_PER_QUERY_VECTOR: usize = 16;
+
+/// Filters that match at most this many embeddings are searched exactly instead of with the approximate index
+const EXACT_FILTER_LIMIT: u64 = 1024;
+
+/// The number of candidates fetched from each search in [`VectorDB::get_closest_hybrid`] for every requested result
+const HYBRID_CANDIDATE_MULTIPLIER: usize = 4;
+
+/// A builder for a [`VectorDB`].
+///
+/// # Example
+/// ```rust, no_run
+/// # use kalosm_language::prelude::*;
+/// # fn main() -> anyhow::Result<()> {
+/// // An exact database for a model trained with dot product similarity
+/// let db: VectorDB<UnknownVectorSpace, DotProduct> = VectorDB::builder()
+///     .with_distance::<DotProduct>()
+///     .with_index(VectorIndex::Flat)
+///     .at("./db/embeddings")
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct VectorDBBuilder<S = UnknownVectorSpace, D = Angular> {
+    location: Option<std::path::PathBuf>,
+    index: VectorIndex,
+    quantization: Quantization,
+    rescore: Option<usize>,
+    dimensions: Option<usize>,
+    _phantom: std::marker::PhantomData<(S, D)>,
+}
+
+impl<S, D> Default for VectorDBBuilder<S, D> {
+    fn default() -> Self {
+        Self {
+            location: None,
+            index: VectorIndex::default(),
+            quantization: Quantization::default(),
+            rescore: None,
+            dimensions: None,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace + Sync, D: VectorDistance> VectorDBBuilder<S, D> {
+    /// Store the database at the given path. If no path is set, the database is stored in a temporary directory.
+    pub fn at(mut self, path: impl AsRef<std::path::Path>) -> Self {
+        self.location = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the distance metric the database searches with (defaults to [`Angular`]).
+    ///
+    /// The distance is not stored with the database, so reopen an existing database with the same distance it was created with.
+    pub fn with_distance<D2: VectorDistance>(self) -> VectorDBBuilder<S, D2> {
+        VectorDBBuilder {
+            location: self.location,
+            index: self.index,
+            quantization: self.quantization,
+            rescore: self.rescore,
+            dimensions: self.dimensions,
+            _phantom: std::marker::PhantomData,
+        }
+    }
+
+    /// Set the index the database searches with (defaults to [`VectorIndex::Approximate`]).
+    pub fn with_index(mut self, index: VectorIndex) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Store quantized embeddings instead of full f32 embeddings (defaults to [`Quantization::None`]).
+    ///
+    /// Quantized embeddings are always searched with an exact scan, so the [`VectorIndex`] is ignored.
+    pub fn with_quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = quantization;
+        self
+    }
+
+    /// Rescore the closest `oversample * n` quantized results with the full f32 embeddings when searching for n results.
+    ///
+    /// The full embeddings are kept on disk next to the quantized embeddings, so rescoring restores most of the accuracy lost to quantization at the cost of storage. This has no effect if the database is not quantized.
+    pub fn with_rescoring(mut self, oversample: usize) -> Self {
+        self.rescore = Some(oversample.max(1));
+        self
+    }
+
+    /// Truncate every embedding to the first `dimensions` dimensions before it is stored or searched. The truncated embeddings are normalized to unit length.
+    ///
+    /// This only works well for models trained with [Matryoshka representation learning](https://arxiv.org/abs/2205.13147). See [`Embedding::truncate`].
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Build the vector database.
+    pub fn build(self) -> heed::Result<VectorDB<S, D>> {
+        match &self.location {
+            Some(location) => VectorDB::open(location, &self),
+            None => {
+                let dir = tempfile::tempdir()?;
+                VectorDB::open(dir.path(), &self)
+            }
+        }
+    }
+}
+
+/// How to combine the vector and lexical scores in [`VectorDB::get_closest_hybrid`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HybridFusion {
+    /// [Reciprocal rank fusion](https://plg.uwaterloo.ca/~gvcormac/cormacksigir09-rrf.pdf). Each result scores `1 / (k + rank)` in each search it appears in. This only looks at the order of results, so it works well without tuning.
+    ReciprocalRank {
+        /// A constant that reduces the influence of the top few ranks. 60 is a good default.
+        k: f32,
+    },
+    /// A weighted sum of the vector and lexical scores after both are scaled to the range 0 to 1 across the candidates.
+    Weighted {
+        /// How much the vector score counts towards the final score. The lexical score has a weight of `1 - vector_weight`.
+        vector_weight: f32,
+    },
+}
+
+impl Default for HybridFusion {
+    fn default() -> Self {
+        Self::ReciprocalRank { k: 60. }
+    }
+}
+
+impl HybridFusion {
+    /// Set the fused score of each candidate and sort them from the highest to the lowest score. The ranks function returns the rank of a candidate in the vector and lexical searches.
+    fn fuse(
+        &self,
+        mut candidates: Vec<HybridSearchResult>,
+        ranks: impl Fn(&HybridSearchResult) -> (Option<usize>, Option<usize>),
+    ) -> Vec<HybridSearchResult> {
+        match *self {
+            HybridFusion::ReciprocalRank { k } => {
+                for candidate in &mut candidates {
+                    let (vector_rank, lexical_rank) = ranks(candidate);
+                    candidate.score = [vector_rank, lexical_rank]
+                        .into_iter()
+                        .flatten()
+                        .map(|rank| 1. / (k + rank as f32 + 1.))
+                        .sum();
+                }
+            }
+            HybridFusion::Weighted { vector_weight } => {
+                let (min_distance, max_distance) = min_max(candidates.iter().map(|c| c.distance));
+                let (min_lexical, max_lexical) =
+                    min_max(candidates.iter().map(|c| c.lexical_score));
+                for candidate in &mut candidates {
+                    // Smaller distances are better, so the vector score is flipped
+                    let vector_score = scale(
+                        max_distance - candidate.distance,
+                        max_distance - min_distance,
+                    );
+                    let lexical_score = scale(
+                        candidate.lexical_score - min_lexical,
+                        max_lexical - min_lexical,
+                    );
+                    candidate.score =
+                        vector_weight * vector_score + (1. - vector_weight) * lexical_score;
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        candidates
+    }
+}
+
+fn min_max(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    values.fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    })
+}
+
+/// Scale a value to the range 0 to 1. If every candidate has the same value, they all get the full score.
+fn scale(value: f32, range: f32) -> f32 {
+    if range > 0. {
+        value / range
+    } else {
+        1.
+    }
+}
+
+/// A resulting point from a hybrid search.
+#[derive(Debug, Clone)]
+pub struct HybridSearchResult {
+    /// The fused score of the result. Higher scores are better.
+    pub score: f32,
+    /// The distance from the searched embedding.
+    pub distance: f32,
+    /// The BM25 score of the indexed text for the query. Zero if the text doesn't share any terms with the query.
+    pub lexical_score: f32,
+    /// The value of the point.
+    pub value: EmbeddingId,
+}
+
+/// A resulting group from a late interaction search.
+#[derive(Debug, Clone)]
+pub struct MaxSimSearchResult {
+    /// The sum over the query embeddings of the highest cosine similarity with any embedding in the group. Higher scores are better.
+    pub score: f32,
+    /// The id of the group, which is the id of its first embedding.
+    pub value: EmbeddingId,
+    /// The embeddings in the group.
+    pub embeddings: Vec<EmbeddingId>,
+}
+
+/// A resulting point from a lexical search.
+#[derive(Debug, Clone)]
+pub struct LexicalSearchResult {
+    /// The BM25 score of the indexed text for the query. Higher scores are better.
+    pub score: f32,
+    /// The value of the point.
+    pub value: EmbeddingId,
+}
+
+/// A resulting point from a search.
+#[derive(Debug, Clone)]
+pub struct VectorDBSearchResult {
+    /// The distance from the searched point.
+    pub distance: f32,
+    /// The value of the point.
+    pub value: EmbeddingId,
+}
+
+/// A unique identifier for an embedding. If you delete an embedding, the id will be recycled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct EmbeddingId(pub u32);
+
+#[test]
+fn hybrid_search_finds_exact_terms() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let embedding =
+        |vector: [f32; 2]| Embedding::new(Tensor::new(&vector, &candle_core::Device::Cpu).unwrap());
+    let ids = db
+        .add_embeddings([
+            embedding([1., 0.]),
+            embedding([0.9, 0.1]),
+            embedding([0., 1.]),
+        ])
+        .unwrap();
+    for (id, text) in ids
+        .iter()
+        .zip(["apple pie recipe", "apple tart", "XR-2291 replacement fan"])
+    {
+        db.index_text(*id, text);
+    }
+
+    let reciprocal_rank = db
+        .get_closest_hybrid(embedding([1., 0.]), "xr-2291", 2, HybridFusion::default())
+        .unwrap();
+    assert_eq!(reciprocal_rank.len(), 2);
+    assert_eq!(reciprocal_rank[0].value, ids[2]);
+    assert!(reciprocal_rank[0].lexical_score > 0.);
+    assert_eq!(reciprocal_rank[1].value, ids[0]);
+    assert_eq!(reciprocal_rank[1].lexical_score, 0.);
+
+    let mostly_vector = db
+        .get_closest_hybrid(
+            embedding([1., 0.]),
+            "xr-2291",
+            1,
+            HybridFusion::Weighted { vector_weight: 0.9 },
+        )
+        .unwrap();
+    assert_eq!(mostly_vector[0].value, ids[0]);
+
+    db.remove_embedding(ids[2]).unwrap();
+    assert!(db.get_closest_lexical("xr-2291", 1).is_empty());
+}
+
+#[test]
+fn filtered_search_with_selective_filters() {
+    let db: VectorDB = VectorDB::new().unwrap();
+    let vector = |i: usize| {
+        (0..8)
+            .map(|j| ((i * 7 + j * 13) as f32).sin())
+            .collect::<Vec<_>>()
+    };
+    let ids = db
+        .add_embeddings_with_metadata((0..2000).map(|i| {
+            let embedding =
+                Embedding::new(Tensor::new(vector(i), &candle_core::Device::Cpu).unwrap());
+            let metadata = Metadata::new()
+                .with("tenant", if i % 500 == 0 { "rare" } else { "common" })
+                .with("index", i as i64);
+            (embedding, metadata)
+        }))
+        .unwrap();
+    let query = || Embedding::new(Tensor::new(vector(1234), &candle_core::Device::Cpu).unwrap());
+
+    // Only four embeddings match, so the results must be exactly those embeddings sorted by distance
+    let rare = MetadataFilter::eq("tenant", "rare");
+    let results = db.get_closest_filtered(query(), 10, &rare).unwrap();
+    let mut expected = (0..2000)
+        .filter(|i| i % 500 == 0)
+        .map(|i| (ids[i], Angular::distance(&vector(1234), &vector(i))))
+        .collect::<Vec<_>>();
+    expected.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    assert_eq!(
+        results.iter().map(|r| r.value).collect::<Vec<_>>(),
+        expected.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+    );
+
+    // A filter that matches a single embedding
+    let single = MetadataFilter::eq("index", 1999);
+    let results = db.get_closest_filtered(query(), 3, &single).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].value, ids[1999]);
+
+    // Larger filters go through the approximate index but still only return matching embeddings
+    let large = MetadataFilter::at_least("index", 300);
+    let results = db.get_closest_filtered(query(), 20, &large).unwrap();
+    assert_eq!(results.len(), 20);
+    for result in &results {
+        let metadata = db.get_metadata(result.value).unwrap().unwrap();
+        assert!(large.matches(&metadata));
+    }
+
+    // No embeddings match
+    let none = MetadataFilter::eq("tenant", "missing");
+    assert!(db
+        .get_closest_filtered(query(), 3, &none)
+        .unwrap()
+        .is_empty());
+
+    // Removed embeddings lose their metadata
+    db.remove_embedding(ids[1999]).unwrap();
+    assert!(db
+        .get_closest_filtered(query(), 3, &single)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn flat_index_is_exact_for_each_distance() {
+    fn check<D: VectorDistance>() {
+        let db: VectorDB<UnknownVectorSpace, D> = VectorDB::builder()
+            .with_distance::<D>()
+            .with_index(VectorIndex::Flat)
+            .build()
+            .unwrap();
+        let vector = |i: usize| {
+            (0..8)
+                .map(|j| ((i * 5 + j * 11) as f32).cos() * (1 + i % 3) as f32)
+                .collect::<Vec<_>>()
+        };
+        let embedding =
+            |i: usize| Embedding::new(Tensor::new(vector(i), &candle_core::Device::Cpu).unwrap());
+        let ids = db.add_embeddings((0..200).map(embedding)).unwrap();
+        db.remove_embedding(ids[17]).unwrap();
+
+        let mut expected = (0..200)
+            .filter(|i| *i != 17)
+            .map(|i| (ids[i], D::distance(&vector(1000), &vector(i))))
+            .collect::<Vec<_>>();
+        expected.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        expected.truncate(5);
+
+        let results = db.get_closest(embedding(1000), 5).unwrap();
+        assert_eq!(
+            results.iter().map(|r| r.value).collect::<Vec<_>>(),
+            expected.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+        for (result, (_, distance)) in results.iter().zip(&expected) {
+            assert!((result.distance - distance).abs() < 1e-4);
+        }
+        assert_eq!(db.get_embedding(ids[3]).unwrap().to_vec(), vector(3));
+    }
+
+    check::<Angular>();
+    check::<Euclidean>();
+    check::<DotProduct>();
+    check::<Manhattan>();
+}
+
+#[test]
+fn quantized_search_finds_near_duplicates() {
+    let vector = |i: usize| {
+        (0..64)
+            .map(|j| ((i * 31 + j * 7) as f32).sin() + ((i + j) as f32 * 0.3).cos())
+            .collect::<Vec<_>>()
+    };
+    let embedding =
+        |vector: Vec<f32>| Embedding::new(Tensor::new(vector, &candle_core::Device::Cpu).unwrap());
+    // A slightly perturbed copy of item 42
+    let query = || {
+        embedding(
+            vector(42)
+                .iter()
+                .enumerate()
+                .map(|(j, v)| v + 0.01 * (j as f32).cos())
+                .collect(),
+        )
+    };
+
+    for (quantization, rescore) in [
+        (Quantization::Binary, None),
+        (Quantization::Binary, Some(4)),
+        (Quantization::Int8, None),
+    ] {
+        let mut builder = VectorDB::builder().with_quantization(quantization);
+        if let Some(oversample) = rescore {
+            builder = builder.with_rescoring(oversample);
+        }
+        let db: VectorDB = builder.build().unwrap();
+        assert_eq!(db.quantization(), quantization);
+        let ids = db
+            .add_embeddings((0..300).map(|i| embedding(vector(i))))
+            .unwrap();
+
+        let results = db.get_closest(query(), 3).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].value, ids[42]);
+        assert!(results.windows(2).all(|w| w[0].distance <= w[1].distance));
+
+        db.remove_embedding(ids[42]).unwrap();
+        let results = db.get_closest(query(), 3).unwrap();
+        assert!(results.iter().all(|result| result.value != ids[42]));
+    }
+
+    // Rescored results have the exact distances of the full embeddings
+    let db: VectorDB = VectorDB::builder()
+        .with_quantization(Quantization::Binary)
+        .with_rescoring(4)
+        .build()
+        .unwrap();
+    let ids = db
+        .add_embeddings((0..300).map(|i| embedding(vector(i))))
+        .unwrap();
+    let results = db.get_closest(query(), 1).unwrap();
+    let exact = Angular::distance(&query().to_vec(), &vector(42));
+    assert_eq!(results[0].value, ids[42]);
+    assert!((results[0].distance - exact).abs() < 1e-5);
+
+    // Int8 embeddings are dequantized when they are read back
+    let db: VectorDB = VectorDB::builder()
+        .with_quantization(Quantization::Int8)
+        .build()
+        .unwrap();
+    let id = db.add_embedding(embedding(vector(7))).unwrap();
+    let stored = db.get_embedding(id).unwrap().to_vec();
+    for (stored, original) in stored.iter().zip(vector(7)) {
+        assert!((stored - original).abs() < 0.02);
+    }
+
+    // Matryoshka truncation keeps only the first dimensions
+    let db: VectorDB = VectorDB::builder().with_dimensions(16).build().unwrap();
+    let ids = db
+        .add_embeddings((0..50).map(|i| embedding(vector(i))))
+        .unwrap();
+    assert_eq!(db.get_embedding(ids[0]).unwrap().to_vec().len(), 16);
+    assert_eq!(db.get_closest(query(), 1).unwrap()[0].value, ids[42]);
+}
+
+#[test]
+fn max_sim_search_scores_whole_groups() {
+    let db: VectorDB = VectorDB::builder()
+        .with_index(VectorIndex::Flat)
+        .build()
+        .unwrap();
+    let embedding =
+        |vector: [f32; 3]| Embedding::new(Tensor::new(&vector, &candle_core::Device::Cpu).unwrap());
+    // The first group matches every query token, the second only matches one token very closely
+    let full = db
+        .add_embedding_group([
+            embedding([1., 0.1, 0.]),
+            embedding([0.1, 1., 0.]),
+            embedding([0., 0.1, 1.]),
+        ])
+        .unwrap();
+    let partial = db
+        .add_embedding_group([embedding([1., 0., 0.]), embedding([1., 0.01, 0.])])
+        .unwrap();
+    let single = db.add_embedding(embedding([0., 1., 0.])).unwrap();
+
+    let query = [
+        embedding([1., 0., 0.]),
+        embedding([0., 1., 0.]),
+        embedding([0., 0., 1.]),
+    ];
+    let results = db.get_closest_max_sim(&query, 3).unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].value, full[0]);
+    assert_eq!(results[0].embeddings, full);
+    assert!(results.windows(2).all(|w| w[0].score >= w[1].score));
+    let single_result = results.iter().find(|r| r.value == single).unwrap();
+    assert_eq!(single_result.embeddings, vec![single]);
+    assert!((single_result.score - 1.).abs() < 1e-5);
+
+    // Hybrid search returns each group once under the id of the group
+    db.index_text(full[0], "factory reset");
+    let results = db
+        .get_closest_hybrid(query[2].clone(), "reset", 10, HybridFusion::default())
+        .unwrap();
+    assert_eq!(results[0].value, full[0]);
+    assert_eq!(results.iter().filter(|r| r.value == full[0]).count(), 1);
+    assert!(results.iter().all(|r| r.value != full[2]));
+
+    // Removing embeddings removes them from their group
+    for id in &partial {
+        db.remove_embedding(*id).unwrap();
+    }
+    db.remove_embedding(full[2]).unwrap();
+    let results = db.get_closest_max_sim(&query, 3).unwrap();
+    assert!(results.iter().all(|r| r.value != partial[0]));
+    assert_eq!(results[0].embeddings, full[..2]);
+}
@@ -0,0 +1,287 @@
+use heed::types::{Bytes, U32};
+use kalosm_language_model::{BinaryEmbedding, Embedding, Int8Embedding, UnknownVectorSpace};
+use roaring::RoaringBitmap;
+
+use super::VectorDistance;
+
+type QuantizedDatabase = heed::Database<U32<heed::byteorder::NativeEndian>, Bytes>;
+
+/// How a [`super::VectorDB`] stores embeddings for search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quantization {
+    /// Store every dimension as a 32 bit float.
+    #[default]
+    None,
+    /// Store a single bit per dimension and search with the fraction of dimensions that have a different sign. The distance metric of the database is only used for rescoring.
+    Binary,
+    /// Store a signed byte per dimension and search with the distance metric of the database.
+    Int8,
+}
+
+/// An embedding stored in a quantized database.
+pub(crate) enum QuantizedVector {
+    Binary(BinaryEmbedding<UnknownVectorSpace>),
+    Int8(Int8Embedding<UnknownVectorSpace>),
+}
+
+impl QuantizedVector {
+    fn new(quantization: Quantization, vector: &[f32]) -> Self {
+        let embedding = Embedding::<UnknownVectorSpace>::from(vector.iter().copied());
+        match quantization {
+            Quantization::Binary => Self::Binary(embedding.quantize_binary()),
+            Quantization::Int8 | Quantization::None => Self::Int8(embedding.quantize_int8()),
+        }
+    }
+
+    /// Binary vectors are stored as the number of dimensions followed by the packed bits. Int8 vectors are stored as the scale followed by the values.
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::Binary(embedding) => {
+                let mut bytes = Vec::with_capacity(4 + embedding.words().len() * 8);
+                bytes.extend_from_slice(&(embedding.dimensions() as u32).to_le_bytes());
+                for word in embedding.words() {
+                    bytes.extend_from_slice(&word.to_le_bytes());
+                }
+                bytes
+            }
+            Self::Int8(embedding) => {
+                let mut bytes = Vec::with_capacity(4 + embedding.dimensions());
+                bytes.extend_from_slice(&embedding.scale().to_le_bytes());
+                bytes.extend(embedding.values().iter().map(|value| *value as u8));
+                bytes
+            }
+        }
+    }
+
+    fn decode(quantization: Quantization, bytes: &[u8]) -> anyhow::Result<Self> {
+        let (header, body) = bytes
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow::anyhow!("Quantized embedding is too short"))?;
+        Ok(match quantization {
+            Quantization::Binary => {
+                let dimensions = u32::from_le_bytes(*header) as usize;
+                let words = body
+                    .chunks_exact(8)
+                    .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+                    .collect::<Vec<_>>();
+                if words.len() != dimensions.div_ceil(64) {
+                    anyhow::bail!("Quantized embedding has the wrong number of dimensions");
+                }
+                Self::Binary(BinaryEmbedding::new(words, dimensions))
+            }
+            Quantization::Int8 | Quantization::None => Self::Int8(Int8Embedding::new(
+                body.iter().map(|value| *value as i8).collect(),
+                f32::from_le_bytes(*header),
+            )),
+        })
+    }
+
+    fn dimensions(&self) -> usize {
+        match self {
+            Self::Binary(embedding) => embedding.dimensions(),
+            Self::Int8(embedding) => embedding.dimensions(),
+        }
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<f32> {
+        match self {
+            Self::Binary(embedding) => embedding.to_vec(),
+            Self::Int8(embedding) => embedding.to_vec(),
+        }
+    }
+}
+
+/// A query prepared for a quantized search. The query is quantized once so every stored embedding can be scored from its encoded bytes without decoding it.
+pub(crate) enum QuantizedQuery {
+    Binary(Vec<u64>),
+    Int8 { values: Vec<i8>, scale: f32 },
+}
+
+impl QuantizedQuery {
+    pub(crate) fn new(quantization: Quantization, vector: &[f32]) -> Self {
+        match QuantizedVector::new(quantization, vector) {
+            QuantizedVector::Binary(binary) => Self::Binary(binary.words().to_vec()),
+            QuantizedVector::Int8(int8) => Self::Int8 {
+                values: int8.values().to_vec(),
+                scale: int8.scale(),
+            },
+        }
+    }
+
+    /// Score an encoded embedding (see [`QuantizedVector::encode`]). Binary embeddings are compared with the fraction of bits that differ. Int8 embeddings are compared with the distance metric of the database using integer arithmetic.
+    fn distance<D: VectorDistance>(&self, bytes: &[u8]) -> anyhow::Result<f32> {
+        let (header, body) = bytes
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow::anyhow!("Quantized embedding is too short"))?;
+        match self {
+            Self::Binary(query) => {
+                let dimensions = u32::from_le_bytes(*header) as usize;
+                if body.len() != query.len() * 8 {
+                    anyhow::bail!("Quantized embedding has the wrong number of dimensions");
+                }
+                let different_bits: u32 = query
+                    .iter()
+                    .zip(body.chunks_exact(8))
+                    .map(|(query, word)| {
+                        (query ^ u64::from_le_bytes(word.try_into().unwrap())).count_ones()
+                    })
+                    .sum();
+                Ok(different_bits as f32 / dimensions.max(1) as f32)
+            }
+            Self::Int8 { values, scale } => {
+                if body.len() != values.len() {
+                    anyhow::bail!("Quantized embedding has the wrong number of dimensions");
+                }
+                // Safety: i8 and u8 have the same size and alignment
+                let item =
+                    unsafe { std::slice::from_raw_parts(body.as_ptr() as *const i8, body.len()) };
+                Ok(D::int8_distance(
+                    values,
+                    *scale,
+                    item,
+                    f32::from_le_bytes(*header),
+                ))
+            }
+        }
+    }
+}
+
+/// The quantized embeddings of a [`super::VectorDB`]. The embeddings are stored in the same environment as the full embeddings so both can be written in one transaction.
+pub(crate) struct QuantizedStore {
+    quantization: Quantization,
+    database: QuantizedDatabase,
+}
+
+impl QuantizedStore {
+    pub(crate) fn open(
+        env: &heed::Env,
+        wtxn: &mut heed::RwTxn,
+        quantization: Quantization,
+    ) -> heed::Result<Self> {
+        let database: QuantizedDatabase = env.create_database(wtxn, Some("quantized"))?;
+        Ok(Self {
+            quantization,
+            database,
+        })
+    }
+
+    pub(crate) fn quantization(&self) -> Quantization {
+        self.quantization
+    }
+
+    pub(crate) fn insert<'a>(
+        &self,
+        wtxn: &mut heed::RwTxn,
+        items: impl IntoIterator<Item = (u32, &'a [f32])>,
+    ) -> anyhow::Result<()> {
+        for (id, vector) in items {
+            let quantized = QuantizedVector::new(self.quantization, vector);
+            self.database.put(wtxn, &id, &quantized.encode())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, wtxn: &mut heed::RwTxn, id: u32) -> anyhow::Result<()> {
+        self.database.delete(wtxn, &id)?;
+        Ok(())
+    }
+
+    pub(crate) fn clear(&self, wtxn: &mut heed::RwTxn) -> anyhow::Result<()> {
+        self.database.clear(wtxn)?;
+        Ok(())
+    }
+
+    pub(crate) fn get(
+        &self,
+        rtxn: &heed::RoTxn,
+        id: u32,
+    ) -> anyhow::Result<Option<QuantizedVector>> {
+        self.database
+            .get(rtxn, &id)?
+            .map(|bytes| QuantizedVector::decode(self.quantization, bytes))
+            .transpose()
+    }
+
+    /// Get the number of dimensions of the stored embeddings, or `None` if the store is empty.
+    pub(crate) fn dimensions(&self, rtxn: &heed::RoTxn) -> anyhow::Result<Option<usize>> {
+        self.database
+            .first(rtxn)?
+            .map(|(_, bytes)| {
+                QuantizedVector::decode(self.quantization, bytes).map(|item| item.dimensions())
+            })
+            .transpose()
+    }
+
+    /// Score the stored embeddings (or only the candidates) and return the closest N to the query.
+    pub(crate) fn closest<D: VectorDistance>(
+        &self,
+        rtxn: &heed::RoTxn,
+        query: &QuantizedQuery,
+        n: usize,
+        candidates: Option<&RoaringBitmap>,
+    ) -> anyhow::Result<Vec<(u32, f32)>> {
+        let mut results = Vec::new();
+        match candidates {
+            Some(candidates) => {
+                for id in candidates {
+                    if let Some(bytes) = self.database.get(rtxn, &id)? {
+                        results.push((id, query.distance::<D>(bytes)?));
+                    }
+                }
+            }
+            None => {
+                for item in self.database.iter(rtxn)? {
+                    let (id, bytes) = item?;
+                    results.push((id, query.distance::<D>(bytes)?));
+                }
+            }
+        }
+        results.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+        results.truncate(n);
+        Ok(results)
+    }
+
+    /// Get the distance between the query and a single stored embedding.
+    pub(crate) fn distance<D: VectorDistance>(
+        &self,
+        rtxn: &heed::RoTxn,
+        query: &QuantizedQuery,
+        id: u32,
+    ) -> anyhow::Result<Option<f32>> {
+        self.database
+            .get(rtxn, &id)?
+            .map(|bytes| query.distance::<D>(bytes))
+            .transpose()
+    }
+}
+
+#[test]
+fn int8_scores_match_dequantized_distances() {
+    use super::{Angular, DotProduct, Euclidean, Manhattan};
+
+    fn check<D: VectorDistance>() {
+        let query = (0..32).map(|i| (i as f32).sin()).collect::<Vec<_>>();
+        let item = (0..32)
+            .map(|i| (i as f32 * 0.7).cos() * 2.)
+            .collect::<Vec<_>>();
+        let encoded = QuantizedVector::new(Quantization::Int8, &item).encode();
+        let scored = QuantizedQuery::new(Quantization::Int8, &query)
+            .distance::<D>(&encoded)
+            .unwrap();
+
+        let dequantized =
+            |vector: &[f32]| QuantizedVector::new(Quantization::Int8, vector).to_vec();
+        let expected = D::distance(&dequantized(&query), &dequantized(&item));
+        assert!((scored - expected).abs() < 1e-3 * expected.abs().max(1.));
+
+        // Stored embeddings with a different number of dimensions are an error instead of a panic
+        assert!(QuantizedQuery::new(Quantization::Int8, &query[..16])
+            .distance::<D>(&encoded)
+            .is_err());
+    }
+
+    check::<Angular>();
+    check::<Euclidean>();
+    check::<DotProduct>();
+    check::<Manhattan>();
+}
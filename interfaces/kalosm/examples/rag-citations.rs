@@ -0,0 +1,62 @@
+use kalosm::language::*;
+use surrealdb::{engine::local::RocksDb, Surreal};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let exists = std::path::Path::new("./db").exists();
+
+    // Create database connection
+    let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+
+    // Select a specific namespace / database
+    db.use_ns("test").use_db("test").await?;
+
+    // Create a table in the surreal database to store the embeddings
+    let document_table = db
+        .document_table_builder("documents")
+        .with_chunker(TokenChunker::new())
+        .at("./db/embeddings.db")
+        .build::<Document>()
+        .await?;
+
+    // If the database is new, add documents to it
+    if !exists {
+        let context = [
+            "https://floneum.com/kalosm/docs",
+            "https://floneum.com/kalosm/docs/guides/retrieval_augmented_generation",
+        ]
+        .iter()
+        .map(|url| Url::parse(url).unwrap());
+
+        document_table.add_context(context).await?;
+    }
+
+    // Search with both vector and keyword search, then rerank the top candidates
+    let retriever = RerankedRetriever::new(
+        document_table.hybrid_retriever(HybridFusion::default()),
+        RerankStage::new(BertReranker::new().await?),
+    );
+
+    let rag = Rag::new(retriever, Llama::new_chat().await?)
+        .with_top_k(5)
+        .with_context_tokens(1024)
+        .with_no_answer("I couldn't find the answer in the documents.");
+
+    loop {
+        let user_question = prompt_input("\n> ")?;
+        let answer = rag.answer(&user_question).await?;
+        println!("{}", answer.text);
+
+        // Show where each sentence of the answer came from
+        for citation in &answer.citations {
+            println!("\n{}", answer.sentence(citation));
+            for source in &citation.sources {
+                let chunk = &answer.chunks[source.marker - 1];
+                println!(
+                    "  [{}] {} ({:?} bytes {:?})",
+                    source.marker, chunk.location, source.record_id, source.byte_range
+                );
+            }
+        }
+    }
+}
@@ -17,7 +17,9 @@ pub mod language {
     pub use kalosm_language::kalosm_llama::{Llama, LlamaBuilder, LlamaSession, LlamaSource};
     pub use kalosm_language::kalosm_sample::{self, *};
     pub use kalosm_language::prelude::Html;
-    pub use kalosm_language::rbert::{Bert, BertBuilder, BertSource, BertSpace};
+    pub use kalosm_language::rbert::{
+        Bert, BertBuilder, BertReranker, BertRerankerBuilder, BertSource, BertSpace,
+    };
     pub use kalosm_language::rphi::{Phi, PhiBuilder, PhiSource};
     pub use kalosm_language::search::*;
     pub use kalosm_language::task::*;
@@ -27,6 +29,10 @@ pub mod language {
 
     #[cfg(feature = "surrealdb")]
     pub use crate::surrealdb_integration::document_table::*;
+    #[cfg(feature = "surrealdb")]
+    pub use crate::surrealdb_integration::rag::*;
+    #[cfg(feature = "surrealdb")]
+    pub use crate::surrealdb_integration::sync::*;
 }
 #[cfg(feature = "sound")]
 pub mod sound {
@@ -118,6 +118,8 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
     }
 
     /// Insert a new record into the table and return the id of the record.
+    ///
+    /// The metadata and source of the document are attached to every chunk so searches can filter by them, and [`EmbeddingIndexedTableSearchResult::source_location`] finds the pages and headings each result came from.
     pub async fn insert(&self, value: R) -> anyhow::Result<Id>
     where
         R: AsRef<Document> + Serialize + DeserializeOwned,
@@ -126,7 +128,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .chunker
             .chunk(value.as_ref(), &self.embedding_model)
             .await?;
-        self.insert_with_chunks(value, chunks).await
+        self.table.insert_with_text(chunks, value).await
     }
 
     /// Extend the table with a iterator of new records.
@@ -143,7 +145,7 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
             .await?;
         let mut ids = Vec::new();
         for (value, embeddings) in entries.into_iter().zip(embeddings) {
-            let id = self.table.insert(embeddings, value).await?;
+            let id = self.table.insert_with_text(embeddings, value).await?;
             ids.push(id);
         }
         Ok(ids)
@@ -195,6 +197,283 @@ impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
         let embedding = embedding.into_embedding(&self.embedding_model).await?;
         self.table.select_nearest(embedding, k).await
     }
+
+    /// Attach metadata to a record so searches can filter by it with [`DocumentTable::select_nearest_filtered`].
+    pub async fn set_metadata(&self, id: Id, metadata: &Metadata) -> anyhow::Result<()>
+    where
+        R: DeserializeOwned,
+    {
+        self.table.set_metadata(id, metadata).await
+    }
+
+    /// Insert a new record into the table with metadata that searches can filter by and return the id of the record. The metadata is added to the metadata and source of the document and the metadata of each chunk.
+    pub async fn insert_with_metadata(&self, value: R, metadata: &Metadata) -> anyhow::Result<Id>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let chunks = self
+            .chunker
+            .chunk(value.as_ref(), &self.embedding_model)
+            .await?
+            .into_iter()
+            .map(|mut chunk| {
+                // The metadata of each chunk, like the symbols of a code chunk, takes priority
+                let mut combined = metadata.clone();
+                combined.merge(std::mem::take(&mut chunk.metadata));
+                chunk.with_metadata(combined)
+            });
+        self.table.insert_with_text(chunks, value).await
+    }
+
+    /// Select the top k records nearest to the given item with metadata that matches the filter.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     document_table
+    ///         .insert_with_metadata(
+    ///             Document::from_parts("Refunds", "Our refund window is 30 days"),
+    ///             &Metadata::new().with("tenant", "acme").with("language", "en"),
+    ///         )
+    ///         .await?;
+    ///
+    ///     let results = document_table
+    ///         .select_nearest_filtered(
+    ///             "How do refunds work?",
+    ///             5,
+    ///             &MetadataFilter::eq("tenant", "acme"),
+    ///         )
+    ///         .await?;
+    ///     println!("{:?}", results);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn select_nearest_filtered(
+        &self,
+        embedding: impl IntoEmbedding<M::VectorSpace>,
+        k: usize,
+        filter: &MetadataFilter,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let embedding = embedding.into_embedding(&self.embedding_model).await?;
+        self.table
+            .select_nearest_filtered(embedding, k, filter)
+            .await
+    }
+
+    /// Select the top k records by combining a vector search with a lexical search for the exact words in the query. This finds identifiers, product codes and rare names that embedding search alone often misses.
+    ///
+    /// Each result includes the vector distance, the lexical score and the fused score. Records inserted with [`DocumentTable::insert_with_chunks`] are only found by the vector search.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///     // The lexical index is kept in memory, so index the existing records again
+    ///     document_table.rebuild_lexical_index().await?;
+    ///
+    ///     let results = document_table
+    ///         .select_hybrid("Which fan fits XR-2291?", 5, HybridFusion::default())
+    ///         .await?;
+    ///     for result in results {
+    ///         println!("{:?} {:?} {}", result.score, result.lexical_score, result.text());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn select_hybrid(
+        &self,
+        query: &str,
+        k: usize,
+        fusion: HybridFusion,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let embedding = self.embedding_model.embed_query(query).await?;
+        self.table.select_hybrid(embedding, query, k, fusion).await
+    }
+
+    /// Select the top k records for a query expanded by a [`QueryTransformer`]. The nearest k records to the original query and each variant are combined with [`reciprocal_rank_fusion`] and the fused score is stored in [`EmbeddingIndexedTableSearchResult::score`].
+    ///
+    /// The results can be reranked against [`ExpandedQuery::query`] with a [`RerankStage`] like the results of any other search.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     // Search with paraphrases of the query and a hypothetical answer
+    ///     let llm = Llama::new_chat().await?;
+    ///     let expanded = (MultiQuery::new(), HypotheticalDocument::new())
+    ///         .expand("how do i make my rust program faster", &llm)
+    ///         .await?;
+    ///     let results = document_table.select_nearest_expanded(&expanded, 20).await?;
+    ///
+    ///     // Then rerank the fused results against the original query
+    ///     let reranked = RerankStage::new(BertReranker::new().await?)
+    ///         .with_top_k(5)
+    ///         .rerank(expanded.query(), results)
+    ///         .await?;
+    ///     for result in reranked {
+    ///         println!("{} {}", result.score, result.result.text());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn select_nearest_expanded(
+        &self,
+        query: &ExpandedQuery,
+        k: usize,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let embeddings = query.embed(&self.embedding_model).await?;
+        let mut searches = Vec::with_capacity(embeddings.len());
+        for embedding in embeddings {
+            searches.push(self.table.select_nearest(embedding, k).await?);
+        }
+        let fused = reciprocal_rank_fusion(searches, 60., |result| {
+            (result.record_id.clone(), result.byte_range.clone())
+        });
+        Ok(fused
+            .into_iter()
+            .take(k)
+            .map(|fused| EmbeddingIndexedTableSearchResult {
+                score: Some(fused.score),
+                ..fused.result
+            })
+            .collect())
+    }
+
+    /// Index the text of every record in the table for [`DocumentTable::select_hybrid`]. The lexical index is kept in memory, so call this after reopening a table that was created in an earlier run.
+    pub async fn rebuild_lexical_index(&self) -> anyhow::Result<()>
+    where
+        R: AsRef<Document> + DeserializeOwned,
+    {
+        self.table.rebuild_lexical_index().await
+    }
+}
+
+impl<C: Connection, R, M: MultiVectorEmbedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Insert a new record into the table with one embedding per token for each chunk and return the id of the record. The chunks are scored with late interaction by [`DocumentTable::select_nearest_max_sim`].
+    ///
+    /// The token embeddings of each chunk are also stored as normal embeddings, so they can show up in [`DocumentTable::select_nearest`] as well.
+    pub async fn insert_multi_vector(&self, value: R) -> anyhow::Result<Id>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let document = value.as_ref();
+        // Only the chunk boundaries are needed, so skip the pooled embeddings if the chunker can split without them
+        let byte_ranges = match self.chunker.chunk_ranges(document, &self.embedding_model) {
+            Some(byte_ranges) => byte_ranges,
+            None => self
+                .chunker
+                .chunk(document, &self.embedding_model)
+                .await?
+                .into_iter()
+                .map(|chunk| chunk.byte_range)
+                .collect(),
+        };
+        let inputs = byte_ranges
+            .iter()
+            .map(|byte_range| {
+                EmbeddingInput::new(
+                    &document.body()[byte_range.clone()],
+                    EmbeddingVariant::Document,
+                )
+            })
+            .collect();
+        let token_embeddings = self.embedding_model.embed_tokens_vec_for(inputs).await?;
+        let chunks = byte_ranges
+            .into_iter()
+            .zip(token_embeddings)
+            .map(|(byte_range, embeddings)| Chunk::new(byte_range, embeddings))
+            .collect::<Vec<_>>();
+        self.table
+            .insert_multi_vector_with_text(chunks, value)
+            .await
+    }
+
+    /// Select the top k records for the query with [ColBERT](https://arxiv.org/abs/2004.12832) style late interaction scoring. The token embeddings of records inserted with [`DocumentTable::insert_multi_vector`] are scored together and records inserted with a single embedding per chunk are scored as a group of one.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     document_table
+    ///         .insert_multi_vector(Document::from_parts(
+    ///             "Router manual",
+    ///             "Hold the reset button for ten seconds to restore the factory settings",
+    ///         ))
+    ///         .await?;
+    ///
+    ///     let results = document_table
+    ///         .select_nearest_max_sim("How do I reset the router?", 5)
+    ///         .await?;
+    ///     for result in results {
+    ///         println!("{:?} {}", result.score, result.text());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn select_nearest_max_sim(
+        &self,
+        query: &str,
+        k: usize,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let query = self.embedding_model.embed_query_tokens(query).await?;
+        self.table.select_nearest_max_sim(&query, k).await
+    }
 }
 
 impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
@@ -305,3 +584,37 @@ impl<C: Connection> DocumentTableSurrealExt<C> for Surreal<C> {
         DocumentTableBuilder::new(table, self.clone())
     }
 }
+
+#[tokio::test]
+async fn code_chunks_can_be_filtered_by_symbol() {
+    use super::{test_table, TestEmbedder};
+
+    let source =
+        "struct Point {\n    x: i32,\n}\n\nfn origin() -> Point {\n    Point { x: 0 }\n}\n";
+    let table = DocumentTable::new(
+        TestEmbedder::default(),
+        test_table::<Document>().await,
+        CodeChunker::new().with_min_chunk_size(0),
+    );
+    table
+        .insert(Document::from_parts("point.rs", source).with_metadata("language", "rust"))
+        .await
+        .unwrap();
+
+    let results = table
+        .select_nearest_filtered("origin", 5, &MetadataFilter::eq("symbols", "origin"))
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(
+        results[0].text(),
+        "fn origin() -> Point {\n    Point { x: 0 }\n}"
+    );
+
+    let results = table
+        .select_nearest_filtered("point", 5, &MetadataFilter::between("start_line", 1, 3))
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].text(), "struct Point {\n    x: i32,\n}");
+}
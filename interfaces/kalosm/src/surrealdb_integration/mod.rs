@@ -7,6 +7,10 @@ use surrealdb::{Connection, Surreal};
 
 #[cfg(feature = "language")]
 pub(crate) mod document_table;
+#[cfg(feature = "language")]
+pub(crate) mod rag;
+#[cfg(feature = "language")]
+pub(crate) mod sync;
 
 /// A link between a document and an embedding.
 ///
@@ -17,6 +21,40 @@ pub struct DocumentLink {
     byte_range: std::ops::Range<usize>,
 }
 
+/// The records created from a source by [`DocumentTable::sync`](crate::language::DocumentTable::sync) and a hash of the content they were created from.
+///
+/// This type is stored in the [`EmbeddingIndexedTable::table_sources`] table.
+#[derive(Serialize, Deserialize)]
+pub struct SyncedSource {
+    source: String,
+    content_hash: String,
+    record_ids: Vec<Id>,
+    #[serde(default)]
+    file_stamp: Option<FileStamp>,
+}
+
+/// The size and modification time of a synced file. [`DocumentTable::sync_folder`](crate::language::DocumentTable::sync_folder) doesn't read files with the same stamp as the last sync again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileStamp {
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+}
+
+impl FileStamp {
+    pub(crate) fn new(metadata: &std::fs::Metadata) -> Self {
+        Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+
+    pub(crate) fn read(path: &std::path::Path) -> Option<Self> {
+        std::fs::metadata(path)
+            .ok()
+            .map(|metadata| Self::new(&metadata))
+    }
+}
+
 /// An object with associated embedding ids.
 ///
 /// This type is stored in the [`EmbeddingIndexedTable::table`] table.
@@ -46,6 +84,11 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         format!("{}-links", &self.table)
     }
 
+    /// Get the name of the table that tracks the sources synced into the table.
+    pub fn table_sources(&self) -> String {
+        format!("{}-sources", &self.table)
+    }
+
     /// Get the raw vector database.
     pub fn vector_db(&self) -> &VectorDB<S> {
         &self.vector_db
@@ -62,6 +105,7 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         R: DeserializeOwned,
     {
         let _: Vec<DocumentLink> = self.db.delete(self.table_links()).await?;
+        let _: Vec<SyncedSource> = self.db.delete(self.table_sources()).await?;
         let embeddings: Vec<ObjectWithEmbeddingIds<R>> = self.db.delete(&self.table).await?;
 
         let mut documents = Vec::with_capacity(embeddings.len());
@@ -74,10 +118,7 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
                     let embedding = self.vector_db.get_embedding(embedding_id)?;
                     embeddings.push(embedding);
                 }
-                chunks.push(Chunk {
-                    byte_range,
-                    embeddings,
-                });
+                chunks.push(Chunk::new(byte_range, embeddings));
             }
             documents.push((embedding.object, chunks));
         }
@@ -92,10 +133,66 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         chunks: impl IntoIterator<Item = Chunk<S>>,
         value: R,
     ) -> anyhow::Result<Id>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        self.insert_inner(chunks, value, None, false).await
+    }
+
+    /// Insert a new record into the table with the given embedding and index the text of each chunk for [`EmbeddingIndexedTable::select_hybrid`].
+    ///
+    /// The metadata and source of the document are attached to every chunk, so searches can filter by them with [`EmbeddingIndexedTable::select_nearest_filtered`].
+    pub async fn insert_with_text(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+    ) -> anyhow::Result<Id>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let document = value.as_ref().clone();
+        self.insert_inner(chunks, value, Some(&document), false)
+            .await
+    }
+
+    /// Insert a new record into the table where the embeddings of each chunk are the token embeddings from a [`MultiVectorEmbedder`]. The embeddings of each chunk are stored as a group that is scored together by [`EmbeddingIndexedTable::select_nearest_max_sim`].
+    pub async fn insert_multi_vector(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+    ) -> anyhow::Result<Id>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        self.insert_inner(chunks, value, None, true).await
+    }
+
+    /// Insert a new record with multi vector chunks like [`EmbeddingIndexedTable::insert_multi_vector`] and index the text of each chunk for [`EmbeddingIndexedTable::select_hybrid`].
+    pub async fn insert_multi_vector_with_text(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+    ) -> anyhow::Result<Id>
+    where
+        R: AsRef<Document> + Serialize + DeserializeOwned,
+    {
+        let document = value.as_ref().clone();
+        self.insert_inner(chunks, value, Some(&document), true)
+            .await
+    }
+
+    async fn insert_inner(
+        &self,
+        chunks: impl IntoIterator<Item = Chunk<S>>,
+        value: R,
+        document: Option<&Document>,
+        grouped: bool,
+    ) -> anyhow::Result<Id>
     where
         R: Serialize + DeserializeOwned,
     {
         let id = Id::uuid();
+        let document_metadata = document.map(document_metadata).unwrap_or_default();
 
         let mut embedding_ids = Vec::new();
         let thing = Thing {
@@ -104,9 +201,29 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
         };
 
         for chunk in chunks {
-            let chunk_embedding_ids = self.vector_db.add_embeddings(chunk.embeddings)?;
-            for embedding_id in &chunk_embedding_ids {
+            // Metadata of the chunk, like the symbols of a code chunk, is stored along with the metadata of the document
+            let mut metadata = document_metadata.clone();
+            metadata.merge(chunk.metadata);
+            let chunk_embedding_ids = if grouped {
+                self.vector_db.add_embedding_group(chunk.embeddings)?
+            } else {
+                self.vector_db.add_embeddings(chunk.embeddings)?
+            };
+            // A group is found through its first embedding, so only that embedding needs the text, metadata and link
+            let linked_ids = if grouped {
+                &chunk_embedding_ids[..chunk_embedding_ids.len().min(1)]
+            } else {
+                &chunk_embedding_ids[..]
+            };
+            for embedding_id in linked_ids {
                 let byte_range = chunk.byte_range.clone();
+                if let Some(document) = document {
+                    self.vector_db
+                        .index_text(*embedding_id, &document.body()[byte_range.clone()]);
+                }
+                if !metadata.is_empty() {
+                    self.vector_db.set_metadata(*embedding_id, &metadata)?;
+                }
 
                 let link = Thing {
                     tb: self.table_links(),
@@ -225,26 +342,176 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
     {
         let ids = self.vector_db.get_closest(embedding, k)?;
         let mut records = Vec::new();
+        let mut seen = std::collections::HashSet::new();
         for id in ids {
-            let main_table_id = self
-                .db
-                .select::<Option<DocumentLink>>(Thing {
-                    tb: self.table_links(),
-                    id: Id::Number(id.value.0 as i64),
-                })
-                .await?
-                .ok_or_else(|| anyhow::anyhow!("Record not found"))?;
-            let record = self.select(main_table_id.document_id.clone()).await?;
-            records.push(EmbeddingIndexedTableSearchResult {
-                distance: id.distance,
-                id: id.value,
-                record_id: main_table_id.document_id,
-                byte_range: main_table_id.byte_range,
-                record,
-            });
+            // Token embeddings from the same multi vector chunk are returned once
+            let linked_id = self.linked_id(id.value)?;
+            if seen.insert(linked_id) {
+                records.push(
+                    self.search_result(linked_id, id.distance, None, None)
+                        .await?,
+                );
+            }
         }
         Ok(records)
     }
+
+    /// Attach metadata to every chunk of a record so searches can filter by it with [`EmbeddingIndexedTable::select_nearest_filtered`].
+    pub async fn set_metadata(&self, id: Id, metadata: &Metadata) -> anyhow::Result<()>
+    where
+        R: DeserializeOwned,
+    {
+        let thing = Thing {
+            tb: self.table.clone(),
+            id,
+        };
+        let record = self
+            .db
+            .select::<Option<ObjectWithEmbeddingIds<R>>>(thing)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Record not found"))?;
+        for embedding_id in record.chunks.iter().flat_map(|(_, ids)| ids.iter()) {
+            if self.linked_id(*embedding_id)? == *embedding_id {
+                self.vector_db.set_metadata(*embedding_id, metadata)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Select the top k records nearest to the given embedding with metadata that matches the filter. The filter is applied during the search, so you get k results even if most records don't match.
+    pub async fn select_nearest_filtered(
+        &self,
+        embedding: Embedding<S>,
+        k: usize,
+        filter: &MetadataFilter,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let ids = self.vector_db.get_closest_filtered(embedding, k, filter)?;
+        let mut records = Vec::new();
+        for id in ids {
+            records.push(
+                self.search_result(id.value, id.distance, None, None)
+                    .await?,
+            );
+        }
+        Ok(records)
+    }
+
+    /// Select the top k records by combining a vector search for the embedding with a lexical search for the query text. The results include the vector distance, the lexical score and the fused score of each record.
+    ///
+    /// Only chunks inserted with [`EmbeddingIndexedTable::insert_with_text`] can be found by the lexical search. The lexical index is kept in memory, so if you reopen a table from disk, call [`EmbeddingIndexedTable::rebuild_lexical_index`] first.
+    pub async fn select_hybrid(
+        &self,
+        embedding: Embedding<S>,
+        query: &str,
+        k: usize,
+        fusion: HybridFusion,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let ids = self
+            .vector_db
+            .get_closest_hybrid(embedding, query, k, fusion)?;
+        let mut records = Vec::new();
+        for id in ids {
+            records.push(
+                self.search_result(
+                    id.value,
+                    id.distance,
+                    Some(id.lexical_score),
+                    Some(id.score),
+                )
+                .await?,
+            );
+        }
+        Ok(records)
+    }
+
+    /// Select the top k chunks for the token embeddings of a query with late interaction scoring. See [`VectorDB::get_closest_max_sim`].
+    ///
+    /// The token embeddings of chunks inserted with [`EmbeddingIndexedTable::insert_multi_vector`] are scored together. Chunks inserted with a single embedding are scored as a group of one, so mixing both in one table makes the scores hard to compare.
+    ///
+    /// The score of each result is the MaxSim score and the distance is one minus the average similarity of each query embedding, so it is comparable to the angular distance of [`EmbeddingIndexedTable::select_nearest`].
+    pub async fn select_nearest_max_sim(
+        &self,
+        query: &[Embedding<S>],
+        k: usize,
+    ) -> anyhow::Result<Vec<EmbeddingIndexedTableSearchResult<R>>>
+    where
+        R: DeserializeOwned,
+    {
+        let groups = self.vector_db.get_closest_max_sim(query, k)?;
+        let mut records = Vec::new();
+        for group in groups {
+            let distance = 1. - group.score / query.len().max(1) as f32;
+            records.push(
+                self.search_result(group.value, distance, None, Some(group.score))
+                    .await?,
+            );
+        }
+        Ok(records)
+    }
+
+    /// Index the text of every chunk in the table for [`EmbeddingIndexedTable::select_hybrid`]. This is only required if you reopen a table that was created in an earlier run.
+    pub async fn rebuild_lexical_index(&self) -> anyhow::Result<()>
+    where
+        R: AsRef<Document> + DeserializeOwned,
+    {
+        let records = self
+            .db
+            .select::<Vec<ObjectWithEmbeddingIds<R>>>(self.table.clone())
+            .await?;
+        for record in records {
+            let body = record.object.as_ref().body();
+            for (byte_range, embedding_ids) in record.chunks {
+                for embedding_id in embedding_ids {
+                    if self.linked_id(embedding_id)? == embedding_id {
+                        self.vector_db
+                            .index_text(embedding_id, &body[byte_range.clone()]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the id of the embedding that links a chunk to its record. Chunks inserted with [`EmbeddingIndexedTable::insert_multi_vector`] are linked through the first embedding in their group.
+    fn linked_id(&self, id: EmbeddingId) -> anyhow::Result<EmbeddingId> {
+        Ok(self.vector_db.get_group(id)?.unwrap_or(id))
+    }
+
+    async fn search_result(
+        &self,
+        id: EmbeddingId,
+        distance: f32,
+        lexical_score: Option<f32>,
+        score: Option<f32>,
+    ) -> anyhow::Result<EmbeddingIndexedTableSearchResult<R>>
+    where
+        R: DeserializeOwned,
+    {
+        let main_table_id = self
+            .db
+            .select::<Option<DocumentLink>>(Thing {
+                tb: self.table_links(),
+                id: Id::Number(id.0 as i64),
+            })
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Record not found"))?;
+        let record = self.select(main_table_id.document_id.clone()).await?;
+        Ok(EmbeddingIndexedTableSearchResult {
+            distance,
+            lexical_score,
+            score,
+            id,
+            record_id: main_table_id.document_id,
+            byte_range: main_table_id.byte_range,
+            record,
+        })
+    }
 }
 
 /// The result of a search in an embedding indexed table.
@@ -252,6 +519,10 @@ impl<C: Connection, R, S: VectorSpace> EmbeddingIndexedTable<C, R, S> {
 pub struct EmbeddingIndexedTableSearchResult<R> {
     /// The distance from the searched point.
     pub distance: f32,
+    /// The BM25 score of the chunk for the query text. Only set for results from [`EmbeddingIndexedTable::select_hybrid`].
+    pub lexical_score: Option<f32>,
+    /// The fused vector and lexical score of the chunk for results from [`EmbeddingIndexedTable::select_hybrid`], the fused score across the query variants for results from [`DocumentTable::select_nearest_expanded`](crate::language::DocumentTable::select_nearest_expanded), or the MaxSim score for results from [`EmbeddingIndexedTable::select_nearest_max_sim`]. Higher scores are better.
+    pub score: Option<f32>,
     /// The embedding id of the record.
     pub id: EmbeddingId,
     /// The record id.
@@ -273,6 +544,34 @@ where
     {
         self.record.as_ref().body()[self.byte_range.clone()].to_string()
     }
+
+    /// Get the source, pages and headings the text of the search result came from so it can be cited.
+    pub fn source_location(&self) -> SourceLocation
+    where
+        R: AsRef<Document>,
+    {
+        self.record
+            .as_ref()
+            .source_location(self.byte_range.clone())
+    }
+}
+
+impl<R> RerankCandidate for EmbeddingIndexedTableSearchResult<R>
+where
+    R: AsRef<Document>,
+{
+    fn rerank_text(&self) -> std::borrow::Cow<'_, str> {
+        std::borrow::Cow::Borrowed(&self.record.as_ref().body()[self.byte_range.clone()])
+    }
+}
+
+/// The metadata attached to the chunks of a document: the metadata of the document and the source under the `source` key.
+pub(crate) fn document_metadata(document: &Document) -> Metadata {
+    let mut metadata = document.metadata().clone();
+    if let Some(source) = document.source() {
+        metadata.insert("source", source.to_string());
+    }
+    metadata
 }
 
 /// A builder for creating a new document table.
@@ -327,3 +626,72 @@ impl<C: Connection> VectorDbSurrealExt<C> for Surreal<C> {
         EmbeddingIndexedTableBuilder::new(table, self.clone())
     }
 }
+
+/// An embedder for tests that embeds text by its length and counts the texts it embedded.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct TestEmbedder(std::sync::atomic::AtomicUsize);
+
+#[cfg(test)]
+impl TestEmbedder {
+    pub(crate) fn embedded(&self) -> usize {
+        self.0.load(std::sync::atomic::Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+impl Embedder for TestEmbedder {
+    type VectorSpace = UnknownVectorSpace;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> kalosm_common::BoxedFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Box::pin(async move { Ok(Embedding::from([input.text.len() as f32, 1.])) })
+    }
+}
+
+/// An empty table in an in memory database.
+#[cfg(test)]
+pub(crate) async fn test_table<R: Serialize + DeserializeOwned>(
+) -> EmbeddingIndexedTable<surrealdb::engine::any::Any, R, UnknownVectorSpace> {
+    let db = surrealdb::engine::any::connect("memory").await.unwrap();
+    db.use_ns("test").use_db("test").await.unwrap();
+    db.vector_indexed_table_builder("documents")
+        .build()
+        .unwrap()
+}
+
+#[cfg(feature = "language")]
+#[tokio::test]
+async fn multi_vector_chunks_are_returned_once() {
+    let table = test_table::<Document>().await;
+
+    let document = Document::from_parts("Router manual", "Hold the reset button");
+    let chunk = Chunk::new(
+        0..document.body().len(),
+        vec![
+            Embedding::from([1., 0., 0.]),
+            Embedding::from([0., 1., 0.]),
+            Embedding::from([0., 0., 1.]),
+        ],
+    );
+    table
+        .insert_multi_vector_with_text([chunk], document)
+        .await
+        .unwrap();
+
+    // Every token embedding matches the query, but the chunk is only returned once
+    let query = || Embedding::from([0.5, 0.5, 0.5]);
+    let results = table
+        .select_hybrid(query(), "reset button", 3, HybridFusion::default())
+        .await
+        .unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].text(), "Hold the reset button");
+    assert!(results[0].lexical_score.unwrap() > 0.);
+
+    let results = table.select_nearest(query(), 3).await.unwrap();
+    assert_eq!(results.len(), 1);
+}
@@ -0,0 +1,542 @@
+use std::future::Future;
+use std::ops::Range;
+
+use kalosm_language::prelude::*;
+use serde::de::DeserializeOwned;
+use surrealdb::sql::Id;
+use surrealdb::Connection;
+
+use super::document_table::DocumentTable;
+use super::EmbeddingIndexedTableSearchResult;
+
+/// A chunk of a document found by a [`Retriever`].
+#[derive(Debug, Clone)]
+pub struct RetrievedChunk {
+    /// The id of the record the chunk is from.
+    pub record_id: Id,
+    /// The byte range of the chunk in the body of the record.
+    pub byte_range: Range<usize>,
+    /// The text of the chunk.
+    pub text: String,
+    /// The source, pages and headings of the chunk.
+    pub location: SourceLocation,
+    /// How relevant the chunk is to the query. Higher scores are more relevant. Scores from different retrievers are not comparable.
+    pub score: f32,
+}
+
+impl RetrievedChunk {
+    fn from_search_result<R: AsRef<Document> + DeserializeOwned>(
+        result: EmbeddingIndexedTableSearchResult<R>,
+    ) -> Self {
+        Self {
+            text: result.text(),
+            location: result.source_location(),
+            // Smaller distances are more relevant
+            score: result.score.unwrap_or(-result.distance),
+            record_id: result.record_id,
+            byte_range: result.byte_range,
+        }
+    }
+}
+
+impl RerankCandidate for RetrievedChunk {
+    fn rerank_text(&self) -> std::borrow::Cow<'_, str> {
+        std::borrow::Cow::Borrowed(&self.text)
+    }
+}
+
+/// A source of chunks relevant to a query for [`Rag`].
+///
+/// A [`DocumentTable`] retrieves chunks with vector search. Use [`DocumentTable::hybrid_retriever`] to combine vector and keyword search and [`RerankedRetriever`] to re-order the chunks of any retriever with a reranker.
+pub trait Retriever {
+    /// Find the k chunks most relevant to the query, from most to least relevant.
+    fn retrieve(
+        &self,
+        query: &str,
+        k: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<RetrievedChunk>>> + Send;
+}
+
+impl<T: Retriever + Sync> Retriever for &T {
+    fn retrieve(
+        &self,
+        query: &str,
+        k: usize,
+    ) -> impl Future<Output = anyhow::Result<Vec<RetrievedChunk>>> + Send {
+        (**self).retrieve(query, k)
+    }
+}
+
+impl<C, R, M, K> Retriever for DocumentTable<C, R, M, K>
+where
+    C: Connection,
+    R: AsRef<Document> + DeserializeOwned + Send + Sync,
+    M: Embedder + Sync,
+    K: Chunker + Sync,
+{
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<RetrievedChunk>> {
+        let embedding = self.embedding_model().embed_query(query).await?;
+        Ok(self
+            .select_nearest(embedding, k)
+            .await?
+            .into_iter()
+            .map(RetrievedChunk::from_search_result)
+            .collect())
+    }
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Create a [`Retriever`] that searches the table with [`DocumentTable::select_hybrid`].
+    pub fn hybrid_retriever(&self, fusion: HybridFusion) -> HybridRetriever<'_, C, R, M, K> {
+        HybridRetriever {
+            table: self,
+            fusion,
+        }
+    }
+}
+
+/// A [`Retriever`] that combines vector and keyword search over a [`DocumentTable`]. Created with [`DocumentTable::hybrid_retriever`].
+pub struct HybridRetriever<'a, C: Connection, R, M: Embedder, K: Chunker> {
+    table: &'a DocumentTable<C, R, M, K>,
+    fusion: HybridFusion,
+}
+
+impl<'a, C, R, M, K> Retriever for HybridRetriever<'a, C, R, M, K>
+where
+    C: Connection,
+    R: AsRef<Document> + DeserializeOwned + Send + Sync,
+    M: Embedder + Sync,
+    K: Chunker + Sync,
+{
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<RetrievedChunk>> {
+        Ok(self
+            .table
+            .select_hybrid(query, k, self.fusion)
+            .await?
+            .into_iter()
+            .map(RetrievedChunk::from_search_result)
+            .collect())
+    }
+}
+
+/// A [`Retriever`] that fetches extra candidates from another retriever and re-orders them with a [`RerankStage`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use surrealdb::{engine::local::RocksDb, Surreal};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+///     db.use_ns("rag").use_db("rag").await?;
+///     let document_table = db
+///         .document_table_builder("documents")
+///         .at("./db/embeddings.db")
+///         .build::<Document>()
+///         .await?;
+///
+///     // Rerank the top 20 chunks from a hybrid search
+///     let retriever = RerankedRetriever::new(
+///         document_table.hybrid_retriever(HybridFusion::default()),
+///         RerankStage::new(BertReranker::new().await?),
+///     )
+///     .with_candidates(20);
+///     let chunks = retriever.retrieve("What is Kalosm?", 5).await?;
+///     for chunk in chunks {
+///         println!("{}: {}", chunk.location, chunk.text);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct RerankedRetriever<T, R> {
+    retriever: T,
+    rerank: RerankStage<R>,
+    candidates: usize,
+}
+
+impl<T: Retriever, R: Reranker> RerankedRetriever<T, R> {
+    /// Create a new retriever that reranks the chunks from another retriever.
+    pub fn new(retriever: T, rerank: RerankStage<R>) -> Self {
+        Self {
+            retriever,
+            rerank,
+            candidates: 20,
+        }
+    }
+
+    /// Set the number of chunks fetched from the inner retriever before reranking. At least k chunks are always fetched. (default: 20)
+    pub fn with_candidates(mut self, candidates: usize) -> Self {
+        self.candidates = candidates;
+        self
+    }
+}
+
+impl<T: Retriever + Sync, R: Reranker> Retriever for RerankedRetriever<T, R> {
+    async fn retrieve(&self, query: &str, k: usize) -> anyhow::Result<Vec<RetrievedChunk>> {
+        let candidates = self
+            .retriever
+            .retrieve(query, self.candidates.max(k))
+            .await?;
+        let mut reranked = self.rerank.rerank(query, candidates).await?;
+        reranked.truncate(k);
+        Ok(reranked
+            .into_iter()
+            .map(|reranked| RetrievedChunk {
+                score: reranked.score,
+                ..reranked.result
+            })
+            .collect())
+    }
+}
+
+/// A chunk that supports part of an answer from [`Rag`].
+#[derive(Debug, Clone)]
+pub struct CitedChunk {
+    /// The number of the chunk in the citation markers of the answer.
+    pub marker: usize,
+    /// The id of the record the chunk is from.
+    pub record_id: Id,
+    /// The byte range of the chunk in the body of the record.
+    pub byte_range: Range<usize>,
+}
+
+/// A segment of an answer from [`Rag`] and the chunks it cites. A segment is the text before a group of citation markers, so it can hold more than one sentence if the model only cites after the last one.
+#[derive(Debug, Clone)]
+pub struct Citation {
+    /// The byte range of the segment in [`RagAnswer::text`], without the citation markers.
+    pub sentence: Range<usize>,
+    /// The chunks the segment cites.
+    pub sources: Vec<CitedChunk>,
+}
+
+/// An answer from [`Rag`].
+#[derive(Debug, Clone)]
+pub struct RagAnswer {
+    /// The text of the answer, including the citation markers like `[1]`.
+    pub text: String,
+    /// The segments of the answer and the chunks each segment cites. Empty if the model couldn't answer from the retrieved chunks.
+    pub citations: Vec<Citation>,
+    /// The chunks that were given to the model. The chunk with marker `[n]` is at index `n - 1`.
+    pub chunks: Vec<RetrievedChunk>,
+}
+
+impl RagAnswer {
+    /// Check if the model answered the question from the retrieved chunks.
+    pub fn is_answered(&self) -> bool {
+        !self.citations.is_empty()
+    }
+
+    /// Get the text of a segment of the answer without the citation markers.
+    pub fn sentence(&self, citation: &Citation) -> &str {
+        &self.text[citation.sentence.clone()]
+    }
+}
+
+/// Retrieval augmented generation with citations. [`Rag`] retrieves chunks relevant to a question, fills the context of the model with as many chunks as fit in the context budget and generates an answer.
+///
+/// The chunks are numbered in the prompt and generation is constrained so every segment of the answer ends with citation markers like `[1][3]` that can only reference the numbers of the retrieved chunks. The model is asked to cite after every sentence, but a segment may hold several sentences that share one group of markers.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+/// use surrealdb::{engine::local::RocksDb, Surreal};
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+///     db.use_ns("rag").use_db("rag").await?;
+///     let document_table = db
+///         .document_table_builder("documents")
+///         .at("./db/embeddings.db")
+///         .build::<Document>()
+///         .await?;
+///
+///     let rag = Rag::new(&document_table, Llama::new_chat().await?)
+///         .with_context_tokens(1024)
+///         .with_no_answer("I don't know.");
+///     let answer = rag.answer("What is Kalosm?").await?;
+///     println!("{}", answer.text);
+///     for citation in &answer.citations {
+///         for source in &citation.sources {
+///             let chunk = &answer.chunks[source.marker - 1];
+///             println!("{} -> {}", answer.sentence(citation), chunk.location);
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct Rag<T, M> {
+    retriever: T,
+    model: M,
+    context_tokens: usize,
+    top_k: usize,
+    no_answer: Option<String>,
+}
+
+impl<T: Retriever, M: Model> Rag<T, M>
+where
+    <<M as Model>::SyncModel as SyncModel>::Session: Send + Sync,
+{
+    /// Create a new retrieval augmented generation pipeline with a retriever and a model.
+    pub fn new(retriever: T, model: M) -> Self {
+        Self {
+            retriever,
+            model,
+            context_tokens: 2048,
+            top_k: 10,
+            no_answer: None,
+        }
+    }
+
+    /// Set the maximum number of tokens of the prompt, including the instructions, the question and the retrieved chunks. Chunks that don't fit are left out, starting with the least relevant. (default: 2048)
+    pub fn with_context_tokens(mut self, context_tokens: usize) -> Self {
+        self.context_tokens = context_tokens;
+        self
+    }
+
+    /// Set the number of chunks to retrieve for each question. (default: 10)
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Let the model respond with this text instead of an answer if the retrieved chunks don't answer the question. (Defaults to always answering)
+    pub fn with_no_answer(mut self, no_answer: impl ToString) -> Self {
+        self.no_answer = Some(no_answer.to_string());
+        self
+    }
+
+    /// Get the retriever.
+    pub fn retriever(&self) -> &T {
+        &self.retriever
+    }
+
+    /// Get the model.
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+
+    /// Answer a question from the retrieved chunks.
+    pub async fn answer(&self, question: &str) -> anyhow::Result<RagAnswer> {
+        let retrieved = self.retriever.retrieve(question, self.top_k).await?;
+
+        let mut description = String::from("You answer questions using only the numbered passages you are given. End every sentence with the numbers of the passages that support it in square brackets, like: Kalosm runs models locally. [1][2]\nRespond on a single line.");
+        if let Some(no_answer) = &self.no_answer {
+            description.push_str(&format!(
+                "\nIf the passages don't answer the question, respond with exactly: {no_answer}"
+            ));
+        }
+        let question = format!("Question: {question}");
+
+        // Fill the rest of the context budget from the most relevant chunk down
+        let tokenizer = self.model.tokenizer();
+        let count_tokens = |text: &str| {
+            tokenizer
+                .encode(text, false)
+                .map(|encoding| encoding.len())
+                .map_err(|err| anyhow::anyhow!(err))
+        };
+        let mut used_tokens = count_tokens(&description)? + count_tokens(&question)?;
+        let mut chunks = Vec::new();
+        let mut passages = String::new();
+        for chunk in retrieved {
+            let passage = format!(
+                "[{}] {}\n{}\n\n",
+                chunks.len() + 1,
+                chunk.location,
+                chunk.text.trim()
+            );
+            let tokens = count_tokens(&passage)?;
+            if used_tokens + tokens > self.context_tokens {
+                continue;
+            }
+            used_tokens += tokens;
+            passages.push_str(&passage);
+            chunks.push(chunk);
+        }
+
+        if chunks.is_empty() {
+            return Ok(RagAnswer {
+                text: self.no_answer.clone().unwrap_or_default(),
+                citations: Vec::new(),
+                chunks,
+            });
+        }
+
+        let constraints =
+            RegexParser::new(&citation_regex(chunks.len(), self.no_answer.as_deref()))
+                .map_err(|err| anyhow::anyhow!(err))?;
+        let task = Task::builder(description)
+            .with_constraints(constraints)
+            .build();
+
+        let text = task
+            .run(format!("{passages}{question}"), &self.model)
+            .await?;
+        let text = text.trim_end().to_string();
+        let citations = parse_citations(&text, &chunks);
+        Ok(RagAnswer {
+            text,
+            citations,
+            chunks,
+        })
+    }
+}
+
+/// A regex that matches segments of text that each end with citation markers for chunks `1..=chunk_count`, or the no answer text, followed by a new line.
+fn citation_regex(chunk_count: usize, no_answer: Option<&str>) -> String {
+    let markers = (1..=chunk_count)
+        .map(|marker| marker.to_string())
+        .collect::<Vec<_>>()
+        .join("|");
+    let answer = format!(r"(?:[^\[\]\n]+(?:\[(?:{markers})\])+)+");
+    match no_answer {
+        Some(no_answer) => format!(r"(?:{}|{answer})\n", escape_regex(no_answer)),
+        None => format!(r"{answer}\n"),
+    }
+}
+
+/// Escape the characters in text that have a special meaning in a regex.
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if r"\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Find the segments of an answer and the chunks cited by the markers after each segment. Markers that don't reference a chunk are skipped.
+fn parse_citations(text: &str, chunks: &[RetrievedChunk]) -> Vec<Citation> {
+    let mut citations = Vec::new();
+    let mut position = 0;
+    while let Some(offset) = text[position..].find('[') {
+        let sentence_start =
+            position + (text[position..].len() - text[position..].trim_start().len());
+        let sentence = sentence_start..(position + offset).max(sentence_start);
+        position += offset;
+
+        let mut sources = Vec::new();
+        while let Some(rest) = text[position..].strip_prefix('[') {
+            let Some(end) = rest.find(']') else {
+                // An unclosed bracket is not a marker. Skip it so the next search starts after it
+                position += 1;
+                break;
+            };
+            let marker = rest[..end].parse::<usize>().ok();
+            position += end + 2;
+            // Markers are numbered from one
+            let chunk = marker
+                .filter(|&marker| marker > 0)
+                .and_then(|marker| Some((marker, chunks.get(marker - 1)?)));
+            if let Some((marker, chunk)) = chunk {
+                sources.push(CitedChunk {
+                    marker,
+                    record_id: chunk.record_id.clone(),
+                    byte_range: chunk.byte_range.clone(),
+                });
+            }
+        }
+
+        let sentence = sentence.start..sentence.start + text[sentence].trim_end().len();
+        if !sources.is_empty() {
+            citations.push(Citation { sentence, sources });
+        }
+    }
+    citations
+}
+
+#[cfg(test)]
+fn test_chunks(count: usize) -> Vec<RetrievedChunk> {
+    let document = Document::from_parts("Manual", "Hold the reset button");
+    (0..count)
+        .map(|index| RetrievedChunk {
+            record_id: Id::Number(index as i64),
+            byte_range: 0..document.body().len(),
+            text: document.body().to_string(),
+            location: document.source_location(0..document.body().len()),
+            score: 1.,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn matches_citation_regex(chunk_count: usize, no_answer: Option<&str>, text: &str) -> bool {
+    let parser = RegexParser::new(&citation_regex(chunk_count, no_answer)).unwrap();
+    let state = parser.create_parser_state();
+    matches!(
+        parser.parse(&state, text.as_bytes()),
+        Ok(ParseStatus::Finished { remaining, .. }) if remaining.is_empty()
+    )
+}
+
+#[test]
+fn citation_regex_only_allows_retrieved_markers() {
+    assert!(matches_citation_regex(10, None, "Hold reset. [10]\n"));
+    assert!(matches_citation_regex(10, None, "Hold reset. [1][10]\n"));
+    assert!(!matches_citation_regex(1, None, "Hold reset. [10]\n"));
+    assert!(!matches_citation_regex(10, None, "Hold reset. [11]\n"));
+    assert!(!matches_citation_regex(10, None, "Hold reset. [0]\n"));
+    assert!(!matches_citation_regex(10, None, "Hold reset.\n"));
+
+    // Special characters in the no answer text are matched literally
+    let no_answer = Some("I don't know [n/a].");
+    assert!(matches_citation_regex(
+        2,
+        no_answer,
+        "I don't know [n/a].\n"
+    ));
+    assert!(!matches_citation_regex(
+        2,
+        no_answer,
+        "I don't know [n/a]!\n"
+    ));
+}
+
+#[test]
+fn parse_citations_reads_marker_groups() {
+    let chunks = test_chunks(10);
+
+    let text = "Hold the button. [10] Wait. [1][3]";
+    let citations = parse_citations(text, &chunks);
+    assert_eq!(citations.len(), 2);
+    assert_eq!(&text[citations[0].sentence.clone()], "Hold the button.");
+    let markers = |citation: &Citation| {
+        citation
+            .sources
+            .iter()
+            .map(|source| source.marker)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(markers(&citations[0]), [10]);
+    assert_eq!(&text[citations[1].sentence.clone()], "Wait.");
+    assert_eq!(markers(&citations[1]), [1, 3]);
+    assert_eq!(citations[1].sources[1].record_id, Id::Number(2));
+
+    // Several sentences before one group of markers are one segment
+    let text = "Unplug the router. Hold the button. [2]";
+    let citations = parse_citations(text, &chunks);
+    assert_eq!(citations.len(), 1);
+    assert_eq!(
+        &text[citations[0].sentence.clone()],
+        "Unplug the router. Hold the button."
+    );
+
+    // Markers that don't reference a chunk are skipped
+    let citations = parse_citations("Hold the button. [4][2]", &chunks[..3]);
+    assert_eq!(citations.len(), 1);
+    assert_eq!(markers(&citations[0]), [2]);
+    assert!(parse_citations("Hold the button. [4]", &chunks[..3]).is_empty());
+
+    // A no answer text with brackets has no citations
+    assert!(parse_citations("I don't know [n/a].", &chunks).is_empty());
+
+    // A bracket that is never closed ends the search instead of being found again
+    assert!(parse_citations("I don't know [", &chunks).is_empty());
+    let citations = parse_citations("Hold the button. [1] Then wait [", &chunks);
+    assert_eq!(citations.len(), 1);
+    assert_eq!(markers(&citations[0]), [1]);
+}
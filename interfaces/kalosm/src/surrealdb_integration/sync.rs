@@ -0,0 +1,543 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use kalosm_language::prelude::*;
+use notify::Watcher;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use surrealdb::sql::{Id, Thing};
+use surrealdb::Connection;
+
+use super::document_table::DocumentTable;
+use super::{FileStamp, SyncedSource};
+
+/// How long [`DocumentTable::watch`] waits for more changes before syncing. Editors often write a file in several steps.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// The changes made to a [`DocumentTable`] by [`DocumentTable::sync`].
+#[derive(Debug, Clone, Default)]
+pub struct SyncSummary {
+    /// Sources that were not in the table before.
+    pub added: Vec<String>,
+    /// Sources whose content changed. The records from these sources were embedded again.
+    pub updated: Vec<String>,
+    /// Sources that disappeared. The records from these sources were removed from the table.
+    pub removed: Vec<String>,
+    /// The number of sources that did not change.
+    pub unchanged: usize,
+    /// Files that failed to load in [`DocumentTable::sync_folder`]. The records from these files are kept as they were.
+    pub failed: Vec<FailedFile>,
+}
+
+impl<C: Connection, R, M: Embedder, K: Chunker> DocumentTable<C, R, M, K> {
+    /// Make the table match the documents from the context. Only documents that are new or whose content changed since the last sync are chunked and embedded. Records synced from sources that are not in the context anymore are removed.
+    ///
+    /// Documents are identified by their [`Document::source`], or a hash of their content if they don't have a source. Documents that share a source, like the emails in a mailbox, are synced together. Records inserted without syncing are never changed.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     // Only the files that changed since the last run are embedded again
+    ///     let summary = document_table
+    ///         .sync(DocumentFolder::new("./documents")?)
+    ///         .await?;
+    ///     println!("{:?}", summary);
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn sync(&self, context: impl IntoDocuments) -> anyhow::Result<SyncSummary>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        let documents = context.into_documents().await?;
+        let mut synced = self.synced_sources().await?;
+        let mut summary = SyncSummary::default();
+        self.sync_documents(&mut synced, documents, None, &mut summary)
+            .await?;
+        self.remove_sources(synced, &mut summary).await?;
+        Ok(summary)
+    }
+
+    /// Make the table match the files in a folder like [`DocumentTable::sync`]. The folder is read one file at a time with [`DocumentFolder::stream`], so large folders never need to fit in memory.
+    ///
+    /// Files with the same size and modification time as the last sync are not read again. Files that fail to load are listed in [`SyncSummary::failed`] and the records from an earlier sync of those files are kept.
+    pub async fn sync_folder(&self, folder: &DocumentFolder) -> anyhow::Result<SyncSummary>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        let mut synced = self.synced_sources().await?;
+        let mut summary = SyncSummary::default();
+
+        let last_stamps = synced
+            .iter()
+            .filter_map(|(source, synced)| Some((source.clone(), synced.file_stamp.clone()?)))
+            .collect::<HashMap<_, _>>();
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let stamps = Arc::new(Mutex::new(HashMap::new()));
+        let mut stream = folder.stream_filtered({
+            let skipped = skipped.clone();
+            let stamps = stamps.clone();
+            move |path, metadata| {
+                let source = DocumentSource::from(path.to_path_buf()).to_string();
+                let stamp = FileStamp::new(metadata);
+                if last_stamps.get(&source) == Some(&stamp) {
+                    skipped.lock().unwrap().push(source);
+                    return false;
+                }
+                stamps.lock().unwrap().insert(path.to_path_buf(), stamp);
+                true
+            }
+        });
+
+        // Every document from a file is yielded before the next file
+        let mut current: Option<(PathBuf, Vec<Document>)> = None;
+        while let Some(file) = stream.next().await {
+            match file.document {
+                Ok(document) => match &mut current {
+                    Some((path, documents)) if *path == file.path => documents.push(document),
+                    _ => {
+                        if let Some((path, documents)) = current.take() {
+                            let stamp = stamps.lock().unwrap().remove(&path);
+                            self.sync_documents(&mut synced, documents, stamp, &mut summary)
+                                .await?;
+                        }
+                        current = Some((file.path, vec![document]));
+                    }
+                },
+                Err(_) => {
+                    synced.remove(&DocumentSource::from(file.path).to_string());
+                }
+            }
+        }
+        if let Some((path, documents)) = current {
+            let stamp = stamps.lock().unwrap().remove(&path);
+            self.sync_documents(&mut synced, documents, stamp, &mut summary)
+                .await?;
+        }
+        let skipped = std::mem::take(&mut *skipped.lock().unwrap());
+        for source in skipped {
+            if synced.remove(&source).is_some() {
+                summary.unchanged += 1;
+            }
+        }
+        self.remove_sources(synced, &mut summary).await?;
+        summary.failed = stream.summary().failed;
+        Ok(summary)
+    }
+
+    /// Sync the table with a folder with [`DocumentTable::sync_folder`], then keep it in sync as files in the folder are created, changed or removed. This runs until the file watcher fails, so spawn it or select it with the rest of your program.
+    ///
+    /// Errors from the table while syncing are logged and the files are synced again the next time they change.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// use surrealdb::{engine::local::RocksDb, Surreal};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> anyhow::Result<()> {
+    ///     let db = Surreal::new::<RocksDb>("./db/temp.db").await?;
+    ///     db.use_ns("rag").use_db("rag").await?;
+    ///     let document_table = db
+    ///         .document_table_builder("documents")
+    ///         .at("./db/embeddings.db")
+    ///         .build::<Document>()
+    ///         .await?;
+    ///
+    ///     let folder = DocumentFolder::new("./documents")?.with_include("**/*.md")?;
+    ///     tokio::select! {
+    ///         result = document_table.watch(folder) => result?,
+    ///         _ = tokio::signal::ctrl_c() => {}
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn watch(&self, folder: DocumentFolder) -> anyhow::Result<()>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                _ = tx.send(event);
+            })?;
+        // Start watching before the first sync so changes made during the sync are not missed
+        watcher.watch(folder.path(), notify::RecursiveMode::Recursive)?;
+
+        match self.sync_folder(&folder).await {
+            Ok(summary) => tracing::info!(
+                "Synced {}: {} added, {} updated, {} removed, {} unchanged, {} failed",
+                folder.path().display(),
+                summary.added.len(),
+                summary.updated.len(),
+                summary.removed.len(),
+                summary.unchanged,
+                summary.failed.len()
+            ),
+            Err(err) => tracing::error!("Failed to sync {}: {err}", folder.path().display()),
+        }
+
+        // The watcher may report absolute paths even if the folder path is relative
+        let root = tokio::fs::canonicalize(folder.path()).await?;
+        while let Some(event) = rx.recv().await {
+            let mut paths = BTreeSet::new();
+            let mut next = Some(event);
+            while let Some(event) = next {
+                match event {
+                    Ok(event) if !event.kind.is_access() => {
+                        paths.extend(event.paths.into_iter().map(|path| {
+                            match path.strip_prefix(&root) {
+                                Ok(relative) => folder.path().join(relative),
+                                Err(_) => path,
+                            }
+                        }))
+                    }
+                    Ok(_) => {}
+                    Err(err) => tracing::warn!("Error watching {}: {err}", folder.path().display()),
+                }
+                next = tokio::time::timeout(WATCH_DEBOUNCE, rx.recv())
+                    .await
+                    .ok()
+                    .flatten();
+            }
+            if let Err(err) = self.sync_paths(&folder, paths).await {
+                tracing::error!("Failed to sync {}: {err}", folder.path().display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync the files at the paths that changed in a watched folder.
+    async fn sync_paths(
+        &self,
+        folder: &DocumentFolder,
+        paths: BTreeSet<PathBuf>,
+    ) -> anyhow::Result<()>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        // A folder that was moved in only reports the folder itself, so sync everything
+        if paths.iter().any(|path| path.is_dir()) {
+            self.sync_folder(folder).await?;
+            return Ok(());
+        }
+
+        let mut synced = self.synced_sources().await?;
+        let mut summary = SyncSummary::default();
+        for path in paths {
+            if folder.contains(&path) {
+                // Editors sometimes touch a file without changing it
+                let stamp = FileStamp::read(&path);
+                let source = DocumentSource::from(path.clone()).to_string();
+                if stamp.is_some()
+                    && synced
+                        .get(&source)
+                        .and_then(|synced| synced.file_stamp.as_ref())
+                        == stamp.as_ref()
+                {
+                    continue;
+                }
+                let documents = match FsDocument::try_from(path.clone()) {
+                    Ok(document) => document.into_documents().await,
+                    Err(err) => Err(err),
+                };
+                match documents {
+                    Ok(documents) => {
+                        self.sync_documents(&mut synced, documents, stamp, &mut summary)
+                            .await?;
+                    }
+                    Err(err) => tracing::warn!("Failed to read {}: {err}", path.display()),
+                }
+            } else {
+                // The file or folder was removed or is no longer part of the folder
+                let path = path.display().to_string();
+                let prefix = format!("{path}{MAIN_SEPARATOR}");
+                let removed;
+                (removed, synced) = synced
+                    .into_iter()
+                    .partition(|(source, _)| *source == path || source.starts_with(&prefix));
+                self.remove_sources(removed, &mut summary).await?;
+            }
+        }
+        for source in &summary.added {
+            tracing::info!("Added {source}");
+        }
+        for source in &summary.updated {
+            tracing::info!("Updated {source}");
+        }
+        for source in &summary.removed {
+            tracing::info!("Removed {source}");
+        }
+        Ok(())
+    }
+
+    /// Sync documents. If the documents were read from a file, the stamp of the file is stored with them.
+    async fn sync_documents(
+        &self,
+        synced: &mut HashMap<String, SyncedSource>,
+        documents: Vec<Document>,
+        file_stamp: Option<FileStamp>,
+        summary: &mut SyncSummary,
+    ) -> anyhow::Result<()>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        for (source, documents) in group_by_source(documents) {
+            self.sync_source(synced, source, documents, file_stamp.clone(), summary)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Embed the documents from a source again if their content changed since the last sync.
+    async fn sync_source(
+        &self,
+        synced: &mut HashMap<String, SyncedSource>,
+        source: String,
+        documents: Vec<Document>,
+        file_stamp: Option<FileStamp>,
+        summary: &mut SyncSummary,
+    ) -> anyhow::Result<()>
+    where
+        R: From<Document> + AsRef<Document> + Serialize + DeserializeOwned,
+        K: Sync,
+    {
+        let content_hash = content_hash(&documents);
+        let old = match synced.remove(&source) {
+            Some(old) if old.content_hash == content_hash => {
+                summary.unchanged += 1;
+                // Store the new stamp so the file is not read again next time
+                if old.file_stamp != file_stamp {
+                    self.table()
+                        .db()
+                        .update::<Option<SyncedSource>>(self.source_thing(&source))
+                        .content(SyncedSource { file_stamp, ..old })
+                        .await?;
+                }
+                return Ok(());
+            }
+            Some(old) => {
+                summary.updated.push(source.clone());
+                Some(old)
+            }
+            None => {
+                summary.added.push(source.clone());
+                None
+            }
+        };
+
+        // Insert the new records before removing the old ones, so the source keeps its old records if embedding or writing the new records fails
+        let record_ids = self.extend(documents.into_iter().map(R::from)).await?;
+        let new = SyncedSource {
+            source,
+            content_hash,
+            record_ids,
+            file_stamp,
+        };
+        match old {
+            Some(old) => {
+                self.table()
+                    .db()
+                    .update::<Option<SyncedSource>>(self.source_thing(&new.source))
+                    .content(new)
+                    .await?;
+                self.delete_records(old.record_ids).await?;
+            }
+            None => {
+                self.table()
+                    .db()
+                    .create::<Option<SyncedSource>>(self.source_thing(&new.source))
+                    .content(new)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn remove_sources(
+        &self,
+        sources: HashMap<String, SyncedSource>,
+        summary: &mut SyncSummary,
+    ) -> anyhow::Result<()>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        for (source, old) in sources {
+            self.remove_source(old).await?;
+            summary.removed.push(source);
+        }
+        Ok(())
+    }
+
+    /// Delete the records synced from a source.
+    async fn remove_source(&self, old: SyncedSource) -> anyhow::Result<()>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        self.delete_records(old.record_ids).await?;
+        self.table()
+            .db()
+            .delete::<Option<SyncedSource>>(self.source_thing(&old.source))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete records by id.
+    async fn delete_records(&self, record_ids: Vec<Id>) -> anyhow::Result<()>
+    where
+        R: Serialize + DeserializeOwned,
+    {
+        for id in record_ids {
+            self.delete(id).await?;
+        }
+        Ok(())
+    }
+
+    async fn synced_sources(&self) -> anyhow::Result<HashMap<String, SyncedSource>> {
+        let sources = self
+            .table()
+            .db()
+            .select::<Vec<SyncedSource>>(self.table().table_sources())
+            .await?;
+        Ok(sources
+            .into_iter()
+            .map(|source| (source.source.clone(), source))
+            .collect())
+    }
+
+    fn source_thing(&self, source: &str) -> Thing {
+        Thing {
+            tb: self.table().table_sources(),
+            id: Id::String(hex(&Sha256::digest(source.as_bytes()))),
+        }
+    }
+}
+
+/// Group documents by their source. Documents without a source are identified by a hash of their content, so documents with the same title don't replace each other.
+fn group_by_source(documents: Vec<Document>) -> BTreeMap<String, Vec<Document>> {
+    let mut groups = BTreeMap::<String, Vec<Document>>::new();
+    for document in documents {
+        let source = match document.source() {
+            Some(source) => source.to_string(),
+            None => format!("content:{}", content_hash(std::slice::from_ref(&document))),
+        };
+        groups.entry(source).or_default().push(document);
+    }
+    groups
+}
+
+/// A hash of the title and body of each document that stays the same between runs.
+fn content_hash(documents: &[Document]) -> String {
+    let mut hasher = Sha256::new();
+    for document in documents {
+        for text in [document.title(), document.body()] {
+            hasher.update((text.len() as u64).to_le_bytes());
+            hasher.update(text.as_bytes());
+        }
+    }
+    hex(&hasher.finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[tokio::test]
+async fn sync_folder_only_embeds_changed_files() {
+    use super::{test_table, TestEmbedder};
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    std::fs::write(root.join("a.txt"), "The first file").unwrap();
+    std::fs::write(root.join("b.txt"), "The second file").unwrap();
+    let folder = DocumentFolder::new(root).unwrap();
+    let table = DocumentTable::new(
+        TestEmbedder::default(),
+        test_table::<Document>().await,
+        ChunkStrategy::Paragraph {
+            paragraph_count: 1,
+            overlap: 0,
+        },
+    );
+
+    let summary = table.sync_folder(&folder).await.unwrap();
+    assert_eq!(summary.added.len(), 2);
+    let embedded = table.embedding_model().embedded();
+    assert_eq!(embedded, 2);
+
+    // Unchanged files are skipped without embedding them again
+    let summary = table.sync_folder(&folder).await.unwrap();
+    assert_eq!(summary.unchanged, 2);
+    assert!(summary.added.is_empty() && summary.updated.is_empty());
+    assert_eq!(table.embedding_model().embedded(), embedded);
+
+    // Changed files are replaced and deleted files are removed
+    std::fs::write(root.join("a.txt"), "The first file, edited").unwrap();
+    std::fs::remove_file(root.join("b.txt")).unwrap();
+    let summary = table.sync_folder(&folder).await.unwrap();
+    assert_eq!(summary.updated, [root.join("a.txt").display().to_string()]);
+    assert_eq!(summary.removed, [root.join("b.txt").display().to_string()]);
+    let bodies = table
+        .select_all()
+        .await
+        .unwrap()
+        .iter()
+        .map(|document| document.body().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(bodies, ["The first file, edited"]);
+}
+
+#[tokio::test]
+async fn documents_without_a_source_are_keyed_by_content() {
+    use super::{test_table, TestEmbedder};
+
+    let table = DocumentTable::new(
+        TestEmbedder::default(),
+        test_table::<Document>().await,
+        ChunkStrategy::Paragraph {
+            paragraph_count: 1,
+            overlap: 0,
+        },
+    );
+
+    // Documents with the same title don't replace each other
+    let notes = || {
+        [
+            Document::from_parts("Note", "Buy milk"),
+            Document::from_parts("Note", "Call the bank"),
+        ]
+    };
+    let summary = table.sync(notes()).await.unwrap();
+    assert_eq!(summary.added.len(), 2);
+    let summary = table.sync(notes()).await.unwrap();
+    assert_eq!(summary.unchanged, 2);
+
+    let summary = table
+        .sync([Document::from_parts("Note", "Buy milk")])
+        .await
+        .unwrap();
+    assert_eq!(summary.unchanged, 1);
+    assert_eq!(summary.removed.len(), 1);
+    assert_eq!(table.select_all().await.unwrap().len(), 1);
+}
@@ -4,7 +4,7 @@ use std::{hash::BuildHasher, io::BufWriter, num::NonZeroUsize, path::Path, sync:
 use postcard::{from_bytes, to_io};
 use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{Embedder, Embedding, EmbeddingInput};
+use crate::{Embedder, Embedding, EmbeddingInput, EmbeddingVariant};
 
 /// Embedding models can be expensive to run. This struct wraps an embedding model with a cache that stores embeddings that have been computed before.
 ///
@@ -251,6 +251,22 @@ impl<M: Embedder> Embedder for CachedEmbeddingModel<M> {
             Ok(embeddings)
         })
     }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        self.model.count_tokens(text)
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<std::ops::Range<usize>>> {
+        self.model.token_offsets(text)
+    }
+
+    fn max_input_tokens(&self) -> Option<usize> {
+        self.model.max_input_tokens()
+    }
+
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        self.model.input_prefix(variant)
+    }
 }
 
 /// An extension trait for [`Embedder`] that allows for caching embeddings.
@@ -276,6 +292,33 @@ pub trait EmbedderCacheExt: Embedder {
     {
         CachedEmbeddingModel::new(self, cache_size)
     }
+
+    /// Wrap the embedder with a persistent cache on disk. Embeddings are written to the cache as they are computed, so they are reused after a restart.
+    ///
+    /// The model id is part of the cache key. Use a different id for every model (and version of a model) that shares the cache.
+    ///
+    /// # Example
+    /// ```rust, no_run
+    /// # use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let bert = Bert::new_for_search()
+    ///     .await?
+    ///     .cached_on_disk(DiskEmbeddingCache::open("./embedding-cache")?, "bge-small-en-v1.5");
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "disk-cache")]
+    fn cached_on_disk(
+        self,
+        cache: crate::DiskEmbeddingCache,
+        model_id: impl Into<String>,
+    ) -> crate::DiskCachedEmbeddingModel<Self>
+    where
+        Self: Sized,
+    {
+        crate::DiskCachedEmbeddingModel::new(self, cache, model_id)
+    }
 }
 
 impl<M: Embedder> EmbedderCacheExt for M {}
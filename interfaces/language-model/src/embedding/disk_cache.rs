@@ -0,0 +1,461 @@
+use futures_util::future::BoxFuture;
+use heed::byteorder::BigEndian;
+use heed::types::{Bytes, Str, U64};
+use heed::EnvOpenOptions;
+use std::path::Path;
+
+use crate::{Embedder, Embedding, EmbeddingInput, EmbeddingVariant, FingerprintHasher};
+
+type EmbeddingDatabase = heed::Database<Str, Bytes>;
+// Big endian keys iterate in numeric order, so the first entry is always the least recently used
+type InsertionOrderDatabase = heed::Database<U64<BigEndian>, Str>;
+
+/// A persistent embedding cache stored on disk with [LMDB](http://www.lmdb.tech/doc/).
+///
+/// Entries are keyed by a hash of the model id, the [`EmbeddingVariant`] and the text, so one cache can be shared between several models. Reads never block each other, even across processes. When the cache grows past the maximum number of entries, the least recently used entries are removed first. With a maximum number of entries, every read that finds embeddings also writes their new position in the eviction order.
+///
+/// Opening the same path twice in one process returns the same cache, so you can open the cache wherever you need it.
+#[derive(Clone)]
+pub struct DiskEmbeddingCache {
+    env: heed::Env,
+    embeddings: EmbeddingDatabase,
+    insertion_order: InsertionOrderDatabase,
+    max_entries: Option<usize>,
+}
+
+impl DiskEmbeddingCache {
+    /// The default maximum size of the memory map (10 GiB). The file on disk only grows as entries are added.
+    pub const DEFAULT_MAP_SIZE: usize = 10 * 1024 * 1024 * 1024;
+
+    /// Open or create a cache in the directory at the given path with the default map size.
+    pub fn open(path: impl AsRef<Path>) -> heed::Result<Self> {
+        Self::open_with_map_size(path, Self::DEFAULT_MAP_SIZE)
+    }
+
+    /// Open or create a cache in the directory at the given path. `map_size` is the largest size in bytes the cache can ever reach.
+    pub fn open_with_map_size(path: impl AsRef<Path>, map_size: usize) -> heed::Result<Self> {
+        let path = path.as_ref();
+        std::fs::create_dir_all(path)?;
+        let env = unsafe {
+            EnvOpenOptions::new()
+                .map_size(map_size)
+                .max_dbs(2)
+                .open(path)
+        }?;
+        let mut wtxn = env.write_txn()?;
+        let embeddings = env.create_database(&mut wtxn, Some("embeddings"))?;
+        let insertion_order = env.create_database(&mut wtxn, Some("insertion-order"))?;
+        wtxn.commit()?;
+        Ok(Self {
+            env,
+            embeddings,
+            insertion_order,
+            max_entries: None,
+        })
+    }
+
+    /// Limit the number of embeddings in the cache. When an insert pushes the cache over the limit, the least recently used embeddings are removed.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Get the number of embeddings in the cache.
+    pub fn len(&self) -> heed::Result<u64> {
+        let rtxn = self.env.read_txn()?;
+        self.embeddings.len(&rtxn)
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> heed::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Remove every embedding from the cache.
+    pub fn clear(&self) -> heed::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        self.embeddings.clear(&mut wtxn)?;
+        self.insertion_order.clear(&mut wtxn)?;
+        wtxn.commit()
+    }
+
+    /// Get the cache key for an input to a model.
+    fn key(model_id: &str, input: &EmbeddingInput) -> String {
+        FingerprintHasher::new()
+            .update(model_id)
+            .update(match input.variant {
+                EmbeddingVariant::Query => "query",
+                EmbeddingVariant::Document => "document",
+            })
+            .update(&input.text)
+            .finish()
+    }
+
+    /// Get the embeddings for the inputs that are in the cache. Entries that can't be decoded are treated as missing.
+    fn get_all(
+        &self,
+        model_id: &str,
+        inputs: &[EmbeddingInput],
+    ) -> heed::Result<Vec<Option<Vec<f32>>>> {
+        let keys = inputs
+            .iter()
+            .map(|input| Self::key(model_id, input))
+            .collect::<Vec<_>>();
+        let rtxn = self.env.read_txn()?;
+        let found = keys
+            .iter()
+            .map(|key| Ok(self.embeddings.get(&rtxn, key)?.and_then(decode_embedding)))
+            .collect::<heed::Result<Vec<_>>>()?;
+        drop(rtxn);
+
+        // Recency only matters if entries are evicted
+        if self.max_entries.is_some() {
+            self.touch(
+                keys.iter()
+                    .zip(&found)
+                    .filter(|(_, found)| found.is_some())
+                    .map(|(key, _)| key.as_str()),
+            )?;
+        }
+        Ok(found)
+    }
+
+    /// Get the insertion index the next entry should use.
+    fn next_index(&self, wtxn: &heed::RwTxn) -> heed::Result<u64> {
+        Ok(match self.insertion_order.last(wtxn)? {
+            Some((last, _)) => last + 1,
+            None => 0,
+        })
+    }
+
+    /// Move the entries to the end of the eviction order.
+    fn touch<'a>(&self, keys: impl IntoIterator<Item = &'a str>) -> heed::Result<()> {
+        let mut keys = keys.into_iter().peekable();
+        if keys.peek().is_none() {
+            return Ok(());
+        }
+        let mut wtxn = self.env.write_txn()?;
+        let mut next = self.next_index(&wtxn)?;
+        for key in keys {
+            // Another process may have evicted the entry since it was read
+            let Some(mut bytes) = self.embeddings.get(&wtxn, key)?.map(<[u8]>::to_vec) else {
+                continue;
+            };
+            let Some(old) = insertion_index(&bytes) else {
+                continue;
+            };
+            bytes[..8].copy_from_slice(&next.to_be_bytes());
+            self.insertion_order.delete(&mut wtxn, &old)?;
+            self.embeddings.put(&mut wtxn, key, &bytes)?;
+            self.insertion_order.put(&mut wtxn, &next, key)?;
+            next += 1;
+        }
+        wtxn.commit()
+    }
+
+    /// Add embeddings to the cache and remove the least recently used embeddings if the cache is over the size limit.
+    fn insert_all<'a>(
+        &self,
+        model_id: &str,
+        items: impl IntoIterator<Item = (&'a EmbeddingInput, Vec<f32>)>,
+    ) -> heed::Result<()> {
+        let mut wtxn = self.env.write_txn()?;
+        let next = self.next_index(&wtxn)?;
+        for (index, (input, embedding)) in (next..).zip(items) {
+            let key = Self::key(model_id, input);
+            self.embeddings
+                .put(&mut wtxn, &key, &encode_embedding(index, &embedding))?;
+            self.insertion_order.put(&mut wtxn, &index, &key)?;
+        }
+
+        if let Some(max_entries) = self.max_entries {
+            let mut len = self.embeddings.len(&wtxn)?;
+            while len > max_entries as u64 {
+                let Some((order, key)) = self.insertion_order.first(&wtxn)? else {
+                    break;
+                };
+                let key = key.to_string();
+                self.insertion_order.delete(&mut wtxn, &order)?;
+                // If the key was inserted or read again later, the newer entry owns the embedding
+                let owned = match self.embeddings.get(&wtxn, &key)?.map(insertion_index) {
+                    Some(Some(index)) => index == order,
+                    // Entries that can't be decoded are always removed
+                    Some(None) => true,
+                    None => false,
+                };
+                if owned {
+                    self.embeddings.delete(&mut wtxn, &key)?;
+                    len -= 1;
+                }
+            }
+        }
+
+        wtxn.commit()
+    }
+}
+
+/// Entries are stored as the big endian insertion index followed by the little endian floats of the embedding.
+fn encode_embedding(insertion_index: u64, embedding: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(8 + embedding.len() * 4);
+    bytes.extend_from_slice(&insertion_index.to_be_bytes());
+    for value in embedding {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes
+}
+
+/// Get the insertion index of an entry, or `None` if the entry is too short.
+fn insertion_index(bytes: &[u8]) -> Option<u64> {
+    let (index, _) = bytes.split_first_chunk::<8>()?;
+    Some(u64::from_be_bytes(*index))
+}
+
+/// Decode the embedding of an entry, or `None` if the entry is corrupted.
+fn decode_embedding(bytes: &[u8]) -> Option<Vec<f32>> {
+    let (_, embedding) = bytes.split_first_chunk::<8>()?;
+    if embedding.len() % 4 != 0 {
+        return None;
+    }
+    Some(
+        embedding
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect(),
+    )
+}
+
+/// An embedding model wrapped with a [`DiskEmbeddingCache`]. Unlike [`crate::CachedEmbeddingModel`], the cache is written to disk as embeddings are computed, so it survives restarts without saving it manually.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let cache = DiskEmbeddingCache::open("./embedding-cache")?.with_max_entries(1_000_000);
+///     let bert = Bert::new_for_search()
+///         .await?
+///         // The model id separates the embeddings of different models in the same cache
+///         .cached_on_disk(cache, "bge-small-en-v1.5");
+///
+///     // The first run embeds the sentence and stores it in the cache. Later runs read it from disk.
+///     let embedding = bert.embed("Cats are cool").await?;
+///     println!("{:?}", embedding);
+///     Ok(())
+/// }
+/// ```
+pub struct DiskCachedEmbeddingModel<M: Embedder> {
+    model: M,
+    model_id: String,
+    cache: DiskEmbeddingCache,
+}
+
+impl<M: Embedder> DiskCachedEmbeddingModel<M> {
+    /// Wrap a model with a disk cache. The model id should change whenever the embeddings the model produces change.
+    pub fn new(model: M, cache: DiskEmbeddingCache, model_id: impl Into<String>) -> Self {
+        Self {
+            model,
+            model_id: model_id.into(),
+            cache,
+        }
+    }
+
+    /// Get a reference to the underlying embedder.
+    pub fn get_embedder(&self) -> &M {
+        &self.model
+    }
+
+    /// Get a mutable reference to the underlying embedder.
+    pub fn get_embedder_mut(&mut self) -> &mut M {
+        &mut self.model
+    }
+
+    /// Get the cache the embeddings are stored in.
+    pub fn cache(&self) -> &DiskEmbeddingCache {
+        &self.cache
+    }
+}
+
+impl<M: Embedder> Embedder for DiskCachedEmbeddingModel<M> {
+    /// The vector space that this embedder uses.
+    type VectorSpace = M::VectorSpace;
+
+    /// Embed a single string.
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        Box::pin(async move {
+            let mut embeddings = self.embed_vec_for(vec![input]).await?;
+            Ok(embeddings.remove(0))
+        })
+    }
+
+    /// Embed a batch of documents. All documents missing from the cache are embedded in a single batch.
+    fn embed_vec(
+        &self,
+        inputs: Vec<String>,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        self.embed_vec_for(
+            inputs
+                .into_iter()
+                .map(|text| EmbeddingInput::new(text, EmbeddingVariant::Document))
+                .collect(),
+        )
+    }
+
+    /// Embed a batch of strings.
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        Box::pin(async move {
+            let cached = self.cache.get_all(&self.model_id, &inputs)?;
+            let mut embeddings = Vec::with_capacity(inputs.len());
+            let mut inputs_not_in_cache = Vec::new();
+            let mut indices_not_in_cache = Vec::new();
+            for (i, (input, cached)) in inputs.iter().zip(cached).enumerate() {
+                match cached {
+                    Some(embedding) => embeddings.push(Embedding::from(embedding)),
+                    None => {
+                        embeddings.push(Embedding::from([]));
+                        inputs_not_in_cache.push(input.clone());
+                        indices_not_in_cache.push(i);
+                    }
+                }
+            }
+
+            // If everything is in the cache, we can just return the embeddings
+            if inputs_not_in_cache.is_empty() {
+                return Ok(embeddings);
+            }
+
+            let embeddings_not_in_cache = self
+                .model
+                .embed_vec_for(inputs_not_in_cache.clone())
+                .await?;
+            self.cache.insert_all(
+                &self.model_id,
+                inputs_not_in_cache
+                    .iter()
+                    .zip(&embeddings_not_in_cache)
+                    .map(|(input, embedding)| (input, embedding.to_vec())),
+            )?;
+            for (i, embedding) in indices_not_in_cache
+                .into_iter()
+                .zip(embeddings_not_in_cache)
+            {
+                embeddings[i] = embedding;
+            }
+            Ok(embeddings)
+        })
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        self.model.count_tokens(text)
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<std::ops::Range<usize>>> {
+        self.model.token_offsets(text)
+    }
+
+    fn max_input_tokens(&self) -> Option<usize> {
+        self.model.max_input_tokens()
+    }
+
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        self.model.input_prefix(variant)
+    }
+}
+
+#[cfg(test)]
+struct CountingEmbedder(std::sync::atomic::AtomicUsize);
+
+#[cfg(test)]
+impl Embedder for CountingEmbedder {
+    type VectorSpace = crate::UnknownVectorSpace;
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
+        self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Box::pin(async move { Ok(Embedding::from([input.text.len() as f32, 1.0])) })
+    }
+
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        Box::pin(async move {
+            let mut embeddings = Vec::new();
+            for input in inputs {
+                embeddings.push(self.embed_for(input).await?);
+            }
+            Ok(embeddings)
+        })
+    }
+}
+
+#[tokio::test]
+async fn disk_cache_persists_and_evicts() {
+    use crate::EmbedderExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let dir = tempfile::tempdir().unwrap();
+
+    let cache = DiskEmbeddingCache::open(dir.path())
+        .unwrap()
+        .with_max_entries(3);
+    let model = DiskCachedEmbeddingModel::new(CountingEmbedder(AtomicUsize::new(0)), cache, "a");
+    let embeddings = model.embed_batch(["one", "three", "one"]).await.unwrap();
+    assert_eq!(embeddings[1].to_vec(), vec![5.0, 1.0]);
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 3);
+
+    // Reopening the cache finds the embeddings without running the model
+    let cache = DiskEmbeddingCache::open(dir.path())
+        .unwrap()
+        .with_max_entries(3);
+    let model = DiskCachedEmbeddingModel::new(CountingEmbedder(AtomicUsize::new(0)), cache, "a");
+    let embeddings = model.embed_batch(["three", "one"]).await.unwrap();
+    assert_eq!(embeddings[1].to_vec(), vec![3.0, 1.0]);
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 0);
+
+    // Queries are cached separately from documents
+    model.embed_query("one").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 1);
+
+    // Other models are cached separately in the same cache. The least recently used entry ("three") is evicted
+    let other = DiskCachedEmbeddingModel::new(
+        CountingEmbedder(AtomicUsize::new(0)),
+        model.cache().clone(),
+        "b",
+    );
+    other.embed("one").await.unwrap();
+    assert_eq!(other.get_embedder().0.load(Ordering::SeqCst), 1);
+    assert_eq!(model.cache().len().unwrap(), 3);
+
+    // Reading an entry makes it the most recently used, so the query is evicted instead
+    model.embed("one").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 1);
+    model.embed("four").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 2);
+    model.embed("one").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 2);
+    model.embed_query("one").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 3);
+    model.embed("three").await.unwrap();
+    assert_eq!(model.get_embedder().0.load(Ordering::SeqCst), 4);
+}
+
+#[test]
+fn corrupted_entries_are_misses() {
+    assert_eq!(insertion_index(&[1, 2, 3]), None);
+    assert_eq!(decode_embedding(&[0; 4]), None);
+    assert_eq!(decode_embedding(&[0; 10]), None);
+    assert_eq!(
+        decode_embedding(&encode_embedding(7, &[1.0, 2.0])),
+        Some(vec![1.0, 2.0])
+    );
+    assert_eq!(insertion_index(&encode_embedding(7, &[1.0])), Some(7));
+}
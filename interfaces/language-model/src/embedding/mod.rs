@@ -11,10 +11,18 @@ use serde::{Deserialize, Deserializer, Serialize, Serializer};
 mod cache;
 #[cfg(feature = "cache")]
 pub use cache::*;
+#[cfg(feature = "disk-cache")]
+mod disk_cache;
+#[cfg(feature = "disk-cache")]
+pub use disk_cache::*;
 mod model;
 pub use model::*;
 mod into_embedding;
 pub use into_embedding::*;
+mod quantized;
+pub use quantized::*;
+mod multi_vector;
+pub use multi_vector::*;
 
 /// An untyped vector space that is not associated with a model. This can be used to erase the vector type from an embedding.
 pub struct UnknownVectorSpace;
@@ -1,4 +1,5 @@
 use kalosm_common::BoxedFuture;
+use std::ops::Range;
 
 use crate::embedding::{Embedding, VectorSpace};
 use crate::UnknownVectorSpace;
@@ -75,6 +76,35 @@ pub trait Embedder: Send + Sync + 'static {
             Ok(embeddings)
         })
     }
+
+    /// Count the tokens the model splits some text into, without the special tokens the model adds around each input. Returns `None` if the tokenizer of the model is not available.
+    ///
+    /// Chunkers use this to size chunks so they fit in [`Embedder::max_input_tokens`].
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        _ = text;
+        None
+    }
+
+    /// The byte ranges of the tokens the model splits some text into, without the special tokens the model adds around each input. Returns `None` if the tokenizer of the model is not available.
+    ///
+    /// Chunkers tokenize a document once with this method and count the tokens in any part of the document from the offsets.
+    fn token_offsets(&self, text: &str) -> Option<Vec<Range<usize>>> {
+        _ = text;
+        None
+    }
+
+    /// The maximum number of tokens of text the model can embed at once, not counting special tokens. Returns `None` if the limit is unknown.
+    fn max_input_tokens(&self) -> Option<usize> {
+        None
+    }
+
+    /// The text the model adds before inputs of a [`EmbeddingVariant`] before embedding them, like `passage: ` for e5 models. Returns `None` if the model embeds inputs as they are.
+    ///
+    /// The tokens of the prefix count towards [`Embedder::max_input_tokens`].
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        _ = variant;
+        None
+    }
 }
 
 /// The input to an embedding model. This includes the text to be embedded and the type of embedding to output.
@@ -211,4 +241,20 @@ impl<E: Embedder + Send + Sync + 'static> Embedder for AnyEmbedder<E> {
                 .map(|e| e.into_iter().map(|e| e.cast()).collect())
         })
     }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        self.0.count_tokens(text)
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<Range<usize>>> {
+        self.0.token_offsets(text)
+    }
+
+    fn max_input_tokens(&self) -> Option<usize> {
+        self.0.max_input_tokens()
+    }
+
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        self.0.input_prefix(variant)
+    }
 }
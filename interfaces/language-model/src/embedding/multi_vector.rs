@@ -0,0 +1,112 @@
+use kalosm_common::BoxedFuture;
+
+use crate::{Embedder, Embedding, EmbeddingInput, EmbeddingVariant, VectorSpace};
+
+/// A model that can embed text into one embedding per token instead of a single pooled embedding.
+///
+/// Token level embeddings are used for [ColBERT](https://arxiv.org/abs/2004.12832) style late interaction retrieval. Instead of comparing one embedding for the query with one embedding for the document, every query token is matched with the most similar document token and the similarities are summed with [`max_sim`]. This keeps details that are lost when the tokens are pooled into a single embedding.
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let bert = Bert::new_for_search().await?;
+///     let query = bert.embed_query_tokens("How do I reset the router?").await?;
+///     let document = bert
+///         .embed_document_tokens("Hold the reset button for ten seconds to restore the factory settings")
+///         .await?;
+///     println!("score: {}", max_sim(&query, &document));
+///     Ok(())
+/// }
+/// ```
+pub trait MultiVectorEmbedder: Embedder {
+    /// Embed a batch of inputs into one embedding per token for each input.
+    #[allow(clippy::type_complexity)]
+    fn embed_tokens_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Vec<Embedding<Self::VectorSpace>>>>>;
+
+    /// Embed an input into one embedding per token.
+    fn embed_tokens_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        Box::pin(async move {
+            let mut embeddings = self.embed_tokens_vec_for(vec![input]).await?;
+            Ok(embeddings.remove(0))
+        })
+    }
+
+    /// Embed a query into one embedding per token.
+    fn embed_query_tokens(
+        &self,
+        input: impl ToString,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        self.embed_tokens_for(EmbeddingInput::new(input, EmbeddingVariant::Query))
+    }
+
+    /// Embed a document into one embedding per token.
+    fn embed_document_tokens(
+        &self,
+        input: impl ToString,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
+        self.embed_tokens_for(EmbeddingInput::new(input, EmbeddingVariant::Document))
+    }
+}
+
+/// Score a document for a query with late interaction: the sum over every query embedding of the highest cosine similarity with any document embedding. Higher scores are better.
+///
+/// The score grows with the number of query embeddings, so only compare scores for the same query.
+pub fn max_sim<S: VectorSpace>(query: &[Embedding<S>], document: &[Embedding<S>]) -> f32 {
+    let query = query.iter().map(Embedding::to_vec).collect::<Vec<_>>();
+    let document = document.iter().map(Embedding::to_vec).collect::<Vec<_>>();
+    max_sim_vectors(&query, &document)
+}
+
+/// [`max_sim`] for raw vectors.
+pub fn max_sim_vectors(query: &[Vec<f32>], document: &[Vec<f32>]) -> f32 {
+    let document = document
+        .iter()
+        .map(|vector| normalized(vector))
+        .collect::<Vec<_>>();
+    query
+        .iter()
+        .map(|query| {
+            let query = normalized(query);
+            document
+                .iter()
+                .map(|document| query.iter().zip(document).map(|(a, b)| a * b).sum::<f32>())
+                .reduce(f32::max)
+                .unwrap_or(0.)
+        })
+        .sum()
+}
+
+fn normalized(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0. {
+        vector.iter().map(|value| value / norm).collect()
+    } else {
+        vector.to_vec()
+    }
+}
+
+#[test]
+fn max_sim_matches_each_query_token() {
+    let embedding = |vector: [f32; 2]| Embedding::<crate::UnknownVectorSpace>::from(vector);
+    let query = [embedding([1., 0.]), embedding([0., 1.])];
+    // Each query token has an exact match in the first document
+    let exact = [
+        embedding([0., 2.]),
+        embedding([3., 0.]),
+        embedding([1., 1.]),
+    ];
+    // The second document only matches the first query token
+    let partial = [embedding([1., 0.]), embedding([1., 0.1])];
+    assert!((max_sim(&query, &exact) - 2.).abs() < 1e-5);
+    assert!(max_sim(&query, &partial) < max_sim(&query, &exact));
+    assert_eq!(max_sim(&query, &[]), 0.);
+}
@@ -0,0 +1,271 @@
+use std::marker::PhantomData;
+
+use candle_core::{Device, Tensor};
+
+use crate::{Embedding, VectorSpace};
+
+/// An embedding quantized to a single bit per dimension. Each bit is set if the dimension was positive.
+///
+/// Binary embeddings are 32 times smaller than f32 embeddings and are compared with the [Hamming distance](https://en.wikipedia.org/wiki/Hamming_distance), which is very fast to compute. They lose most of the precision of the original embedding, so they work best to find candidates that are rescored with the full embedding.
+///
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let a = Embedding::<UnknownVectorSpace>::from([0.5, -0.1, 0.3, -0.8]).quantize_binary();
+/// let b = Embedding::<UnknownVectorSpace>::from([0.2, 0.4, 0.1, -0.3]).quantize_binary();
+/// assert_eq!(a.hamming_distance(&b), 1);
+/// ```
+pub struct BinaryEmbedding<S: VectorSpace> {
+    words: Vec<u64>,
+    dimensions: usize,
+    model: PhantomData<S>,
+}
+
+impl<S: VectorSpace> BinaryEmbedding<S> {
+    /// Create a binary embedding from the packed bits. Bit `i % 64` of word `i / 64` is dimension `i`.
+    pub fn new(words: Vec<u64>, dimensions: usize) -> Self {
+        assert_eq!(
+            words.len(),
+            dimensions.div_ceil(64),
+            "The number of words doesn't match the number of dimensions"
+        );
+        Self {
+            words,
+            dimensions,
+            model: PhantomData,
+        }
+    }
+
+    /// Get the packed bits of this embedding.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    /// Get the number of dimensions of this embedding.
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    /// Get the number of dimensions that differ between this embedding and another embedding.
+    pub fn hamming_distance(&self, other: &Self) -> u32 {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+
+    /// Expand the bits into a vector of `1.0` for set bits and `-1.0` for unset bits.
+    pub fn to_vec(&self) -> Vec<f32> {
+        (0..self.dimensions)
+            .map(|i| {
+                if self.words[i / 64] & (1 << (i % 64)) != 0 {
+                    1.
+                } else {
+                    -1.
+                }
+            })
+            .collect()
+    }
+
+    /// Cast this embedding to a different vector space.
+    pub fn cast<S2: VectorSpace>(self) -> BinaryEmbedding<S2> {
+        BinaryEmbedding {
+            words: self.words,
+            dimensions: self.dimensions,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> Clone for BinaryEmbedding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            words: self.words.clone(),
+            dimensions: self.dimensions,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> PartialEq for BinaryEmbedding<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.dimensions == other.dimensions && self.words == other.words
+    }
+}
+
+impl<S: VectorSpace> std::fmt::Debug for BinaryEmbedding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BinaryEmbedding")
+            .field("dimensions", &self.dimensions)
+            .field("model", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+/// An embedding quantized to a signed byte per dimension with a single scale for the whole embedding.
+///
+/// Int8 embeddings are 4 times smaller than f32 embeddings and keep most of the precision of the original embedding.
+///
+/// ```rust
+/// use kalosm_language_model::*;
+///
+/// let embedding = Embedding::<UnknownVectorSpace>::from([0.5, -0.25, 1.0, 0.0]);
+/// let quantized = embedding.quantize_int8();
+/// assert_eq!(quantized.values(), &[64, -32, 127, 0]);
+/// for (original, dequantized) in embedding.to_vec().iter().zip(quantized.to_vec()) {
+///     assert!((original - dequantized).abs() < 0.01);
+/// }
+/// ```
+pub struct Int8Embedding<S: VectorSpace> {
+    values: Vec<i8>,
+    scale: f32,
+    model: PhantomData<S>,
+}
+
+impl<S: VectorSpace> Int8Embedding<S> {
+    /// Create an int8 embedding from the quantized values and the scale that converts them back to floats.
+    pub fn new(values: Vec<i8>, scale: f32) -> Self {
+        Self {
+            values,
+            scale,
+            model: PhantomData,
+        }
+    }
+
+    /// Get the quantized values of this embedding.
+    pub fn values(&self) -> &[i8] {
+        &self.values
+    }
+
+    /// Get the scale that converts the quantized values back to floats.
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Get the number of dimensions of this embedding.
+    pub fn dimensions(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Convert the quantized values back to floats.
+    pub fn to_vec(&self) -> Vec<f32> {
+        self.values
+            .iter()
+            .map(|value| *value as f32 * self.scale)
+            .collect()
+    }
+
+    /// Convert this embedding back to a float embedding.
+    pub fn dequantize(&self) -> Embedding<S> {
+        Embedding::from(self.to_vec())
+    }
+
+    /// Cast this embedding to a different vector space.
+    pub fn cast<S2: VectorSpace>(self) -> Int8Embedding<S2> {
+        Int8Embedding {
+            values: self.values,
+            scale: self.scale,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> Clone for Int8Embedding<S> {
+    fn clone(&self) -> Self {
+        Self {
+            values: self.values.clone(),
+            scale: self.scale,
+            model: PhantomData,
+        }
+    }
+}
+
+impl<S: VectorSpace> PartialEq for Int8Embedding<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.scale == other.scale && self.values == other.values
+    }
+}
+
+impl<S: VectorSpace> std::fmt::Debug for Int8Embedding<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Int8Embedding")
+            .field("values", &self.values)
+            .field("scale", &self.scale)
+            .field("model", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: VectorSpace> Embedding<S> {
+    /// Quantize this embedding to a single bit per dimension.
+    pub fn quantize_binary(&self) -> BinaryEmbedding<S> {
+        let vector = self.to_vec();
+        let mut words = vec![0u64; vector.len().div_ceil(64)];
+        for (i, value) in vector.iter().enumerate() {
+            if *value > 0. {
+                words[i / 64] |= 1 << (i % 64);
+            }
+        }
+        BinaryEmbedding::new(words, vector.len())
+    }
+
+    /// Quantize this embedding to a signed byte per dimension. The largest absolute value is mapped to 127.
+    pub fn quantize_int8(&self) -> Int8Embedding<S> {
+        let vector = self.to_vec();
+        let max = vector.iter().fold(0f32, |max, value| max.max(value.abs()));
+        if max == 0. {
+            return Int8Embedding::new(vec![0; vector.len()], 0.);
+        }
+        let scale = max / i8::MAX as f32;
+        let values = vector
+            .iter()
+            .map(|value| (value / scale).round() as i8)
+            .collect();
+        Int8Embedding::new(values, scale)
+    }
+
+    /// Keep the first `dimensions` dimensions of this embedding and normalize the result to unit length.
+    ///
+    /// Models trained with [Matryoshka representation learning](https://arxiv.org/abs/2205.13147) put the most important information in the first dimensions, so truncated embeddings are smaller while staying useful for search. Truncating embeddings from other models will hurt search quality significantly.
+    ///
+    /// The result is always normalized because Matryoshka models are trained to compare the truncated prefixes with cosine similarity. Normalizing doesn't change cosine similarity, but it does change dot product and euclidean distances. If you compare embeddings with one of those metrics, the truncated embeddings are compared as if they were unit length.
+    pub fn truncate(&self, dimensions: usize) -> Self {
+        let mut vector = self.to_vec();
+        vector.truncate(dimensions);
+        let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+        if norm > 0. {
+            vector.iter_mut().for_each(|value| *value /= norm);
+        }
+        let shape = [vector.len()];
+        Embedding::new(Tensor::from_vec(vector, &shape, &Device::Cpu).unwrap())
+    }
+}
+
+#[test]
+fn quantized_embeddings_keep_order() {
+    let query = Embedding::<crate::UnknownVectorSpace>::from((0..100).map(|i| (i as f32).sin()));
+    let close = Embedding::from((0..100).map(|i| (i as f32).sin() + 0.1 * (i as f32).cos()));
+    let far = Embedding::from((0..100).map(|i| (i as f32 * 3.).cos()));
+
+    let binary = query.quantize_binary();
+    assert_eq!(binary.dimensions(), 100);
+    assert_eq!(binary.words().len(), 2);
+    assert!(
+        binary.hamming_distance(&close.quantize_binary())
+            < binary.hamming_distance(&far.quantize_binary())
+    );
+
+    let int8 = query.quantize_int8();
+    let dequantized = int8.dequantize();
+    assert!(dequantized.cosine_similarity(&query) > 0.999);
+    assert!(
+        int8.dequantize().cosine_similarity(&close) > int8.dequantize().cosine_similarity(&far)
+    );
+
+    let truncated = query.truncate(10);
+    assert_eq!(truncated.to_vec().len(), 10);
+    assert!((truncated.cosine_similarity(&truncated) - 1.).abs() < 1e-5);
+    let norm = truncated.to_vec().iter().map(|v| v * v).sum::<f32>();
+    assert!((norm - 1.).abs() < 1e-5);
+}
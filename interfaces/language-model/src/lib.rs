@@ -38,3 +38,7 @@ mod embedding;
 pub use embedding::*;
 mod model;
 pub use model::*;
+mod rerank;
+pub use rerank::*;
+mod session;
+pub use session::*;
@@ -1,4 +1,5 @@
 use crate::structured::generate_structured;
+use crate::ModelFingerprint;
 use crate::TokenOutputStream;
 use futures_util::{Future, FutureExt};
 use futures_util::{Stream, StreamExt};
@@ -612,6 +613,22 @@ pub trait SyncModel {
 
     /// Return the tokenizer associated with this model.
     fn tokenizer(&self) -> Arc<Tokenizer>;
+
+    /// Get the fingerprint of the weights and tokenizer this model was loaded from. Models that return a fingerprint will reject sessions created by other models in [`SyncModel::load_session`].
+    fn fingerprint(&self) -> Option<ModelFingerprint> {
+        None
+    }
+
+    /// Load a session saved with [`Session::save_to`] and check that it was created by this model.
+    ///
+    /// If the model has a [`SyncModel::fingerprint`], sessions that were created by a different model (or that do not record a fingerprint) are rejected with a [`crate::SessionLoadError`].
+    fn load_session(&self, path: &Path) -> anyhow::Result<Self::Session> {
+        let session = Self::Session::load_from(path)?;
+        if let Some(fingerprint) = self.fingerprint() {
+            fingerprint.verify(Session::fingerprint(&session))?;
+        }
+        Ok(session)
+    }
 }
 
 /// A session for a model.
@@ -641,6 +658,11 @@ pub trait Session {
     {
         Err(anyhow::Error::msg("Not implemented"))
     }
+
+    /// Get the fingerprint of the model that created this session, if it is known.
+    fn fingerprint(&self) -> Option<&ModelFingerprint> {
+        None
+    }
 }
 
 impl Session for () {
@@ -985,12 +1007,18 @@ pub type BoxedSyncModel = Box<dyn SyncModel<Session = AnySession>>;
 
 trait AnySessionTrait {
     fn save_to(&self, path: &Path) -> anyhow::Result<()>;
+
+    fn fingerprint(&self) -> Option<&ModelFingerprint>;
 }
 
 impl<S: Any + Session> AnySessionTrait for S {
     fn save_to(&self, path: &Path) -> anyhow::Result<()> {
         Session::save_to(self, path)
     }
+
+    fn fingerprint(&self) -> Option<&ModelFingerprint> {
+        Session::fingerprint(self)
+    }
 }
 
 /// A type-erased session.
@@ -1004,6 +1032,10 @@ impl Session for AnySession {
     fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
         self.session.save_to(path.as_ref())
     }
+
+    fn fingerprint(&self) -> Option<&ModelFingerprint> {
+        self.session.fingerprint()
+    }
 }
 
 impl SyncModel for BoxedSyncModel {
@@ -1043,6 +1075,16 @@ impl SyncModel for BoxedSyncModel {
         let self_ref: &(dyn SyncModel<Session = AnySession>) = self.as_ref();
         self_ref.tokenizer()
     }
+
+    fn fingerprint(&self) -> Option<ModelFingerprint> {
+        let self_ref: &dyn SyncModel<Session = AnySession> = self.as_ref();
+        self_ref.fingerprint()
+    }
+
+    fn load_session(&self, path: &Path) -> anyhow::Result<Self::Session> {
+        let self_ref: &dyn SyncModel<Session = AnySession> = self.as_ref();
+        self_ref.load_session(path)
+    }
 }
 
 struct AnyModel<M>(M);
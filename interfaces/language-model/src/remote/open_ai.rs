@@ -386,4 +386,7 @@ impl Embedder for AdaEmbedder {
                 .collect())
         })
     }
+    fn max_input_tokens(&self) -> Option<usize> {
+        Some(8191)
+    }
 }
@@ -0,0 +1,77 @@
+use kalosm_common::BoxedFuture;
+
+/// A model that scores how relevant documents are to a query. Unlike an [`crate::Embedder`], a reranker sees the query and the document together, so it is more accurate but too slow to run over a whole corpus. Rerankers are typically used to re-score the top results of a vector search.
+///
+/// # Example
+///
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let reranker = BertReranker::new().await.unwrap();
+///     let documents = [
+///         "Paris is the capital of France",
+///         "The quick brown fox jumps over the lazy dog",
+///     ];
+///     let ranked = reranker
+///         .rerank("What is the capital of France?", documents)
+///         .await
+///         .unwrap();
+///     for result in ranked {
+///         println!("{}: {}", result.score, documents[result.index]);
+///     }
+/// }
+/// ```
+pub trait Reranker: Send + Sync + 'static {
+    /// Score how relevant each document is to the query. Higher scores are more relevant. Returns a score for each document in the same order as the inputs.
+    fn score_batch(
+        &self,
+        query: String,
+        documents: Vec<String>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<f32>>>;
+
+    /// Score how relevant a document is to the query. Higher scores are more relevant.
+    fn score(&self, query: String, document: String) -> BoxedFuture<'_, anyhow::Result<f32>> {
+        Box::pin(async move {
+            let mut scores = self.score_batch(query, vec![document]).await?;
+            scores
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("No score returned from the reranker"))
+        })
+    }
+
+    /// Score each document and return the documents sorted from most to least relevant.
+    fn rerank<I>(
+        &self,
+        query: impl ToString,
+        documents: I,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<RerankResult>>>
+    where
+        Self: Sized,
+        I: IntoIterator,
+        I::Item: ToString,
+    {
+        let query = query.to_string();
+        let documents = documents.into_iter().map(|d| d.to_string()).collect();
+        Box::pin(async move {
+            let scores = self.score_batch(query, documents).await?;
+            let mut results = scores
+                .into_iter()
+                .enumerate()
+                .map(|(index, score)| RerankResult { index, score })
+                .collect::<Vec<_>>();
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+            Ok(results)
+        })
+    }
+}
+
+/// The relevance score of a document from [`Reranker::rerank`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RerankResult {
+    /// The index of the document in the input.
+    pub index: usize,
+    /// How relevant the document is to the query. Higher scores are more relevant.
+    pub score: f32,
+}
@@ -0,0 +1,447 @@
+use candle_core::quantized::{ggml_file, gguf_file, GgmlDType};
+use candle_core::{Device, Tensor};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use tokenizers::Tokenizer;
+
+/// The version of the session file format written by [`save_session_tensors`].
+///
+/// Session files written before versioning was introduced are treated as version 0.
+pub const SESSION_FORMAT_VERSION: u32 = 1;
+
+const FORMAT_VERSION_KEY: &str = "kalosm.session.format_version";
+const WEIGHTS_FINGERPRINT_KEY: &str = "kalosm.session.weights";
+const TOKENIZER_FINGERPRINT_KEY: &str = "kalosm.session.tokenizer";
+
+/// A fingerprint of the weights and tokenizer a model was loaded from. Sessions store the fingerprint of the model that created them so they are not loaded into a different model.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ModelFingerprint {
+    weights: String,
+    tokenizer: String,
+}
+
+impl ModelFingerprint {
+    /// Create a new fingerprint from a digest of the weights and a digest of the tokenizer.
+    pub fn new(weights: impl Into<String>, tokenizer: impl Into<String>) -> Self {
+        Self {
+            weights: weights.into(),
+            tokenizer: tokenizer.into(),
+        }
+    }
+
+    /// Create a new fingerprint from a digest of the weights and the tokenizer the model uses.
+    pub fn with_tokenizer(weights: impl Into<String>, tokenizer: &Tokenizer) -> Self {
+        let mut hasher = FingerprintHasher::new();
+        // The tokenizer serializes its vocab in a stable order, so the json representation is a stable key
+        match tokenizer.to_string(false) {
+            Ok(json) => hasher.update(json),
+            Err(err) => {
+                tracing::warn!("Failed to serialize tokenizer for fingerprinting: {err}");
+                hasher.update(format!("{:?}", tokenizer.get_vocab(true).len()))
+            }
+        };
+        Self::new(weights, hasher.finish())
+    }
+
+    /// Get the digest of the weights.
+    pub fn weights(&self) -> &str {
+        &self.weights
+    }
+
+    /// Get the digest of the tokenizer.
+    pub fn tokenizer(&self) -> &str {
+        &self.tokenizer
+    }
+
+    /// Check that a session created with the `found` fingerprint can be used with a model with this fingerprint.
+    pub fn verify(&self, found: Option<&ModelFingerprint>) -> Result<(), SessionLoadError> {
+        let found = found.ok_or(SessionLoadError::MissingFingerprint)?;
+        if self.weights != found.weights {
+            return Err(SessionLoadError::WeightsMismatch {
+                expected: self.weights.clone(),
+                found: found.weights.clone(),
+            });
+        }
+        if self.tokenizer != found.tokenizer {
+            return Err(SessionLoadError::TokenizerMismatch {
+                expected: self.tokenizer.clone(),
+                found: found.tokenizer.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// The number of bytes hashed from the start, middle and end of each tensor by [`FingerprintHasher`].
+const TENSOR_SAMPLE_BYTES: usize = 4096;
+
+/// The offsets of the samples hashed from a tensor with `len` bytes of data.
+fn tensor_sample_offsets(len: u64) -> Vec<u64> {
+    let sample = TENSOR_SAMPLE_BYTES as u64;
+    let mut offsets = vec![
+        0,
+        len.saturating_sub(sample) / 2,
+        len.saturating_sub(sample),
+    ];
+    offsets.dedup();
+    offsets.retain(|offset| *offset < len);
+    offsets
+}
+
+/// The id of a tensor type in the GGML file formats.
+fn ggml_dtype_id(dtype: GgmlDType) -> u32 {
+    match dtype {
+        GgmlDType::F32 => 0,
+        GgmlDType::F16 => 1,
+        GgmlDType::Q4_0 => 2,
+        GgmlDType::Q4_1 => 3,
+        GgmlDType::Q5_0 => 6,
+        GgmlDType::Q5_1 => 7,
+        GgmlDType::Q8_0 => 8,
+        GgmlDType::Q8_1 => 9,
+        GgmlDType::Q2K => 10,
+        GgmlDType::Q3K => 11,
+        GgmlDType::Q4K => 12,
+        GgmlDType::Q5K => 13,
+        GgmlDType::Q6K => 14,
+        GgmlDType::Q8K => 15,
+    }
+}
+
+/// A stable hasher used to build [`ModelFingerprint`]s. Unlike [`std::hash::Hasher`], the output of this hasher does not change between runs or compiler versions.
+#[derive(Default, Clone)]
+pub struct FingerprintHasher {
+    hasher: Sha256,
+}
+
+impl FingerprintHasher {
+    /// Create a new hasher.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add some bytes to the fingerprint.
+    pub fn update(&mut self, bytes: impl AsRef<[u8]>) -> &mut Self {
+        let bytes = bytes.as_ref();
+        // Prefix each update with the length so the boundaries between updates are part of the hash
+        self.hasher.update((bytes.len() as u64).to_le_bytes());
+        self.hasher.update(bytes);
+        self
+    }
+
+    /// Add the metadata and tensor layout of a GGUF file to the fingerprint. This identifies the architecture and the quantization without reading the weights. Use [`FingerprintHasher::update_gguf_tensor_data`] to tell apart fine-tunes of the same architecture.
+    pub fn update_gguf(&mut self, content: &gguf_file::Content) -> &mut Self {
+        let mut metadata: Vec<_> = content.metadata.iter().collect();
+        metadata.sort_by_key(|(name, _)| *name);
+        for (key, value) in metadata {
+            self.update(key);
+            self.update_gguf_value(value);
+        }
+        let mut tensors: Vec<_> = content.tensor_infos.iter().collect();
+        tensors.sort_by_key(|(name, _)| *name);
+        for (name, info) in tensors {
+            self.update(name);
+            self.update_tensor_layout(info.ggml_dtype, info.shape.dims());
+        }
+        self
+    }
+
+    /// Add a sample of the data of every tensor in a GGUF file to the fingerprint. The start, middle and end of each tensor are read from the file.
+    pub fn update_gguf_tensor_data<R: Read + Seek>(
+        &mut self,
+        content: &gguf_file::Content,
+        reader: &mut R,
+    ) -> std::io::Result<&mut Self> {
+        let mut tensors: Vec<_> = content.tensor_infos.iter().collect();
+        tensors.sort_by_key(|(name, _)| *name);
+        let mut buffer = vec![0; TENSOR_SAMPLE_BYTES];
+        for (name, info) in tensors {
+            let start = content.tensor_data_offset + info.offset;
+            let len = (info.shape.elem_count() / info.ggml_dtype.block_size()
+                * info.ggml_dtype.type_size()) as u64;
+            self.update(name);
+            for offset in tensor_sample_offsets(len) {
+                let sample_len = (len - offset).min(TENSOR_SAMPLE_BYTES as u64) as usize;
+                reader.seek(SeekFrom::Start(start + offset))?;
+                reader.read_exact(&mut buffer[..sample_len])?;
+                self.update(&buffer[..sample_len]);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Add the hyperparameters, tensor layout and a sample of the data of every tensor in a GGML file to the fingerprint.
+    pub fn update_ggml(&mut self, content: &ggml_file::Content) -> candle_core::Result<&mut Self> {
+        let hparams = &content.hparams;
+        for value in [
+            hparams.n_vocab,
+            hparams.n_embd,
+            hparams.n_mult,
+            hparams.n_head,
+            hparams.n_layer,
+            hparams.n_rot,
+            hparams.ftype,
+        ] {
+            self.update(value.to_le_bytes());
+        }
+        let mut tensors: Vec<_> = content.tensors.iter().collect();
+        tensors.sort_by_key(|(name, _)| *name);
+        for (name, tensor) in tensors {
+            self.update(name);
+            self.update_tensor_layout(tensor.dtype(), tensor.shape().dims());
+            let data = tensor.data()?;
+            for offset in tensor_sample_offsets(data.len() as u64) {
+                let offset = offset as usize;
+                let end = (offset + TENSOR_SAMPLE_BYTES).min(data.len());
+                self.update(&data[offset..end]);
+            }
+        }
+        Ok(self)
+    }
+
+    fn update_tensor_layout(&mut self, dtype: GgmlDType, dims: &[usize]) {
+        self.update(ggml_dtype_id(dtype).to_le_bytes());
+        self.update((dims.len() as u64).to_le_bytes());
+        for dim in dims {
+            self.update((*dim as u64).to_le_bytes());
+        }
+    }
+
+    /// Add a GGUF metadata value with the type ids from the GGUF spec, so the encoding doesn't depend on how candle formats values.
+    fn update_gguf_value(&mut self, value: &gguf_file::Value) {
+        use gguf_file::Value;
+        match value {
+            Value::U8(value) => self.update([0]).update(value.to_le_bytes()),
+            Value::I8(value) => self.update([1]).update(value.to_le_bytes()),
+            Value::U16(value) => self.update([2]).update(value.to_le_bytes()),
+            Value::I16(value) => self.update([3]).update(value.to_le_bytes()),
+            Value::U32(value) => self.update([4]).update(value.to_le_bytes()),
+            Value::I32(value) => self.update([5]).update(value.to_le_bytes()),
+            Value::F32(value) => self.update([6]).update(value.to_le_bytes()),
+            Value::Bool(value) => self.update([7]).update([*value as u8]),
+            Value::String(value) => self.update([8]).update(value),
+            Value::Array(values) => {
+                self.update([9]).update((values.len() as u64).to_le_bytes());
+                for value in values {
+                    self.update_gguf_value(value);
+                }
+                self
+            }
+            Value::U64(value) => self.update([10]).update(value.to_le_bytes()),
+            Value::I64(value) => self.update([11]).update(value.to_le_bytes()),
+            Value::F64(value) => self.update([12]).update(value.to_le_bytes()),
+        };
+    }
+
+    /// Finish the hash and return it as a hex string.
+    pub fn finish(&self) -> String {
+        format!("{:x}", self.hasher.clone().finalize())
+    }
+}
+
+/// An error that can occur when loading a saved session.
+#[derive(Debug, thiserror::Error)]
+pub enum SessionLoadError {
+    /// The session was saved with a newer version of the session format.
+    #[error("Session format version {found} is not supported (the newest supported version is {supported})")]
+    UnsupportedFormatVersion {
+        /// The version of the session file.
+        found: u32,
+        /// The newest version this build of kalosm can read.
+        supported: u32,
+    },
+    /// The session does not record which model created it.
+    #[error("Session does not contain a model fingerprint")]
+    MissingFingerprint,
+    /// The session was created with different model weights.
+    #[error(
+        "Session was created with different model weights (expected {expected}, found {found})"
+    )]
+    WeightsMismatch {
+        /// The digest of the weights of the current model.
+        expected: String,
+        /// The digest of the weights stored in the session.
+        found: String,
+    },
+    /// The session was created with a different tokenizer.
+    #[error("Session was created with a different tokenizer (expected {expected}, found {found})")]
+    TokenizerMismatch {
+        /// The digest of the tokenizer of the current model.
+        expected: String,
+        /// The digest of the tokenizer stored in the session.
+        found: String,
+    },
+    /// The session metadata could not be read.
+    #[error("Invalid session metadata: {0}")]
+    InvalidMetadata(String),
+    /// The session file could not be read.
+    #[error("Failed to read session file: {0}")]
+    Io(#[from] std::io::Error),
+    /// The session tensors could not be read.
+    #[error("Failed to read session tensors: {0}")]
+    Tensors(#[from] candle_core::Error),
+}
+
+/// Save a session tensor map to a safetensors file along with the session format version and the fingerprint of the model that created it.
+pub fn save_session_tensors(
+    tensors: &HashMap<String, Tensor>,
+    fingerprint: Option<&ModelFingerprint>,
+    path: impl AsRef<Path>,
+) -> anyhow::Result<()> {
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        FORMAT_VERSION_KEY.to_string(),
+        SESSION_FORMAT_VERSION.to_string(),
+    );
+    if let Some(fingerprint) = fingerprint {
+        metadata.insert(
+            WEIGHTS_FINGERPRINT_KEY.to_string(),
+            fingerprint.weights.clone(),
+        );
+        metadata.insert(
+            TOKENIZER_FINGERPRINT_KEY.to_string(),
+            fingerprint.tokenizer.clone(),
+        );
+    }
+    safetensors::serialize_to_file(tensors, &Some(metadata), path.as_ref())?;
+    Ok(())
+}
+
+/// A session tensor map loaded with [`load_session_tensors`].
+pub struct SessionTensors {
+    /// The version of the session format the file was written with.
+    pub format_version: u32,
+    /// The fingerprint of the model that created the session, if the file recorded one.
+    pub fingerprint: Option<ModelFingerprint>,
+    /// The tensors in the session.
+    pub tensors: HashMap<String, Tensor>,
+}
+
+/// Load a session tensor map saved with [`save_session_tensors`]. Files written by a newer session format are rejected with [`SessionLoadError::UnsupportedFormatVersion`].
+pub fn load_session_tensors(
+    path: impl AsRef<Path>,
+    device: &Device,
+) -> Result<SessionTensors, SessionLoadError> {
+    let bytes = std::fs::read(path)?;
+    let (_, header) = safetensors::SafeTensors::read_metadata(&bytes)
+        .map_err(|err| SessionLoadError::InvalidMetadata(err.to_string()))?;
+    let metadata = header.metadata().clone().unwrap_or_default();
+
+    let format_version = match metadata.get(FORMAT_VERSION_KEY) {
+        Some(version) => version
+            .parse()
+            .map_err(|_| SessionLoadError::InvalidMetadata(format!("format version {version}")))?,
+        None => 0,
+    };
+    if format_version > SESSION_FORMAT_VERSION {
+        return Err(SessionLoadError::UnsupportedFormatVersion {
+            found: format_version,
+            supported: SESSION_FORMAT_VERSION,
+        });
+    }
+
+    let fingerprint = match (
+        metadata.get(WEIGHTS_FINGERPRINT_KEY),
+        metadata.get(TOKENIZER_FINGERPRINT_KEY),
+    ) {
+        (Some(weights), Some(tokenizer)) => Some(ModelFingerprint::new(weights, tokenizer)),
+        _ => None,
+    };
+
+    let tensors = candle_core::safetensors::load_buffer(&bytes, device)?;
+
+    Ok(SessionTensors {
+        format_version,
+        fingerprint,
+        tensors,
+    })
+}
+
+#[test]
+fn session_metadata_round_trip() {
+    let path = std::env::temp_dir().join("kalosm-session-metadata-round-trip.safetensors");
+    let fingerprint = ModelFingerprint::new("weights", "tokenizer");
+    let tensors = HashMap::from([(
+        "tokens".to_string(),
+        Tensor::new(&[1u32, 2, 3], &Device::Cpu).unwrap(),
+    )]);
+    save_session_tensors(&tensors, Some(&fingerprint), &path).unwrap();
+
+    let loaded = load_session_tensors(&path, &Device::Cpu).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.format_version, SESSION_FORMAT_VERSION);
+    assert_eq!(loaded.fingerprint.as_ref(), Some(&fingerprint));
+    assert_eq!(
+        loaded.tensors["tokens"].to_vec1::<u32>().unwrap(),
+        vec![1, 2, 3]
+    );
+
+    fingerprint.verify(loaded.fingerprint.as_ref()).unwrap();
+    assert!(matches!(
+        ModelFingerprint::new("other weights", "tokenizer").verify(loaded.fingerprint.as_ref()),
+        Err(SessionLoadError::WeightsMismatch { .. })
+    ));
+    assert!(matches!(
+        ModelFingerprint::new("weights", "other tokenizer").verify(loaded.fingerprint.as_ref()),
+        Err(SessionLoadError::TokenizerMismatch { .. })
+    ));
+    assert!(matches!(
+        fingerprint.verify(None),
+        Err(SessionLoadError::MissingFingerprint)
+    ));
+}
+
+#[test]
+fn legacy_sessions_have_no_fingerprint() {
+    let path = std::env::temp_dir().join("kalosm-legacy-session.safetensors");
+    let tensors = HashMap::from([(
+        "tokens".to_string(),
+        Tensor::new(&[1u32, 2, 3], &Device::Cpu).unwrap(),
+    )]);
+    candle_core::safetensors::save(&tensors, &path).unwrap();
+
+    let loaded = load_session_tensors(&path, &Device::Cpu).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.format_version, 0);
+    assert!(loaded.fingerprint.is_none());
+}
+
+#[test]
+fn gguf_fingerprint_includes_tensor_data() {
+    use candle_core::quantized::gguf_file::{Content, TensorInfo, Value, VersionedMagic};
+
+    let content = Content {
+        magic: VersionedMagic::GgufV3,
+        metadata: HashMap::from([(
+            "general.architecture".to_string(),
+            Value::String("llama".to_string()),
+        )]),
+        tensor_infos: HashMap::from([(
+            "weight".to_string(),
+            TensorInfo {
+                ggml_dtype: GgmlDType::F32,
+                shape: vec![4].into(),
+                offset: 0,
+            },
+        )]),
+        tensor_data_offset: 0,
+    };
+    let fingerprint = |data: [f32; 4]| {
+        let bytes = data
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect::<Vec<_>>();
+        let mut hasher = FingerprintHasher::new();
+        hasher.update_gguf(&content);
+        hasher
+            .update_gguf_tensor_data(&content, &mut std::io::Cursor::new(bytes))
+            .unwrap();
+        hasher.finish()
+    };
+
+    // Fine-tunes with the same layout have different fingerprints
+    assert_eq!(fingerprint([1., 2., 3., 4.]), fingerprint([1., 2., 3., 4.]));
+    assert_ne!(fingerprint([1., 2., 3., 4.]), fingerprint([1., 2., 3., 5.]));
+}
@@ -0,0 +1,230 @@
+use crate::{Llama, LlamaModel, Task};
+use candle_core::Tensor;
+use kalosm_common::BoxedFuture;
+use kalosm_language_model::{
+    Embedder, Embedding, EmbeddingInput, EmbeddingVariant, SyncModel, VectorSpace,
+};
+
+/// The pooling strategy used to turn the final hidden states of a Llama model into a single embedding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LlamaPooling {
+    /// Use the hidden state of the last token. Decoder models can only attend to the whole input from the last token, so this is what most decoder embedding models (like e5-mistral and gte-Qwen) are trained with.
+    ///
+    /// The stop token is appended to the input before it is embedded. If the input is longer than the context length, the end of the text is dropped and the stop token is kept.
+    #[default]
+    LastToken,
+    /// Take the mean hidden state of all tokens.
+    Mean,
+}
+
+impl LlamaPooling {
+    /// Pool the hidden states of a sequence with the shape `[seq_len, hidden_size]` into a single vector.
+    pub(crate) fn pool(&self, hidden_states: &Tensor) -> candle_core::Result<Tensor> {
+        match self {
+            LlamaPooling::LastToken => {
+                let seq_len = hidden_states.dim(0)?;
+                hidden_states.get(seq_len - 1)
+            }
+            LlamaPooling::Mean => hidden_states.mean(0),
+        }
+    }
+}
+
+/// Fit the tokens of an input into the context length of the model. Long inputs are truncated from the end. With [`LlamaPooling::LastToken`], the stop token is appended and kept after truncation because the embedding is read from it.
+fn embedding_tokens(
+    mut tokens: Vec<u32>,
+    stop_token: Option<u32>,
+    context_length: usize,
+    pooling: LlamaPooling,
+) -> Vec<u32> {
+    match (pooling, stop_token) {
+        (LlamaPooling::LastToken, Some(stop_token)) => {
+            if tokens.last() == Some(&stop_token) {
+                tokens.pop();
+            }
+            tokens.truncate(context_length.saturating_sub(1));
+            tokens.push(stop_token);
+        }
+        _ => tokens.truncate(context_length),
+    }
+    tokens
+}
+
+/// The settings used when a Llama model is used as an [`Embedder`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EmbeddingSettings {
+    pub(crate) pooling: LlamaPooling,
+    pub(crate) query_instruction: Option<String>,
+}
+
+/// A vector space for embeddings from a Llama model.
+pub struct LlamaSpace;
+
+impl VectorSpace for LlamaSpace {}
+
+impl LlamaModel {
+    /// Embed some text by pooling the final hidden state of the model.
+    pub fn embed_text(
+        &self,
+        text: &str,
+        pooling: LlamaPooling,
+    ) -> anyhow::Result<Embedding<LlamaSpace>> {
+        let encoded = self
+            .tokenizer()
+            .encode(text, true)
+            .map_err(anyhow::Error::msg)?;
+        let tokens = embedding_tokens(
+            encoded.get_ids().to_vec(),
+            self.stop_token().ok(),
+            self.context_length(),
+            pooling,
+        );
+        let embedding = self.embed_tokens(&tokens, pooling)?;
+        Ok(Embedding::new(embedding))
+    }
+}
+
+impl Llama {
+    fn embedding_text(&self, input: EmbeddingInput) -> String {
+        match (&self.embedding.query_instruction, input.variant) {
+            (Some(instruction), EmbeddingVariant::Query) => {
+                let mut text = instruction.clone();
+                text.push_str(&input.text);
+                text
+            }
+            _ => input.text,
+        }
+    }
+}
+
+/// Llama models can be used as embedding models by pooling the final hidden state of the model instead of the output logits. This is useful for decoder only embedding models like [`crate::LlamaSource::e5_mistral_7b_instruct`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm::language::*;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let model = Llama::builder()
+///         .with_source(LlamaSource::e5_mistral_7b_instruct())
+///         .build()
+///         .await
+///         .unwrap();
+///     let document = model.embed("Cats are cool").await.unwrap();
+///     let query = model.embed_query("What animals are cool?").await.unwrap();
+///     println!("similarity: {}", document.cosine_similarity(&query));
+/// }
+/// ```
+impl Embedder for Llama {
+    type VectorSpace = LlamaSpace;
+
+    fn embed_string(
+        &self,
+        input: String,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<LlamaSpace>>> {
+        Box::pin(async move {
+            let mut embeddings = self.embed_vec(vec![input]).await?;
+            embeddings
+                .pop()
+                .ok_or_else(|| anyhow::anyhow!("No embedding returned from Llama"))
+        })
+    }
+
+    fn embed_vec(
+        &self,
+        inputs: Vec<String>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<LlamaSpace>>>> {
+        let pooling = self.embedding.pooling;
+        Box::pin(async move {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            self.task_sender
+                .send(Task::RunSync {
+                    callback: Box::new(move |model: &mut LlamaModel| {
+                        Box::pin(async move {
+                            let embeddings = inputs
+                                .iter()
+                                .map(|input| model.embed_text(input, pooling))
+                                .collect::<anyhow::Result<Vec<_>>>();
+                            _ = tx.send(embeddings);
+                        })
+                    }),
+                })
+                .map_err(|_| anyhow::anyhow!("Failed to send task to Llama thread"))?;
+            rx.await?
+        })
+    }
+
+    fn embed_for(
+        &self,
+        input: EmbeddingInput,
+    ) -> BoxedFuture<'_, anyhow::Result<Embedding<LlamaSpace>>> {
+        let text = self.embedding_text(input);
+        self.embed_string(text)
+    }
+
+    fn embed_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<LlamaSpace>>>> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| self.embedding_text(input))
+            .collect();
+        self.embed_vec(inputs)
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        let encoding = self.tokenizer.encode(text, false).ok()?;
+        Some(encoding.len())
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<std::ops::Range<usize>>> {
+        let encoding = self.tokenizer.encode(text, false).ok()?;
+        Some(
+            encoding
+                .get_offsets()
+                .iter()
+                .map(|&(start, end)| start..end)
+                .collect(),
+        )
+    }
+
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        match variant {
+            EmbeddingVariant::Query => self.embedding.query_instruction.as_deref(),
+            EmbeddingVariant::Document => None,
+        }
+    }
+}
+
+#[test]
+fn last_token_pooling_keeps_stop_token() {
+    let stop_token = 2;
+    let long = (10..20).collect::<Vec<u32>>();
+
+    let last_token = embedding_tokens(long.clone(), Some(stop_token), 4, LlamaPooling::LastToken);
+    assert_eq!(last_token, [10, 11, 12, stop_token]);
+
+    let short = embedding_tokens(
+        vec![10, stop_token],
+        Some(stop_token),
+        4,
+        LlamaPooling::LastToken,
+    );
+    assert_eq!(short, [10, stop_token]);
+
+    let mean = embedding_tokens(long, Some(stop_token), 4, LlamaPooling::Mean);
+    assert_eq!(mean, [10, 11, 12, 13]);
+}
+
+#[test]
+fn pooling_selects_hidden_states() {
+    let device = candle_core::Device::Cpu;
+    let hidden_states = Tensor::new(&[[1f32, 0.], [3., 2.], [5., 4.]], &device).unwrap();
+
+    let last_token = LlamaPooling::LastToken.pool(&hidden_states).unwrap();
+    assert_eq!(last_token.to_vec1::<f32>().unwrap(), [5., 4.]);
+
+    let mean = LlamaPooling::Mean.pool(&hidden_states).unwrap();
+    assert_eq!(mean.to_vec1::<f32>().unwrap(), [3., 2.]);
+}
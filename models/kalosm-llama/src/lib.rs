@@ -32,22 +32,23 @@ extern crate intel_mkl_src;
 #[cfg(feature = "accelerate")]
 extern crate accelerate_src;
 
+mod embedding;
 mod language_model;
 mod model;
 mod raw;
 mod session;
 mod source;
+mod weights;
 
+pub use crate::embedding::*;
 pub use crate::model::LlamaModel;
 pub use crate::raw::cache::*;
 use crate::raw::Model;
 pub use crate::session::LlamaSession;
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    Device,
-};
+use crate::weights::{load_weights, WeightsOptions};
+use candle_core::Device;
 pub use kalosm_common::*;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, ModelFingerprint};
 use llm_samplers::types::Sampler;
 pub use source::*;
 use std::sync::{Arc, Mutex};
@@ -56,7 +57,7 @@ use tokenizers::Tokenizer;
 /// A prelude of commonly used items in kalosm-llama.
 pub mod prelude {
     pub use crate::session::LlamaSession;
-    pub use crate::{Llama, LlamaBuilder, LlamaSource};
+    pub use crate::{Llama, LlamaBuilder, LlamaPooling, LlamaSource, LlamaSpace};
     pub use kalosm_language_model::*;
 }
 
@@ -85,6 +86,7 @@ pub struct Llama {
     task_sender: tokio::sync::mpsc::UnboundedSender<Task>,
     tokenizer: Arc<Tokenizer>,
     chat_markers: Arc<Option<ChatMarkers>>,
+    embedding: Arc<EmbeddingSettings>,
 }
 
 impl Drop for Llama {
@@ -127,11 +129,13 @@ impl Llama {
 
     #[allow(clippy::too_many_arguments)]
     fn from_build(
-        model: Model,
+        model: Arc<Model>,
         tokenizer: Tokenizer,
         device: Device,
         cache: LlamaCache,
+        fingerprint: ModelFingerprint,
         chat_markers: Option<ChatMarkers>,
+        embedding: EmbeddingSettings,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
         let arc_tokenizer = Arc::new(tokenizer);
@@ -139,7 +143,7 @@ impl Llama {
         std::thread::spawn({
             let arc_tokenizer = arc_tokenizer.clone();
             move || {
-                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache);
+                let mut inner = LlamaModel::new(model, arc_tokenizer, device, cache, fingerprint);
                 tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()
@@ -169,6 +173,7 @@ impl Llama {
             task_sender,
             tokenizer: arc_tokenizer,
             chat_markers: chat_markers.into(),
+            embedding: embedding.into(),
         }
     }
 
@@ -200,6 +205,7 @@ pub struct LlamaBuilder {
     source: source::LlamaSource,
     device: Option<Device>,
     flash_attn: bool,
+    weights: WeightsOptions,
 }
 
 impl LlamaBuilder {
@@ -221,6 +227,27 @@ impl LlamaBuilder {
         self
     }
 
+    /// Set whether to share the weights with other models loaded from a file with the same contents on the same device. (Defaults to true)
+    ///
+    /// Models that share weights still keep independent caches, so loading the same model twice only uses the memory for the weights once.
+    ///
+    /// ```rust, no_run
+    /// use kalosm::language::*;
+    /// # #[tokio::main]
+    /// # async fn main() -> Result<(), anyhow::Error> {
+    /// // Both models share a single copy of the weights
+    /// let first = Llama::builder().with_shared_weights(true).build().await?;
+    /// let second = Llama::builder().with_shared_weights(true).build().await?;
+    /// // This model loads its own copy of the weights
+    /// let separate = Llama::builder().with_shared_weights(false).build().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_shared_weights(mut self, share: bool) -> Self {
+        self.weights.share = share;
+        self
+    }
+
     /// Get the device or the default device if not set.
     pub(crate) fn get_device(&self) -> anyhow::Result<Device> {
         match self.device.clone() {
@@ -284,18 +311,13 @@ impl LlamaBuilder {
         };
         let filename = filename.await??;
 
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = self.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let (model, weights_digest) = load_weights(
+            &filename,
+            self.source.group_query_attention,
+            &device,
+            self.weights,
+        )?;
+        let fingerprint = ModelFingerprint::with_tokenizer(weights_digest, &tokenizer);
 
         let cache = LlamaCache::new(&model.config);
 
@@ -304,7 +326,9 @@ impl LlamaBuilder {
             tokenizer,
             device,
             cache,
+            fingerprint,
             self.source.markers,
+            self.source.embedding,
         ))
     }
 
@@ -1,14 +1,14 @@
 use crate::raw::cache::LlamaCache;
+use crate::weights::load_weights;
+use crate::LlamaPooling;
 use crate::{raw::Model, session::LlamaSession};
 use anyhow::{Error as E, Result};
 use kalosm_common::*;
+use kalosm_language_model::ModelFingerprint;
 use kalosm_language_model::SyncModelExt;
 use std::sync::Arc;
 
-use candle_core::{
-    quantized::{ggml_file, gguf_file},
-    DType, Device,
-};
+use candle_core::{DType, Device, Tensor};
 use kalosm_language_model::SyncModel;
 use tokenizers::Tokenizer;
 
@@ -16,10 +16,11 @@ use crate::InferenceSettings;
 
 /// The inner, synchronous Llama model.
 pub struct LlamaModel {
-    model: Model,
+    model: Arc<Model>,
     device: Device,
     tokenizer: Arc<Tokenizer>,
     cache: LlamaCache,
+    fingerprint: ModelFingerprint,
 }
 
 impl SyncModel for LlamaModel {
@@ -27,7 +28,10 @@ impl SyncModel for LlamaModel {
 
     fn new_session(&self) -> anyhow::Result<Self::Session> {
         let cache = self.cache.clone();
-        Ok(Self::Session { cache })
+        Ok(Self::Session {
+            cache,
+            fingerprint: Some(self.fingerprint.clone()),
+        })
     }
 
     fn feed_text(
@@ -68,6 +72,10 @@ impl SyncModel for LlamaModel {
     fn tokenizer(&self) -> Arc<Tokenizer> {
         self.tokenizer.clone()
     }
+
+    fn fingerprint(&self) -> Option<ModelFingerprint> {
+        Some(self.fingerprint.clone())
+    }
 }
 
 impl LlamaModel {
@@ -90,6 +98,33 @@ impl LlamaModel {
         Ok(())
     }
 
+    /// Get the maximum number of tokens the model can process at once.
+    pub(crate) fn context_length(&self) -> usize {
+        self.model.config.context_length
+    }
+
+    /// Embed a sequence of tokens by pooling the final hidden state of the model.
+    pub fn embed_tokens(&self, tokens: &[u32], pooling: LlamaPooling) -> anyhow::Result<Tensor> {
+        if tokens.is_empty() {
+            return Err(anyhow::anyhow!("Cannot embed empty input"));
+        }
+
+        let context_length = self.context_length();
+        if tokens.len() > context_length {
+            return Err(anyhow::anyhow!(
+                "Cannot embed {} tokens with a context length of {}",
+                tokens.len(),
+                context_length
+            ));
+        }
+
+        let hidden_states = self.model.hidden_states(tokens, &self.device)?;
+        let pooled = pooling.pool(&hidden_states)?.to_dtype(DType::F32)?;
+        // Normalize the embedding so cosine similarity and dot product agree
+        let norm = pooled.sqr()?.sum_all()?.sqrt()?;
+        Ok(pooled.broadcast_div(&norm)?)
+    }
+
     /// Create a new sync Llama model from a builder.
     pub async fn from_builder(
         builder: crate::LlamaBuilder,
@@ -110,18 +145,13 @@ impl LlamaModel {
             .source
             .model(|progress| handler(create_progress(progress)))
             .await?;
-        let mut file = std::fs::File::open(&filename)?;
-        let model = match filename.extension().and_then(|v| v.to_str()) {
-            Some("gguf") => {
-                let model = gguf_file::Content::read(&mut file)?;
-                Model::from_gguf(model, &mut file, &device)?
-            }
-            Some("ggml" | "bin") | Some(_) | None => {
-                let model = ggml_file::Content::read(&mut file, &device)?;
-                let gqa = builder.source.group_query_attention;
-                Model::from_ggml(model, gqa as usize, &device)?
-            }
-        };
+        let (model, weights_digest) = load_weights(
+            &filename,
+            builder.source.group_query_attention,
+            &device,
+            builder.weights,
+        )?;
+        let fingerprint = ModelFingerprint::with_tokenizer(weights_digest, &tokenizer);
 
         let cache = LlamaCache::new(&model.config);
         Ok(Self {
@@ -129,21 +159,24 @@ impl LlamaModel {
             tokenizer: Arc::new(tokenizer),
             device,
             cache,
+            fingerprint,
         })
     }
 
     #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
-        model: Model,
+        model: Arc<Model>,
         tokenizer: Arc<Tokenizer>,
         device: Device,
         cache: LlamaCache,
+        fingerprint: ModelFingerprint,
     ) -> Self {
         Self {
             cache,
             model,
             device,
             tokenizer,
+            fingerprint,
         }
     }
 
@@ -276,9 +276,33 @@ impl Model {
             }
             (Tensor::new(tokens, device)?.unsqueeze(0)?, index_pos)
         };
+        let x = self.forward_layers(&x, seq_len, index_pos, device, cache)?;
+        let x = x.i((.., seq_len - 1, ..))?;
+        self.output.forward(&x)
+    }
+
+    /// Get the final hidden state of the model for each token without projecting it to the vocabulary. The output has the shape `(seq_len, hidden_size)`.
+    ///
+    /// Inputs longer than the context length of the model are truncated to the context length.
+    pub fn hidden_states(&self, tokens: &[u32], device: &Device) -> Result<Tensor> {
+        let tokens = &tokens[..tokens.len().min(self.config.context_length)];
+        let seq_len = tokens.len();
+        let x = Tensor::new(tokens, device)?.unsqueeze(0)?;
+        let x = self.forward_layers(&x, seq_len, 0, device, None)?;
+        x.squeeze(0)
+    }
+
+    fn forward_layers(
+        &self,
+        x: &Tensor,
+        seq_len: usize,
+        index_pos: usize,
+        device: &Device,
+        mut cache: Option<&mut LlamaCache>,
+    ) -> Result<Tensor> {
         let mask = self.masks.get_mask(seq_len, index_pos, device)?;
 
-        let mut layer_in = self.tok_embeddings.forward(&x)?;
+        let mut layer_in = self.tok_embeddings.forward(x)?;
         for (i, layer) in self.layers.iter().enumerate() {
             let x = layer_in;
             let residual = &x;
@@ -297,8 +321,6 @@ impl Model {
 
             layer_in = (&layer.feed_forward_variant.forward(&x)? + residual)?;
         }
-        let x = self.norm.forward(&layer_in)?;
-        let x = x.i((.., seq_len - 1, ..))?;
-        self.output.forward(&x)
+        self.norm.forward(&layer_in)
     }
 }
@@ -1,20 +1,22 @@
 use crate::accelerated_device_if_available;
 use crate::raw::cache::LlamaCache;
 use candle_core::{Device, Tensor};
-use kalosm_language_model::Session;
+use kalosm_language_model::{load_session_tensors, save_session_tensors};
+use kalosm_language_model::{ModelFingerprint, Session};
 use std::collections::HashMap;
 
 /// A Llama session with cached state for the current fed prompt
 #[derive(Debug, Clone)]
 pub struct LlamaSession {
     pub(crate) cache: LlamaCache,
+    pub(crate) fingerprint: Option<ModelFingerprint>,
 }
 
 impl Session for LlamaSession {
     fn save_to(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
         let device = accelerated_device_if_available()?;
         let tensors = self.get_tensor_map(&device);
-        Ok(candle_core::safetensors::save(&tensors, path)?)
+        save_session_tensors(&tensors, self.fingerprint.as_ref(), path)
     }
 
     fn tokens(&self) -> &[u32] {
@@ -26,9 +28,11 @@ impl Session for LlamaSession {
         Self: std::marker::Sized,
     {
         let device = accelerated_device_if_available()?;
-        let tensors = candle_core::safetensors::load(path, &device)?;
+        let session = load_session_tensors(path, &device)?;
 
-        Ok(Self::from_tensor_map(tensors)?)
+        let mut loaded = Self::from_tensor_map(session.tensors)?;
+        loaded.fingerprint = session.fingerprint;
+        Ok(loaded)
     }
 
     fn try_clone(&self) -> anyhow::Result<Self>
@@ -37,6 +41,10 @@ impl Session for LlamaSession {
     {
         Ok(self.clone())
     }
+
+    fn fingerprint(&self) -> Option<&ModelFingerprint> {
+        self.fingerprint.as_ref()
+    }
 }
 
 impl LlamaSession {
@@ -55,6 +63,7 @@ impl LlamaSession {
     pub fn from_tensor_map(map: HashMap<String, Tensor>) -> candle_core::Result<Self> {
         Ok(Self {
             cache: LlamaCache::from_tensor_map(map)?,
+            fingerprint: None,
         })
     }
 }
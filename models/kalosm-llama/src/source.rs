@@ -1,7 +1,11 @@
+use crate::{EmbeddingSettings, LlamaPooling};
 use kalosm_common::FileSource;
 use kalosm_language_model::ChatMarkers;
 use tokenizers::Tokenizer;
 
+const E5_MISTRAL_QUERY_INSTRUCTION: &str =
+    "Instruct: Given a web search query, retrieve relevant passages that answer the query\nQuery: ";
+
 fn llama_tokenizer() -> FileSource {
     FileSource::huggingface(
         "hf-internal-testing/llama-tokenizer".to_string(),
@@ -34,6 +38,7 @@ pub struct LlamaSource {
     pub(crate) group_query_attention: u8,
     pub(crate) markers: Option<ChatMarkers>,
     pub(crate) cache: kalosm_common::Cache,
+    pub(crate) embedding: EmbeddingSettings,
 }
 
 impl LlamaSource {
@@ -45,6 +50,7 @@ impl LlamaSource {
             group_query_attention: 1,
             markers: Default::default(),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -62,6 +68,20 @@ impl LlamaSource {
         self
     }
 
+    /// Set the pooling strategy used when the model is used as an [`kalosm_language_model::Embedder`]
+    pub fn with_embedding_pooling(mut self, pooling: LlamaPooling) -> Self {
+        self.embedding.pooling = pooling;
+
+        self
+    }
+
+    /// Set the instruction that is prepended to queries (inputs embedded with [`kalosm_language_model::EmbeddingVariant::Query`]) when the model is used as an [`kalosm_language_model::Embedder`]
+    pub fn with_query_instruction(mut self, instruction: impl Into<String>) -> Self {
+        self.embedding.query_instruction = Some(instruction.into());
+
+        self
+    }
+
     /// Set the group query attention for the model
     /// For the llama family of models, this is typically 1
     /// For the mistral family of models, this is typically 8
@@ -97,6 +117,26 @@ impl LlamaSource {
         }
     }
 
+    /// A preset for the E5 Mistral 7b instruct embedding model
+    pub fn e5_mistral_7b_instruct() -> Self {
+        Self {
+            model: FileSource::huggingface(
+                "second-state/E5-Mistral-7B-Instruct-Embedding-GGUF".to_string(),
+                "main".to_string(),
+                "e5-mistral-7b-instruct-Q4_K_M.gguf".to_string(),
+            ),
+            tokenizer: FileSource::huggingface(
+                "intfloat/e5-mistral-7b-instruct".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ),
+            group_query_attention: 8,
+            ..Default::default()
+        }
+        .with_embedding_pooling(LlamaPooling::LastToken)
+        .with_query_instruction(E5_MISTRAL_QUERY_INSTRUCTION)
+    }
+
     /// A preset for Mistral7bInstruct
     pub fn mistral_7b_instruct() -> Self {
         Self {
@@ -116,6 +156,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -138,6 +179,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -160,6 +202,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|im_end|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -186,6 +229,7 @@ impl LlamaSource {
                 end_assistant_marker: "\n",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -208,6 +252,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -230,6 +275,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -256,6 +302,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end_of_turn|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -282,6 +329,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end_of_turn|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -308,6 +356,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end_of_turn|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -330,6 +379,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -356,6 +406,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -400,6 +451,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -427,6 +479,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -454,6 +507,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|end|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -504,6 +558,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|eot_id|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -527,6 +582,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|eot_id|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -549,6 +605,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|eot_id|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -571,6 +628,7 @@ impl LlamaSource {
                 end_assistant_marker: "<|eot_id|>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -586,6 +644,7 @@ impl LlamaSource {
             group_query_attention: 1,
             markers: Default::default(),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -622,6 +681,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -644,6 +704,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -666,6 +727,7 @@ impl LlamaSource {
                 end_assistant_marker: "</s>",
             }),
             cache: Default::default(),
+            embedding: Default::default(),
         }
     }
 
@@ -0,0 +1,282 @@
+//! Loading model weights and sharing them between every [`crate::Llama`] loaded from a file with the same contents.
+//!
+//! The tensors own their data, so sharing only avoids loading a second copy of the same weights. It doesn't map the file into memory.
+
+use crate::raw::Model;
+use candle_core::quantized::{ggml_file, gguf_file};
+use candle_core::Device;
+use kalosm_language_model::FingerprintHasher;
+use once_cell::sync::{Lazy, OnceCell};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::SystemTime;
+
+/// Weights that are currently loaded in the process.
+static LOADED_WEIGHTS: Lazy<WeightsCache<Model>> = Lazy::new(WeightsCache::default);
+
+/// Content hashes of files that were already hashed in this process. If the file is replaced, the length or modification time will change and the file will be hashed again.
+static CONTENT_HASHES: Lazy<Mutex<HashMap<FileKey, String>>> = Lazy::new(Default::default);
+
+/// The path, length and modification time of a file.
+type FileKey = (PathBuf, u64, Option<SystemTime>);
+
+/// The number of bytes hashed from the start of a file. This covers the metadata of most gguf files.
+const HASH_PREFIX_BYTES: u64 = 1 << 20;
+
+/// The number of samples hashed from the rest of the file.
+const HASH_SAMPLES: u64 = 16;
+
+/// The number of bytes in each sample.
+const HASH_SAMPLE_BYTES: u64 = 1 << 16;
+
+/// Get a hash of the length, the start and evenly spaced samples of the contents of a file. Two copies of the same weights at different paths have the same hash.
+///
+/// Only a few megabytes are read, so the weights aren't read twice the first time a file is loaded.
+fn content_hash(path: &Path) -> std::io::Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let path = path.canonicalize()?;
+    let metadata = std::fs::metadata(&path)?;
+    let len = metadata.len();
+    let file_key = (path, len, metadata.modified().ok());
+    if let Some(hash) = CONTENT_HASHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&file_key)
+    {
+        return Ok(hash.clone());
+    }
+
+    let mut hasher = FingerprintHasher::new();
+    hasher.update(len.to_le_bytes());
+    let mut file = std::fs::File::open(&file_key.0)?;
+    let mut buffer = Vec::new();
+    let mut hash_range = |offset: u64, bytes: u64| -> std::io::Result<()> {
+        file.seek(SeekFrom::Start(offset))?;
+        buffer.clear();
+        (&mut file).take(bytes).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+        Ok(())
+    };
+    hash_range(0, HASH_PREFIX_BYTES)?;
+    if len > HASH_PREFIX_BYTES {
+        let rest = len - HASH_PREFIX_BYTES;
+        for sample in 0..HASH_SAMPLES {
+            hash_range(
+                HASH_PREFIX_BYTES + rest * sample / HASH_SAMPLES,
+                HASH_SAMPLE_BYTES,
+            )?;
+        }
+    }
+    let hash = hasher.finish();
+    CONTENT_HASHES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(file_key, hash.clone());
+    Ok(hash)
+}
+
+/// Identifies the weights loaded from a file by the hash of the file's contents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct WeightsKey {
+    content_hash: String,
+    group_query_attention: u8,
+}
+
+/// A cache of weights keyed by the contents of the file they were loaded from. Entries are weak so the weights are freed once the last model that uses them is dropped.
+///
+/// The map is only locked to look up or insert an entry. Each entry has its own [`OnceCell`] that is initialized while the file is read, so loading one file doesn't block loading another and two loads of the same file only read it once.
+struct WeightsCache<T> {
+    loaded: Mutex<HashMap<WeightsKey, Vec<LoadedWeights<T>>>>,
+}
+
+impl<T> Default for WeightsCache<T> {
+    fn default() -> Self {
+        Self {
+            loaded: Default::default(),
+        }
+    }
+}
+
+struct LoadedWeights<T> {
+    device: Device,
+    slot: Arc<OnceCell<(Weak<T>, String)>>,
+}
+
+impl<T> LoadedWeights<T> {
+    /// Check if the weights are still being loaded or are used by a model.
+    fn is_live(&self) -> bool {
+        match self.slot.get() {
+            Some((model, _)) => model.strong_count() > 0,
+            None => true,
+        }
+    }
+}
+
+impl<T> WeightsCache<T> {
+    /// Get the weights for the key and device, or load them with `load` if no model is using them.
+    fn get_or_load(
+        &self,
+        key: WeightsKey,
+        device: &Device,
+        mut load: impl FnMut() -> anyhow::Result<(T, String)>,
+    ) -> anyhow::Result<(Arc<T>, String)> {
+        loop {
+            let slot = self.slot(&key, device);
+
+            let mut loaded = None;
+            let (model, digest) = slot.get_or_try_init(|| {
+                let (model, digest) = load()?;
+                let model = Arc::new(model);
+                let weak = Arc::downgrade(&model);
+                loaded = Some(model);
+                anyhow::Ok((weak, digest))
+            })?;
+            if let Some(model) = loaded {
+                return Ok((model, digest.clone()));
+            }
+            if let Some(model) = model.upgrade() {
+                return Ok((model, digest.clone()));
+            }
+
+            // The last model using the weights was dropped after we found the entry. Remove it and load the weights again
+            let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entries) = loaded.get_mut(&key) {
+                entries.retain(|entry| !Arc::ptr_eq(&entry.slot, &slot));
+            }
+        }
+    }
+
+    /// Find the entry for the key and device, or insert an empty entry for the caller to load.
+    fn slot(&self, key: &WeightsKey, device: &Device) -> Arc<OnceCell<(Weak<T>, String)>> {
+        let mut loaded = self.loaded.lock().unwrap_or_else(|e| e.into_inner());
+        let entries = loaded.entry(key.clone()).or_default();
+        entries.retain(LoadedWeights::is_live);
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.device.same_device(device))
+        {
+            return entry.slot.clone();
+        }
+        let slot = Arc::new(OnceCell::new());
+        entries.push(LoadedWeights {
+            device: device.clone(),
+            slot: slot.clone(),
+        });
+        slot
+    }
+}
+
+/// Options for loading model weights.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WeightsOptions {
+    /// Share the weights with any other model loaded from a file with the same contents on the same device.
+    pub(crate) share: bool,
+}
+
+impl Default for WeightsOptions {
+    fn default() -> Self {
+        Self { share: true }
+    }
+}
+
+/// Load the model weights from a gguf or ggml file. Returns the model and a digest of the file's metadata and a sample of every tensor that identifies the weights and quantization.
+///
+/// If sharing is enabled and another model already loaded a file with the same contents onto the same device, the existing weights are returned instead of reading the file again.
+pub(crate) fn load_weights(
+    filename: &Path,
+    group_query_attention: u8,
+    device: &Device,
+    options: WeightsOptions,
+) -> anyhow::Result<(Arc<Model>, String)> {
+    if !options.share {
+        let (model, digest) = read_weights(filename, group_query_attention, device)?;
+        return Ok((Arc::new(model), digest));
+    }
+
+    let key = WeightsKey {
+        content_hash: content_hash(filename)?,
+        group_query_attention,
+    };
+    LOADED_WEIGHTS.get_or_load(key, device, || {
+        read_weights(filename, group_query_attention, device)
+    })
+}
+
+fn read_weights(
+    filename: &Path,
+    group_query_attention: u8,
+    device: &Device,
+) -> anyhow::Result<(Model, String)> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(filename)?);
+    read_weights_from(&mut reader, filename, group_query_attention, device)
+}
+
+fn read_weights_from<R: std::io::Read + std::io::Seek>(
+    reader: &mut R,
+    filename: &Path,
+    group_query_attention: u8,
+    device: &Device,
+) -> anyhow::Result<(Model, String)> {
+    let mut hasher = FingerprintHasher::new();
+    let model = match filename.extension().and_then(|v| v.to_str()) {
+        Some("gguf") => {
+            let model = gguf_file::Content::read(reader)?;
+            hasher.update_gguf(&model);
+            hasher.update_gguf_tensor_data(&model, reader)?;
+            Model::from_gguf(model, reader, device)?
+        }
+        Some("ggml" | "bin") | Some(_) | None => {
+            let model = ggml_file::Content::read(reader, device)?;
+            hasher.update_ggml(&model)?;
+            Model::from_ggml(model, group_query_attention as usize, device)?
+        }
+    };
+    Ok((model, hasher.finish()))
+}
+
+#[test]
+fn loads_of_the_same_file_share_weights() {
+    use std::io::Write;
+
+    let dir = tempfile::tempdir().unwrap();
+    let mut hashes = Vec::new();
+    for name in ["first.gguf", "copy.gguf"] {
+        let path = dir.path().join(name);
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(b"weights")
+            .unwrap();
+        hashes.push(content_hash(&path).unwrap());
+    }
+    assert_eq!(hashes[0], hashes[1]);
+
+    let other = dir.path().join("other.gguf");
+    std::fs::write(&other, b"other weights").unwrap();
+    assert_ne!(content_hash(&other).unwrap(), hashes[0]);
+
+    let cache = WeightsCache::default();
+    let key = WeightsKey {
+        content_hash: hashes[0].clone(),
+        group_query_attention: 1,
+    };
+    let mut loads = 0;
+    let mut load = || {
+        loads += 1;
+        Ok((vec![0u8; 16], String::from("digest")))
+    };
+    let (first, _) = cache
+        .get_or_load(key.clone(), &Device::Cpu, &mut load)
+        .unwrap();
+    let (second, digest) = cache
+        .get_or_load(key.clone(), &Device::Cpu, &mut load)
+        .unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(digest, "digest");
+
+    // Once every model is dropped, the weights are freed and loaded again
+    drop((first, second));
+    cache.get_or_load(key, &Device::Cpu, &mut load).unwrap();
+    assert_eq!(loads, 2);
+}
@@ -1,10 +1,11 @@
 pub use crate::Bert;
 use crate::BertBuilder;
 use crate::Pooling;
+use candle_core::IndexOp;
 use kalosm_common::*;
 pub use kalosm_language_model::{
     Embedder, EmbedderCacheExt, EmbedderExt, Embedding, EmbeddingInput, EmbeddingVariant,
-    ModelBuilder, VectorSpace,
+    ModelBuilder, MultiVectorEmbedder, VectorSpace,
 };
 use serde::Deserialize;
 use serde::Serialize;
@@ -52,6 +53,40 @@ impl Bert {
 
         Ok(embeddings)
     }
+
+    /// Embed a batch of sentences into one normalized embedding per token (including the special tokens) without pooling.
+    pub fn embed_tokens_batch(
+        &self,
+        inputs: Vec<&str>,
+    ) -> anyhow::Result<Vec<Vec<Embedding<BertSpace>>>> {
+        let tensors = self.embed_tokens_raw(inputs)?;
+
+        tensors
+            .into_iter()
+            .map(|tensor| {
+                let tokens = tensor.dim(0)?;
+                (0..tokens)
+                    .map(|i| Ok(Embedding::new(tensor.i(i)?)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Add the query or document prefix the model was trained with to the input.
+    fn prefixed_input(&self, input: EmbeddingInput) -> String {
+        let prefix = match input.variant {
+            EmbeddingVariant::Query => &*self.embedding_search_prefix,
+            EmbeddingVariant::Document => &*self.embedding_document_prefix,
+        };
+        match prefix {
+            Some(prefix) => {
+                let mut new_input = prefix.clone();
+                new_input.push_str(&input.text);
+                new_input
+            }
+            None => input.text,
+        }
+    }
 }
 
 impl Embedder for Bert {
@@ -61,14 +96,7 @@ impl Embedder for Bert {
         &self,
         input: EmbeddingInput,
     ) -> BoxedFuture<'_, anyhow::Result<Embedding<Self::VectorSpace>>> {
-        match (&*self.embedding_search_prefix, input.variant) {
-            (Some(prefix), EmbeddingVariant::Query) => {
-                let mut new_input = prefix.clone();
-                new_input.push_str(&input.text);
-                self.embed_string(new_input)
-            }
-            _ => self.embed_string(input.text),
-        }
+        self.embed_string(self.prefixed_input(input))
     }
 
     fn embed_vec_for(
@@ -77,16 +105,7 @@ impl Embedder for Bert {
     ) -> BoxedFuture<'_, anyhow::Result<Vec<Embedding<Self::VectorSpace>>>> {
         let inputs = inputs
             .into_iter()
-            .map(
-                |input| match (&*self.embedding_search_prefix, input.variant) {
-                    (Some(prefix), EmbeddingVariant::Query) => {
-                        let mut new_input = prefix.clone();
-                        new_input.push_str(&input.text);
-                        new_input
-                    }
-                    _ => input.text,
-                },
-            )
+            .map(|input| self.prefixed_input(input))
             .collect::<Vec<_>>();
         self.embed_vec(inputs)
     }
@@ -94,8 +113,10 @@ impl Embedder for Bert {
     fn embed_string(&self, input: String) -> BoxedFuture<'_, anyhow::Result<Embedding<BertSpace>>> {
         Box::pin(async move {
             let self_clone = self.clone();
-            tokio::task::spawn_blocking(move || self_clone.embed_with_pooling(&input, Pooling::CLS))
-                .await?
+            tokio::task::spawn_blocking(move || {
+                self_clone.embed_with_pooling(&input, self_clone.pooling)
+            })
+            .await?
         })
     }
 
@@ -107,7 +128,55 @@ impl Embedder for Bert {
             let self_clone = self.clone();
             tokio::task::spawn_blocking(move || {
                 let inputs_borrowed = inputs.iter().map(|s| s.as_str()).collect::<Vec<_>>();
-                self_clone.embed_batch_with_pooling(inputs_borrowed, Pooling::CLS)
+                self_clone.embed_batch_with_pooling(inputs_borrowed, self_clone.pooling)
+            })
+            .await?
+        })
+    }
+
+    fn count_tokens(&self, text: &str) -> Option<usize> {
+        let encoding = self.counting_tokenizer.encode(text, false).ok()?;
+        Some(encoding.len())
+    }
+
+    fn token_offsets(&self, text: &str) -> Option<Vec<std::ops::Range<usize>>> {
+        let encoding = self.counting_tokenizer.encode(text, false).ok()?;
+        Some(
+            encoding
+                .get_offsets()
+                .iter()
+                .map(|&(start, end)| start..end)
+                .collect(),
+        )
+    }
+
+    fn max_input_tokens(&self) -> Option<usize> {
+        // The start and end tokens take up two positions
+        Some(self.max_position_embeddings.saturating_sub(2))
+    }
+
+    fn input_prefix(&self, variant: EmbeddingVariant) -> Option<&str> {
+        match variant {
+            EmbeddingVariant::Query => self.embedding_search_prefix.as_deref(),
+            EmbeddingVariant::Document => self.embedding_document_prefix.as_deref(),
+        }
+    }
+}
+
+impl MultiVectorEmbedder for Bert {
+    fn embed_tokens_vec_for(
+        &self,
+        inputs: Vec<EmbeddingInput>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<Vec<Embedding<BertSpace>>>>> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| self.prefixed_input(input))
+            .collect::<Vec<_>>();
+        Box::pin(async move {
+            let self_clone = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let inputs_borrowed = inputs.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+                self_clone.embed_tokens_batch(inputs_borrowed)
             })
             .await?
         })
@@ -58,18 +58,23 @@ use tokenizers::{Encoding, PaddingParams, Tokenizer};
 
 mod language_model;
 mod raw;
+mod reranker;
 mod source;
+mod windowing;
 
 pub use crate::language_model::*;
 use crate::raw::DTYPE;
-pub use crate::raw::{BertModel, Config};
+pub use crate::raw::{BertForSequenceClassification, BertModel, BertVariant, Config};
+pub use crate::reranker::*;
 pub use crate::source::*;
+pub use crate::windowing::*;
 
 /// A builder for a [`Bert`] model
 #[derive(Default)]
 pub struct BertBuilder {
     source: BertSource,
     cache: kalosm_common::Cache,
+    token_windows: Option<TokenWindows>,
 }
 
 impl BertBuilder {
@@ -79,6 +84,14 @@ impl BertBuilder {
         self
     }
 
+    /// Split inputs that are longer than the context length of the model into overlapping windows and pool the windows into a single embedding. (Defaults to disabled)
+    ///
+    /// When this is disabled, long inputs are handled however the tokenizer of the model is configured. Most tokenizers truncate the input to the context length.
+    pub fn with_token_windows(mut self, token_windows: TokenWindows) -> Self {
+        self.token_windows = Some(token_windows);
+        self
+    }
+
     /// Build the model
     pub async fn build(self) -> anyhow::Result<Bert> {
         self.build_with_loading_handler(ModelLoadingProgress::multi_bar_loading_indicator())
@@ -128,14 +141,24 @@ impl BertBuilder {
 }
 
 /// The pooling strategy to use when embedding text.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum Pooling {
     /// Take the mean embedding value for all tokens (except padding)
     Mean,
     /// Take the embedding of the CLS token for each sequence
+    #[default]
     CLS,
 }
 
+/// What the model outputs for each sequence it embeds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EmbeddingOutput {
+    /// Pool the embeddings of every token into a single embedding
+    Pooled(Pooling),
+    /// Keep the normalized embedding of every token except padding
+    Tokens,
+}
+
 /// A bert embedding model. The main interface for this model is [`EmbedderExt`].
 ///
 /// # Example
@@ -177,8 +200,14 @@ pub enum Pooling {
 #[derive(Clone)]
 pub struct Bert {
     embedding_search_prefix: Arc<Option<String>>,
+    embedding_document_prefix: Arc<Option<String>>,
+    pooling: Pooling,
     model: Arc<BertModel>,
     tokenizer: Arc<RwLock<Tokenizer>>,
+    /// The tokenizer without truncation, used to count the tokens in text
+    counting_tokenizer: Arc<Tokenizer>,
+    max_position_embeddings: usize,
+    token_windows: Option<TokenWindows>,
 }
 
 impl Bert {
@@ -204,36 +233,23 @@ impl Bert {
         builder: BertBuilder,
         mut progress_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
     ) -> anyhow::Result<Self> {
-        let BertBuilder { source, cache } = builder;
+        let BertBuilder {
+            source,
+            cache,
+            token_windows,
+        } = builder;
+        let BertFiles {
+            config_filename,
+            tokenizer_filename,
+            weights_filename,
+        } = source.download(&cache, &mut progress_handler).await?;
         let BertSource {
-            config,
-            tokenizer,
-            model,
             search_embedding_prefix,
+            document_embedding_prefix,
+            pooling,
+            ..
         } = source;
 
-        let source = format!("Config ({})", config);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(source);
-        let config_filename = cache
-            .get(&config, |progress| {
-                progress_handler(create_progress(progress))
-            })
-            .await?;
-        let tokenizer_source = format!("Tokenizer ({})", tokenizer);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
-        let tokenizer_filename = cache
-            .get(&tokenizer, |progress| {
-                progress_handler(create_progress(progress))
-            })
-            .await?;
-        let model_source = format!("Model ({})", model);
-        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
-        let weights_filename = cache
-            .get(&model, |progress| {
-                progress_handler(create_progress(progress))
-            })
-            .await?;
-
         let config = std::fs::read_to_string(config_filename)?;
         let config: Config = serde_json::from_str(&config)?;
 
@@ -244,11 +260,20 @@ impl Bert {
         let mut tokenizer =
             Tokenizer::from_file(&tokenizer_filename).map_err(anyhow::Error::msg)?;
         tokenizer.with_padding(None);
+        let counting_tokenizer = Arc::new(tokenizer.clone());
+        if let Some(token_windows) = &token_windows {
+            token_windows.apply(&mut tokenizer, config.max_position_embeddings())?;
+        }
 
         Ok(Bert {
             tokenizer: Arc::new(RwLock::new(tokenizer)),
+            counting_tokenizer,
+            max_position_embeddings: config.max_position_embeddings(),
             model: Arc::new(model),
             embedding_search_prefix: Arc::new(search_embedding_prefix),
+            embedding_document_prefix: Arc::new(document_embedding_prefix),
+            pooling,
+            token_windows,
         })
     }
 
@@ -258,16 +283,79 @@ impl Bert {
         sentences: Vec<&str>,
         pooling: Pooling,
     ) -> anyhow::Result<Vec<Tensor>> {
-        let embedding_dim = self.model.embedding_dim();
-        // The batch size limit (input length * memory per token)
-        let limit = embedding_dim * 512usize.pow(2) * 2;
+        self.embed_batch_output(sentences, EmbeddingOutput::Pooled(pooling))
+    }
 
-        // The sentences we are embedding may have a very different length. First we sort them so that similar length sentences are grouped together in the same batch to reduce the overhead of padding.
+    /// Embed a batch of sentences into a `[tokens, embedding_dim]` tensor for each sentence
+    pub(crate) fn embed_tokens_raw(&self, sentences: Vec<&str>) -> anyhow::Result<Vec<Tensor>> {
+        self.embed_batch_output(sentences, EmbeddingOutput::Tokens)
+    }
+
+    fn embed_batch_output(
+        &self,
+        sentences: Vec<&str>,
+        output: EmbeddingOutput,
+    ) -> anyhow::Result<Vec<Tensor>> {
         let encodings = {
             let tokenizer_read = self.tokenizer.read().unwrap();
             tokenizer_read.encode_batch(sentences, true)
         }
         .map_err(anyhow::Error::msg)?;
+
+        let Some(token_windows) = &self.token_windows else {
+            return self.embed_encodings(encodings, output);
+        };
+
+        // Embed every window of every sentence in one batch, then pool the windows that belong to each sentence
+        let mut windows = Vec::new();
+        let mut window_owners = Vec::new();
+        for (index, mut encoding) in encodings.into_iter().enumerate() {
+            let overflowing = encoding.take_overflowing();
+            for window in std::iter::once(encoding).chain(overflowing) {
+                window_owners.push((index, window.len()));
+                windows.push(window);
+            }
+        }
+        let n_sentences = window_owners.last().map_or(0, |(index, _)| index + 1);
+        let mut grouped = vec![Vec::new(); n_sentences];
+        let window_embeddings = self.embed_encodings(windows, output)?;
+        for ((index, tokens), embedding) in window_owners.into_iter().zip(window_embeddings) {
+            grouped[index].push((embedding, tokens));
+        }
+        grouped
+            .iter()
+            .map(|windows| {
+                let pooling = match output {
+                    EmbeddingOutput::Pooled(pooling) => pooling,
+                    // The tokens of every window are kept. Tokens in the overlap between windows appear twice.
+                    EmbeddingOutput::Tokens => {
+                        let embeddings = windows
+                            .iter()
+                            .map(|(embedding, _)| embedding)
+                            .collect::<Vec<_>>();
+                        return Ok(Tensor::cat(&embeddings, 0)?);
+                    }
+                };
+                let combined = token_windows.combine(windows)?;
+                match pooling {
+                    // Mean pooled embeddings are normalized, so the combined embedding should be too
+                    Pooling::Mean if windows.len() > 1 => normalize_l2(&combined),
+                    _ => Ok(combined),
+                }
+            })
+            .collect()
+    }
+
+    fn embed_encodings(
+        &self,
+        encodings: Vec<Encoding>,
+        output: EmbeddingOutput,
+    ) -> anyhow::Result<Vec<Tensor>> {
+        let embedding_dim = self.model.embedding_dim();
+        // The batch size limit (input length * memory per token)
+        let limit = embedding_dim * 512usize.pow(2) * 2;
+
+        // The sentences we are embedding may have a very different length. First we sort them so that similar length sentences are grouped together in the same batch to reduce the overhead of padding.
         let mut encodings_with_indices = encodings.into_iter().enumerate().collect::<Vec<_>>();
 
         encodings_with_indices.sort_unstable_by_key(|(_, encoding)| encoding.len());
@@ -303,7 +391,7 @@ impl Bert {
 
         for (indices, encodings) in chunks {
             let embeddings =
-                maybe_autoreleasepool(|| self.embed_batch_raw_inner(encodings, pooling))?;
+                maybe_autoreleasepool(|| self.embed_batch_raw_inner(encodings, output))?;
             for (i, embedding) in indices.iter().zip(embeddings) {
                 combined[*i] = Some(embedding);
             }
@@ -314,7 +402,7 @@ impl Bert {
     fn embed_batch_raw_inner(
         &self,
         mut tokens: Vec<Encoding>,
-        pooling: Pooling,
+        output: EmbeddingOutput,
     ) -> anyhow::Result<Vec<Tensor>> {
         let device = &self.model.device;
         let pp = PaddingParams {
@@ -349,7 +437,24 @@ impl Bert {
             self.model
                 .forward(&token_ids, &token_type_ids, Some(&attention_mask), false)?;
 
-        let (_n_sentence, n_tokens, _hidden_size) = embeddings.dims3()?;
+        let pooling = match output {
+            EmbeddingOutput::Pooled(pooling) => pooling,
+            EmbeddingOutput::Tokens => {
+                // Padding is added to the end of each sequence, so the real tokens are the first tokens in the sequence
+                return tokens
+                    .iter()
+                    .enumerate()
+                    .map(|(i, encoding)| {
+                        let length = encoding
+                            .get_attention_mask()
+                            .iter()
+                            .filter(|mask| **mask == 1)
+                            .count();
+                        normalize_l2(&embeddings.i(i)?.narrow(0, 0, length)?)
+                    })
+                    .collect();
+            }
+        };
 
         match pooling {
             Pooling::Mean => {
@@ -360,7 +465,8 @@ impl Bert {
                         .unsqueeze(2)?
                         .broadcast_as(embeddings.shape())?,
                 )?;
-                let embeddings = (embeddings.sum(1)? / (n_tokens as f64))?;
+                let token_counts = attention_mask.to_dtype(DTYPE)?.sum_keepdim(1)?;
+                let embeddings = embeddings.sum(1)?.broadcast_div(&token_counts)?;
                 let embeddings = normalize_l2(&embeddings)?;
                 Ok(embeddings.chunk(n_sentences, 0)?)
             }
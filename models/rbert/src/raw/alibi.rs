@@ -0,0 +1,58 @@
+//! ALiBi attention biases used by jina-bert instead of position embeddings.
+
+use candle_core::{DType, Device, Result, Tensor};
+
+// https://huggingface.co/jinaai/jina-bert-implementation/blob/main/modeling_bert.py
+pub(crate) struct AlibiBias {
+    /// The slope for each attention head with the shape (1, num_heads, 1, 1)
+    slopes: Tensor,
+    span: tracing::Span,
+}
+
+impl AlibiBias {
+    pub(crate) fn new(config: &super::Config, device: &Device) -> Result<Self> {
+        let n_heads = config.num_attention_heads;
+        // The slopes are a geometric sequence for the closest power of two number of heads. If the number of heads is not a power of two, the extra heads take every other slope from the next power of two
+        let mut n_heads2 = 1;
+        while n_heads2 < n_heads {
+            n_heads2 *= 2
+        }
+        let slopes = (1..=n_heads2)
+            .map(|v| -1f32 / 2f32.powf((v * 8) as f32 / n_heads2 as f32))
+            .collect::<Vec<_>>();
+        let slopes = if n_heads2 == n_heads {
+            slopes
+        } else {
+            slopes
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .chain(slopes.iter().step_by(2))
+                .take(n_heads)
+                .copied()
+                .collect()
+        };
+        let slopes = Tensor::new(slopes, device)?.reshape((1, n_heads, 1, 1))?;
+        Ok(Self {
+            slopes,
+            span: tracing::span!(tracing::Level::TRACE, "alibi"),
+        })
+    }
+
+    /// Create the bias for a sequence. The output has the shape (1, num_heads, seq_len, seq_len)
+    pub(crate) fn forward(&self, seq_len: usize) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        // Build the bias for the current sequence length instead of the whole context so long context models don't allocate a (heads, context, context) tensor up front
+        let positions =
+            Tensor::arange(0, seq_len as u32, self.slopes.device())?.to_dtype(DType::F32)?;
+        let distance = positions
+            .reshape((1, seq_len))?
+            .broadcast_sub(&positions.reshape((seq_len, 1))?)?
+            .abs()?;
+        distance
+            .unsqueeze(0)?
+            .unsqueeze(0)?
+            .broadcast_mul(&self.slopes)?
+            .to_dtype(super::DTYPE)
+    }
+}
@@ -25,12 +25,13 @@ impl BertAttention {
         &self,
         hidden_states: &Tensor,
         attention_mask: Option<&Tensor>,
+        position_bias: Option<&Tensor>,
         train: bool,
     ) -> Result<Tensor> {
         let _enter = self.span.enter();
-        let self_outputs = self
-            .self_attention
-            .forward(hidden_states, attention_mask, train)?;
+        let self_outputs =
+            self.self_attention
+                .forward(hidden_states, attention_mask, position_bias, train)?;
         let attention_output = self
             .self_output
             .forward(&self_outputs, hidden_states, train)?;
@@ -0,0 +1,92 @@
+//! Sequence classification heads used by cross-encoder rerankers.
+
+use candle_core::{IndexOp, Result, Tensor};
+use candle_nn::{Module, VarBuilder};
+use candle_transformers::models::with_tracing::{linear, Linear};
+
+use super::{BertModel, BertVariant};
+
+enum ClassificationHead {
+    // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L1540
+    Bert { pooler: Linear, classifier: Linear },
+    // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/roberta/modeling_roberta.py#L1445
+    Roberta { dense: Linear, out_proj: Linear },
+}
+
+/// A raw synchronous Bert model with a sequence classification head. You should generally use the [`crate::BertReranker`] instead.
+pub struct BertForSequenceClassification {
+    bert: BertModel,
+    head: ClassificationHead,
+    span: tracing::Span,
+}
+
+impl BertForSequenceClassification {
+    /// Load a new [`BertForSequenceClassification`] from [`VarBuilder`] with a [`super::Config`].
+    pub fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let bert = BertModel::load(vb.clone(), config)?;
+        let num_labels = config.num_labels();
+        let head = match config.variant() {
+            BertVariant::XlmRoberta => ClassificationHead::Roberta {
+                dense: linear(
+                    config.hidden_size,
+                    config.hidden_size,
+                    vb.pp("classifier.dense"),
+                )?,
+                out_proj: linear(config.hidden_size, num_labels, vb.pp("classifier.out_proj"))?,
+            },
+            _ => {
+                // The pooler is part of the bert model, so it is stored under the same prefix as the encoder
+                let pooler = linear(
+                    config.hidden_size,
+                    config.hidden_size,
+                    vb.pp("bert.pooler.dense"),
+                )
+                .or_else(|_| {
+                    linear(
+                        config.hidden_size,
+                        config.hidden_size,
+                        vb.pp("pooler.dense"),
+                    )
+                })?;
+                ClassificationHead::Bert {
+                    pooler,
+                    classifier: linear(config.hidden_size, num_labels, vb.pp("classifier"))?,
+                }
+            }
+        };
+        Ok(Self {
+            bert,
+            head,
+            span: tracing::span!(tracing::Level::TRACE, "classifier"),
+        })
+    }
+
+    /// Run the model with a batch of inputs and return the logits for each input with the shape (batch_size, num_labels).
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        token_type_ids: &Tensor,
+        attention_mask: Option<&Tensor>,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let sequence_output =
+            self.bert
+                .forward(input_ids, token_type_ids, attention_mask, false)?;
+        // Both heads classify the first (CLS) token
+        let cls = sequence_output.i((.., 0, ..))?;
+        match &self.head {
+            ClassificationHead::Bert { pooler, classifier } => {
+                let pooled = pooler.forward(&cls)?.tanh()?;
+                classifier.forward(&pooled)
+            }
+            ClassificationHead::Roberta { dense, out_proj } => {
+                let hidden = dense.forward(&cls)?.tanh()?;
+                out_proj.forward(&hidden)
+            }
+        }
+    }
+
+    pub(crate) fn device(&self) -> &candle_core::Device {
+        &self.bert.device
+    }
+}
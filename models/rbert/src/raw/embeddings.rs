@@ -7,6 +7,8 @@ use candle_nn::Dropout;
 use candle_nn::{embedding, Embedding, Module, ModuleT, VarBuilder};
 use candle_transformers::models::with_tracing::{layer_norm, LayerNorm};
 
+use super::BertVariant;
+
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L180
 pub(crate) struct BertEmbeddings {
     word_embeddings: Embedding,
@@ -14,37 +16,51 @@ pub(crate) struct BertEmbeddings {
     token_type_embeddings: Embedding,
     layer_norm: LayerNorm,
     dropout: Dropout,
+    position_offset: u32,
     span: tracing::Span,
 }
 
 impl BertEmbeddings {
+    /// Load the embeddings from the root of the model. Nomic bert stores the embedding layer norm outside of the embeddings module
     pub(crate) fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let variant = config.variant();
+        let embeddings_vb = vb.pp("embeddings");
         let word_embeddings = embedding(
             config.vocab_size,
             config.hidden_size,
-            vb.pp("word_embeddings"),
-        )?;
-        let position_embeddings = embedding(
-            config.max_position_embeddings,
-            config.hidden_size,
-            vb.pp("position_embeddings"),
+            embeddings_vb.pp("word_embeddings"),
         )?;
+        // Nomic bert and jina bert encode positions in the attention layers instead
+        let position_embeddings = match variant {
+            BertVariant::Bert | BertVariant::XlmRoberta => Some(embedding(
+                config.max_position_embeddings,
+                config.hidden_size,
+                embeddings_vb.pp("position_embeddings"),
+            )?),
+            BertVariant::NomicBert | BertVariant::JinaBert => None,
+        };
         let token_type_embeddings = embedding(
             config.type_vocab_size,
             config.hidden_size,
-            vb.pp("token_type_embeddings"),
-        )?;
-        let layer_norm = layer_norm(
-            config.hidden_size,
-            config.layer_norm_eps,
-            vb.pp("LayerNorm"),
+            embeddings_vb.pp("token_type_embeddings"),
         )?;
+        let layer_norm_vb = match variant {
+            BertVariant::NomicBert => vb.pp("emb_ln"),
+            _ => embeddings_vb.pp("LayerNorm"),
+        };
+        let layer_norm = layer_norm(config.hidden_size, config.layer_norm_eps, layer_norm_vb)?;
+        // Roberta position ids start after the padding token
+        let position_offset = match variant {
+            BertVariant::XlmRoberta => config.pad_token_id as u32 + 1,
+            _ => 0,
+        };
         Ok(Self {
             word_embeddings,
-            position_embeddings: Some(position_embeddings),
+            position_embeddings,
             token_type_embeddings,
             layer_norm,
             dropout: Dropout::new(config.hidden_dropout_prob),
+            position_offset,
             span: tracing::span!(tracing::Level::TRACE, "embeddings"),
         })
     }
@@ -61,7 +77,11 @@ impl BertEmbeddings {
         let token_type_embeddings = self.token_type_embeddings.forward(token_type_ids)?;
         let mut embeddings = (&input_embeddings + token_type_embeddings)?;
         if let Some(position_embeddings) = &self.position_embeddings {
-            let position_ids = Tensor::arange(0, seq_len as u32, input_ids.device())?;
+            let position_ids = Tensor::arange(
+                self.position_offset,
+                self.position_offset + seq_len as u32,
+                input_ids.device(),
+            )?;
             embeddings = embeddings.broadcast_add(&position_embeddings.forward(&position_ids)?)?
         }
         let embeddings = self.layer_norm.forward(&embeddings)?;
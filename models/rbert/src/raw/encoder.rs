@@ -1,19 +1,43 @@
 use candle_core::{Result, Tensor};
 use candle_nn::VarBuilder;
 
-use super::BertLayer;
+use super::{AlibiBias, BertLayer, BertVariant, NomicBertLayer, RotaryEmbedding};
+
+enum BertEncoderLayers {
+    Bert {
+        layers: Vec<BertLayer>,
+        alibi: Option<AlibiBias>,
+    },
+    Nomic {
+        layers: Vec<NomicBertLayer>,
+        rotary: RotaryEmbedding,
+    },
+}
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L556
 pub(crate) struct BertEncoder {
-    layers: Vec<BertLayer>,
+    layers: BertEncoderLayers,
     span: tracing::Span,
 }
 
 impl BertEncoder {
     pub(crate) fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
-        let layers = (0..config.num_hidden_layers)
-            .map(|index| BertLayer::load(vb.pp(format!("layer.{index}")), config))
-            .collect::<Result<Vec<_>>>()?;
+        let layers = match config.variant() {
+            BertVariant::NomicBert => BertEncoderLayers::Nomic {
+                layers: (0..config.num_hidden_layers)
+                    .map(|index| NomicBertLayer::load(vb.pp(format!("layers.{index}")), config))
+                    .collect::<Result<Vec<_>>>()?,
+                rotary: RotaryEmbedding::new(config, vb.device())?,
+            },
+            variant => BertEncoderLayers::Bert {
+                layers: (0..config.num_hidden_layers)
+                    .map(|index| BertLayer::load(vb.pp(format!("layer.{index}")), config))
+                    .collect::<Result<Vec<_>>>()?,
+                alibi: (variant == BertVariant::JinaBert)
+                    .then(|| AlibiBias::new(config, vb.device()))
+                    .transpose()?,
+            },
+        };
         let span = tracing::span!(tracing::Level::TRACE, "encoder");
         Ok(BertEncoder { layers, span })
     }
@@ -25,10 +49,31 @@ impl BertEncoder {
         train: bool,
     ) -> Result<Tensor> {
         let _enter = self.span.enter();
+        let seq_len = hidden_states.dim(1)?;
         let mut hidden_states = hidden_states.clone();
         // Use a loop rather than a fold as it's easier to modify when adding debug/...
-        for layer in self.layers.iter() {
-            hidden_states = layer.forward(&hidden_states, attention_mask, train)?
+        match &self.layers {
+            BertEncoderLayers::Bert { layers, alibi } => {
+                let position_bias = alibi
+                    .as_ref()
+                    .map(|alibi| alibi.forward(seq_len))
+                    .transpose()?;
+                for layer in layers.iter() {
+                    hidden_states = layer.forward(
+                        &hidden_states,
+                        attention_mask,
+                        position_bias.as_ref(),
+                        train,
+                    )?
+                }
+            }
+            BertEncoderLayers::Nomic { layers, rotary } => {
+                let (cos, sin) = rotary.forward(seq_len)?;
+                for layer in layers.iter() {
+                    hidden_states =
+                        layer.forward(&hidden_states, attention_mask, (&cos, &sin), train)?
+                }
+            }
         }
         Ok(hidden_states)
     }
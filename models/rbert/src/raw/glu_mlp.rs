@@ -0,0 +1,57 @@
+use candle_core::{Result, Tensor, D};
+use candle_nn::{Dropout, Module, ModuleT, VarBuilder};
+use candle_transformers::models::with_tracing::{
+    layer_norm, linear, linear_no_bias, LayerNorm, Linear,
+};
+
+use super::HiddenActLayer;
+
+// The gated feed forward layer jina-bert uses in place of the intermediate and output layers
+// https://huggingface.co/jinaai/jina-bert-implementation/blob/main/modeling_bert.py
+pub(crate) struct BertGluMlp {
+    gated_layers: Linear,
+    act: HiddenActLayer,
+    wo: Linear,
+    layer_norm: LayerNorm,
+    dropout: Dropout,
+    intermediate_size: usize,
+    span: tracing::Span,
+}
+
+impl BertGluMlp {
+    pub(crate) fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let gated_layers = linear_no_bias(
+            config.hidden_size,
+            config.intermediate_size * 2,
+            vb.pp("gated_layers"),
+        )?;
+        let wo = linear(config.intermediate_size, config.hidden_size, vb.pp("wo"))?;
+        let layer_norm = layer_norm(
+            config.hidden_size,
+            config.layer_norm_eps,
+            vb.pp("layernorm"),
+        )?;
+        Ok(Self {
+            gated_layers,
+            act: HiddenActLayer::new(config.hidden_act),
+            wo,
+            layer_norm,
+            dropout: Dropout::new(config.hidden_dropout_prob),
+            intermediate_size: config.intermediate_size,
+            span: tracing::span!(tracing::Level::TRACE, "glu-mlp"),
+        })
+    }
+
+    pub(crate) fn forward(&self, hidden_states: &Tensor, train: bool) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let residual = hidden_states;
+        let hidden_states = self.gated_layers.forward(hidden_states)?;
+        let gated = hidden_states.narrow(D::Minus1, 0, self.intermediate_size)?;
+        let non_gated =
+            hidden_states.narrow(D::Minus1, self.intermediate_size, self.intermediate_size)?;
+        let hidden_states = (self.act.forward(&gated)? * non_gated)?;
+        let hidden_states = self.wo.forward(&hidden_states)?;
+        let hidden_states = self.dropout.forward_t(&hidden_states, train)?;
+        self.layer_norm.forward(&(hidden_states + residual)?)
+    }
+}
@@ -1,25 +1,38 @@
 use candle_core::{Result, Tensor};
 use candle_nn::{Module, VarBuilder};
 
-use super::{BertAttention, BertIntermediate, BertOutput};
+use super::{BertAttention, BertGluMlp, BertIntermediate, BertOutput, BertVariant};
+
+enum BertFeedForward {
+    Dense {
+        intermediate: BertIntermediate,
+        output: BertOutput,
+    },
+    Gated(BertGluMlp),
+}
 
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/modeling_bert.py#L470
 pub(crate) struct BertLayer {
     attention: BertAttention,
-    intermediate: BertIntermediate,
-    output: BertOutput,
+    feed_forward: BertFeedForward,
     span: tracing::Span,
 }
 
 impl BertLayer {
     pub(crate) fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
         let attention = BertAttention::load(vb.pp("attention"), config)?;
-        let intermediate = BertIntermediate::load(vb.pp("intermediate"), config)?;
-        let output = BertOutput::load(vb.pp("output"), config)?;
+        let feed_forward = match config.variant() {
+            BertVariant::JinaBert => {
+                BertFeedForward::Gated(BertGluMlp::load(vb.pp("mlp"), config)?)
+            }
+            _ => BertFeedForward::Dense {
+                intermediate: BertIntermediate::load(vb.pp("intermediate"), config)?,
+                output: BertOutput::load(vb.pp("output"), config)?,
+            },
+        };
         Ok(Self {
             attention,
-            intermediate,
-            output,
+            feed_forward,
             span: tracing::span!(tracing::Level::TRACE, "layer"),
         })
     }
@@ -28,16 +41,22 @@ impl BertLayer {
         &self,
         hidden_states: &Tensor,
         attention_mask: Option<&Tensor>,
+        position_bias: Option<&Tensor>,
         train: bool,
     ) -> Result<Tensor> {
         let _enter = self.span.enter();
-        let attention_output = self
-            .attention
-            .forward(hidden_states, attention_mask, train)?;
-        let intermediate_output = self.intermediate.forward(&attention_output)?;
-        let layer_output = self
-            .output
-            .forward(&intermediate_output, &attention_output, train)?;
-        Ok(layer_output)
+        let attention_output =
+            self.attention
+                .forward(hidden_states, attention_mask, position_bias, train)?;
+        match &self.feed_forward {
+            BertFeedForward::Dense {
+                intermediate,
+                output,
+            } => {
+                let intermediate_output = intermediate.forward(&attention_output)?;
+                output.forward(&intermediate_output, &attention_output, train)
+            }
+            BertFeedForward::Gated(mlp) => mlp.forward(&attention_output, train),
+        }
     }
 }
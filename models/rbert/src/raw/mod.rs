@@ -16,10 +16,19 @@ mod self_output;
 use self_output::*;
 mod intermediate_layer;
 use intermediate_layer::*;
+mod glu_mlp;
+use glu_mlp::*;
+mod alibi;
+use alibi::*;
+mod nomic;
+use nomic::*;
+mod classifier;
+pub use classifier::*;
 
 use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::VarBuilder;
 use serde::Deserialize;
+use std::collections::HashMap;
 
 pub(crate) const DTYPE: DType = DType::F32;
 
@@ -29,6 +38,8 @@ pub enum HiddenAct {
     Gelu,
     GeluApproximate,
     Relu,
+    #[serde(alias = "swiglu")]
+    Silu,
 }
 
 struct HiddenActLayer {
@@ -49,6 +60,7 @@ impl HiddenActLayer {
             HiddenAct::Gelu => xs.gelu_erf(),
             HiddenAct::GeluApproximate => xs.gelu(),
             HiddenAct::Relu => xs.relu(),
+            HiddenAct::Silu => xs.silu(),
         }
     }
 }
@@ -58,23 +70,48 @@ impl HiddenActLayer {
 enum PositionEmbeddingType {
     #[default]
     Absolute,
+    Alibi,
+}
+
+/// The encoder architecture of a [`BertModel`]. The variant is detected from the `config.json` of the model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BertVariant {
+    /// The original BERT architecture with absolute position embeddings.
+    Bert,
+    /// The (XLM-)RoBERTa architecture. This is the same as BERT except the position ids start after the padding token id. Multilingual models like multilingual-e5 and bge-m3 use this architecture.
+    XlmRoberta,
+    /// The nomic-bert architecture with rotary position embeddings and a SwiGLU feed forward layer.
+    NomicBert,
+    /// The jina-bert architecture with ALiBi attention biases and a GeGLU feed forward layer.
+    JinaBert,
 }
 
 /// The configuration of a [`BertModel`].
 // https://github.com/huggingface/transformers/blob/6eedfa6dd15dc1e22a55ae036f681914e5a0d9a1/src/transformers/models/bert/configuration_bert.py#L1
 #[derive(Debug, Clone, PartialEq, Deserialize)]
+// Nomic bert uses the GPT-2 style names for the config, so some fields have aliases
 pub struct Config {
     vocab_size: usize,
+    #[serde(alias = "n_embd")]
     hidden_size: usize,
+    #[serde(alias = "n_layer")]
     num_hidden_layers: usize,
+    #[serde(alias = "n_head")]
     num_attention_heads: usize,
+    #[serde(alias = "n_inner")]
     intermediate_size: usize,
+    #[serde(alias = "activation_function")]
     hidden_act: HiddenAct,
+    #[serde(default, alias = "resid_pdrop")]
     hidden_dropout_prob: f32,
+    #[serde(alias = "n_positions")]
     max_position_embeddings: usize,
     type_vocab_size: usize,
+    #[serde(default)]
     initializer_range: f64,
+    #[serde(alias = "layer_norm_epsilon")]
     layer_norm_eps: f64,
+    #[serde(default)]
     pad_token_id: usize,
     #[serde(default)]
     position_embedding_type: PositionEmbeddingType,
@@ -82,6 +119,54 @@ pub struct Config {
     use_cache: bool,
     classifier_dropout: Option<f64>,
     model_type: Option<String>,
+    #[serde(default = "default_rotary_emb_base")]
+    rotary_emb_base: f32,
+    #[serde(default = "default_true")]
+    qkv_proj_bias: bool,
+    #[serde(default = "default_true")]
+    mlp_fc1_bias: bool,
+    #[serde(default = "default_true")]
+    mlp_fc2_bias: bool,
+    #[serde(default)]
+    id2label: Option<HashMap<String, String>>,
+}
+
+fn default_rotary_emb_base() -> f32 {
+    10000.
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Config {
+    /// Get the encoder architecture this config describes.
+    pub fn variant(&self) -> BertVariant {
+        match self.model_type.as_deref() {
+            Some("nomic_bert") => BertVariant::NomicBert,
+            Some("xlm-roberta" | "roberta") => BertVariant::XlmRoberta,
+            _ if self.position_embedding_type == PositionEmbeddingType::Alibi => {
+                BertVariant::JinaBert
+            }
+            _ => BertVariant::Bert,
+        }
+    }
+
+    /// The number of labels a sequence classification head outputs. Rerankers have a single label.
+    pub fn num_labels(&self) -> usize {
+        self.id2label
+            .as_ref()
+            .map_or(1, |labels| labels.len().max(1))
+    }
+
+    /// The maximum number of tokens the model can embed at once.
+    pub fn max_position_embeddings(&self) -> usize {
+        match self.variant() {
+            // Roberta models reserve the first positions for padding
+            BertVariant::XlmRoberta => self.max_position_embeddings - self.pad_token_id - 1,
+            _ => self.max_position_embeddings,
+        }
+    }
 }
 
 /// A raw synchronous Bert model. You should generally use the [`super::Bert`] instead.
@@ -96,26 +181,28 @@ pub struct BertModel {
 impl BertModel {
     /// Load a new [`BertModel`] from [`VarBuilder`] with a [`Config`].
     pub fn load(vb: VarBuilder, config: &Config) -> Result<Self> {
-        let (embeddings, encoder) = match (
-            BertEmbeddings::load(vb.pp("embeddings"), config),
-            BertEncoder::load(vb.pp("encoder"), config),
-        ) {
-            (Ok(embeddings), Ok(encoder)) => (embeddings, encoder),
-            (Err(err), _) | (_, Err(err)) => {
-                if let Some(model_type) = &config.model_type {
-                    if let (Ok(embeddings), Ok(encoder)) = (
-                        BertEmbeddings::load(vb.pp(format!("{model_type}.embeddings")), config),
-                        BertEncoder::load(vb.pp(format!("{model_type}.encoder")), config),
-                    ) {
-                        (embeddings, encoder)
-                    } else {
-                        return Err(err);
+        match Self::load_with_prefix(vb.clone(), config) {
+            Ok(model) => Ok(model),
+            Err(err) => {
+                // Some models store the weights under the name of the architecture. Roberta models store the weights under `roberta` even if the model type is `xlm-roberta`
+                let prefixes = config
+                    .model_type
+                    .iter()
+                    .map(String::as_str)
+                    .chain(["roberta", "bert"]);
+                for prefix in prefixes {
+                    if let Ok(model) = Self::load_with_prefix(vb.pp(prefix), config) {
+                        return Ok(model);
                     }
-                } else {
-                    return Err(err);
                 }
+                Err(err)
             }
-        };
+        }
+    }
+
+    fn load_with_prefix(vb: VarBuilder, config: &Config) -> Result<Self> {
+        let embeddings = BertEmbeddings::load(vb.clone(), config)?;
+        let encoder = BertEncoder::load(vb.pp("encoder"), config)?;
         Ok(Self {
             embeddings,
             encoder,
@@ -149,3 +236,98 @@ impl BertModel {
         self.embeddings.embedding_dim()
     }
 }
+
+#[test]
+fn load_encoder_variants() {
+    let bert = serde_json::json!({
+        "vocab_size": 32,
+        "hidden_size": 8,
+        "num_hidden_layers": 2,
+        "num_attention_heads": 2,
+        "intermediate_size": 16,
+        "hidden_act": "gelu",
+        "hidden_dropout_prob": 0.0,
+        "max_position_embeddings": 16,
+        "type_vocab_size": 2,
+        "initializer_range": 0.02,
+        "layer_norm_eps": 1e-12,
+        "pad_token_id": 1,
+        "classifier_dropout": null,
+        "model_type": "bert"
+    });
+    let mut xlm_roberta = bert.clone();
+    xlm_roberta["model_type"] = "xlm-roberta".into();
+    let mut jina_bert = bert.clone();
+    jina_bert["position_embedding_type"] = "alibi".into();
+    let nomic_bert = serde_json::json!({
+        "vocab_size": 32,
+        "n_embd": 8,
+        "n_layer": 2,
+        "n_head": 2,
+        "n_inner": 16,
+        "activation_function": "swiglu",
+        "n_positions": 16,
+        "type_vocab_size": 2,
+        "layer_norm_epsilon": 1e-12,
+        "rotary_emb_base": 1000,
+        "qkv_proj_bias": false,
+        "mlp_fc1_bias": false,
+        "mlp_fc2_bias": false,
+        "model_type": "nomic_bert"
+    });
+
+    for (config, variant) in [
+        (bert, BertVariant::Bert),
+        (xlm_roberta, BertVariant::XlmRoberta),
+        (jina_bert, BertVariant::JinaBert),
+        (nomic_bert, BertVariant::NomicBert),
+    ] {
+        let config: Config = serde_json::from_value(config).unwrap();
+        assert_eq!(config.variant(), variant);
+
+        let device = Device::Cpu;
+        let varmap = candle_nn::VarMap::new();
+        let model =
+            BertModel::load(VarBuilder::from_varmap(&varmap, DTYPE, &device), &config).unwrap();
+        let forward = |tokens: &[u32], mask: &[u32]| {
+            let input_ids = Tensor::new(tokens, &device).unwrap().unsqueeze(0).unwrap();
+            let attention_mask = Tensor::new(mask, &device).unwrap().unsqueeze(0).unwrap();
+            model
+                .forward(
+                    &input_ids,
+                    &input_ids.zeros_like().unwrap(),
+                    Some(&attention_mask),
+                    false,
+                )
+                .unwrap()
+                .squeeze(0)
+                .unwrap()
+                .to_vec2::<f32>()
+                .unwrap()
+        };
+
+        let padded = forward(&[2, 3, 4, 1], &[1, 1, 1, 0]);
+        assert_eq!(padded.len(), 4);
+        assert!(padded.iter().flatten().all(|value| value.is_finite()));
+
+        // Padding is masked out, so it doesn't change the hidden states of the real tokens
+        let unpadded = forward(&[2, 3, 4], &[1, 1, 1]);
+        for (padded, unpadded) in padded.iter().zip(&unpadded) {
+            assert_eq!(padded.len(), 8);
+            for (padded, unpadded) in padded.iter().zip(unpadded) {
+                assert!((padded - unpadded).abs() < 1e-4, "{variant:?}");
+            }
+        }
+
+        // Every variant encodes positions, so moving a token changes its hidden state
+        let first = forward(&[2, 3, 3], &[1, 1, 1]);
+        let middle = forward(&[3, 2, 3], &[1, 1, 1]);
+        assert!(
+            first[0]
+                .iter()
+                .zip(&middle[1])
+                .any(|(first, middle)| (first - middle).abs() > 1e-4),
+            "{variant:?}"
+        );
+    }
+}
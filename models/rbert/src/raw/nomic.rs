@@ -0,0 +1,207 @@
+//! The nomic-bert encoder layer. Nomic bert replaces the absolute position embeddings with rotary embeddings and uses a SwiGLU feed forward layer, which lets it embed much longer inputs than BERT.
+
+use candle_core::{DType, Device, Result, Tensor, D};
+use candle_nn::{Dropout, Module, ModuleT, VarBuilder};
+use candle_transformers::models::with_tracing::{layer_norm, linear_b, LayerNorm, Linear};
+
+use super::{apply_attention_mask, HiddenActLayer};
+
+/// The rotary embeddings shared by every layer of the encoder.
+pub(crate) struct RotaryEmbedding {
+    inv_freq: Tensor,
+    span: tracing::Span,
+}
+
+impl RotaryEmbedding {
+    pub(crate) fn new(config: &super::Config, device: &Device) -> Result<Self> {
+        let head_dim = config.hidden_size / config.num_attention_heads;
+        let inv_freq = (0..head_dim)
+            .step_by(2)
+            .map(|i| 1f32 / config.rotary_emb_base.powf(i as f32 / head_dim as f32))
+            .collect::<Vec<_>>();
+        let inv_freq_len = inv_freq.len();
+        let inv_freq = Tensor::from_vec(inv_freq, (1, inv_freq_len), device)?;
+        Ok(Self {
+            inv_freq,
+            span: tracing::span!(tracing::Level::TRACE, "rotary"),
+        })
+    }
+
+    /// Get the cos and sin tables for a sequence. Each has the shape (seq_len, head_dim / 2)
+    pub(crate) fn forward(&self, seq_len: usize) -> Result<(Tensor, Tensor)> {
+        let _enter = self.span.enter();
+        let positions = Tensor::arange(0, seq_len as u32, self.inv_freq.device())?
+            .to_dtype(DType::F32)?
+            .reshape((seq_len, 1))?;
+        let freqs = positions.matmul(&self.inv_freq)?;
+        let cos = freqs.cos()?.to_dtype(super::DTYPE)?.contiguous()?;
+        let sin = freqs.sin()?.to_dtype(super::DTYPE)?.contiguous()?;
+        Ok((cos, sin))
+    }
+}
+
+// https://huggingface.co/nomic-ai/nomic-bert-2048/blob/main/modeling_hf_nomic_bert.py
+struct NomicBertAttention {
+    wqkv: Linear,
+    out_proj: Linear,
+    dropout: Dropout,
+    num_attention_heads: usize,
+    attention_head_size: usize,
+    span: tracing::Span,
+}
+
+impl NomicBertAttention {
+    fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let hidden_size = config.hidden_size;
+        let wqkv = linear_b(
+            hidden_size,
+            3 * hidden_size,
+            config.qkv_proj_bias,
+            vb.pp("Wqkv"),
+        )?;
+        let out_proj = linear_b(
+            hidden_size,
+            hidden_size,
+            config.qkv_proj_bias,
+            vb.pp("out_proj"),
+        )?;
+        Ok(Self {
+            wqkv,
+            out_proj,
+            dropout: Dropout::new(config.hidden_dropout_prob),
+            num_attention_heads: config.num_attention_heads,
+            attention_head_size: hidden_size / config.num_attention_heads,
+            span: tracing::span!(tracing::Level::TRACE, "nomic-attn"),
+        })
+    }
+
+    fn forward(
+        &self,
+        hidden_states: &Tensor,
+        attention_mask: Option<&Tensor>,
+        (cos, sin): (&Tensor, &Tensor),
+        train: bool,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let (b_size, seq_len, _) = hidden_states.dims3()?;
+        let qkv = self.wqkv.forward(hidden_states)?.reshape((
+            b_size,
+            seq_len,
+            3,
+            self.num_attention_heads,
+            self.attention_head_size,
+        ))?;
+        // (b_size, seq_len, num_heads, head_size) -> (b_size, num_heads, seq_len, head_size)
+        let split = |index: usize| {
+            qkv.narrow(2, index, 1)?
+                .squeeze(2)?
+                .transpose(1, 2)?
+                .contiguous()
+        };
+        let query_layer = candle_nn::rotary_emb::rope(&split(0)?, cos, sin)?;
+        let key_layer = candle_nn::rotary_emb::rope(&split(1)?, cos, sin)?;
+        let value_layer = split(2)?;
+
+        let attention_scores = query_layer.matmul(&key_layer.t()?)?;
+        let attention_scores = (attention_scores / (self.attention_head_size as f64).sqrt())?;
+        let attention_scores = apply_attention_mask(&attention_scores, attention_mask)?;
+        let attention_probs = candle_nn::ops::softmax(&attention_scores, D::Minus1)?;
+        let attention_probs = self.dropout.forward_t(&attention_probs, train)?;
+        let context_layer = attention_probs.matmul(&value_layer)?;
+        let context_layer = context_layer.transpose(1, 2)?.contiguous()?;
+        let context_layer = context_layer.flatten_from(D::Minus2)?;
+        self.out_proj.forward(&context_layer)
+    }
+}
+
+struct NomicBertGatedMlp {
+    fc11: Linear,
+    fc12: Linear,
+    fc2: Linear,
+    act: HiddenActLayer,
+    span: tracing::Span,
+}
+
+impl NomicBertGatedMlp {
+    fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let fc11 = linear_b(
+            config.hidden_size,
+            config.intermediate_size,
+            config.mlp_fc1_bias,
+            vb.pp("fc11"),
+        )?;
+        let fc12 = linear_b(
+            config.hidden_size,
+            config.intermediate_size,
+            config.mlp_fc1_bias,
+            vb.pp("fc12"),
+        )?;
+        let fc2 = linear_b(
+            config.intermediate_size,
+            config.hidden_size,
+            config.mlp_fc2_bias,
+            vb.pp("fc2"),
+        )?;
+        Ok(Self {
+            fc11,
+            fc12,
+            fc2,
+            act: HiddenActLayer::new(config.hidden_act),
+            span: tracing::span!(tracing::Level::TRACE, "nomic-mlp"),
+        })
+    }
+}
+
+impl Module for NomicBertGatedMlp {
+    fn forward(&self, hidden_states: &Tensor) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        let y = self.fc11.forward(hidden_states)?;
+        let gate = self.act.forward(&self.fc12.forward(hidden_states)?)?;
+        self.fc2.forward(&(y * gate)?)
+    }
+}
+
+pub(crate) struct NomicBertLayer {
+    attention: NomicBertAttention,
+    mlp: NomicBertGatedMlp,
+    norm1: LayerNorm,
+    norm2: LayerNorm,
+    dropout: Dropout,
+    span: tracing::Span,
+}
+
+impl NomicBertLayer {
+    pub(crate) fn load(vb: VarBuilder, config: &super::Config) -> Result<Self> {
+        let attention = NomicBertAttention::load(vb.pp("attn"), config)?;
+        let mlp = NomicBertGatedMlp::load(vb.pp("mlp"), config)?;
+        let norm1 = layer_norm(config.hidden_size, config.layer_norm_eps, vb.pp("norm1"))?;
+        let norm2 = layer_norm(config.hidden_size, config.layer_norm_eps, vb.pp("norm2"))?;
+        Ok(Self {
+            attention,
+            mlp,
+            norm1,
+            norm2,
+            dropout: Dropout::new(config.hidden_dropout_prob),
+            span: tracing::span!(tracing::Level::TRACE, "nomic-layer"),
+        })
+    }
+
+    pub(crate) fn forward(
+        &self,
+        hidden_states: &Tensor,
+        attention_mask: Option<&Tensor>,
+        rotary: (&Tensor, &Tensor),
+        train: bool,
+    ) -> Result<Tensor> {
+        let _enter = self.span.enter();
+        // Nomic bert uses post-norm layers like BERT
+        let attention_output =
+            self.attention
+                .forward(hidden_states, attention_mask, rotary, train)?;
+        let attention_output = self.dropout.forward_t(&attention_output, train)?;
+        let hidden_states = self.norm1.forward(&(attention_output + hidden_states)?)?;
+        let mlp_output = self.mlp.forward(&hidden_states)?;
+        let mlp_output = self.dropout.forward_t(&mlp_output, train)?;
+        self.norm2.forward(&(mlp_output + hidden_states)?)
+    }
+}
@@ -47,6 +47,7 @@ impl BertSelfAttention {
         &self,
         hidden_states: &Tensor,
         attention_mask: Option<&Tensor>,
+        position_bias: Option<&Tensor>,
         train: bool,
     ) -> Result<Tensor> {
         let _enter = self.span.enter();
@@ -60,19 +61,11 @@ impl BertSelfAttention {
 
         let attention_scores = query_layer.matmul(&key_layer.t()?)?;
         let mut attention_scores = (attention_scores / (self.attention_head_size as f64).sqrt())?;
-        // If there is an attention mask, filter the attention scores by that mask
-        if let Some(attention_mask) = attention_mask {
-            // The attention mask is a tensor of shape (bsize, seq_len)
-            // the attention scores are a tensor of shape (bsize, _, seq_len, seq_len)
-            // We expand the attention mask to (bsize, 1, 1, seq_len)
-            let mask = attention_mask.unsqueeze(1)?.unsqueeze(2)?;
-            let shape = attention_scores.shape();
-            let mask = mask.broadcast_as(shape)?.to_dtype(DType::U8)?;
-            // We use a value slightly larger that the true f32 min value to avoid NaN
-            const FALSE_MIN: f32 = -3.4028235e34f32;
-            let on_false = Tensor::new(FALSE_MIN, mask.device())?.broadcast_as(shape)?;
-            attention_scores = mask.where_cond(&attention_scores, &on_false)?;
+        // Models without position embeddings (like jina-bert) add a bias to the attention scores instead
+        if let Some(position_bias) = position_bias {
+            attention_scores = attention_scores.broadcast_add(position_bias)?;
         }
+        let attention_scores = apply_attention_mask(&attention_scores, attention_mask)?;
 
         let attention_probs = {
             let _enter_sm = self.span_softmax.enter();
@@ -85,3 +78,26 @@ impl BertSelfAttention {
         Ok(context_layer)
     }
 }
+
+/// Mask out the attention scores for padding tokens.
+pub(crate) fn apply_attention_mask(
+    attention_scores: &Tensor,
+    attention_mask: Option<&Tensor>,
+) -> Result<Tensor> {
+    // If there is an attention mask, filter the attention scores by that mask
+    match attention_mask {
+        Some(attention_mask) => {
+            // The attention mask is a tensor of shape (bsize, seq_len)
+            // the attention scores are a tensor of shape (bsize, _, seq_len, seq_len)
+            // We expand the attention mask to (bsize, 1, 1, seq_len)
+            let mask = attention_mask.unsqueeze(1)?.unsqueeze(2)?;
+            let shape = attention_scores.shape();
+            let mask = mask.broadcast_as(shape)?.to_dtype(DType::U8)?;
+            // We use a value slightly larger that the true f32 min value to avoid NaN
+            const FALSE_MIN: f32 = -3.4028235e34f32;
+            let on_false = Tensor::new(FALSE_MIN, mask.device())?.broadcast_as(shape)?;
+            mask.where_cond(attention_scores, &on_false)
+        }
+        None => Ok(attention_scores.clone()),
+    }
+}
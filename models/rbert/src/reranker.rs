@@ -0,0 +1,205 @@
+use crate::raw::{BertForSequenceClassification, Config, DTYPE};
+use crate::{BertFiles, BertSource};
+use candle_core::{IndexOp, Tensor};
+use candle_nn::VarBuilder;
+use kalosm_common::*;
+use kalosm_language_model::{ModelBuilder, Reranker};
+use std::sync::Arc;
+use tokenizers::{Encoding, PaddingParams, Tokenizer, TruncationParams};
+
+/// The number of query/document pairs to run through the model at once
+const RERANK_BATCH_SIZE: usize = 16;
+
+/// A builder for a [`BertReranker`] model
+pub struct BertRerankerBuilder {
+    source: BertSource,
+    cache: kalosm_common::Cache,
+}
+
+impl Default for BertRerankerBuilder {
+    fn default() -> Self {
+        Self {
+            source: BertSource::bge_reranker_base(),
+            cache: Default::default(),
+        }
+    }
+}
+
+impl BertRerankerBuilder {
+    /// Set the source of the model. The source must be a cross-encoder with a sequence classification head like [`BertSource::bge_reranker_base`] or [`BertSource::ms_marco_mini_lm_l6_v2`]
+    pub fn with_source(mut self, source: BertSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Set the cache location to use for the model (defaults DATA_DIR/kalosm/cache)
+    pub fn with_cache(mut self, cache: kalosm_common::Cache) -> Self {
+        self.cache = cache;
+
+        self
+    }
+
+    /// Build the model
+    pub async fn build(self) -> anyhow::Result<BertReranker> {
+        self.build_with_loading_handler(ModelLoadingProgress::multi_bar_loading_indicator())
+            .await
+    }
+
+    /// Build the model with a loading handler
+    pub async fn build_with_loading_handler(
+        self,
+        mut loading_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<BertReranker> {
+        let BertFiles {
+            config_filename,
+            tokenizer_filename,
+            weights_filename,
+        } = self
+            .source
+            .download(&self.cache, &mut loading_handler)
+            .await?;
+
+        let config = std::fs::read_to_string(config_filename)?;
+        let config: Config = serde_json::from_str(&config)?;
+
+        let device = accelerated_device_if_available()?;
+        let vb =
+            unsafe { VarBuilder::from_mmaped_safetensors(&[&weights_filename], DTYPE, &device)? };
+        let model = BertForSequenceClassification::load(vb, &config)?;
+        let mut tokenizer =
+            Tokenizer::from_file(&tokenizer_filename).map_err(anyhow::Error::msg)?;
+        tokenizer.with_padding(None);
+        // Truncate the longest of the query and document so the pair fits in the model
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings(),
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+
+        Ok(BertReranker {
+            model: Arc::new(model),
+            tokenizer: Arc::new(tokenizer),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ModelBuilder for BertRerankerBuilder {
+    type Model = BertReranker;
+
+    async fn start_with_loading_handler(
+        self,
+        loading_handler: impl FnMut(ModelLoadingProgress) + Send + 'static,
+    ) -> anyhow::Result<Self::Model> {
+        self.build_with_loading_handler(loading_handler).await
+    }
+
+    fn requires_download(&self) -> bool {
+        true
+    }
+}
+
+/// A cross-encoder that scores how relevant documents are to a query. The main interface for this model is [`Reranker`].
+///
+/// # Example
+/// ```rust, no_run
+/// use kalosm_language_model::Reranker;
+/// use rbert::*;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     let reranker = BertReranker::new().await?;
+///     let documents = [
+///         "Paris is the capital of France",
+///         "The quick brown fox jumps over the lazy dog",
+///     ];
+///     let ranked = reranker
+///         .rerank("What is the capital of France?", documents)
+///         .await?;
+///     println!("most relevant: {}", documents[ranked[0].index]);
+///
+///     Ok(())
+/// }
+/// ```
+#[derive(Clone)]
+pub struct BertReranker {
+    model: Arc<BertForSequenceClassification>,
+    tokenizer: Arc<Tokenizer>,
+}
+
+impl BertReranker {
+    /// Create a new [`BertRerankerBuilder`]
+    pub fn builder() -> BertRerankerBuilder {
+        BertRerankerBuilder::default()
+    }
+
+    /// Create a new default reranker model
+    pub async fn new() -> anyhow::Result<Self> {
+        Self::builder().build().await
+    }
+
+    /// Score how relevant each document is to the query. The scores are between 0 and 1.
+    pub fn score_batch_raw(&self, query: &str, documents: &[&str]) -> anyhow::Result<Vec<f32>> {
+        let pairs = documents
+            .iter()
+            .map(|document| (query, *document))
+            .collect::<Vec<_>>();
+        let encodings = self
+            .tokenizer
+            .encode_batch(pairs, true)
+            .map_err(anyhow::Error::msg)?;
+
+        let mut scores = Vec::with_capacity(documents.len());
+        for batch in encodings.chunks(RERANK_BATCH_SIZE) {
+            let batch_scores = maybe_autoreleasepool(|| self.score_encodings(batch.to_vec()))?;
+            scores.extend(batch_scores);
+        }
+        Ok(scores)
+    }
+
+    fn score_encodings(&self, mut encodings: Vec<Encoding>) -> anyhow::Result<Vec<f32>> {
+        let device = self.model.device();
+        let pp = PaddingParams {
+            strategy: tokenizers::PaddingStrategy::BatchLongest,
+            ..Default::default()
+        };
+        tokenizers::pad_encodings(&mut encodings, &pp).map_err(anyhow::Error::msg)?;
+
+        let stack = |get: fn(&Encoding) -> &[u32]| {
+            let rows = encodings
+                .iter()
+                .map(|encoding| Tensor::new(get(encoding), device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Tensor::stack(&rows, 0)
+        };
+        let token_ids = stack(Encoding::get_ids)?;
+        // Unlike embedding, the token type ids matter here because they separate the query from the document
+        let token_type_ids = stack(Encoding::get_type_ids)?;
+        let attention_mask = stack(Encoding::get_attention_mask)?;
+
+        let logits = self
+            .model
+            .forward(&token_ids, &token_type_ids, Some(&attention_mask))?;
+        // Rerankers output a single relevance logit for each pair
+        let relevance = candle_nn::ops::sigmoid(&logits.i((.., 0))?)?;
+        Ok(relevance.to_vec1()?)
+    }
+}
+
+impl Reranker for BertReranker {
+    fn score_batch(
+        &self,
+        query: String,
+        documents: Vec<String>,
+    ) -> BoxedFuture<'_, anyhow::Result<Vec<f32>>> {
+        Box::pin(async move {
+            let self_clone = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let documents = documents.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+                self_clone.score_batch_raw(&query, &documents)
+            })
+            .await?
+        })
+    }
+}
@@ -1,11 +1,21 @@
-use kalosm_common::FileSource;
+use crate::Pooling;
+use kalosm_common::{FileSource, ModelLoadingProgress};
+use std::path::PathBuf;
 
 const SNOWFLAKE_EMBEDDING_PREFIX: &str =
     "Represent this sentence for searching relevant passages: ";
+const E5_QUERY_PREFIX: &str = "query: ";
+const E5_DOCUMENT_PREFIX: &str = "passage: ";
+const NOMIC_QUERY_PREFIX: &str = "search_query: ";
+const NOMIC_DOCUMENT_PREFIX: &str = "search_document: ";
 
 /// A the source of a [`crate::Bert`] model
+///
+/// The encoder architecture (BERT, XLM-RoBERTa, nomic-bert or jina-bert) is detected from the config file.
 pub struct BertSource {
     pub(crate) search_embedding_prefix: Option<String>,
+    pub(crate) document_embedding_prefix: Option<String>,
+    pub(crate) pooling: Pooling,
     pub(crate) config: FileSource,
     pub(crate) tokenizer: FileSource,
     pub(crate) model: FileSource,
@@ -49,6 +59,21 @@ impl BertSource {
         self
     }
 
+    /// Set the prefix to use when embedding documents
+    pub(crate) fn with_document_embedding_prefix(
+        mut self,
+        prefix: impl Into<Option<String>>,
+    ) -> Self {
+        self.document_embedding_prefix = prefix.into();
+        self
+    }
+
+    /// Set the pooling strategy the model was trained with (defaults to [`Pooling::CLS`])
+    pub fn with_pooling(mut self, pooling: Pooling) -> Self {
+        self.pooling = pooling;
+        self
+    }
+
     /// Create a new [`BertSource`] with the BGE large english preset
     pub fn bge_large_en() -> Self {
         Self::default()
@@ -108,6 +133,8 @@ impl BertSource {
                 "model.safetensors".to_string(),
             ),
             search_embedding_prefix: None,
+            document_embedding_prefix: None,
+            pooling: Pooling::CLS,
         }
     }
 
@@ -237,6 +264,258 @@ impl BertSource {
             ))
             .with_search_embedding_prefix(SNOWFLAKE_EMBEDDING_PREFIX.to_string())
     }
+
+    /// Create a new [`BertSource`] with the [multilingual-e5-small](https://huggingface.co/intfloat/multilingual-e5-small) model
+    ///
+    /// This is a XLM-RoBERTa model that supports about 100 languages.
+    pub fn multilingual_e5_small() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "intfloat/multilingual-e5-small".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "intfloat/multilingual-e5-small".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "intfloat/multilingual-e5-small".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+            .with_search_embedding_prefix(E5_QUERY_PREFIX.to_string())
+            .with_document_embedding_prefix(E5_DOCUMENT_PREFIX.to_string())
+    }
+
+    /// Create a new [`BertSource`] with the [multilingual-e5-base](https://huggingface.co/intfloat/multilingual-e5-base) model
+    ///
+    /// This is a XLM-RoBERTa model that supports about 100 languages.
+    pub fn multilingual_e5_base() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "intfloat/multilingual-e5-base".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "intfloat/multilingual-e5-base".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "intfloat/multilingual-e5-base".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+            .with_search_embedding_prefix(E5_QUERY_PREFIX.to_string())
+            .with_document_embedding_prefix(E5_DOCUMENT_PREFIX.to_string())
+    }
+
+    /// Create a new [`BertSource`] with the [multilingual-e5-large](https://huggingface.co/intfloat/multilingual-e5-large) model
+    ///
+    /// This is a XLM-RoBERTa model that supports about 100 languages.
+    pub fn multilingual_e5_large() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "intfloat/multilingual-e5-large".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "intfloat/multilingual-e5-large".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "intfloat/multilingual-e5-large".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+            .with_search_embedding_prefix(E5_QUERY_PREFIX.to_string())
+            .with_document_embedding_prefix(E5_DOCUMENT_PREFIX.to_string())
+    }
+
+    /// Create a new [`BertSource`] with the [bge-m3](https://huggingface.co/BAAI/bge-m3) model
+    ///
+    /// This is a multilingual XLM-RoBERTa model that supports inputs up to 8192 tokens.
+    pub fn bge_m3() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "BAAI/bge-m3".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "BAAI/bge-m3".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "BAAI/bge-m3".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+    }
+
+    /// Create a new [`BertSource`] with the [nomic-embed-text-v1.5](https://huggingface.co/nomic-ai/nomic-embed-text-v1.5) model
+    ///
+    /// This is a nomic-bert model with rotary position embeddings that supports inputs up to 8192 tokens.
+    pub fn nomic_embed_text_v1_5() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "nomic-ai/nomic-embed-text-v1.5".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "nomic-ai/nomic-embed-text-v1.5".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "nomic-ai/nomic-embed-text-v1.5".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+            .with_search_embedding_prefix(NOMIC_QUERY_PREFIX.to_string())
+            .with_document_embedding_prefix(NOMIC_DOCUMENT_PREFIX.to_string())
+    }
+
+    /// Create a new [`BertSource`] with the [jina-embeddings-v2-small-en](https://huggingface.co/jinaai/jina-embeddings-v2-small-en) model
+    ///
+    /// This is a jina-bert model with ALiBi attention that supports inputs up to 8192 tokens.
+    pub fn jina_embeddings_v2_small_en() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-small-en".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-small-en".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-small-en".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+    }
+
+    /// Create a new [`BertSource`] with the [jina-embeddings-v2-base-en](https://huggingface.co/jinaai/jina-embeddings-v2-base-en) model
+    ///
+    /// This is a jina-bert model with ALiBi attention that supports inputs up to 8192 tokens.
+    pub fn jina_embeddings_v2_base_en() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-base-en".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-base-en".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "jinaai/jina-embeddings-v2-base-en".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+            .with_pooling(Pooling::Mean)
+    }
+
+    /// Create a new [`BertSource`] with the [bge-reranker-base](https://huggingface.co/BAAI/bge-reranker-base) cross-encoder for use with [`crate::BertReranker`]
+    pub fn bge_reranker_base() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "BAAI/bge-reranker-base".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "BAAI/bge-reranker-base".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "BAAI/bge-reranker-base".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+    }
+
+    /// Create a new [`BertSource`] with the [ms-marco-MiniLM-L-6-v2](https://huggingface.co/cross-encoder/ms-marco-MiniLM-L-6-v2) cross-encoder for use with [`crate::BertReranker`]
+    pub fn ms_marco_mini_lm_l6_v2() -> Self {
+        Self::default()
+            .with_model(FileSource::huggingface(
+                "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+                "main".to_string(),
+                "model.safetensors".to_string(),
+            ))
+            .with_tokenizer(FileSource::huggingface(
+                "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+                "main".to_string(),
+                "tokenizer.json".to_string(),
+            ))
+            .with_config(FileSource::huggingface(
+                "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string(),
+                "main".to_string(),
+                "config.json".to_string(),
+            ))
+    }
+}
+
+/// The files of a [`BertSource`] after they have been downloaded.
+pub(crate) struct BertFiles {
+    pub(crate) config_filename: PathBuf,
+    pub(crate) tokenizer_filename: PathBuf,
+    pub(crate) weights_filename: PathBuf,
+}
+
+impl BertSource {
+    /// Download the config, tokenizer and weights of the model if they are not already cached.
+    pub(crate) async fn download(
+        &self,
+        cache: &kalosm_common::Cache,
+        mut progress_handler: impl FnMut(ModelLoadingProgress),
+    ) -> anyhow::Result<BertFiles> {
+        let source = format!("Config ({})", self.config);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(source);
+        let config_filename = cache
+            .get(&self.config, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let tokenizer_source = format!("Tokenizer ({})", self.tokenizer);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(tokenizer_source);
+        let tokenizer_filename = cache
+            .get(&self.tokenizer, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+        let model_source = format!("Model ({})", self.model);
+        let mut create_progress = ModelLoadingProgress::downloading_progress(model_source);
+        let weights_filename = cache
+            .get(&self.model, |progress| {
+                progress_handler(create_progress(progress))
+            })
+            .await?;
+
+        Ok(BertFiles {
+            config_filename,
+            tokenizer_filename,
+            weights_filename,
+        })
+    }
 }
 
 impl Default for BertSource {
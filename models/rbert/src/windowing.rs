@@ -0,0 +1,172 @@
+use candle_core::Tensor;
+use tokenizers::{PostProcessor, Tokenizer, TruncationParams};
+
+/// How to combine the embeddings of each window of a long input into a single embedding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowPooling {
+    /// Take the mean of the embeddings of every window
+    #[default]
+    Mean,
+    /// Take the mean of the embeddings of every window weighted by the number of tokens in the window. This keeps a short trailing window from having as much influence as the full windows before it.
+    Weighted,
+}
+
+/// Settings for embedding inputs that are longer than the context length of the model.
+///
+/// When enabled, inputs that don't fit in the model are split into windows of tokens that overlap by [`TokenWindows::with_overlap`] tokens. Each window is embedded with the normal [`crate::Pooling`] strategy and then the windows are pooled into a single embedding with [`WindowPooling`].
+///
+/// ```rust, no_run
+/// use rbert::*;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// let bert = Bert::builder()
+///     .with_token_windows(TokenWindows::default().with_pooling(WindowPooling::Weighted))
+///     .build()
+///     .await?;
+/// let embedding = bert.embed("a very long document...".repeat(1000)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenWindows {
+    overlap: usize,
+    pooling: WindowPooling,
+}
+
+impl Default for TokenWindows {
+    fn default() -> Self {
+        Self {
+            overlap: 64,
+            pooling: WindowPooling::Mean,
+        }
+    }
+}
+
+impl TokenWindows {
+    /// Set the number of tokens each window shares with the window before it (defaults to 64)
+    ///
+    /// The overlap must be smaller than the context length of the model minus the special tokens the tokenizer adds to each window. Building the model fails otherwise.
+    pub fn with_overlap(mut self, overlap: usize) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Set the strategy used to combine the embeddings of each window (defaults to [`WindowPooling::Mean`])
+    pub fn with_pooling(mut self, pooling: WindowPooling) -> Self {
+        self.pooling = pooling;
+        self
+    }
+
+    /// Get the number of tokens each window shares with the window before it
+    pub fn overlap(&self) -> usize {
+        self.overlap
+    }
+
+    /// Get the strategy used to combine the embeddings of each window
+    pub fn pooling(&self) -> WindowPooling {
+        self.pooling
+    }
+
+    /// Make the tokenizer split inputs longer than `max_length` tokens into overlapping windows. Returns an error if the overlap doesn't leave room for new tokens in each window.
+    pub(crate) fn apply(&self, tokenizer: &mut Tokenizer, max_length: usize) -> anyhow::Result<()> {
+        let added_tokens = tokenizer
+            .get_post_processor()
+            .map_or(0, |processor| processor.added_tokens(false));
+        let window_tokens = max_length.saturating_sub(added_tokens);
+        if self.overlap >= window_tokens {
+            anyhow::bail!(
+                "The token window overlap ({}) must be smaller than the number of tokens in each window ({} context length - {} special tokens)",
+                self.overlap,
+                max_length,
+                added_tokens
+            );
+        }
+        // Truncating with a stride makes the tokenizer return the rest of the input as overlapping windows
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length,
+                stride: self.overlap,
+                ..Default::default()
+            }))
+            .map_err(anyhow::Error::msg)?;
+        Ok(())
+    }
+
+    /// Combine the embeddings of each window of an input. Each window is paired with the number of tokens it contains.
+    pub(crate) fn combine(&self, windows: &[(Tensor, usize)]) -> candle_core::Result<Tensor> {
+        if let [(embedding, _)] = windows {
+            return Ok(embedding.clone());
+        }
+        let weights = windows
+            .iter()
+            .map(|(_, tokens)| match self.pooling {
+                WindowPooling::Mean => 1.,
+                WindowPooling::Weighted => *tokens as f64,
+            })
+            .collect::<Vec<_>>();
+        let total_weight: f64 = weights.iter().sum();
+        let mut combined: Option<Tensor> = None;
+        for ((embedding, _), weight) in windows.iter().zip(weights) {
+            let weighted = (embedding * (weight / total_weight))?;
+            combined = Some(match combined {
+                Some(combined) => (combined + weighted)?,
+                None => weighted,
+            });
+        }
+        combined.ok_or_else(|| candle_core::Error::Msg("No windows to combine".to_string()))
+    }
+}
+
+#[test]
+fn combine_windows() {
+    let device = candle_core::Device::Cpu;
+    let windows = [
+        (Tensor::new(&[[1f32, 0.]], &device).unwrap(), 3),
+        (Tensor::new(&[[0f32, 1.]], &device).unwrap(), 1),
+    ];
+
+    let mean = TokenWindows::default().combine(&windows).unwrap();
+    assert_eq!(mean.to_vec2::<f32>().unwrap(), vec![vec![0.5, 0.5]]);
+
+    let weighted = TokenWindows::default()
+        .with_pooling(WindowPooling::Weighted)
+        .combine(&windows)
+        .unwrap();
+    assert_eq!(weighted.to_vec2::<f32>().unwrap(), vec![vec![0.75, 0.25]]);
+}
+
+#[test]
+fn overlap_must_fit_in_window() {
+    use tokenizers::models::wordlevel::WordLevel;
+    use tokenizers::pre_tokenizers::whitespace::Whitespace;
+
+    let vocab = [("[UNK]", 0), ("a", 1), ("b", 2)]
+        .into_iter()
+        .map(|(token, id)| (token.to_string(), id))
+        .collect();
+    let model = WordLevel::builder()
+        .vocab(vocab)
+        .unk_token("[UNK]".to_string())
+        .build()
+        .unwrap();
+    let mut tokenizer = Tokenizer::new(model);
+    tokenizer.with_pre_tokenizer(Whitespace {});
+
+    // The tokenizer accepts a stride equal to the window size, but panics when it encodes a long input with it
+    assert!(TokenWindows::default()
+        .with_overlap(4)
+        .apply(&mut tokenizer.clone(), 4)
+        .is_err());
+    assert!(TokenWindows::default()
+        .with_overlap(10)
+        .apply(&mut tokenizer.clone(), 4)
+        .is_err());
+
+    TokenWindows::default()
+        .with_overlap(2)
+        .apply(&mut tokenizer, 4)
+        .unwrap();
+    let encoding = tokenizer.encode("a b a b a b a b", false).unwrap();
+    assert_eq!(encoding.get_ids().len(), 4);
+    assert!(!encoding.get_overflowing().is_empty());
+}
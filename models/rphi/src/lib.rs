@@ -39,7 +39,7 @@ mod source;
 use kalosm_common::accelerated_device_if_available;
 use kalosm_common::ModelLoadingProgress;
 pub use kalosm_language_model;
-use kalosm_language_model::ChatMarkers;
+use kalosm_language_model::{ChatMarkers, FingerprintHasher, ModelFingerprint};
 use raw::PhiCache;
 pub use source::*;
 
@@ -53,6 +53,7 @@ use anyhow::Error as E;
 
 use crate::raw::Config;
 use crate::raw::MixFormerSequentialForCausalLM as QMixFormer;
+use candle_core::quantized::gguf_file;
 use candle_core::Device;
 use llm_samplers::prelude::Sampler;
 use model::PhiModel;
@@ -120,6 +121,7 @@ impl Phi {
         tokenizer: Tokenizer,
         device: Device,
         cache: PhiCache,
+        fingerprint: ModelFingerprint,
         chat_markers: Option<ChatMarkers>,
     ) -> Self {
         let (task_sender, mut task_receiver) = tokio::sync::mpsc::unbounded_channel();
@@ -128,7 +130,7 @@ impl Phi {
         std::thread::spawn({
             let arc_tokenizer = arc_tokenizer.clone();
             move || {
-                let mut inner = PhiModel::new(model, arc_tokenizer, device, cache);
+                let mut inner = PhiModel::new(model, arc_tokenizer, device, cache, fingerprint);
                 tokio::runtime::Builder::new_current_thread()
                     .enable_all()
                     .build()
@@ -250,6 +252,17 @@ impl PhiBuilder {
             .await?;
         let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(E::msg)?;
 
+        // The var builder does not expose the gguf header, so read it separately to fingerprint the weights
+        let weights_digest = {
+            let mut file = std::fs::File::open(&filename)?;
+            let content = gguf_file::Content::read(&mut file)?;
+            FingerprintHasher::new()
+                .update_gguf(&content)
+                .update_gguf_tensor_data(&content, &mut file)?
+                .finish()
+        };
+        let fingerprint = ModelFingerprint::with_tokenizer(weights_digest, &tokenizer);
+
         let config = self.source.phi_config;
         let device = accelerated_device_if_available()?;
         let vb =
@@ -267,6 +280,7 @@ impl PhiBuilder {
             tokenizer,
             device,
             cache,
+            fingerprint,
             self.source.chat_markers,
         ))
     }
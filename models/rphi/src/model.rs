@@ -3,6 +3,7 @@ use kalosm_common::copy_tensor_into_vec;
 use kalosm_language_model::Session;
 use kalosm_language_model::SyncModel;
 use kalosm_language_model::SyncModelExt;
+use kalosm_language_model::{load_session_tensors, save_session_tensors, ModelFingerprint};
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
@@ -20,12 +21,13 @@ use crate::InferenceSettings;
 pub struct PhiSession {
     cache: PhiCache,
     current_tokens: Vec<u32>,
+    fingerprint: Option<ModelFingerprint>,
 }
 
 impl Session for PhiSession {
     fn save_to(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
         let tensors = self.get_tensor_map();
-        Ok(candle_core::safetensors::save(&tensors, path)?)
+        save_session_tensors(&tensors, self.fingerprint.as_ref(), path)
     }
 
     fn tokens(&self) -> &[u32] {
@@ -37,9 +39,11 @@ impl Session for PhiSession {
         Self: std::marker::Sized,
     {
         let device = Device::cuda_if_available(0)?;
-        let tensors = candle_core::safetensors::load(path, &device)?;
+        let session = load_session_tensors(path, &device)?;
 
-        Ok(Self::from_tensor_map(tensors))
+        let mut loaded = Self::from_tensor_map(session.tensors);
+        loaded.fingerprint = session.fingerprint;
+        Ok(loaded)
     }
 
     fn try_clone(&self) -> anyhow::Result<Self>
@@ -48,6 +52,10 @@ impl Session for PhiSession {
     {
         Ok(self.clone())
     }
+
+    fn fingerprint(&self) -> Option<&ModelFingerprint> {
+        self.fingerprint.as_ref()
+    }
 }
 
 impl PhiSession {
@@ -76,6 +84,7 @@ impl PhiSession {
         Self {
             cache: PhiCache::from_tensor_map(map),
             current_tokens,
+            fingerprint: None,
         }
     }
 
@@ -91,6 +100,7 @@ pub struct PhiModel {
     model: QMixFormer,
     device: Device,
     tokenizer: Arc<Tokenizer>,
+    fingerprint: ModelFingerprint,
 }
 
 impl SyncModel for PhiModel {
@@ -102,6 +112,7 @@ impl SyncModel for PhiModel {
         Ok(PhiSession {
             cache,
             current_tokens: vec![],
+            fingerprint: Some(self.fingerprint.clone()),
         })
     }
 
@@ -148,6 +159,10 @@ impl SyncModel for PhiModel {
     fn tokenizer(&self) -> Arc<Tokenizer> {
         self.tokenizer.clone()
     }
+
+    fn fingerprint(&self) -> Option<ModelFingerprint> {
+        Some(self.fingerprint.clone())
+    }
 }
 
 impl PhiModel {
@@ -179,12 +194,14 @@ impl PhiModel {
         tokenizer: Arc<Tokenizer>,
         device: Device,
         cache: PhiCache,
+        fingerprint: ModelFingerprint,
     ) -> Self {
         Self {
             model,
             device,
             tokenizer,
             cache,
+            fingerprint,
         }
     }
 